@@ -0,0 +1,306 @@
+use crate::compression;
+use crate::export::{rewrite_attachment_links, safe_file_name, write_bundle};
+use crate::tags::{is_within, normalize_tag, tags_of};
+use crate::{load_notes, Note};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tauri::{AppHandle, Wry};
+
+// Flashcards for Anki, taken from notes by convention:
+//
+// - `Q: question` followed by `A: answer`, anywhere in a note. Lines after either
+//   belong to it until a blank line or the next `Q:`.
+// - In notes with `flashcards: headings` in their frontmatter, every heading below
+//   the title is a question and the text under it the answer.
+//
+// The deck is written as Anki's tab-separated text import (`<deck>.txt`, notetype
+// Basic, tags in the third column) next to a `media/` folder with the images the
+// cards show, into a directory or a `.zip`. The media files go into Anki's
+// `collection.media` folder before importing the text file.
+
+const DECK_NAME: &str = "Himoji Notes";
+// Frontmatter field choosing heading/body cards
+const FLASHCARDS_FIELD: &str = "flashcards";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnkiScope {
+    All,
+    Notes(Vec<String>),
+    // Notes with this tag or one nested below it
+    Tag(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnkiExport {
+    cards: usize,
+    // Notes that had at least one card
+    notes: usize,
+    media: usize,
+}
+
+struct Card {
+    front: String,
+    back: String,
+}
+
+impl Card {
+    fn is_complete(&self) -> bool {
+        !self.front.trim().is_empty() && !self.back.trim().is_empty()
+    }
+}
+
+fn push_line(text: &mut String, line: &str) {
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text.push_str(line);
+}
+
+fn is_fence(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with("```") || trimmed.starts_with("~~~")
+}
+
+// Cards written as `Q:` and `A:` lines
+fn qa_cards(content: &str) -> Vec<Card> {
+    let mut cards = Vec::new();
+    let mut card: Option<Card> = None;
+    let mut in_answer = false;
+    let mut in_fence = false;
+
+    for line in content.lines() {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+        let trimmed = line.trim_start();
+
+        if !in_fence {
+            if let Some(question) = trimmed.strip_prefix("Q:") {
+                cards.extend(card.take().filter(Card::is_complete));
+                card = Some(Card {
+                    front: question.trim().to_string(),
+                    back: String::new(),
+                });
+                in_answer = false;
+                continue;
+            }
+            if let Some(answer) = trimmed.strip_prefix("A:") {
+                if let Some(card) = card.as_mut().filter(|_| !in_answer) {
+                    card.back = answer.trim().to_string();
+                    in_answer = true;
+                    continue;
+                }
+            }
+            if trimmed.is_empty() {
+                cards.extend(card.take().filter(Card::is_complete));
+                continue;
+            }
+        }
+
+        if let Some(card) = card.as_mut() {
+            let text = if in_answer {
+                &mut card.back
+            } else {
+                &mut card.front
+            };
+            push_line(text, line);
+        }
+    }
+
+    cards.extend(card.filter(Card::is_complete));
+    cards
+}
+
+// Level and text of a markdown heading
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let text = line[level..].strip_prefix(' ')?;
+    (1..=6).contains(&level).then_some((level, text.trim()))
+}
+
+// Cards made of each heading below the title and the text under it
+fn heading_cards(content: &str) -> Vec<Card> {
+    let mut cards = Vec::new();
+    let mut card: Option<Card> = None;
+    let mut in_fence = false;
+
+    for line in content.lines() {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+        if !in_fence {
+            if let Some((level, text)) = heading(line) {
+                cards.extend(card.take().filter(Card::is_complete));
+                if level > 1 {
+                    card = Some(Card {
+                        front: text.to_string(),
+                        back: String::new(),
+                    });
+                }
+                continue;
+            }
+        }
+        if let Some(card) = card.as_mut() {
+            push_line(&mut card.back, line);
+        }
+    }
+
+    cards.extend(card.filter(Card::is_complete));
+    cards
+}
+
+fn cards_of(note: &Note) -> Vec<Card> {
+    let mut cards = qa_cards(&note.content);
+    let by_headings = note
+        .fields
+        .get(FLASHCARDS_FIELD)
+        .and_then(|v| v.as_str())
+        .is_some_and(|v| v == "headings");
+    if by_headings {
+        cards.extend(heading_cards(&note.content));
+    }
+    cards
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Markdown images as `<img>` tags; other markdown is left as text
+fn images_to_html(text: &str) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("![") {
+        let image = rest[start..].find("](").and_then(|middle| {
+            let target_start = start + middle + 2;
+            let len = rest[target_start..].find(')')?;
+            Some((target_start, len))
+        });
+        let Some((target_start, len)) = image else {
+            break;
+        };
+        html.push_str(&rest[..start]);
+        html.push_str(&format!(
+            "<img src=\"{}\">",
+            &rest[target_start..target_start + len]
+        ));
+        rest = &rest[target_start + len + 1..];
+    }
+
+    html.push_str(rest);
+    html
+}
+
+// A card side as a single line of HTML, with the note's attachments pointing at
+// their media file names
+fn field_html(note: &Note, text: &str) -> String {
+    let text = rewrite_attachment_links(text.trim(), &note.attachments, |name| {
+        Some(media_name(note, name))
+    });
+    images_to_html(&escape_html(&text))
+        .replace('\t', "    ")
+        .replace('\n', "<br>")
+}
+
+// Attachments of different notes may share a name
+fn media_name(note: &Note, attachment: &str) -> String {
+    format!("{}-{}", note.id, attachment.replace(' ', "_"))
+}
+
+// Anki nests tags with `::` and doesn't allow spaces in them
+fn anki_tags(note: &Note) -> String {
+    tags_of(note)
+        .iter()
+        .map(|tag| tag.replace('/', "::").replace(' ', "_"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn in_scope(note: &Note, scope: &AnkiScope) -> bool {
+    match scope {
+        AnkiScope::All => true,
+        AnkiScope::Notes(ids) => ids.contains(&note.id),
+        AnkiScope::Tag(tag) => {
+            let tag = normalize_tag(tag);
+            tags_of(note).iter().any(|t| is_within(t, &tag))
+        }
+    }
+}
+
+// Export the flashcards of the notes in `scope` as a deck, into a directory or,
+// when `path` ends in `.zip`, a zip archive
+#[tauri::command]
+pub async fn export_anki(
+    app_handle: AppHandle<Wry>,
+    scope: AnkiScope,
+    path: String,
+) -> Result<AnkiExport, String> {
+    let path = Path::new(&path);
+    if path.extension().and_then(|e| e.to_str()) == Some("apkg") {
+        return Err("Export to a folder or .zip; .apkg packages aren't supported".to_string());
+    }
+
+    let mut deck = format!(
+        "#separator:tab\n#html:true\n#notetype:Basic\n#deck:{}\n#tags column:3\n",
+        DECK_NAME
+    );
+    let mut files = Vec::new();
+    let mut export = AnkiExport {
+        cards: 0,
+        notes: 0,
+        media: 0,
+    };
+
+    for note in load_notes(&app_handle)?
+        .iter()
+        .filter(|n| in_scope(n, &scope))
+    {
+        let cards = cards_of(note);
+        if cards.is_empty() {
+            continue;
+        }
+
+        let tags = anki_tags(note);
+        let rows: Vec<String> = cards
+            .iter()
+            .map(|card| {
+                let front = field_html(note, &card.front);
+                let back = field_html(note, &card.back);
+                format!("{}\t{}\t{}\n", front, back, tags)
+            })
+            .collect();
+
+        // Only the attachments the cards show
+        for name in note.attachments.iter().filter(|name| {
+            let file = format!("\"{}\"", media_name(note, name));
+            rows.iter().any(|row| row.contains(&file))
+        }) {
+            match compression::read_note_attachment(&app_handle, &note.id, name) {
+                Ok(data) => {
+                    files.push((format!("media/{}", media_name(note, name)), data));
+                    export.media += 1;
+                }
+                Err(e) => println!("Skipping missing attachment {}: {}", name, e),
+            }
+        }
+        deck.extend(rows);
+        export.cards += cards.len();
+        export.notes += 1;
+    }
+
+    if export.cards == 0 {
+        return Err("No flashcards found in the selected notes".to_string());
+    }
+
+    files.push((
+        format!("{}.txt", safe_file_name(DECK_NAME)),
+        deck.into_bytes(),
+    ));
+    write_bundle(path, &files)?;
+    Ok(export)
+}
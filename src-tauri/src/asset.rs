@@ -0,0 +1,211 @@
+use crate::{compression, encryption, get_attachments_dir, protocol};
+use percent_encoding::percent_decode_str;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::{AppHandle, UriSchemeContext, UriSchemeResponder, Wry};
+
+// Attachments reach the webview through the `notes-asset` protocol, so an image
+// is just `<img src>` and its bytes never cross the IPC bridge. The frontend makes
+// URLs with `convertFileSrc("<note id>/<file name>", "notes-asset")`, which gives
+// `notes-asset://localhost/...` or `http://notes-asset.localhost/...` depending on
+// the platform; both arrive here as the same path. Range requests are answered so
+// audio and video can seek without loading the whole file into the page; from plain
+// files only the requested bytes are read. Attachments can be replaced under the
+// same name, so responses carry an ETag from the stored file's modification time
+// and size, and the webview checks it before using a cached copy.
+
+pub const SCHEME: &str = "notes-asset";
+
+// The note id and file name an asset URL points at
+fn attachment_of(request: &Request<Vec<u8>>) -> Option<(String, String)> {
+    let path = percent_decode_str(request.uri().path().trim_start_matches('/'))
+        .decode_utf8()
+        .ok()?;
+    let (note_id, file_name) = path.split_once('/')?;
+    protocol::check_id("note_id", note_id).ok()?;
+    protocol::check_file_name("file_name", file_name).ok()?;
+    Some((note_id.to_string(), file_name.to_string()))
+}
+
+// The first range of a `Range: bytes=start-end` header, clamped to `len`
+fn byte_range(request: &Request<Vec<u8>>, len: usize) -> Option<(usize, usize)> {
+    let spec = request
+        .headers()
+        .get(header::RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes=")?;
+    let (start, end) = spec.split(',').next()?.trim().split_once('-')?;
+    let (start, end) = match (start.parse::<usize>().ok(), end.parse::<usize>().ok()) {
+        (Some(start), Some(end)) => (start, end.min(len.saturating_sub(1))),
+        (Some(start), None) => (start, len.saturating_sub(1)),
+        // `bytes=-n` is the last n bytes
+        (None, Some(suffix)) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+        (None, None) => return None,
+    };
+    (start <= end && end < len).then_some((start, end))
+}
+
+// Validator of the stored file, which changes whenever it is rewritten
+fn etag_of(metadata: &fs::Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "\"{:x}-{:x}\"",
+        modified.as_nanos(),
+        metadata.len()
+    ))
+}
+
+// Bytes `start..=end` of a file, without reading the rest
+fn read_span(path: &Path, start: usize, end: usize) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start as u64))?;
+    let mut data = vec![0; end - start + 1];
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+// The requested part of an attachment, the range it covers, and the attachment's
+// full length. Compressed or encrypted attachments, and those of notes in cold
+// storage, have to be decoded whole.
+type Body = (Vec<u8>, Option<(usize, usize)>, usize);
+
+fn read_body(
+    app_handle: &AppHandle<Wry>,
+    request: &Request<Vec<u8>>,
+    note_id: &str,
+    name: &str,
+) -> io::Result<Body> {
+    let path = get_attachments_dir(app_handle, note_id).join(name);
+    if path.is_file() && !encryption::is_sealed_file(&path)? {
+        let len = fs::metadata(&path)?.len() as usize;
+        return match byte_range(request, len) {
+            Some((start, end)) => Ok((read_span(&path, start, end)?, Some((start, end)), len)),
+            None => Ok((fs::read(&path)?, None, len)),
+        };
+    }
+
+    let data = compression::read_note_attachment(app_handle, note_id, name)?;
+    let len = data.len();
+    Ok(match byte_range(request, len) {
+        Some((start, end)) => (data[start..=end].to_vec(), Some((start, end)), len),
+        None => (data, None, len),
+    })
+}
+
+fn status(code: StatusCode) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(code)
+        .body(Vec::new())
+        .unwrap_or_default()
+}
+
+fn respond(app_handle: &AppHandle<Wry>, request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let Some((note_id, file_name)) = attachment_of(request) else {
+        return status(StatusCode::BAD_REQUEST);
+    };
+    let attachments_dir = get_attachments_dir(app_handle, &note_id);
+    let path = attachments_dir.join(&file_name);
+    let stored = if path.is_file() {
+        path
+    } else {
+        compression::compressed_path(&path)
+    };
+    let etag = fs::metadata(&stored).ok().and_then(|m| etag_of(&m));
+
+    let cached = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok());
+    if let (Some(etag), Some(cached)) = (&etag, cached) {
+        if cached == etag {
+            return Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, etag)
+                .body(Vec::new())
+                .unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+    }
+
+    let (body, range, len) = match read_body(app_handle, request, &note_id, &file_name) {
+        Ok(body) => body,
+        Err(e) => {
+            println!(
+                "Failed to serve attachment {}/{}: {}",
+                note_id, file_name, e
+            );
+            return status(StatusCode::NOT_FOUND);
+        }
+    };
+
+    let mime = mime_guess::from_path(&file_name).first_or_octet_stream();
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, mime.essence_str())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, "no-cache");
+    if let Some(etag) = &etag {
+        builder = builder.header(header::ETAG, etag);
+    }
+    let response = match range {
+        Some((start, end)) => builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, len),
+            )
+            .body(body),
+        None => builder.status(StatusCode::OK).body(body),
+    };
+    response.unwrap_or_else(|_| status(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+// Handler for `register_asynchronous_uri_scheme_protocol`. Reading a large or
+// compressed attachment takes a while, so it happens off the main thread.
+pub fn handle(
+    ctx: UriSchemeContext<'_, Wry>,
+    request: Request<Vec<u8>>,
+    responder: UriSchemeResponder,
+) {
+    let app_handle = ctx.app_handle().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        responder.respond(respond(&app_handle, &request));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(spec: Option<&str>, len: usize) -> Option<(usize, usize)> {
+        let mut request = Request::builder();
+        if let Some(spec) = spec {
+            request = request.header(header::RANGE, spec);
+        }
+        byte_range(&request.body(Vec::new()).unwrap(), len)
+    }
+
+    #[test]
+    fn reads_a_plain_range() {
+        assert_eq!(range(Some("bytes=0-9"), 100), Some((0, 9)));
+        assert_eq!(range(Some("bytes=90-"), 100), Some((90, 99)));
+        assert_eq!(range(None, 100), None);
+    }
+
+    #[test]
+    fn suffix_is_the_last_bytes() {
+        assert_eq!(range(Some("bytes=-10"), 100), Some((90, 99)));
+        // A suffix longer than the file is the whole file
+        assert_eq!(range(Some("bytes=-500"), 100), Some((0, 99)));
+    }
+
+    #[test]
+    fn overrun_is_clamped_or_refused() {
+        assert_eq!(range(Some("bytes=90-500"), 100), Some((90, 99)));
+        assert_eq!(range(Some("bytes=100-"), 100), None);
+        assert_eq!(range(Some("bytes=0-"), 0), None);
+        assert_eq!(range(Some("bytes=20-10"), 100), None);
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Crash-safe file writes. Contents go to a temporary file next to the target, are
+// flushed to disk, and the temporary file is renamed over the target, so a crash or
+// power loss leaves either the old file or the new one, never a torn mix.
+//
+// Temporary files start with `.~` and are skipped wherever a directory's files are
+// listed; one left behind by a crash is harmless.
+
+const TEMP_PREFIX: &str = ".~";
+
+// Whether a file name is that of a write still in progress, or one cut short
+pub fn is_temp(name: &str) -> bool {
+    name.starts_with(TEMP_PREFIX)
+}
+
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+    // Unique, as the same file may be written twice at once (autosave and save)
+    let unique = uuid::Uuid::new_v4().simple();
+    Ok(path.with_file_name(format!("{}{}.{}", TEMP_PREFIX, name, unique)))
+}
+
+// The rename is only durable once the directory holding it is flushed as well.
+// Windows can't open a directory as a file and commits renames with the file.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// Files only the owner may read are created that way, not narrowed afterwards
+#[cfg(unix)]
+fn create(path: &Path, private: bool) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut options = File::options();
+    options.write(true).create(true).truncate(true);
+    if private {
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+#[cfg(not(unix))]
+fn create(path: &Path, _private: bool) -> io::Result<File> {
+    File::create(path)
+}
+
+// `fs::write`, replacing the file at `path` in one step
+pub fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    write_file(path.as_ref(), contents.as_ref(), false)
+}
+
+// `write` for secrets such as keys and tokens, readable by the owner only
+pub fn write_private(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    write_file(path.as_ref(), contents.as_ref(), true)
+}
+
+fn write_file(path: &Path, contents: &[u8], private: bool) -> io::Result<()> {
+    let temp = temp_path(path)?;
+    let result = create(&temp, private)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+    sync_dir(path)
+}
@@ -0,0 +1,190 @@
+use crate::{
+    atomic, compression, encryption, get_attachments_dir, open_externally, overrides, protocol,
+    writable_attachments_dir,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Wry};
+
+// Attachments keep the name they were added with, so a PDF stays `report.pdf`
+// rather than becoming an image. What is known about each one (the name it came
+// with, its MIME type and size) is kept in `attachment-info/<note id>.json` in the
+// app data directory, apart from the notes so it never turns up as an attachment
+// itself. Attachments added before this, or by a peer, have no entry; their
+// details are worked out from the file when asked for.
+
+const INFO_DIR: &str = "attachment-info";
+// Fallback name for data without one, such as a pasted image
+const UNNAMED: &str = "attachment";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttachmentInfo {
+    // The name notes refer to it by
+    pub name: String,
+    // The name of the file it was added from, before any renaming to keep it unique
+    pub original_name: String,
+    pub mime: String,
+    pub size: u64,
+    // RFC 3339, when it was added; None when that isn't known
+    #[serde(default)]
+    pub added_at: Option<String>,
+}
+
+fn info_path(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<PathBuf, String> {
+    let dir = overrides::app_data_dir(app_handle)
+        .map_err(|e| e.to_string())?
+        .join(INFO_DIR);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.join(format!("{}.json", note_id)))
+}
+
+fn load_info(app_handle: &AppHandle<Wry>, note_id: &str) -> HashMap<String, AttachmentInfo> {
+    // A missing file just means nothing was recorded for the note
+    info_path(app_handle, note_id)
+        .and_then(|path| fs::read_to_string(path).map_err(|e| e.to_string()))
+        .and_then(|raw| serde_json::from_str(&raw).map_err(|e| e.to_string()))
+        .unwrap_or_default()
+}
+
+fn record_info(app_handle: &AppHandle<Wry>, note_id: &str, info: AttachmentInfo) {
+    let mut infos = load_info(app_handle, note_id);
+    infos.insert(info.name.clone(), info);
+    let result = info_path(app_handle, note_id).and_then(|path| {
+        let raw = serde_json::to_string_pretty(&infos).map_err(|e| e.to_string())?;
+        atomic::write(path, raw).map_err(|e| e.to_string())
+    });
+    if let Err(e) = result {
+        println!("Failed to record attachment info for {}: {}", note_id, e);
+    }
+}
+
+// The MIME type of `data`, from its contents where they are recognised and from
+// the extension of `name` otherwise
+pub fn detect_mime(name: &str, data: &[u8]) -> String {
+    match infer::get(data) {
+        Some(kind) => kind.mime_type().to_string(),
+        None => mime_guess::from_path(name)
+            .first_or_octet_stream()
+            .essence_str()
+            .to_string(),
+    }
+}
+
+// `name` made safe to store: no directories, no hidden files, and an extension
+// matching the contents when it had none
+fn clean_name(name: Option<&str>, data: &[u8]) -> String {
+    let base = name
+        .and_then(|name| Path::new(name).file_name())
+        .and_then(|name| name.to_str())
+        .map(|name| name.trim().trim_start_matches('.'))
+        .filter(|name| protocol::check_file_name("file_name", name).is_ok())
+        .unwrap_or(UNNAMED)
+        .to_string();
+    if Path::new(&base).extension().is_some() {
+        return base;
+    }
+    match infer::get(data) {
+        Some(kind) => format!("{}.{}", base, kind.extension()),
+        None => base,
+    }
+}
+
+// `name`, or `name (2).ext`, `name (3).ext`... when the note already has it
+fn unique_name(dir: &Path, name: &str) -> String {
+    if !compression::attachment_exists(dir, name) {
+        return name.to_string();
+    }
+    let path = Path::new(name);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(name);
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| format!(".{}", e))
+        .unwrap_or_default();
+    (2..)
+        .map(|n| format!("{} ({}){}", stem, n, extension))
+        .find(|candidate| !compression::attachment_exists(dir, candidate))
+        .unwrap_or_else(|| name.to_string())
+}
+
+// Add `data` to a note under the name it came with, made unique. Returns the
+// name the note should refer to it by.
+pub fn store(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    original_name: Option<&str>,
+    data: &[u8],
+) -> Result<String, String> {
+    protocol::check_id("note_id", note_id).map_err(|e| e.to_string())?;
+    let attachments_dir = writable_attachments_dir(app_handle, note_id)?;
+    let cleaned = clean_name(original_name, data);
+    let name = unique_name(&attachments_dir, &cleaned);
+    compression::write_attachment(app_handle, &attachments_dir.join(&name), data)?;
+
+    record_info(
+        app_handle,
+        note_id,
+        AttachmentInfo {
+            original_name: original_name.unwrap_or(&cleaned).to_string(),
+            mime: detect_mime(&name, data),
+            size: data.len() as u64,
+            added_at: Some(chrono::Utc::now().to_rfc3339()),
+            name: name.clone(),
+        },
+    );
+    Ok(name)
+}
+
+#[tauri::command]
+pub async fn get_attachment_info(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    file_name: String,
+) -> Result<AttachmentInfo, String> {
+    protocol::check_id("note_id", &note_id).map_err(|e| e.to_string())?;
+    protocol::check_file_name("file_name", &file_name).map_err(|e| e.to_string())?;
+    let data = compression::read_note_attachment(&app_handle, &note_id, &file_name)
+        .map_err(|e| format!("Can't read attachment {}: {}", file_name, e))?;
+
+    let mut info = load_info(&app_handle, &note_id)
+        .remove(&file_name)
+        .unwrap_or_else(|| AttachmentInfo {
+            name: file_name.clone(),
+            original_name: file_name.clone(),
+            mime: detect_mime(&file_name, &data),
+            size: 0,
+            added_at: None,
+        });
+    // The file may have been replaced since, by a sync for one
+    info.size = data.len() as u64;
+    Ok(info)
+}
+
+// Open an attachment in the application the system picks for it. Compressed and
+// encrypted attachments are opened from a plain copy in the cache directory.
+#[tauri::command]
+pub async fn open_attachment(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    file_name: String,
+) -> Result<(), String> {
+    protocol::check_id("note_id", &note_id).map_err(|e| e.to_string())?;
+    protocol::check_file_name("file_name", &file_name).map_err(|e| e.to_string())?;
+    let stored = get_attachments_dir(&app_handle, &note_id).join(&file_name);
+    if stored.is_file() && !encryption::is_enabled(&app_handle) {
+        return open_externally(&stored);
+    }
+
+    let data = compression::read_note_attachment(&app_handle, &note_id, &file_name)
+        .map_err(|e| format!("Can't read attachment {}: {}", file_name, e))?;
+    let dir = overrides::app_cache_dir(&app_handle)
+        .map_err(|e| e.to_string())?
+        .join("opened")
+        .join(&note_id);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let copy = dir.join(&file_name);
+    atomic::write(&copy, data).map_err(|e| e.to_string())?;
+    open_externally(&copy)
+}
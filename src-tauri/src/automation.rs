@@ -0,0 +1,149 @@
+use crate::{load_notes, new_note_id, persist_note, Note};
+use rhai::{Dynamic, Engine, EvalAltResult};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Wry};
+
+const MAX_OPERATIONS: u64 = 5_000_000;
+const MAX_RUNTIME: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize)]
+pub struct AutomationResult {
+    value: serde_json::Value,
+    output: Vec<String>,
+    notes_changed: usize,
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn find_note(app_handle: &AppHandle<Wry>, id: &str) -> ScriptResult<Note> {
+    load_notes(app_handle)?
+        .into_iter()
+        .find(|n| n.id == id)
+        .ok_or_else(|| format!("Note not found: {}", id).into())
+}
+
+fn save(app_handle: &AppHandle<Wry>, note: Note) -> ScriptResult<()> {
+    // Scripts run on a blocking thread, so it is safe to wait on the async save path here
+    tauri::async_runtime::block_on(persist_note(app_handle, note)).map_err(|e| e.into())
+}
+
+// Build an engine that only knows about notes: no file, network or process access,
+// and bounded in both operations and wall-clock time.
+fn build_engine(
+    app_handle: &AppHandle<Wry>,
+    output: Arc<Mutex<Vec<String>>>,
+    changed: Arc<Mutex<usize>>,
+) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_expr_depths(64, 32);
+
+    let started = Instant::now();
+    engine.on_progress(move |_| {
+        if started.elapsed() > MAX_RUNTIME {
+            Some("Script timed out".into())
+        } else {
+            None
+        }
+    });
+
+    engine.on_print(move |text| {
+        if let Ok(mut output) = output.lock() {
+            output.push(text.to_string());
+        }
+    });
+
+    let app = app_handle.clone();
+    engine.register_fn("list_notes", move || -> ScriptResult<Dynamic> {
+        rhai::serde::to_dynamic(load_notes(&app)?)
+    });
+
+    let app = app_handle.clone();
+    engine.register_fn("get_note", move |id: &str| -> ScriptResult<Dynamic> {
+        rhai::serde::to_dynamic(find_note(&app, id)?)
+    });
+
+    let app = app_handle.clone();
+    engine.register_fn(
+        "search_notes",
+        move |query: &str| -> ScriptResult<Dynamic> {
+            let query = query.to_lowercase();
+            let matches: Vec<Note> = load_notes(&app)?
+                .into_iter()
+                .filter(|n| {
+                    n.title.to_lowercase().contains(&query)
+                        || n.content.to_lowercase().contains(&query)
+                })
+                .collect();
+            rhai::serde::to_dynamic(matches)
+        },
+    );
+
+    let app = app_handle.clone();
+    let created = changed.clone();
+    engine.register_fn(
+        "create_note",
+        move |title: &str, content: &str| -> ScriptResult<String> {
+            let note = Note {
+                id: new_note_id(),
+                title: title.to_string(),
+                content: content.to_string(),
+                datetime: chrono::Utc::now().timestamp().to_string(),
+                ..Default::default()
+            };
+            let id = note.id.clone();
+            save(&app, note)?;
+            *created.lock().map_err(|e| e.to_string())? += 1;
+            Ok(id)
+        },
+    );
+
+    let app = app_handle.clone();
+    let updated = changed;
+    engine.register_fn(
+        "update_note",
+        move |id: &str, title: &str, content: &str| -> ScriptResult<()> {
+            let mut note = find_note(&app, id)?;
+            note.title = title.to_string();
+            note.content = content.to_string();
+            save(&app, note)?;
+            *updated.lock().map_err(|e| e.to_string())? += 1;
+            Ok(())
+        },
+    );
+
+    engine
+}
+
+#[tauri::command]
+pub async fn run_automation(
+    app_handle: AppHandle<Wry>,
+    script: String,
+) -> Result<AutomationResult, String> {
+    let app = app_handle.clone();
+
+    let (value, output, notes_changed) = tokio::task::spawn_blocking(move || {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let changed = Arc::new(Mutex::new(0));
+        let engine = build_engine(&app, output.clone(), changed.clone());
+
+        let value = engine
+            .eval::<Dynamic>(&script)
+            .map(|value| rhai::serde::from_dynamic(&value).unwrap_or(serde_json::Value::Null))
+            .map_err(|e| e.to_string());
+
+        let output = output.lock().map(|o| o.clone()).unwrap_or_default();
+        let notes_changed = changed.lock().map(|c| *c).unwrap_or_default();
+        (value, output, notes_changed)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(AutomationResult {
+        value: value?,
+        output,
+        notes_changed,
+    })
+}
@@ -0,0 +1,211 @@
+use crate::readonly::{self, SaveError};
+use crate::{get_note_path, persist_note, read_note, Note};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, Wry};
+
+// Autosave for the editor. `autosave_note` is meant to be called on every change:
+// each call replaces the note's pending content, and the note is written once the
+// calls stop for `DEBOUNCE`. Which revision is dirty and which is on disk is tracked
+// here rather than in the UI, and `note-saved` reports each write, so the editor
+// only shows a revision as saved once it really is.
+//
+// Revisions are counters kept by the caller per note. A call with a revision older
+// than one already received is out of order and ignored.
+//
+// Writes go through the same check as `save_note`: the content was edited from the
+// note's stored revision the caller passed first, and if the note changed elsewhere
+// since, the write is refused with a conflict instead of overwriting it. The pending
+// content is then dropped, and the next call starts from the revision it passes.
+
+const DEBOUNCE: Duration = Duration::from_millis(750);
+
+#[derive(Default)]
+struct Draft {
+    // Latest revision received
+    revision: u64,
+    // Latest revision written to disk
+    saved: u64,
+    // Content of `revision` while it waits to be written
+    content: Option<String>,
+    // The note's stored revision the content was edited from
+    base: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Autosave {
+    drafts: Mutex<HashMap<String, Draft>>,
+    // Writes happen one at a time, so an older revision can't land after a newer one
+    writing: tokio::sync::Mutex<()>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct AutosaveStatus {
+    pub note_id: String,
+    pub revision: u64,
+    pub saved_revision: u64,
+    // Whether `revision` is still waiting to be written
+    pub dirty: bool,
+    // Why the last write failed. The content is kept and written with the next call,
+    // unless the note changed elsewhere.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<SaveError>,
+    // The note's stored revision after a write, for `save_note` calls that follow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note_revision: Option<String>,
+}
+
+fn status(note_id: &str, draft: &Draft, error: Option<SaveError>) -> AutosaveStatus {
+    AutosaveStatus {
+        note_id: note_id.to_string(),
+        revision: draft.revision,
+        saved_revision: draft.saved,
+        dirty: draft.saved < draft.revision,
+        error,
+        note_revision: None,
+    }
+}
+
+// Write `content` as the note's body, if the note is still at revision `base`. A
+// title heading in the content becomes the note's title, as when the file is read
+// back. Formatting on save is left to `save_note`, so the text doesn't change under
+// the cursor while typing. Returns the note's new revision.
+async fn write(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    content: String,
+    base: Option<&str>,
+) -> Result<Option<String>, SaveError> {
+    readonly::ensure_writable(app_handle, note_id)?;
+    if let Some(base) = base {
+        readonly::ensure_current(app_handle, note_id, base)?;
+    }
+
+    let path = get_note_path(app_handle, note_id);
+    let mut note = if path.exists() {
+        read_note(app_handle, note_id, &path)?
+    } else {
+        Note {
+            id: note_id.to_string(),
+            title: "Untitled".to_string(),
+            ..Default::default()
+        }
+    };
+    if let Some(title) = content.lines().next().and_then(|l| l.strip_prefix("# ")) {
+        note.title = title.to_string();
+    }
+    note.content = content;
+    persist_note(app_handle, note).await?;
+
+    let saved = read_note(app_handle, note_id, &path)?;
+    Ok(readonly::revision_of(&saved))
+}
+
+// Write the note's pending content. With `only`, nothing is written unless that is
+// still the latest revision, since a newer call will write its own.
+async fn flush(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    only: Option<u64>,
+) -> Result<AutosaveStatus, String> {
+    let autosave = app_handle.state::<Autosave>();
+    let _writing = autosave.writing.lock().await;
+
+    let (revision, content, base) = {
+        let mut drafts = autosave.drafts.lock().map_err(|e| e.to_string())?;
+        let draft = drafts.entry(note_id.to_string()).or_default();
+        if only.is_some_and(|revision| revision != draft.revision) {
+            return Ok(status(note_id, draft, None));
+        }
+        match draft.content.take() {
+            Some(content) => (draft.revision, content, draft.base.clone()),
+            None => return Ok(status(note_id, draft, None)),
+        }
+    };
+
+    let result = write(app_handle, note_id, content.clone(), base.as_deref()).await;
+
+    let update = {
+        let mut drafts = autosave.drafts.lock().map_err(|e| e.to_string())?;
+        let draft = drafts.entry(note_id.to_string()).or_default();
+        match result {
+            Ok(note_revision) => {
+                draft.saved = draft.saved.max(revision);
+                // Later content is edited from what was just written
+                draft.base = note_revision.clone();
+                AutosaveStatus {
+                    note_revision,
+                    ..status(note_id, draft, None)
+                }
+            }
+            Err(e @ SaveError::Conflict { .. }) => {
+                println!("Autosave of note {} refused: {}", note_id, e);
+                // The caller reloads the note and continues from its revision
+                draft.content = None;
+                draft.base = None;
+                status(note_id, draft, Some(e))
+            }
+            Err(e) => {
+                println!("Autosave of note {} failed: {}", note_id, e);
+                // Keep the content unless a newer revision arrived meanwhile
+                if draft.content.is_none() {
+                    draft.content = Some(content);
+                }
+                status(note_id, draft, Some(e))
+            }
+        }
+    };
+    app_handle
+        .emit("note-saved", update.clone())
+        .map_err(|e| e.to_string())?;
+    Ok(update)
+}
+
+// Queue the note's content for writing. `expected_revision` is the note's stored
+// revision the editor started from; it counts until a write succeeds, after which
+// the content is taken to continue from what was written.
+#[tauri::command]
+pub async fn autosave_note(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    content: String,
+    revision: u64,
+    expected_revision: Option<String>,
+) -> Result<AutosaveStatus, String> {
+    let pending = {
+        let autosave = app_handle.state::<Autosave>();
+        let mut drafts = autosave.drafts.lock().map_err(|e| e.to_string())?;
+        let draft = drafts.entry(note_id.clone()).or_default();
+        if revision < draft.revision {
+            return Ok(status(&note_id, draft, None));
+        }
+        draft.revision = revision;
+        draft.content = Some(content);
+        if draft.base.is_none() {
+            draft.base = expected_revision;
+        }
+        status(&note_id, draft, None)
+    };
+
+    let handle = app_handle.clone();
+    let id = note_id.clone();
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(DEBOUNCE).await;
+        if let Err(e) = flush(&handle, &id, Some(revision)).await {
+            println!("Autosave of note {} failed: {}", id, e);
+        }
+    });
+
+    Ok(pending)
+}
+
+// Write a note's pending content now, e.g. before switching to another note
+#[tauri::command]
+pub async fn flush_autosave(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+) -> Result<AutosaveStatus, String> {
+    flush(&app_handle, &note_id, None).await
+}
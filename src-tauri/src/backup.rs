@@ -0,0 +1,368 @@
+use crate::{
+    atomic, blobs, changes, compression, conditions, encryption, events, get_notes_dir, lock,
+    notebooks, overrides, storage,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tauri::{AppHandle, Wry};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+// The whole library as one zip, for backups and for moving to a device that isn't
+// on the same network. The archive is laid out like the notes directory: note
+// files at the top and in notebook directories, `attachments/<note id>/`, and the
+// dotfiles that hold ordering, tombstones, vault settings, the trash and cold storage.
+// Attachments are stored uncompressed so the archive reads as plain markdown.
+// `library.json` lists every file with its hash, and an import checks them all
+// before anything is written.
+//
+// Importing adds what the library doesn't have yet. Notes and files that already
+// exist here are kept as they are and counted as skipped.
+
+const MANIFEST_FILE: &str = "library.json";
+const FORMAT_VERSION: u32 = 1;
+const STAGING_DIR: &str = "library-incoming";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct LibraryFile {
+    // Relative to the notes directory, using `/`
+    path: String,
+    size: u64,
+    sha256: String,
+    // Milliseconds since the epoch; note dates and cold storage go by it
+    modified: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LibraryManifest {
+    format: u32,
+    schema: u32,
+    exported_at: String,
+    files: Vec<LibraryFile>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct LibraryExport {
+    notes: usize,
+    files: usize,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct LibraryImport {
+    notes_imported: usize,
+    notes_skipped: usize,
+    files_imported: usize,
+    files_skipped: usize,
+}
+
+fn modified_millis(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as u64)
+}
+
+// The id of a note file in the archive, whether at the top or in a notebook
+fn note_id_of(path: &str) -> Option<&str> {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let in_notebook = dir.is_empty()
+        || (dir.split('/').next() != Some("attachments")
+            && !dir.split('/').any(|segment| segment.starts_with('.')));
+    name.strip_suffix(".md").filter(|_| in_notebook)
+}
+
+fn is_note(path: &str) -> bool {
+    note_id_of(path).is_some()
+}
+
+// Every file of the library, by its path in the archive
+fn library_files(
+    dir: &Path,
+    notes_dir: &Path,
+    files: &mut Vec<(String, PathBuf)>,
+) -> Result<(), String> {
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        // The store only links to attachments that are exported anyway
+        if path == notes_dir.join(blobs::BLOBS_DIR) {
+            continue;
+        }
+        if path.is_dir() {
+            library_files(&path, notes_dir, files)?;
+            continue;
+        }
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        // Notes staged by a pending sync haven't been accepted, the lock is ours and
+        // temporary files are writes in progress
+        if name.ends_with(".sync") || name == lock::LOCK_FILE || atomic::is_temp(name) {
+            continue;
+        }
+        let Ok(relative) = path.strip_prefix(notes_dir) else {
+            continue;
+        };
+        let relative = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.push((relative, path));
+    }
+    Ok(())
+}
+
+// The name and contents a file is exported with: decrypted, and compressed
+// attachments as the file they stand for
+fn exported(relative: &str, path: &Path) -> Result<(String, Vec<u8>), String> {
+    let in_attachments = relative.starts_with("attachments/");
+    match relative.strip_suffix(compression::COMPRESSED_SUFFIX) {
+        Some(plain) if in_attachments => {
+            let (Some(dir), Some(name)) = (path.parent(), Path::new(plain).file_name()) else {
+                return Err(format!("Not an attachment: {}", relative));
+            };
+            let data = compression::read_attachment(dir, &name.to_string_lossy())
+                .map_err(|e| format!("{}: {}", relative, e))?;
+            Ok((plain.to_string(), data))
+        }
+        _ => Ok((
+            relative.to_string(),
+            encryption::read(path).map_err(|e| format!("{}: {}", relative, e))?,
+        )),
+    }
+}
+
+fn write_archive(notes_dir: &Path, dest: &Path) -> Result<LibraryExport, String> {
+    let mut sources = Vec::new();
+    library_files(notes_dir, notes_dir, &mut sources)?;
+    sources.sort();
+
+    // Written next to the destination and moved over it once complete
+    let mut partial = dest.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let mut zip = ZipWriter::new(File::create(&partial).map_err(|e| e.to_string())?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut report = LibraryExport::default();
+    let mut files = Vec::new();
+    for (relative, path) in &sources {
+        let (name, data) = exported(relative, path)?;
+        // A plain copy of a compressed attachment is already in
+        if files.iter().any(|f: &LibraryFile| f.path == name) {
+            continue;
+        }
+        zip.start_file(name.as_str(), options)
+            .map_err(|e| e.to_string())?;
+        zip.write_all(&data).map_err(|e| e.to_string())?;
+        if is_note(&name) {
+            report.notes += 1;
+        }
+        report.bytes += data.len() as u64;
+        files.push(LibraryFile {
+            path: name,
+            size: data.len() as u64,
+            sha256: hex::encode(Sha256::digest(&data)),
+            modified: modified_millis(path),
+        });
+    }
+    report.files = files.len();
+
+    let manifest = LibraryManifest {
+        format: FORMAT_VERSION,
+        schema: crate::schema::SCHEMA_VERSION,
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        files,
+    };
+    zip.start_file(MANIFEST_FILE, options)
+        .map_err(|e| e.to_string())?;
+    zip.write_all(
+        serde_json::to_string_pretty(&manifest)
+            .map_err(|e| e.to_string())?
+            .as_bytes(),
+    )
+    .map_err(|e| e.to_string())?;
+    zip.finish().map_err(|e| e.to_string())?;
+    fs::rename(&partial, dest).map_err(|e| e.to_string())?;
+    Ok(report)
+}
+
+// Package every note, attachment and piece of library metadata into a zip at `path`
+#[tauri::command]
+pub async fn export_library(
+    app_handle: AppHandle<Wry>,
+    path: String,
+) -> Result<LibraryExport, String> {
+    conditions::check_bulk_work(&app_handle).await?;
+    let notes_dir = get_notes_dir(&app_handle);
+    let report = tokio::task::spawn_blocking(move || write_archive(&notes_dir, Path::new(&path)))
+        .await
+        .map_err(|e| e.to_string())??;
+    println!(
+        "Exported library: {} notes, {} files, {} bytes",
+        report.notes, report.files, report.bytes
+    );
+    Ok(report)
+}
+
+// Only plain relative paths, so nothing lands outside the notes directory
+fn is_valid_path(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+// Unpack and check every file of the archive into `staging`
+fn unpack(archive: &Path, staging: &Path) -> Result<Vec<LibraryFile>, String> {
+    let mut zip = ZipArchive::new(File::open(archive).map_err(|e| e.to_string())?)
+        .map_err(|e| format!("Not a library archive: {}", e))?;
+    let manifest: LibraryManifest = {
+        let mut raw = String::new();
+        zip.by_name(MANIFEST_FILE)
+            .map_err(|_| format!("Not a library archive: {} is missing", MANIFEST_FILE))?
+            .read_to_string(&mut raw)
+            .map_err(|e| e.to_string())?;
+        serde_json::from_str(&raw).map_err(|e| format!("Unreadable {}: {}", MANIFEST_FILE, e))?
+    };
+    if manifest.format > FORMAT_VERSION {
+        return Err("The archive was made by a newer version; update to import it".to_string());
+    }
+
+    for file in &manifest.files {
+        if !is_valid_path(&file.path) {
+            return Err(format!("Invalid path in archive: {}", file.path));
+        }
+        let mut data = Vec::new();
+        zip.by_name(&file.path)
+            .map_err(|_| format!("{} is listed but not in the archive", file.path))?
+            .read_to_end(&mut data)
+            .map_err(|e| e.to_string())?;
+        if data.len() as u64 != file.size || hex::encode(Sha256::digest(&data)) != file.sha256 {
+            return Err(format!(
+                "{} doesn't match its hash; the archive is damaged",
+                file.path
+            ));
+        }
+        let dest = staging.join(&file.path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        atomic::write(&dest, data).map_err(|e| e.to_string())?;
+        if let Some(millis) = file.modified {
+            storage::set_modified(&dest, UNIX_EPOCH + Duration::from_millis(millis));
+        }
+    }
+    Ok(manifest.files)
+}
+
+// Move what the library doesn't have yet out of `staging`
+fn install(
+    staging: &Path,
+    notes_dir: &Path,
+    files: &[LibraryFile],
+) -> Result<(LibraryImport, Vec<String>), String> {
+    let mut report = LibraryImport::default();
+    // A note's attachments come with it, or not at all. A note is here already
+    // whichever notebook it is filed in.
+    let skipped_notes: HashMap<&str, bool> = files
+        .iter()
+        .filter_map(|f| note_id_of(&f.path))
+        .map(|id| (id, notebooks::find_note_file(notes_dir, id).is_some()))
+        .collect();
+
+    let mut imported = Vec::new();
+    for file in files {
+        let dest = notes_dir.join(&file.path);
+        let note_id = file
+            .path
+            .strip_prefix("attachments/")
+            .and_then(|rest| rest.split('/').next())
+            .or_else(|| note_id_of(&file.path));
+        let skip = match note_id {
+            Some(id) => skipped_notes.get(id).copied().unwrap_or(dest.exists()),
+            None => dest.exists(),
+        };
+        if skip {
+            if is_note(&file.path) {
+                report.notes_skipped += 1;
+            } else {
+                report.files_skipped += 1;
+            }
+            continue;
+        }
+
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let source = staging.join(&file.path);
+        // Staging and the notes directory may be on different drives
+        fs::rename(&source, &dest)
+            .or_else(|_| fs::copy(&source, &dest).map(|_| ()))
+            .map_err(|e| format!("{}: {}", file.path, e))?;
+        if let Some(id) = note_id_of(&file.path) {
+            report.notes_imported += 1;
+            imported.push(id.to_string());
+        } else {
+            report.files_imported += 1;
+        }
+    }
+    Ok((report, imported))
+}
+
+// Restore a library exported with `export_library`, adding the notes and files it
+// has that this one doesn't
+#[tauri::command]
+pub async fn import_library(
+    app_handle: AppHandle<Wry>,
+    path: String,
+) -> Result<LibraryImport, String> {
+    lock::ensure_writable(&app_handle)?;
+    let staging = overrides::app_data_dir(&app_handle)
+        .map_err(|e| e.to_string())?
+        .join(STAGING_DIR);
+    let notes_dir = get_notes_dir(&app_handle);
+
+    let (report, imported) = tokio::task::spawn_blocking(move || {
+        // Leftovers of an earlier, interrupted import
+        let _ = fs::remove_dir_all(&staging);
+        let result = unpack(Path::new(&path), &staging)
+            .and_then(|files| install(&staging, &notes_dir, &files));
+        let _ = fs::remove_dir_all(&staging);
+        result
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    for id in &imported {
+        if let Err(e) = changes::publish_saved(&app_handle, id, true) {
+            println!("Failed to index imported note {}: {}", id, e);
+        }
+    }
+    let _ = events::notes_reloaded(&app_handle);
+    println!(
+        "Imported library: {} notes added, {} already here",
+        report.notes_imported, report.notes_skipped
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_plain_relative_paths_are_valid() {
+        assert!(is_valid_path("note.md"));
+        assert!(is_valid_path("attachments/note/image.png"));
+        assert!(!is_valid_path(""));
+        assert!(!is_valid_path("../outside.md"));
+        assert!(!is_valid_path("attachments/../../outside.md"));
+        assert!(!is_valid_path("/etc/passwd"));
+    }
+}
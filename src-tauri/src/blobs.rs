@@ -0,0 +1,232 @@
+use crate::{atomic, get_notes_dir};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Wry};
+
+// Attachments are deduplicated through a content-addressed store in
+// `notes/.blobs`: each distinct file is kept once as `objects/<sha256>`, and the
+// attachments of every note that has it are hard links to that one copy. So
+// nothing that reads attachments has to know about the store, and the same
+// screenshot pasted into ten notes takes the space of one.
+//
+// `manifests/<note id>.json` maps the names of a note's attachments to their
+// hashes. A blob is referenced once for every manifest entry that names it, and is
+// removed when the last one goes. Because the files are shared, an attachment is
+// never written through: `compression::write_attachment` replaces it instead.
+// File systems without hard links just keep separate copies.
+
+pub const BLOBS_DIR: &str = ".blobs";
+const OBJECTS_DIR: &str = "objects";
+const MANIFESTS_DIR: &str = "manifests";
+
+// Stored attachment name (with `.zst` when compressed) to the hash of the file
+type Manifest = BTreeMap<String, String>;
+
+#[derive(Debug, Serialize, Default)]
+pub struct DedupReport {
+    // Attachments that now share a file with another one
+    shared: usize,
+    bytes_saved: u64,
+    failed: Vec<String>,
+}
+
+fn blobs_dir(app_handle: &AppHandle<Wry>) -> PathBuf {
+    get_notes_dir(app_handle).join(BLOBS_DIR)
+}
+
+fn object_path(app_handle: &AppHandle<Wry>, hash: &str) -> PathBuf {
+    blobs_dir(app_handle).join(OBJECTS_DIR).join(hash)
+}
+
+fn manifest_path(app_handle: &AppHandle<Wry>, note_id: &str) -> PathBuf {
+    blobs_dir(app_handle)
+        .join(MANIFESTS_DIR)
+        .join(format!("{}.json", note_id))
+}
+
+fn load_manifest(app_handle: &AppHandle<Wry>, note_id: &str) -> Manifest {
+    // A note without a manifest has nothing in the store
+    fs::read_to_string(manifest_path(app_handle, note_id))
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn save_manifest(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    manifest: &Manifest,
+) -> Result<(), String> {
+    let path = manifest_path(app_handle, note_id);
+    if manifest.is_empty() {
+        let _ = fs::remove_file(path);
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let raw = serde_json::to_string_pretty(manifest).map_err(|e| e.to_string())?;
+    atomic::write(path, raw).map_err(|e| e.to_string())
+}
+
+// How many attachments use each blob, across every note's manifest
+fn ref_counts(app_handle: &AppHandle<Wry>) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    let Ok(entries) = fs::read_dir(blobs_dir(app_handle).join(MANIFESTS_DIR)) else {
+        return counts;
+    };
+    for entry in entries.flatten() {
+        let manifest: Manifest = fs::read_to_string(entry.path())
+            .ok()
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default();
+        for hash in manifest.into_values() {
+            *counts.entry(hash).or_default() += 1;
+        }
+    }
+    counts
+}
+
+// Remove the blobs in `hashes` nothing refers to anymore
+fn collect(app_handle: &AppHandle<Wry>, hashes: impl IntoIterator<Item = String>) {
+    let counts = ref_counts(app_handle);
+    for hash in hashes {
+        if counts.get(&hash).copied().unwrap_or(0) == 0 {
+            let _ = fs::remove_file(object_path(app_handle, &hash));
+        }
+    }
+}
+
+// Replace `path` with a hard link to `target`, leaving it as it is if that fails
+fn link_over(target: &Path, path: &Path) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".link");
+    let temp = PathBuf::from(temp);
+    fs::hard_link(target, &temp)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+// The note and stored name of an attachment file. Files outside the notes'
+// attachment directories (sync staging, for one) aren't in the store.
+fn attachment_of(app_handle: &AppHandle<Wry>, path: &Path) -> Option<(String, String)> {
+    let dir = path.parent()?;
+    if dir.parent() != Some(get_notes_dir(app_handle).join("attachments").as_path()) {
+        return None;
+    }
+    Some((
+        dir.file_name()?.to_str()?.to_string(),
+        path.file_name()?.to_str()?.to_string(),
+    ))
+}
+
+// Put a note's newly stored attachment into the store, sharing the file with any
+// other attachment that has the same contents. Returns the bytes this saved.
+pub fn share(app_handle: &AppHandle<Wry>, path: &Path) -> Result<u64, String> {
+    let Some((note_id, name)) = attachment_of(app_handle, path) else {
+        return Ok(0);
+    };
+
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let hash = hex::encode(Sha256::digest(&data));
+    let object = object_path(app_handle, &hash);
+    let saved = if object.exists() {
+        link_over(&object, path).map_err(|e| e.to_string())?;
+        data.len() as u64
+    } else {
+        if let Some(objects) = object.parent() {
+            fs::create_dir_all(objects).map_err(|e| e.to_string())?;
+        }
+        fs::hard_link(path, &object).map_err(|e| e.to_string())?;
+        0
+    };
+
+    let mut manifest = load_manifest(app_handle, &note_id);
+    let replaced = manifest.insert(name, hash.clone());
+    save_manifest(app_handle, &note_id, &manifest)?;
+    if let Some(old) = replaced.filter(|old| *old != hash) {
+        collect(app_handle, [old]);
+    }
+    Ok(saved)
+}
+
+// An attachment is about to be replaced or removed
+fn unshare(app_handle: &AppHandle<Wry>, note_id: &str, stored_name: &str) {
+    let mut manifest = load_manifest(app_handle, note_id);
+    let Some(hash) = manifest.remove(stored_name) else {
+        return;
+    };
+    if let Err(e) = save_manifest(app_handle, note_id, &manifest) {
+        println!("Failed to update attachment manifest of {}: {}", note_id, e);
+    }
+    collect(app_handle, [hash]);
+}
+
+// The attachment file at `path` is about to be replaced or removed
+pub fn unshare_file(app_handle: &AppHandle<Wry>, path: &Path) {
+    if let Some((note_id, name)) = attachment_of(app_handle, path) {
+        unshare(app_handle, &note_id, &name);
+    }
+}
+
+// A note's attachments are going away for good. Returns the stored names of those
+// whose contents other notes still use, which must be unlinked, not overwritten.
+pub fn release(app_handle: &AppHandle<Wry>, note_id: &str) -> HashSet<String> {
+    let manifest = load_manifest(app_handle, note_id);
+    let _ = fs::remove_file(manifest_path(app_handle, note_id));
+    let counts = ref_counts(app_handle);
+    let still_used = manifest
+        .iter()
+        .filter(|(_, hash)| counts.get(*hash).copied().unwrap_or(0) > 0)
+        .map(|(name, _)| name.clone())
+        .collect();
+    collect(app_handle, manifest.into_values());
+    still_used
+}
+
+// One-time pass putting attachments stored before deduplication into the store.
+// Archived notes keep their attachments in cold storage and are left as they are.
+#[tauri::command]
+pub async fn deduplicate_attachments(app_handle: AppHandle<Wry>) -> Result<DedupReport, String> {
+    tokio::task::spawn_blocking(move || {
+        let mut report = DedupReport::default();
+        let attachments_root = get_notes_dir(&app_handle).join("attachments");
+        let Ok(dirs) = fs::read_dir(&attachments_root) else {
+            return Ok(report);
+        };
+        for dir in dirs.flatten() {
+            let dir = dir.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            let note_id = dir.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            let known = load_manifest(&app_handle, note_id);
+            for file in fs::read_dir(&dir).map_err(|e| e.to_string())?.flatten() {
+                let path = file.path();
+                let name = file.file_name().to_string_lossy().to_string();
+                if known.contains_key(&name) || !path.is_file() {
+                    continue;
+                }
+                match share(&app_handle, &path) {
+                    Ok(0) => {}
+                    Ok(saved) => {
+                        report.shared += 1;
+                        report.bytes_saved += saved;
+                    }
+                    Err(e) => report.failed.push(format!("{}: {}", path.display(), e)),
+                }
+            }
+        }
+        println!(
+            "Deduplicated {} attachments, saving {} bytes",
+            report.shared, report.bytes_saved
+        );
+        Ok(report)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
@@ -0,0 +1,81 @@
+use crate::{new_note_id, persist_note, tags, Note};
+use tauri::{AppHandle, Manager, WebviewUrl, WebviewWindowBuilder, Wry};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutEvent, ShortcutState};
+
+// Quick capture: a system-wide shortcut opens a small window for jotting something
+// down, which is saved as a new note tagged for the inbox. The window is a second
+// view of the frontend (`index.html?capture`), so capturing doesn't bring up the
+// main window or depend on it being open.
+
+pub const SHORTCUT: &str = "CommandOrControl+Shift+Space";
+const WINDOW_LABEL: &str = "capture";
+const INBOX_TAG: &str = "inbox";
+
+pub fn plugin() -> tauri::plugin::TauriPlugin<Wry> {
+    tauri_plugin_global_shortcut::Builder::new()
+        .with_handler(|app_handle, _shortcut: &Shortcut, event: ShortcutEvent| {
+            if event.state() == ShortcutState::Pressed {
+                show_window(app_handle);
+            }
+        })
+        .build()
+}
+
+// Claim the shortcut. Another app may hold it already, which only costs the shortcut.
+pub fn register(app_handle: &AppHandle<Wry>) {
+    if let Err(e) = app_handle.global_shortcut().register(SHORTCUT) {
+        println!("Failed to register the quick capture shortcut: {}", e);
+    }
+}
+
+// Bring up the capture window, creating it the first time
+fn show_window(app_handle: &AppHandle<Wry>) {
+    if let Some(window) = app_handle.get_webview_window(WINDOW_LABEL) {
+        let _ = window.show();
+        if let Err(e) = window.set_focus() {
+            println!("Failed to focus the capture window: {}", e);
+        }
+        return;
+    }
+
+    let created = WebviewWindowBuilder::new(
+        app_handle,
+        WINDOW_LABEL,
+        WebviewUrl::App("index.html?capture".into()),
+    )
+    .title("Quick capture")
+    .inner_size(480.0, 240.0)
+    .resizable(false)
+    .always_on_top(true)
+    .skip_taskbar(true)
+    .center()
+    .build();
+    if let Err(e) = created {
+        println!("Failed to open the capture window: {}", e);
+    }
+}
+
+// Save captured text as a new note titled with the time it was taken, and put the
+// capture window away
+#[tauri::command]
+pub async fn quick_capture(app_handle: AppHandle<Wry>, content: String) -> Result<Note, String> {
+    if content.trim().is_empty() {
+        return Err("Nothing to capture".to_string());
+    }
+
+    let now = chrono::Local::now();
+    let mut note = Note {
+        id: new_note_id(),
+        title: format!("Capture {}", now.format("%Y-%m-%d %H:%M")),
+        content: content.trim().to_string(),
+        datetime: chrono::Utc::now().timestamp().to_string(),
+        ..Default::default()
+    };
+    tags::set_tags(&mut note, vec![INBOX_TAG.to_string()]);
+    persist_note(&app_handle, note.clone()).await?;
+
+    if let Some(window) = app_handle.get_webview_window(WINDOW_LABEL) {
+        let _ = window.hide();
+    }
+    Ok(note)
+}
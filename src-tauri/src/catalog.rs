@@ -0,0 +1,350 @@
+use crate::changes::{self, NoteChange};
+use crate::events;
+use crate::index::{self, NoteIndex};
+use crate::ordering::folder_of;
+use crate::{
+    encryption, get_attachments_dir, get_note_path, get_notes_dir, markdown, notebooks, overrides,
+    read_note, Note,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use tauri::{AppHandle, Manager, Wry};
+
+// SQLite catalog of note metadata (title, timestamps, attachments, frontmatter and
+// the excerpt), so listing notes doesn't read every file. The files stay the source
+// of truth: a listing first compares each note's modification times with the
+// catalog and reads only the notes that changed, and notes saved, deleted or synced
+// in the app are caught up from the change bus as it happens. The database lives in
+// the cache directory and can be thrown away at any time; `rebuild_index` does, and
+// `compact_index` reclaims the space deleted notes leave behind. An encrypted vault
+// has no catalog, since it would hold titles and excerpts unencrypted; its notes
+// are listed from their files.
+
+const CATALOG_FILE: &str = "catalog.sqlite";
+// Bumped whenever the tables change; an older catalog is dropped and read again
+const SCHEMA_VERSION: i64 = 2;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS notes (
+        id TEXT PRIMARY KEY,
+        title TEXT NOT NULL,
+        datetime TEXT NOT NULL,
+        modified INTEGER,
+        attachments_modified INTEGER,
+        attachments TEXT NOT NULL,
+        fields TEXT NOT NULL,
+        tags TEXT NOT NULL,
+        excerpt TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+";
+
+// Opened on first use
+#[derive(Default)]
+pub struct Catalog(Mutex<Option<Connection>>);
+
+// Notes without their content, which is left empty, and their excerpts by id
+pub struct Listing {
+    pub notes: Vec<Note>,
+    pub excerpts: HashMap<String, String>,
+}
+
+fn catalog_path(app_handle: &AppHandle<Wry>) -> Result<PathBuf, String> {
+    let dir = overrides::app_cache_dir(app_handle).map_err(|e| e.to_string())?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.join(CATALOG_FILE))
+}
+
+fn open(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version != SCHEMA_VERSION {
+        conn.execute_batch("DROP TABLE IF EXISTS notes; DROP TABLE IF EXISTS meta;")?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    }
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+// Run `f` on the catalog. A database that can't be opened is started afresh, since
+// everything in it can be read again from the notes.
+fn with_catalog<T>(
+    app_handle: &AppHandle<Wry>,
+    f: impl FnOnce(&mut Connection) -> Result<T, String>,
+) -> Result<T, String> {
+    if encryption::is_enabled(app_handle) {
+        return Err("An encrypted vault has no catalog".to_string());
+    }
+    let catalog = app_handle.state::<Catalog>();
+    let mut conn = catalog.0.lock().map_err(|e| e.to_string())?;
+    if conn.is_none() {
+        let path = catalog_path(app_handle)?;
+        let opened = open(&path).or_else(|e| {
+            println!(
+                "Failed to open the catalog {:?}, recreating it: {}",
+                path, e
+            );
+            let _ = fs::remove_file(&path);
+            open(&path)
+        });
+        *conn = Some(opened.map_err(|e| e.to_string())?);
+    }
+    match conn.as_mut() {
+        Some(conn) => f(conn),
+        None => Err("Catalog isn't open".to_string()),
+    }
+}
+
+// Modification time in nanoseconds, to tell whether a file changed since it was read
+fn modified(path: &Path) -> Option<i64> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+    i64::try_from(since_epoch.as_nanos()).ok()
+}
+
+// The times a note's row is compared against: its file, and its attachment
+// directory, which changes when attachments are added or removed
+fn note_times(app_handle: &AppHandle<Wry>, id: &str) -> (Option<i64>, Option<i64>) {
+    (
+        modified(&get_note_path(app_handle, id)),
+        modified(&get_attachments_dir(app_handle, id)),
+    )
+}
+
+fn upsert(conn: &Connection, note: &Note, times: (Option<i64>, Option<i64>)) -> Result<(), String> {
+    let attachments = serde_json::to_string(&note.attachments).map_err(|e| e.to_string())?;
+    let fields = serde_json::to_string(&note.fields).map_err(|e| e.to_string())?;
+    let tags = serde_json::to_string(&note.tags).map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT OR REPLACE INTO notes
+            (id, title, datetime, modified, attachments_modified, attachments, fields, tags,
+                excerpt)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            note.id,
+            note.title,
+            note.datetime,
+            times.0,
+            times.1,
+            attachments,
+            fields,
+            tags,
+            markdown::excerpt(&note.content),
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Bring the catalog up to date with the notes directory, reading only notes whose
+// file or attachments changed. `progress` hears how many of the notes were looked
+// at, every `PROGRESS_BATCH` and at the end. Returns how many notes were read.
+fn refresh(
+    app_handle: &AppHandle<Wry>,
+    conn: &mut Connection,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<usize, String> {
+    let notes_dir = get_notes_dir(app_handle);
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    // Another notes directory shares none of the rows
+    let dir = notes_dir.to_string_lossy().to_string();
+    let cataloged: Option<String> = tx
+        .query_row(
+            "SELECT value FROM meta WHERE key = 'notes_dir'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if cataloged.as_deref() != Some(dir.as_str()) {
+        tx.execute("DELETE FROM notes", [])
+            .map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('notes_dir', ?1)",
+            params![dir],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    let known: HashMap<String, (Option<i64>, Option<i64>)> = {
+        let mut query = tx
+            .prepare("SELECT id, modified, attachments_modified FROM notes")
+            .map_err(|e| e.to_string())?;
+        let rows = query
+            .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<rusqlite::Result<_>>()
+            .map_err(|e| e.to_string())?
+    };
+
+    let paths = notebooks::note_files(&notes_dir);
+
+    let total = paths.len();
+    let mut seen = HashSet::new();
+    let mut read = 0;
+    for (scanned, path) in paths.iter().enumerate() {
+        if scanned > 0 && scanned % events::PROGRESS_BATCH == 0 {
+            progress(scanned, total);
+        }
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        seen.insert(id.to_string());
+
+        let times = note_times(app_handle, id);
+        if times.0.is_some() && known.get(id) == Some(&times) {
+            continue;
+        }
+        match read_note(app_handle, id, path) {
+            Ok(note) => {
+                upsert(&tx, &note, times)?;
+                read += 1;
+            }
+            Err(e) => println!("Failed to catalog {:?}: {}", path, e),
+        }
+    }
+    progress(total, total);
+
+    for id in known.keys().filter(|id| !seen.contains(*id)) {
+        tx.execute("DELETE FROM notes WHERE id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+    }
+
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(read)
+}
+
+// Close the catalog and delete its database
+pub fn discard(app_handle: &AppHandle<Wry>) -> Result<(), String> {
+    let catalog = app_handle.state::<Catalog>();
+    let mut conn = catalog.0.lock().map_err(|e| e.to_string())?;
+    *conn = None;
+    let path = catalog_path(app_handle)?;
+    if path.exists() {
+        fs::remove_file(&path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Every live note without its content, with its excerpt, as of now on disk, or
+// None when the vault is encrypted. Archived notes aren't cataloged.
+pub fn list(app_handle: &AppHandle<Wry>) -> Result<Option<Listing>, String> {
+    if encryption::is_enabled(app_handle) {
+        return Ok(None);
+    }
+    with_catalog(app_handle, |conn| {
+        refresh(app_handle, conn, |_, _| {})?;
+
+        let mut query = conn
+            .prepare("SELECT id, title, datetime, attachments, fields, tags, excerpt FROM notes")
+            .map_err(|e| e.to_string())?;
+        let rows = query
+            .query_map([], |row| {
+                let json = |i: usize| row.get::<_, String>(i);
+                let mut note = Note {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    content: String::new(),
+                    datetime: row.get(2)?,
+                    attachments: serde_json::from_str(&json(3)?).unwrap_or_default(),
+                    fields: serde_json::from_str(&json(4)?).unwrap_or_default(),
+                    tags: serde_json::from_str(&json(5)?).unwrap_or_default(),
+                    ..Default::default()
+                };
+                note.notebook = folder_of(&note.fields).map(str::to_string);
+                Ok((note, row.get::<_, String>(6)?))
+            })
+            .map_err(|e| e.to_string())?;
+
+        let mut listing = Listing {
+            notes: Vec::new(),
+            excerpts: HashMap::new(),
+        };
+        for row in rows {
+            let (note, excerpt) = row.map_err(|e| e.to_string())?;
+            listing.excerpts.insert(note.id.clone(), excerpt);
+            listing.notes.push(note);
+        }
+        Ok(Some(listing))
+    })
+}
+
+// Keep the catalog current with notes changed in the app, so the next listing
+// finds them unchanged
+pub fn spawn_cataloger(app_handle: &AppHandle<Wry>) {
+    changes::subscribe(app_handle, "Note catalog", |app_handle, change| {
+        if encryption::is_enabled(app_handle) {
+            return;
+        }
+        let result = with_catalog(app_handle, |conn| match &change {
+            NoteChange::Created(note) | NoteChange::Updated(note) => {
+                // As on disk, with the attachments and time the file now has
+                let path = get_note_path(app_handle, &note.id);
+                let note = read_note(app_handle, &note.id, &path)?;
+                upsert(conn, &note, note_times(app_handle, &note.id))
+            }
+            NoteChange::Deleted(id) => conn
+                .execute("DELETE FROM notes WHERE id = ?1", params![id])
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            NoteChange::Shared { .. } => Ok(()),
+        });
+        if let Err(e) = result {
+            println!("Failed to update the note catalog: {}", e);
+        }
+    });
+}
+
+// Throw the catalog and the note index away and read every note again, for when
+// they are out of step with the notes or the database is damaged. Reports
+// `index-maintenance` as the notes are read. Returns how many notes were cataloged.
+#[tauri::command]
+pub async fn rebuild_index(app_handle: AppHandle<Wry>) -> Result<usize, String> {
+    discard(&app_handle)?;
+    // The note index reads everything again on its next use
+    index::with_cached_index(&app_handle, |index| *index = NoteIndex::default())?;
+    if encryption::is_enabled(&app_handle) {
+        return Ok(0);
+    }
+
+    with_catalog(&app_handle, |conn| {
+        refresh(&app_handle, conn, |done, total| {
+            events::maintenance_progress(&app_handle, "rebuild", done, total)
+        })
+    })
+}
+
+// Reclaim the space deleted notes leave in the catalog and the note index, e.g.
+// after deleting many notes. Reports `index-maintenance` after each step. Returns
+// how many bytes the catalog shrank by.
+#[tauri::command]
+pub async fn compact_index(app_handle: AppHandle<Wry>) -> Result<u64, String> {
+    const STEPS: usize = 3;
+    let report = |done| events::maintenance_progress(&app_handle, "compact", done, STEPS);
+    let path = catalog_path(&app_handle)?;
+    let size = |path: &Path| fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let before = size(&path);
+
+    if !encryption::is_enabled(&app_handle) {
+        with_catalog(&app_handle, |conn| {
+            // Rows of notes deleted outside the app go first
+            refresh(&app_handle, conn, |_, _| {})?;
+            report(1);
+            conn.execute_batch("VACUUM").map_err(|e| e.to_string())?;
+            report(2);
+            Ok(())
+        })?;
+    }
+    index::with_index(&app_handle, |index| index.entries.shrink_to_fit())?;
+    report(STEPS);
+
+    Ok(before.saturating_sub(size(&path)))
+}
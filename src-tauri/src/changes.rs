@@ -0,0 +1,89 @@
+use crate::{get_note_path, notebooks, read_note, Note};
+use tauri::{AppHandle, Manager, Wry};
+use tokio::sync::broadcast;
+
+// Note changes as they happen. Whatever writes, deletes or shares a note publishes
+// a `NoteChange` here once, and subsystems that react to notes (the frontend
+// events, webhooks, the search index) subscribe instead of each command calling
+// them or each of them rescanning the notes directory. A new reactive feature is
+// one more subscriber.
+
+// Changes a subscriber may fall behind by before it starts missing them
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub enum NoteChange {
+    // The note as written to disk
+    Created(Note),
+    Updated(Note),
+    // Moved to the trash or wiped
+    Deleted(String),
+    // Sent to a peer
+    Shared { note: Note, peer_id: String },
+}
+
+impl NoteChange {
+    pub fn note_id(&self) -> &str {
+        match self {
+            NoteChange::Created(note)
+            | NoteChange::Updated(note)
+            | NoteChange::Shared { note, .. } => &note.id,
+            NoteChange::Deleted(id) => id,
+        }
+    }
+}
+
+pub struct ChangeBus(broadcast::Sender<NoteChange>);
+
+impl Default for ChangeBus {
+    fn default() -> Self {
+        ChangeBus(broadcast::channel(CAPACITY).0)
+    }
+}
+
+pub fn publish(app_handle: &AppHandle<Wry>, change: NoteChange) {
+    // Nobody listening isn't an error
+    let _ = app_handle.state::<ChangeBus>().0.send(change);
+}
+
+// Publish a note written outside `persist_note`, as it now is on disk, filing it in
+// its notebook first
+pub fn publish_saved(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    created: bool,
+) -> Result<(), String> {
+    let note = read_note(app_handle, note_id, &get_note_path(app_handle, note_id))?;
+    notebooks::file_note(app_handle, note_id, note.notebook.as_deref())?;
+    publish(
+        app_handle,
+        if created {
+            NoteChange::Created(note)
+        } else {
+            NoteChange::Updated(note)
+        },
+    );
+    Ok(())
+}
+
+// Call `handle` with every change published from now on, in order, on a task of its
+// own. `name` identifies the subscriber when it falls behind.
+pub fn subscribe(
+    app_handle: &AppHandle<Wry>,
+    name: &'static str,
+    mut handle: impl FnMut(&AppHandle<Wry>, NoteChange) + Send + 'static,
+) {
+    let mut changes = app_handle.state::<ChangeBus>().0.subscribe();
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match changes.recv().await {
+                Ok(change) => handle(&app_handle, change),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    println!("{} fell behind and missed {} note changes", name, missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
@@ -0,0 +1,202 @@
+use crate::{merge, Note};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, Wry};
+
+// Hybrid logical clocks order note changes across devices whose wall clocks disagree.
+// A timestamp is the wall time in milliseconds, a counter for changes within the
+// same millisecond (or while the wall clock lags behind a peer's), and the device id
+// to break ties. Every saved note carries one in its `hlc` field, written
+// `<millis>:<counter>:<device>`, and every note that arrives through sync advances
+// the local clock past it.
+
+pub const CLOCK_FIELD: &str = "hlc";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timestamp {
+    pub millis: i64,
+    pub counter: u32,
+    pub device: String,
+}
+
+impl Ord for Timestamp {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.millis, self.counter, &self.device).cmp(&(other.millis, other.counter, &other.device))
+    }
+}
+
+impl PartialOrd for Timestamp {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.millis, self.counter, self.device)
+    }
+}
+
+impl Timestamp {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.splitn(3, ':');
+        Some(Timestamp {
+            millis: parts.next()?.parse().ok()?,
+            counter: parts.next()?.parse().ok()?,
+            device: parts.next()?.to_string(),
+        })
+    }
+}
+
+pub struct Clock {
+    device: String,
+    millis: i64,
+    counter: u32,
+}
+
+impl Clock {
+    pub fn new(device: &str) -> Self {
+        Clock {
+            device: device.to_string(),
+            millis: 0,
+            counter: 0,
+        }
+    }
+
+    // Timestamp for a change made on this device
+    fn tick(&mut self) -> Timestamp {
+        let now = chrono::Utc::now().timestamp_millis();
+        if now > self.millis {
+            self.millis = now;
+            self.counter = 0;
+        } else {
+            self.counter += 1;
+        }
+        self.current()
+    }
+
+    // Move past a timestamp seen from another device
+    fn observe(&mut self, seen: &Timestamp) {
+        let now = chrono::Utc::now().timestamp_millis();
+        let millis = now.max(self.millis).max(seen.millis);
+        self.counter = if millis == self.millis && millis == seen.millis {
+            self.counter.max(seen.counter) + 1
+        } else if millis == self.millis {
+            self.counter + 1
+        } else if millis == seen.millis {
+            seen.counter + 1
+        } else {
+            0
+        };
+        self.millis = millis;
+    }
+
+    fn current(&self) -> Timestamp {
+        Timestamp {
+            millis: self.millis,
+            counter: self.counter,
+            device: self.device.clone(),
+        }
+    }
+}
+
+pub fn timestamp_of(note: &Note) -> Option<Timestamp> {
+    note.fields
+        .get(CLOCK_FIELD)
+        .and_then(Value::as_str)
+        .and_then(Timestamp::parse)
+}
+
+// The next local timestamp, for a change made on this device
+pub fn tick(app_handle: &AppHandle<Wry>) -> Option<Timestamp> {
+    let state = app_handle.state::<Mutex<Clock>>();
+    let mut clock = state.lock().ok()?;
+    Some(clock.tick())
+}
+
+// Stamp a note that is about to be saved with the next local timestamp
+pub fn stamp(app_handle: &AppHandle<Wry>, note: &mut Note) {
+    let Some(timestamp) = tick(app_handle) else {
+        return;
+    };
+    note.fields.insert(
+        CLOCK_FIELD.to_string(),
+        Value::String(timestamp.to_string()),
+    );
+}
+
+// Advance the local clock past a timestamp seen from a peer
+pub fn observe_timestamp(app_handle: &AppHandle<Wry>, seen: &Timestamp) {
+    let state = app_handle.state::<Mutex<Clock>>();
+    if let Ok(mut clock) = state.lock() {
+        clock.observe(seen);
+    }
+}
+
+// Advance the local clock past a note received from a peer
+pub fn observe(app_handle: &AppHandle<Wry>, note: &Note) {
+    if let Some(seen) = timestamp_of(note) {
+        observe_timestamp(app_handle, &seen);
+    }
+}
+
+// Whether an incoming note is a version this device already has: the one it last
+// synced with the peer, or something older. Notes from peers without clocks are never
+// considered stale.
+pub fn is_stale(app_handle: &AppHandle<Wry>, peer_id: &str, incoming: &Note) -> bool {
+    let Some(seen) = timestamp_of(incoming) else {
+        return false;
+    };
+    merge::base_note(app_handle, peer_id, &incoming.id)
+        .and_then(|base| timestamp_of(&base))
+        .is_some_and(|base| seen <= base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> Timestamp {
+        Timestamp::parse(value).unwrap()
+    }
+
+    #[test]
+    fn timestamps_order_by_millis_then_counter_then_device() {
+        assert!(at("1000:5:b") < at("1001:0:a"));
+        assert!(at("1000:1:b") < at("1000:2:a"));
+        assert!(at("1000:1:a") < at("1000:1:b"));
+        assert_eq!(at("1000:1:a"), at("1000:1:a"));
+    }
+
+    #[test]
+    fn timestamps_round_trip() {
+        let timestamp = at("1700000000000:3:device:with:colons");
+        assert_eq!(timestamp.device, "device:with:colons");
+        assert_eq!(at(&timestamp.to_string()), timestamp);
+        assert_eq!(Timestamp::parse("1700000000000"), None);
+    }
+
+    #[test]
+    fn ticks_keep_increasing() {
+        let mut clock = Clock::new("a");
+        let first = clock.tick();
+        let second = clock.tick();
+        assert!(second > first);
+    }
+
+    #[test]
+    fn ticks_come_after_what_was_observed() {
+        let mut clock = Clock::new("a");
+        let ahead = Timestamp {
+            millis: chrono::Utc::now().timestamp_millis() + 60_000,
+            counter: 7,
+            device: "b".to_string(),
+        };
+        clock.observe(&ahead);
+        let next = clock.tick();
+        assert!(next > ahead);
+        assert_eq!(next.millis, ahead.millis);
+    }
+}
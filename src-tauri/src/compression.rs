@@ -0,0 +1,213 @@
+use crate::settings::load_vault_settings;
+use crate::{atomic, blobs, encryption, get_attachments_dir, get_notes_dir, storage};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Wry};
+
+// Attachments of compressible types (text, data files, SVG, uncompressed images) can
+// be stored zstd-compressed as `<name>.zst`. Notes and the frontend keep using the
+// original name; reads go through `read_attachment`, which decompresses as needed.
+
+pub const COMPRESSED_SUFFIX: &str = ".zst";
+const LEVEL: i32 = 9;
+// Compressing has to save at least this share of the size to be worth keeping
+const MIN_SAVING: f64 = 0.1;
+
+// Extensions of formats that aren't compressed already
+const COMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    "txt", "md", "csv", "tsv", "json", "xml", "html", "htm", "css", "js", "svg", "log", "yaml",
+    "yml", "toml", "ics", "vcf", "bmp", "tif", "tiff", "wav",
+];
+
+#[derive(Debug, Serialize, Default)]
+pub struct CompressionReport {
+    compressed: usize,
+    bytes_before: u64,
+    bytes_after: u64,
+    failed: Vec<String>,
+}
+
+// The attachment's name as notes refer to it
+pub fn attachment_name(stored: &str) -> &str {
+    stored.strip_suffix(COMPRESSED_SUFFIX).unwrap_or(stored)
+}
+
+fn is_compressible(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| COMPRESSIBLE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+pub fn compressed_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(COMPRESSED_SUFFIX);
+    PathBuf::from(name)
+}
+
+// Whether the attachment exists, compressed or not
+pub fn attachment_exists(dir: &Path, name: &str) -> bool {
+    let path = dir.join(name);
+    path.is_file() || compressed_path(&path).is_file()
+}
+
+// Delete an attachment in whichever form it is stored
+pub fn remove_attachment(dir: &Path, name: &str) {
+    let path = dir.join(name);
+    let _ = fs::remove_file(compressed_path(&path));
+    let _ = fs::remove_file(path);
+}
+
+// `remove_attachment` for a file in a note's attachments, letting go of its share
+// in the blob store
+pub fn release_attachment(app_handle: &AppHandle<Wry>, dir: &Path, name: &str) {
+    let path = dir.join(name);
+    blobs::unshare_file(app_handle, &path);
+    blobs::unshare_file(app_handle, &compressed_path(&path));
+    remove_attachment(dir, name);
+}
+
+// Contents of an attachment, decrypting and decompressing it as stored. A plain
+// copy wins over a compressed one, since it was written last.
+pub fn read_attachment(dir: &Path, name: &str) -> io::Result<Vec<u8>> {
+    let path = dir.join(name);
+    match fs::read(&path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let compressed = encryption::open(fs::read(compressed_path(&path))?)?;
+            zstd::decode_all(compressed.as_slice())
+        }
+        result => encryption::open(result?),
+    }
+}
+
+// `read_attachment` for a note's attachment, reading it out of cold storage when the
+// note is archived rather than unpacking the note
+pub fn read_note_attachment(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    name: &str,
+) -> io::Result<Vec<u8>> {
+    match read_attachment(&get_attachments_dir(app_handle, note_id), name) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            storage::read_archived_attachment(app_handle, note_id, name)
+        }
+        result => result,
+    }
+}
+
+// Replace the file with a compressed copy if that saves enough space. Returns the
+// sizes before and after, or None when the file was left alone.
+fn compress_file(path: &Path) -> Result<Option<(u64, u64)>, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let compressed = zstd::encode_all(data.as_slice(), LEVEL).map_err(|e| e.to_string())?;
+    if (compressed.len() as f64) > data.len() as f64 * (1.0 - MIN_SAVING) {
+        return Ok(None);
+    }
+
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    let dest = compressed_path(path);
+    atomic::write(&dest, &compressed).map_err(|e| e.to_string())?;
+    // Cold storage goes by when attachments were last touched
+    if let Some(modified) = modified {
+        storage::set_modified(&dest, modified);
+    }
+    fs::remove_file(path).map_err(|e| e.to_string())?;
+    Ok(Some((data.len() as u64, compressed.len() as u64)))
+}
+
+// Compress a newly written attachment when compression is enabled
+pub fn compress_new_attachment(app_handle: &AppHandle<Wry>, path: &Path) {
+    let compressible = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(is_compressible);
+    if !compressible || !load_vault_settings(app_handle).compress_attachments {
+        return;
+    }
+    if let Err(e) = compress_file(path) {
+        println!("Failed to compress attachment {:?}: {}", path, e);
+    }
+}
+
+// Store an attachment, compressed when that is enabled, sharing its file with
+// identical attachments of other notes. One already stored under the name is
+// replaced rather than written through, as its file may be shared, and stays in
+// place until the new one is complete.
+pub fn write_attachment(
+    app_handle: &AppHandle<Wry>,
+    path: &Path,
+    data: &[u8],
+) -> Result<(), String> {
+    blobs::unshare_file(app_handle, path);
+    blobs::unshare_file(app_handle, &compressed_path(path));
+
+    // Ciphertext neither shrinks nor repeats, so it is stored as it is
+    if encryption::is_enabled(app_handle) {
+        encryption::write(app_handle, path, data)?;
+        let _ = fs::remove_file(compressed_path(path));
+        return Ok(());
+    }
+    atomic::write(path, data).map_err(|e| e.to_string())?;
+    // The plain copy wins over a compressed one from before, which can go now
+    let _ = fs::remove_file(compressed_path(path));
+    compress_new_attachment(app_handle, path);
+    let stored = if path.is_file() {
+        path.to_path_buf()
+    } else {
+        compressed_path(path)
+    };
+    if let Err(e) = blobs::share(app_handle, &stored) {
+        println!("Failed to deduplicate attachment {:?}: {}", stored, e);
+    }
+    Ok(())
+}
+
+fn compress_all(attachments_root: &Path) -> Result<CompressionReport, String> {
+    let mut report = CompressionReport::default();
+    if !attachments_root.exists() {
+        return Ok(report);
+    }
+
+    for dir in fs::read_dir(attachments_root)
+        .map_err(|e| e.to_string())?
+        .flatten()
+    {
+        let dir = dir.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(&dir).map_err(|e| e.to_string())?.flatten() {
+            let path = file.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if name.ends_with(COMPRESSED_SUFFIX) || !is_compressible(name) {
+                continue;
+            }
+            match compress_file(&path) {
+                Ok(Some((before, after))) => {
+                    report.compressed += 1;
+                    report.bytes_before += before;
+                    report.bytes_after += after;
+                }
+                Ok(None) => {}
+                Err(e) => report.failed.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+    }
+    Ok(report)
+}
+
+// One-time pass compressing attachments stored before compression was turned on.
+// Archived notes keep their attachments in cold storage and are left as they are.
+#[tauri::command]
+pub async fn compress_existing_attachments(
+    app_handle: AppHandle<Wry>,
+) -> Result<CompressionReport, String> {
+    let attachments_root = get_notes_dir(&app_handle).join("attachments");
+    tokio::task::spawn_blocking(move || compress_all(&attachments_root))
+        .await
+        .map_err(|e| e.to_string())?
+}
@@ -0,0 +1,238 @@
+use crate::events;
+use crate::settings::load_device_settings;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, Wry};
+use tokio::process::Command;
+
+// Bulk work such as multi-note sync and cold storage sweeps is deferred while the
+// connection is metered or the battery is low, as far as the OS lets us tell. Notes
+// shared in bulk meanwhile are queued in memory and sent once conditions clear.
+//
+// Sync can also be paused by hand, from the tray or the app, until it is resumed or
+// the app restarts. That holds back sending any note, not just bulk work, and turns
+// away notes and library syncs peers send.
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SyncConditions {
+    // Run bulk work regardless of network and battery state
+    pub ignore: bool,
+    // Pause when running on battery below this charge
+    pub low_battery_percent: u8,
+}
+
+impl Default for SyncConditions {
+    fn default() -> Self {
+        SyncConditions {
+            ignore: false,
+            low_battery_percent: 20,
+        }
+    }
+}
+
+// How often queued shares are retried
+const DEFERRED_RETRY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseReason {
+    Metered,
+    LowBattery,
+    Manual,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SyncPaused {
+    reason: PauseReason,
+    message: String,
+}
+
+// Whether sync was paused by hand
+#[derive(Default)]
+pub struct SyncPause(AtomicBool);
+
+// Ids of notes waiting to be shared, by the peer they go to
+#[derive(Default)]
+pub struct DeferredShares(Mutex<HashMap<String, Vec<String>>>);
+
+pub fn is_sync_paused(app_handle: &AppHandle<Wry>) -> bool {
+    app_handle.state::<SyncPause>().0.load(Ordering::SeqCst)
+}
+
+// Pause or resume sync, announcing it with `sync-pause-changed`
+pub fn set_paused(app_handle: &AppHandle<Wry>, paused: bool) {
+    let was_paused = app_handle
+        .state::<SyncPause>()
+        .0
+        .swap(paused, Ordering::SeqCst);
+    if was_paused == paused {
+        return;
+    }
+    println!("Sync {}", if paused { "paused" } else { "resumed" });
+    if let Err(e) = app_handle.emit(events::SYNC_PAUSE_CHANGED, paused) {
+        println!("Failed to announce the sync pause: {}", e);
+    }
+}
+
+// Fail while sync is paused by hand; checked before sending even a single note
+pub fn check_sync_paused(app_handle: &AppHandle<Wry>) -> Result<(), String> {
+    if is_sync_paused(app_handle) {
+        return Err("Sync is paused".to_string());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_sync_paused(app_handle: AppHandle<Wry>) -> Result<bool, String> {
+    Ok(is_sync_paused(&app_handle))
+}
+
+#[tauri::command]
+pub async fn set_sync_paused(app_handle: AppHandle<Wry>, paused: bool) -> Result<(), String> {
+    set_paused(&app_handle, paused);
+    Ok(())
+}
+
+async fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().await.ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(target_os = "linux")]
+async fn is_metered() -> bool {
+    // NetworkManager reports NM_METERED_YES (1) or NM_METERED_GUESS_YES (3)
+    command_output(
+        "busctl",
+        &[
+            "get-property",
+            "org.freedesktop.NetworkManager",
+            "/org/freedesktop/NetworkManager",
+            "org.freedesktop.NetworkManager",
+            "Metered",
+        ],
+    )
+    .await
+    .is_some_and(|output| matches!(output.as_str(), "u 1" | "u 3"))
+}
+
+#[cfg(target_os = "windows")]
+async fn is_metered() -> bool {
+    let script = "[Windows.Networking.Connectivity.NetworkInformation,Windows.Networking.Connectivity,ContentType=WindowsRuntime] > $null; \
+        $p = [Windows.Networking.Connectivity.NetworkInformation]::GetInternetConnectionProfile(); \
+        if ($p) { $p.GetConnectionCost().NetworkCostType }";
+    command_output("powershell", &["-NoProfile", "-Command", script])
+        .await
+        .is_some_and(|cost| matches!(cost.as_str(), "Fixed" | "Variable"))
+}
+
+// macOS doesn't expose whether a connection is expensive outside of the Network framework
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+async fn is_metered() -> bool {
+    false
+}
+
+// The charge of the battery in percent, when the machine is running on it
+fn battery_discharging_percent() -> Option<f32> {
+    let manager = starship_battery::Manager::new().ok()?;
+    manager
+        .batteries()
+        .ok()?
+        .flatten()
+        .filter(|battery| matches!(battery.state(), starship_battery::State::Discharging))
+        .map(|battery| battery.state_of_charge().value * 100.0)
+        .reduce(f32::min)
+}
+
+// Why bulk work should wait right now, if it should
+pub async fn pause_reason(app_handle: &AppHandle<Wry>) -> Option<SyncPaused> {
+    if is_sync_paused(app_handle) {
+        return Some(SyncPaused {
+            reason: PauseReason::Manual,
+            message: "Sync is paused".to_string(),
+        });
+    }
+
+    let conditions = load_device_settings(app_handle).sync_conditions;
+    if conditions.ignore {
+        return None;
+    }
+
+    if let Some(percent) = battery_discharging_percent() {
+        if percent < conditions.low_battery_percent as f32 {
+            return Some(SyncPaused {
+                reason: PauseReason::LowBattery,
+                message: format!("Battery is at {:.0}%", percent),
+            });
+        }
+    }
+
+    if is_metered().await {
+        return Some(SyncPaused {
+            reason: PauseReason::Metered,
+            message: "Network connection is metered".to_string(),
+        });
+    }
+
+    None
+}
+
+// Emit `sync-paused` and return the reason when bulk work should be deferred
+pub async fn check_bulk_work(app_handle: &AppHandle<Wry>) -> Result<(), String> {
+    match pause_reason(app_handle).await {
+        Some(paused) => {
+            let _ = app_handle.emit("sync-paused", paused.clone());
+            Err(format!("Sync paused: {}", paused.message))
+        }
+        None => Ok(()),
+    }
+}
+
+// Queue notes for `peer_id` until bulk work may run again
+pub fn defer_share(app_handle: &AppHandle<Wry>, peer_id: &str, note_ids: Vec<String>) {
+    let deferred = app_handle.state::<DeferredShares>();
+    if let Ok(mut queued) = deferred.0.lock() {
+        let notes = queued.entry(peer_id.to_string()).or_default();
+        for id in note_ids {
+            if !notes.contains(&id) {
+                notes.push(id);
+            }
+        }
+    };
+}
+
+// Send queued shares once nothing holds bulk work back. Peers that are gone by then
+// don't get them.
+pub fn spawn_deferred_sender(app_handle: AppHandle<Wry>) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(DEFERRED_RETRY).await;
+            let waiting = app_handle
+                .state::<DeferredShares>()
+                .0
+                .lock()
+                .is_ok_and(|queued| !queued.is_empty());
+            if !waiting || pause_reason(&app_handle).await.is_some() {
+                continue;
+            }
+
+            let queued = match app_handle.state::<DeferredShares>().0.lock() {
+                Ok(mut queued) => std::mem::take(&mut *queued),
+                Err(_) => continue,
+            };
+            for (peer_id, note_ids) in queued {
+                println!("Sending {} deferred notes to {}", note_ids.len(), peer_id);
+                let sent = crate::share_notes(app_handle.clone(), note_ids, peer_id.clone()).await;
+                if let Err(e) = sent {
+                    println!("Failed to send deferred notes to {}: {}", peer_id, e);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,147 @@
+use crate::{
+    compression, encryption, get_attachments_dir, load_notes, overrides, persist_note, Note,
+};
+use image::imageops::FilterType;
+use serde_json::Value;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Wry};
+
+// A note can name one of its image attachments as its cover in the `cover`
+// frontmatter field. A banner-sized crop is cached outside the notes directory so
+// the gallery view doesn't have to decode full-size photos. In an encrypted vault
+// the cached crop is encrypted like the image it came from.
+
+const COVER_FIELD: &str = "cover";
+const BANNER_WIDTH: u32 = 1200;
+const BANNER_HEIGHT: u32 = 400;
+
+pub fn cover_of(note: &Note) -> Option<&str> {
+    note.fields.get(COVER_FIELD).and_then(Value::as_str)
+}
+
+fn get_banner_path(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<PathBuf, String> {
+    let dir = overrides::app_cache_dir(app_handle)
+        .map_err(|e| e.to_string())?
+        .join("covers");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.join(format!("{}.jpg", note_id)))
+}
+
+// Scale and center-crop the image to fill the banner, as JPEG. The attachment is
+// read as stored, compressed, encrypted or in cold storage.
+fn render_banner(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    attachment: &str,
+) -> Result<Vec<u8>, String> {
+    let data = compression::read_note_attachment(app_handle, note_id, attachment)
+        .map_err(|e| format!("Can't read cover image: {}", e))?;
+    let image =
+        image::load_from_memory(&data).map_err(|e| format!("Can't read cover image: {}", e))?;
+    let mut jpeg = Cursor::new(Vec::new());
+    image
+        .resize_to_fill(BANNER_WIDTH, BANNER_HEIGHT, FilterType::Triangle)
+        .to_rgb8()
+        .write_to(&mut jpeg, image::ImageFormat::Jpeg)
+        .map_err(|e| e.to_string())?;
+    Ok(jpeg.into_inner())
+}
+
+fn is_stale(banner: &Path, dir: &Path, attachment: &str) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    // Whichever form the attachment is stored in
+    let path = dir.join(attachment);
+    let source = modified(&path).or_else(|| modified(&compression::compressed_path(&path)));
+    match (modified(banner), source) {
+        (Some(banner), Some(source)) => banner < source,
+        // The note is in cold storage; its banner was made before
+        (Some(_), None) => false,
+        _ => true,
+    }
+}
+
+async fn ensure_banner(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    attachment: &str,
+) -> Result<PathBuf, String> {
+    let dir = get_attachments_dir(app_handle, note_id);
+    let banner = get_banner_path(app_handle, note_id)?;
+    if is_stale(&banner, &dir, attachment) {
+        let (handle, id, attachment) = (
+            app_handle.clone(),
+            note_id.to_string(),
+            attachment.to_string(),
+        );
+        let jpeg = tokio::task::spawn_blocking(move || render_banner(&handle, &id, &attachment))
+            .await
+            .map_err(|e| e.to_string())??;
+        encryption::write(app_handle, &banner, jpeg)?;
+    }
+    Ok(banner)
+}
+
+// Set or clear the cover of a note. The attachment must be an image the note owns.
+#[tauri::command]
+pub async fn set_cover(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    attachment: Option<String>,
+) -> Result<Note, String> {
+    let mut note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    match attachment {
+        Some(attachment) => {
+            if !note.attachments.contains(&attachment) {
+                return Err(format!("Note has no attachment named {}", attachment));
+            }
+            if mime_guess::from_path(&attachment)
+                .first_or_octet_stream()
+                .type_()
+                != "image"
+            {
+                return Err("Cover must be an image".to_string());
+            }
+            // The new image may be older than the banner made for the previous cover
+            let _ = fs::remove_file(get_banner_path(&app_handle, &note.id)?);
+            ensure_banner(&app_handle, &note.id, &attachment).await?;
+            note.fields
+                .insert(COVER_FIELD.to_string(), Value::String(attachment));
+        }
+        None => {
+            note.fields.remove(COVER_FIELD);
+            if let Ok(banner) = get_banner_path(&app_handle, &note.id) {
+                let _ = fs::remove_file(banner);
+            }
+        }
+    }
+
+    persist_note(&app_handle, note.clone()).await?;
+
+    Ok(note)
+}
+
+// The cached banner crop of a note's cover as JPEG bytes, regenerated when the cover
+// image changed since it was made
+#[tauri::command]
+pub async fn get_cover_banner(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+) -> Result<Vec<u8>, String> {
+    let note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+    let attachment = cover_of(&note).ok_or("Note has no cover")?;
+    if !note.attachments.iter().any(|a| a == attachment) {
+        return Err("Cover image is missing".to_string());
+    }
+
+    let banner = ensure_banner(&app_handle, &note.id, attachment).await?;
+    encryption::read(banner).map_err(|e| e.to_string())
+}
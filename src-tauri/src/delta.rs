@@ -0,0 +1,180 @@
+use crate::protocol::{self, SyncError};
+use crate::{compression, get_note_path, merge, network, pairing, read_note, Note, PeerDevice};
+use axum::body::Bytes;
+use axum::extract::rejection::BytesRejection;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+use tauri::{AppHandle, Wry};
+
+// Delta sync: before a note goes to a peer, the sender asks the peer what it
+// already holds of it (`/sync/have`), sending the hash of the note and of each
+// attachment. The peer answers whether its copy of the note is the same and which
+// attachments it lacks, so only those travel. A note the peer already has as it is
+// isn't sent at all. Peers that don't know `/sync/have` get everything, as before.
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HaveQuery {
+    peer_id: String,
+    note_id: String,
+    // SHA-256 of the note file without its clock
+    hash: String,
+    // SHA-256 of each attachment, by file name
+    attachments: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HaveReply {
+    // The peer's copy of the note is the one being sent
+    current: bool,
+    // Attachments the peer has no copy of, or a different one
+    missing: Vec<String>,
+}
+
+type Reply = (StatusCode, Json<Value>);
+
+pub fn hash_bytes(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// The hash two copies of a note share when only their clocks differ
+pub fn note_hash(note: &Note) -> String {
+    hash_bytes(merge::merge_text(note).as_bytes())
+}
+
+// What we have of a note a peer is about to send
+fn have(app_handle: &AppHandle<Wry>, query: &HaveQuery) -> Result<HaveReply, String> {
+    // Only the one note is read; a note we don't have is simply not current
+    let path = get_note_path(app_handle, &query.note_id);
+    let note = path
+        .exists()
+        .then(|| read_note(app_handle, &query.note_id, &path))
+        .transpose()?;
+    let missing = query
+        .attachments
+        .iter()
+        .filter(|(name, hash)| {
+            !compression::read_note_attachment(app_handle, &query.note_id, name)
+                .is_ok_and(|data| hash_bytes(&data) == **hash)
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    Ok(HaveReply {
+        current: note.is_some_and(|note| note_hash(&note) == query.hash),
+        missing,
+    })
+}
+
+// Leave out of `attachments_data` what `peer` already has of `note`. Returns
+// true when the peer has all of it and nothing needs to be sent.
+pub async fn trim(
+    client: &reqwest::Client,
+    peer: &PeerDevice,
+    own_id: &str,
+    note: &Note,
+    attachments_data: &mut HashMap<String, Vec<u8>>,
+) -> bool {
+    let query = HaveQuery {
+        peer_id: own_id.to_string(),
+        note_id: note.id.clone(),
+        hash: note_hash(note),
+        attachments: attachments_data
+            .iter()
+            .map(|(name, data)| (name.clone(), hash_bytes(data)))
+            .collect(),
+    };
+    let reply = network::post_to_peer(
+        client,
+        &peer.addresses(),
+        peer.port,
+        "/sync/have",
+        &query,
+        Duration::from_secs(5),
+    )
+    .await;
+    let reply: HaveReply = match reply {
+        Ok(response) => match response.json().await {
+            Ok(reply) => reply,
+            Err(e) => {
+                println!("Unreadable answer to /sync/have from {}: {}", peer.name, e);
+                return false;
+            }
+        },
+        // Older peers, or a failure we'll see again when sending the note
+        Err(e) => {
+            println!(
+                "{} can't say what it has, sending everything: {}",
+                peer.name, e
+            );
+            return false;
+        }
+    };
+
+    let before = attachments_data.len();
+    attachments_data.retain(|name, _| reply.missing.contains(name));
+    println!(
+        "{} already has {} of {} attachments of {}",
+        peer.name,
+        before - attachments_data.len(),
+        before,
+        note.id
+    );
+    reply.current && attachments_data.is_empty()
+}
+
+fn failure(status: StatusCode, error: SyncError) -> Reply {
+    println!("Have request failed: {}", error);
+    (status, Json(error.body()))
+}
+
+async fn receive_have(
+    State(app_handle): State<AppHandle<Wry>>,
+    headers: HeaderMap,
+    body: Result<Bytes, BytesRejection>,
+) -> Reply {
+    let query: HaveQuery = match protocol::read_body(body) {
+        Ok(query) => query,
+        Err((status, error)) => return failure(status, error),
+    };
+    // Which notes we hold is as private as the notes
+    if !pairing::is_trusted(&app_handle, &query.peer_id, pairing::bearer_token(&headers)) {
+        return failure(
+            StatusCode::FORBIDDEN,
+            SyncError::new(
+                "not_paired",
+                format!("{} isn't paired with this device", query.peer_id),
+                false,
+            ),
+        );
+    }
+    let checked = protocol::check_id("note_id", &query.note_id).and_then(|()| {
+        query
+            .attachments
+            .keys()
+            .try_for_each(|name| protocol::check_file_name("attachments", name))
+    });
+    if let Err(invalid) = checked {
+        return failure(StatusCode::BAD_REQUEST, invalid);
+    }
+
+    match have(&app_handle, &query)
+        .and_then(|reply| serde_json::to_value(reply).map_err(|e| e.to_string()))
+    {
+        Ok(reply) => (StatusCode::OK, Json(reply)),
+        Err(e) => failure(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            SyncError::new("internal", e, true),
+        ),
+    }
+}
+
+pub fn routes(app_handle: AppHandle<Wry>) -> axum::Router {
+    axum::Router::new()
+        .route("/sync/have", axum::routing::post(receive_have))
+        .with_state(app_handle)
+}
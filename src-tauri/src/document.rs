@@ -0,0 +1,366 @@
+use crate::compression;
+use crate::import::unique_attachment_name;
+use crate::markdown::{self, attachment_link};
+use crate::paste::html_to_markdown;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use tauri::{AppHandle, Wry};
+use zip::ZipArchive;
+
+// Word (.docx) and OpenDocument (.odt) files are zip archives holding the document
+// as XML plus its embedded images. The XML is turned into simple HTML and handed to
+// the same converter used for pasted HTML, so both produce the same markdown.
+
+fn attribute(element: &BytesStart, name: &[u8]) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|a| a.key.as_ref() == name)
+        .and_then(|a| a.unescape_value().ok())
+        .map(|v| v.into_owned())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String, String> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|_| format!("Document is missing {}", name))?;
+    let mut xml = String::new();
+    entry.read_to_string(&mut xml).map_err(|e| e.to_string())?;
+    Ok(xml)
+}
+
+// Relationship ids used by document.xml, mapped to image paths inside the archive
+// or to the URLs of external hyperlinks
+fn docx_relationships(xml: &str) -> Result<HashMap<String, String>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut relationships = HashMap::new();
+
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"Relationship" => {
+                let (Some(id), Some(target)) = (attribute(&e, b"Id"), attribute(&e, b"Target"))
+                else {
+                    continue;
+                };
+                let target = if attribute(&e, b"TargetMode").as_deref() == Some("External") {
+                    target
+                } else if let Some(absolute) = target.strip_prefix('/') {
+                    absolute.to_string()
+                } else {
+                    format!("word/{}", target)
+                };
+                relationships.insert(id, target);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(relationships)
+}
+
+fn heading_level(style: &str) -> Option<usize> {
+    let style = style.to_lowercase();
+    if style == "title" {
+        return Some(1);
+    }
+    style
+        .strip_prefix("heading")
+        .and_then(|level| level.trim().parse::<usize>().ok())
+        .map(|level| level.clamp(1, 6))
+}
+
+fn docx_to_html(xml: &str, relationships: &HashMap<String, String>) -> Result<String, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut html = String::new();
+    let mut paragraph = String::new();
+    let mut run = String::new();
+    let mut heading: Option<usize> = None;
+    let mut list_item = false;
+    let mut in_list = false;
+    let mut in_text = false;
+    let mut link_open = false;
+    let mut table_depth = 0;
+    let (mut bold, mut italic) = (false, false);
+
+    loop {
+        let event = reader.read_event().map_err(|e| e.to_string())?;
+        let is_empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => match e.name().as_ref() {
+                b"w:p" if !is_empty => {
+                    paragraph.clear();
+                    heading = None;
+                    list_item = false;
+                }
+                b"w:pStyle" => heading = attribute(&e, b"w:val").and_then(|s| heading_level(&s)),
+                b"w:numPr" => list_item = true,
+                b"w:r" if !is_empty => {
+                    run.clear();
+                    bold = false;
+                    italic = false;
+                }
+                b"w:b" | b"w:i" => {
+                    let on = !matches!(attribute(&e, b"w:val").as_deref(), Some("0" | "false"));
+                    if e.name().as_ref() == b"w:b" {
+                        bold = on;
+                    } else {
+                        italic = on;
+                    }
+                }
+                b"w:t" if !is_empty => in_text = true,
+                b"w:tab" => run.push(' '),
+                b"w:br" => run.push_str("<br>"),
+                b"a:blip" => {
+                    if let Some(target) =
+                        attribute(&e, b"r:embed").and_then(|id| relationships.get(&id))
+                    {
+                        run.push_str(&format!("<img src=\"{}\">", escape_html(target)));
+                    }
+                }
+                b"w:hyperlink" if !is_empty => {
+                    if let Some(href) = attribute(&e, b"r:id").and_then(|id| relationships.get(&id))
+                    {
+                        paragraph.push_str(&format!("<a href=\"{}\">", escape_html(href)));
+                        link_open = true;
+                    }
+                }
+                b"w:tbl" if !is_empty => {
+                    if in_list {
+                        html.push_str("</ul>");
+                        in_list = false;
+                    }
+                    table_depth += 1;
+                    html.push_str("<table>");
+                }
+                b"w:tr" if !is_empty => html.push_str("<tr>"),
+                b"w:tc" if !is_empty => html.push_str("<td>"),
+                _ => {}
+            },
+            Event::Text(text) if in_text => {
+                run.push_str(&escape_html(&text.unescape().map_err(|e| e.to_string())?));
+            }
+            Event::End(e) => match e.name().as_ref() {
+                b"w:t" => in_text = false,
+                b"w:r" => {
+                    let mut formatted = std::mem::take(&mut run);
+                    if !formatted.trim().is_empty() {
+                        if italic {
+                            formatted = format!("<em>{}</em>", formatted);
+                        }
+                        if bold {
+                            formatted = format!("<strong>{}</strong>", formatted);
+                        }
+                    }
+                    paragraph.push_str(&formatted);
+                }
+                b"w:hyperlink" if link_open => {
+                    paragraph.push_str("</a>");
+                    link_open = false;
+                }
+                b"w:p" => {
+                    if table_depth > 0 {
+                        // Paragraphs inside a table cell run together on one line
+                        html.push_str(&paragraph);
+                        html.push(' ');
+                    } else if list_item {
+                        if !in_list {
+                            html.push_str("<ul>");
+                            in_list = true;
+                        }
+                        html.push_str(&format!("<li>{}</li>", paragraph));
+                    } else {
+                        if in_list {
+                            html.push_str("</ul>");
+                            in_list = false;
+                        }
+                        if !paragraph.trim().is_empty() {
+                            let tag =
+                                heading.map_or("p".to_string(), |level| format!("h{}", level));
+                            html.push_str(&format!("<{0}>{1}</{0}>", tag, paragraph));
+                        }
+                    }
+                    paragraph.clear();
+                }
+                b"w:tc" => html.push_str("</td>"),
+                b"w:tr" => html.push_str("</tr>"),
+                b"w:tbl" => {
+                    html.push_str("</table>");
+                    table_depth -= 1;
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    if in_list {
+        html.push_str("</ul>");
+    }
+    Ok(html)
+}
+
+// OpenDocument keeps bold and italic in named automatic styles, so only structure,
+// links and images are carried over
+fn odt_to_html(xml: &str) -> Result<String, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut html = String::new();
+    // Closing tag for every open element, empty for elements that produce no HTML
+    let mut closers: Vec<String> = Vec::new();
+    let mut text_depth = 0;
+
+    loop {
+        let event = reader.read_event().map_err(|e| e.to_string())?;
+        let is_empty = matches!(event, Event::Empty(_));
+        match event {
+            Event::Start(e) | Event::Empty(e) => {
+                let parent = closers.last().map(String::as_str);
+                let (open, close) = match e.name().as_ref() {
+                    b"text:h" => {
+                        let level = attribute(&e, b"text:outline-level")
+                            .and_then(|l| l.parse::<usize>().ok())
+                            .unwrap_or(1)
+                            .clamp(1, 6);
+                        text_depth += 1;
+                        (format!("<h{}>", level), format!("</h{}>", level))
+                    }
+                    // Paragraphs directly inside list items and cells need no wrapper
+                    b"text:p" if matches!(parent, Some("</li>" | "</td>")) => {
+                        text_depth += 1;
+                        (String::new(), " ".to_string())
+                    }
+                    b"text:p" => {
+                        text_depth += 1;
+                        ("<p>".to_string(), "</p>".to_string())
+                    }
+                    b"text:list" => ("<ul>".to_string(), "</ul>".to_string()),
+                    b"text:list-item" => ("<li>".to_string(), "</li>".to_string()),
+                    b"text:a" => match attribute(&e, b"xlink:href") {
+                        Some(href) => (
+                            format!("<a href=\"{}\">", escape_html(&href)),
+                            "</a>".to_string(),
+                        ),
+                        None => (String::new(), String::new()),
+                    },
+                    b"table:table" => ("<table>".to_string(), "</table>".to_string()),
+                    b"table:table-row" => ("<tr>".to_string(), "</tr>".to_string()),
+                    b"table:table-cell" => ("<td>".to_string(), "</td>".to_string()),
+                    b"text:s" => {
+                        let count = attribute(&e, b"text:c")
+                            .and_then(|c| c.parse::<usize>().ok())
+                            .unwrap_or(1);
+                        (" ".repeat(count), String::new())
+                    }
+                    b"text:tab" => (" ".to_string(), String::new()),
+                    b"text:line-break" => ("<br>".to_string(), String::new()),
+                    b"draw:image" => match attribute(&e, b"xlink:href") {
+                        Some(href) => (
+                            format!("<img src=\"{}\">", escape_html(&href)),
+                            String::new(),
+                        ),
+                        None => (String::new(), String::new()),
+                    },
+                    _ => (String::new(), String::new()),
+                };
+
+                html.push_str(&open);
+                if is_empty {
+                    if matches!(e.name().as_ref(), b"text:p" | b"text:h") {
+                        text_depth -= 1;
+                    }
+                    html.push_str(&close);
+                } else {
+                    closers.push(close);
+                }
+            }
+            Event::Text(text) if text_depth > 0 => {
+                html.push_str(&escape_html(&text.unescape().map_err(|e| e.to_string())?));
+            }
+            Event::End(e) => {
+                if matches!(e.name().as_ref(), b"text:p" | b"text:h") {
+                    text_depth -= 1;
+                }
+                if let Some(close) = closers.pop() {
+                    html.push_str(&close);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(html)
+}
+
+// Convert a .docx or .odt file to markdown, saving the images it embeds into
+// `attachments_dir`. Returns the markdown and the new attachments.
+pub fn convert_document(
+    app_handle: &AppHandle<Wry>,
+    path: &Path,
+    attachments_dir: &Path,
+) -> Result<(String, Vec<String>), String> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut archive = ZipArchive::new(file).map_err(|e| format!("Not a valid document: {}", e))?;
+
+    let html = match extension.as_str() {
+        "docx" => {
+            let relationships = match read_entry(&mut archive, "word/_rels/document.xml.rels") {
+                Ok(xml) => docx_relationships(&xml)?,
+                Err(_) => HashMap::new(),
+            };
+            docx_to_html(
+                &read_entry(&mut archive, "word/document.xml")?,
+                &relationships,
+            )?
+        }
+        "odt" => odt_to_html(&read_entry(&mut archive, "content.xml")?)?,
+        _ => return Err(format!("Unsupported document format: .{}", extension)),
+    };
+
+    let markdown = html_to_markdown(&html);
+
+    // Images still point at their path inside the archive; extract them as attachments
+    let mut extracted: HashMap<String, String> = HashMap::new();
+    let markdown = markdown::rewrite_link_targets(&markdown, |target| {
+        if let Some(name) = extracted.get(target) {
+            return Some(attachment_link(name));
+        }
+
+        let mut entry = archive.by_name(target).ok()?;
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).ok()?;
+
+        let file_name = Path::new(target).file_name()?.to_str()?;
+        let name = unique_attachment_name(attachments_dir, file_name);
+        match compression::write_attachment(app_handle, &attachments_dir.join(&name), &data) {
+            Ok(_) => {
+                extracted.insert(target.to_string(), name.clone());
+                Some(attachment_link(&name))
+            }
+            Err(e) => {
+                println!("Failed to extract document image {}: {}", target, e);
+                None
+            }
+        }
+    });
+
+    Ok((markdown, extracted.into_values().collect()))
+}
@@ -0,0 +1,212 @@
+use crate::proxy::{self, HttpFeature};
+use crate::settings::load_device_settings;
+use crate::{encryption, get_note_path, index, note_from_file, terms};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+use tauri::{AppHandle, Wry};
+
+// Semantic search asks a local embedding server (Ollama or anything speaking the
+// OpenAI embeddings API) for a vector per note and compares notes to the query by
+// meaning rather than shared words. Vectors live in the note index and are only
+// recomputed for notes that changed.
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingApi {
+    // POST {model, prompt} -> {embedding}
+    #[default]
+    Ollama,
+    // POST {model, input} -> {data: [{embedding}]}
+    Openai,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct EmbeddingConfig {
+    pub enabled: bool,
+    pub api: EmbeddingApi,
+    pub url: String,
+    pub model: String,
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        EmbeddingConfig {
+            enabled: false,
+            api: EmbeddingApi::Ollama,
+            url: "http://localhost:11434/api/embeddings".to_string(),
+            model: "nomic-embed-text".to_string(),
+        }
+    }
+}
+
+// Small embedding models only look at the start of long inputs anyway
+const MAX_INPUT_CHARS: usize = 8000;
+const MIN_SCORE: f32 = 0.3;
+
+#[derive(Debug, Serialize)]
+pub struct SemanticMatch {
+    id: String,
+    title: String,
+    score: f32,
+}
+
+#[derive(Deserialize)]
+struct OllamaResponse {
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct OpenaiResponse {
+    data: Vec<OpenaiEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct OpenaiEmbedding {
+    embedding: Vec<f32>,
+}
+
+async fn embed(
+    client: &reqwest::Client,
+    config: &EmbeddingConfig,
+    text: &str,
+) -> Result<Vec<f32>, String> {
+    let text: String = text.chars().take(MAX_INPUT_CHARS).collect();
+    let body = match config.api {
+        EmbeddingApi::Ollama => json!({ "model": config.model, "prompt": text }),
+        EmbeddingApi::Openai => json!({ "model": config.model, "input": text }),
+    };
+    let response = client
+        .post(&config.url)
+        .json(&body)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Embedding server failed: {}", e))?;
+
+    let embedding = match config.api {
+        EmbeddingApi::Ollama => {
+            response
+                .json::<OllamaResponse>()
+                .await
+                .map_err(|e| e.to_string())?
+                .embedding
+        }
+        EmbeddingApi::Openai => {
+            response
+                .json::<OpenaiResponse>()
+                .await
+                .map_err(|e| e.to_string())?
+                .data
+                .into_iter()
+                .next()
+                .ok_or("Embedding server returned no embedding")?
+                .embedding
+        }
+    };
+    if embedding.is_empty() {
+        return Err("Embedding server returned an empty embedding".to_string());
+    }
+    Ok(embedding)
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denominator = norm(a) * norm(b);
+    if denominator > 0.0 {
+        dot / denominator
+    } else {
+        0.0
+    }
+}
+
+// Embed every indexed note that has no vector yet. The index lock isn't held while
+// waiting on the server, so a note edited in the meantime keeps its fresh entry and
+// is embedded on the next search.
+async fn update_embeddings(
+    app_handle: &AppHandle<Wry>,
+    client: &reqwest::Client,
+    config: &EmbeddingConfig,
+) -> Result<(), String> {
+    let pending = index::with_index(app_handle, |index| {
+        if index.embedding_model.as_deref() != Some(config.model.as_str()) {
+            for entry in index.entries.values_mut() {
+                entry.embedding = None;
+            }
+            index.embedding_model = Some(config.model.clone());
+        }
+        index
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.embedding.is_none())
+            .map(|(id, entry)| (id.clone(), entry.modified))
+            .collect::<Vec<_>>()
+    })?;
+
+    for (id, modified) in pending {
+        let Ok(raw) = encryption::read_to_string(get_note_path(app_handle, &id)) else {
+            continue;
+        };
+        let note = note_from_file(&id, &raw, String::new(), Vec::new());
+        let text = format!("{}\n\n{}", note.title, terms::note_text(&note.content));
+        let embedding = embed(client, config, &text).await?;
+
+        index::with_cached_index(app_handle, |index| {
+            if let Some(entry) = index.entries.get_mut(&id) {
+                if entry.modified == modified {
+                    entry.embedding = Some(embedding);
+                }
+            }
+        })?;
+    }
+    Ok(())
+}
+
+// Notes closest in meaning to `query`, best first
+#[tauri::command]
+pub async fn semantic_search(
+    app_handle: AppHandle<Wry>,
+    query: String,
+    limit: Option<usize>,
+) -> Result<Vec<SemanticMatch>, String> {
+    let config = load_device_settings(&app_handle).embeddings;
+    if !config.enabled {
+        return Err("Semantic search is turned off".to_string());
+    }
+    let query = query.trim();
+    if query.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let client = proxy::config_for(&app_handle, HttpFeature::Embeddings)
+        .apply(reqwest::Client::builder())?
+        .timeout(Duration::from_secs(60))
+        .build()
+        .map_err(|e| e.to_string())?;
+    update_embeddings(&app_handle, &client, &config).await?;
+    let target = embed(&client, &config, query).await?;
+
+    let mut matches = index::with_cached_index(&app_handle, |index| {
+        index
+            .entries
+            .iter()
+            .filter_map(|(id, entry)| {
+                let score = cosine(&target, entry.embedding.as_ref()?);
+                (score >= MIN_SCORE).then(|| SemanticMatch {
+                    id: id.clone(),
+                    title: entry.title.clone(),
+                    score,
+                })
+            })
+            .collect::<Vec<_>>()
+    })?;
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(limit.unwrap_or(20));
+
+    Ok(matches)
+}
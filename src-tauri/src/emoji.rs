@@ -0,0 +1,46 @@
+use crate::{load_notes, persist_note, Note};
+use serde_json::Value;
+use tauri::{AppHandle, Wry};
+
+// A note's emoji (or short icon text) lives in its frontmatter, so it is synced and
+// exported along with the rest of the note's metadata.
+
+const EMOJI_FIELD: &str = "emoji";
+
+// Long enough for flags and ZWJ sequences like 👩‍💻, short enough to stay an icon
+const MAX_EMOJI_CHARS: usize = 8;
+
+pub fn emoji_of(note: &Note) -> Option<&str> {
+    note.fields.get(EMOJI_FIELD).and_then(Value::as_str)
+}
+
+#[tauri::command]
+pub async fn set_note_emoji(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    emoji: Option<String>,
+) -> Result<Note, String> {
+    let mut note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    match emoji.as_deref().map(str::trim).filter(|e| !e.is_empty()) {
+        Some(emoji) => {
+            if emoji.chars().count() > MAX_EMOJI_CHARS
+                || emoji.chars().any(|c| c.is_control() || c.is_whitespace())
+            {
+                return Err(format!("Not a valid note icon: {:?}", emoji));
+            }
+            note.fields
+                .insert(EMOJI_FIELD.to_string(), Value::String(emoji.to_string()));
+        }
+        None => {
+            note.fields.remove(EMOJI_FIELD);
+        }
+    }
+
+    persist_note(&app_handle, note.clone()).await?;
+
+    Ok(note)
+}
@@ -0,0 +1,370 @@
+use crate::{atomic, blobs, catalog, events, get_notes_dir, lock, notebooks, storage, translate};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Wry};
+
+// Encryption at rest. Once turned on, note files and attachments are written as
+// AES-256-GCM ciphertext under a random vault key. That key is kept in
+// `notes/.vault-key.json` wrapped by a key derived from the master password with
+// Argon2id, so the directory can be backed up or moved and still be opened with
+// the password alone.
+//
+// An encrypted file starts with `MAGIC`, then the nonce and the ciphertext. Files
+// without it are read as they are, which lets a vault be encrypted in one pass and
+// keeps cold storage archives and files written by older versions readable. While
+// the vault is unlocked the key is held in memory only. Reads of encrypted files and
+// all note writes fail with `LOCKED` until it is unlocked again.
+//
+// Caches that would keep note text outside the notes directory (the catalog and
+// translations) are thrown away when encryption is turned on and not kept while it
+// is; the search index only lives in memory. Attachments written while encrypted
+// aren't deduplicated or compressed, as ciphertext doesn't repeat or shrink.
+
+const KEY_FILE: &str = ".vault-key.json";
+const MAGIC: &[u8] = b"HMJENC1\0";
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+pub const MIN_PASSWORD_LEN: usize = 8;
+pub const LOCKED: &str = "The vault is locked";
+
+// The vault key while unlocked. Readers of attachments don't all have an AppHandle,
+// so it lives here rather than in managed state.
+static VAULT_KEY: Mutex<Option<Key<Aes256Gcm>>> = Mutex::new(None);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyFile {
+    version: u32,
+    // Argon2id parameters the wrapping key was derived with
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    // The vault key, encrypted with the wrapping key
+    nonce: String,
+    wrapped_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EncryptionStatus {
+    enabled: bool,
+    unlocked: bool,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct EncryptionReport {
+    notes: usize,
+    attachments: usize,
+    failed: Vec<String>,
+}
+
+fn key_path(app_handle: &AppHandle<Wry>) -> PathBuf {
+    get_notes_dir(app_handle).join(KEY_FILE)
+}
+
+pub fn is_enabled(app_handle: &AppHandle<Wry>) -> bool {
+    key_path(app_handle).exists()
+}
+
+fn current_key() -> Option<Key<Aes256Gcm>> {
+    VAULT_KEY.lock().ok().and_then(|key| *key)
+}
+
+fn set_key(key: Option<Key<Aes256Gcm>>) {
+    if let Ok(mut current) = VAULT_KEY.lock() {
+        *current = key;
+    }
+}
+
+// Argon2id settings for deriving a key from a password, and a fresh random salt
+pub fn new_kdf() -> (Params, [u8; SALT_LEN]) {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    (Params::default(), salt)
+}
+
+// The AES key for a password under the given Argon2id settings
+pub fn derive_key(
+    password: &str,
+    salt: &[u8],
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+) -> Result<Key<Aes256Gcm>, String> {
+    let params =
+        Params::new(memory_kib, iterations, parallelism, Some(32)).map_err(|e| e.to_string())?;
+    let mut key = Key::<Aes256Gcm>::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+fn derive_wrapping_key(password: &str, key_file: &KeyFile) -> Result<Key<Aes256Gcm>, String> {
+    let salt = BASE64.decode(&key_file.salt).map_err(|e| e.to_string())?;
+    derive_key(
+        password,
+        &salt,
+        key_file.memory_kib,
+        key_file.iterations,
+        key_file.parallelism,
+    )
+}
+
+// A random nonce followed by the ciphertext
+pub fn encrypt_with(key: &Key<Aes256Gcm>, data: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(key)
+        .encrypt(&nonce, data)
+        .map_err(|_| "Encryption failed".to_string())?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+// What `encrypt_with` encrypted, or None for the wrong key or damaged data
+pub fn decrypt_with(key: &Key<Aes256Gcm>, data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    Aes256Gcm::new(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .ok()
+}
+
+fn encrypt(key: &Key<Aes256Gcm>, data: &[u8]) -> Result<Vec<u8>, String> {
+    Ok([MAGIC, &encrypt_with(key, data)?].concat())
+}
+
+fn is_sealed(data: &[u8]) -> bool {
+    data.len() >= MAGIC.len() + NONCE_LEN && data.starts_with(MAGIC)
+}
+
+// Contents of a file as read from disk, decrypted when it is encrypted
+pub fn open(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !is_sealed(&data) {
+        return Ok(data);
+    }
+    let key =
+        current_key().ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, LOCKED))?;
+    decrypt_with(&key, &data[MAGIC.len()..])
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "File failed to decrypt"))
+}
+
+// Whether a file on disk is encrypted, going by its first bytes
+pub fn is_sealed_file(path: impl AsRef<Path>) -> io::Result<bool> {
+    let mut head = Vec::new();
+    fs::File::open(path)?
+        .take((MAGIC.len() + NONCE_LEN) as u64)
+        .read_to_end(&mut head)?;
+    Ok(is_sealed(&head))
+}
+
+// `fs::read` for files that may be encrypted
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    open(fs::read(path)?)
+}
+
+// `fs::read_to_string` for files that may be encrypted
+pub fn read_to_string(path: impl AsRef<Path>) -> io::Result<String> {
+    String::from_utf8(open(fs::read(path)?)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Contents to write to disk: encrypted when the vault is
+pub fn seal(app_handle: &AppHandle<Wry>, data: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_enabled(app_handle) {
+        return Ok(data.to_vec());
+    }
+    let key = current_key().ok_or(LOCKED)?;
+    encrypt(&key, data)
+}
+
+// `atomic::write` for files that are encrypted along with the vault
+pub fn write(
+    app_handle: &AppHandle<Wry>,
+    path: impl AsRef<Path>,
+    contents: impl AsRef<[u8]>,
+) -> Result<(), String> {
+    let sealed = seal(app_handle, contents.as_ref())?;
+    atomic::write(path, sealed).map_err(|e| e.to_string())
+}
+
+// Encrypt a file in place, unless it already is
+fn seal_file(key: &Key<Aes256Gcm>, path: &Path) -> Result<bool, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    if is_sealed(&data) {
+        return Ok(false);
+    }
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+    // Replaced rather than written through, as attachments may be shared
+    atomic::write(path, encrypt(key, &data)?).map_err(|e| e.to_string())?;
+    if let Some(modified) = modified {
+        storage::set_modified(path, modified);
+    }
+    Ok(true)
+}
+
+// Encrypt the notes, the trash, cold storage and every attachment that isn't yet
+fn seal_vault(app_handle: &AppHandle<Wry>, key: &Key<Aes256Gcm>) -> EncryptionReport {
+    let mut report = EncryptionReport::default();
+    let notes_dir = get_notes_dir(app_handle);
+    let mut note_files = notebooks::note_files(&notes_dir);
+    if let Ok(entries) = fs::read_dir(notes_dir.join(".trash")) {
+        note_files.extend(
+            entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("md")),
+        );
+    }
+    // A cold storage archive holds one note with its attachments
+    if let Ok(entries) = fs::read_dir(notes_dir.join(".cold")) {
+        note_files.extend(
+            entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("zip")),
+        );
+    }
+    for path in note_files {
+        match seal_file(key, &path) {
+            Ok(true) => report.notes += 1,
+            Ok(false) => {}
+            Err(e) => report.failed.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    for root in [
+        notes_dir.join("attachments"),
+        notes_dir.join(".trash").join("attachments"),
+    ] {
+        let Ok(dirs) = fs::read_dir(&root) else {
+            continue;
+        };
+        for dir in dirs.flatten().map(|d| d.path()).filter(|d| d.is_dir()) {
+            let Ok(files) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for path in files.flatten().map(|f| f.path()).filter(|p| p.is_file()) {
+                // Encrypted copies can't be shared, so they leave the store
+                blobs::unshare_file(app_handle, &path);
+                match seal_file(key, &path) {
+                    Ok(true) => report.attachments += 1,
+                    Ok(false) => {}
+                    Err(e) => report.failed.push(format!("{}: {}", path.display(), e)),
+                }
+            }
+        }
+    }
+    report
+}
+
+// Turn on encryption with a new master password and encrypt the vault as it is
+#[tauri::command]
+pub async fn enable_vault_encryption(
+    app_handle: AppHandle<Wry>,
+    password: String,
+) -> Result<EncryptionReport, String> {
+    lock::ensure_writable(&app_handle)?;
+    if is_enabled(&app_handle) {
+        return Err("The vault is already encrypted".to_string());
+    }
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(format!(
+            "The master password needs at least {} characters",
+            MIN_PASSWORD_LEN
+        ));
+    }
+
+    let (params, salt) = new_kdf();
+    let mut key_file = KeyFile {
+        version: 1,
+        salt: BASE64.encode(salt),
+        memory_kib: params.m_cost(),
+        iterations: params.t_cost(),
+        parallelism: params.p_cost(),
+        nonce: String::new(),
+        wrapped_key: String::new(),
+    };
+    let vault_key = Aes256Gcm::generate_key(&mut OsRng);
+    let wrapping_key = derive_wrapping_key(&password, &key_file)?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let wrapped = Aes256Gcm::new(&wrapping_key)
+        .encrypt(&nonce, vault_key.as_slice())
+        .map_err(|_| "Encryption failed".to_string())?;
+    key_file.nonce = BASE64.encode(nonce);
+    key_file.wrapped_key = BASE64.encode(wrapped);
+
+    let raw = serde_json::to_string_pretty(&key_file).map_err(|e| e.to_string())?;
+    // Losing the key file loses the vault, so it is never left half written
+    atomic::write_private(key_path(&app_handle), raw).map_err(|e| e.to_string())?;
+    set_key(Some(vault_key));
+    if let Err(e) = catalog::discard(&app_handle) {
+        println!("Failed to delete the note catalog: {}", e);
+    }
+    translate::clear_cache(&app_handle);
+
+    let report = tokio::task::spawn_blocking({
+        let app_handle = app_handle.clone();
+        move || seal_vault(&app_handle, &vault_key)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    println!(
+        "Encrypted {} notes and {} attachments",
+        report.notes, report.attachments
+    );
+    Ok(report)
+}
+
+#[tauri::command]
+pub async fn unlock_vault(app_handle: AppHandle<Wry>, password: String) -> Result<(), String> {
+    let raw = fs::read_to_string(key_path(&app_handle))
+        .map_err(|_| "The vault isn't encrypted".to_string())?;
+    let key_file: KeyFile = serde_json::from_str(&raw).map_err(|e| e.to_string())?;
+
+    let vault_key = tokio::task::spawn_blocking(move || {
+        let wrapping_key = derive_wrapping_key(&password, &key_file)?;
+        let nonce = BASE64.decode(&key_file.nonce).map_err(|e| e.to_string())?;
+        let wrapped = BASE64
+            .decode(&key_file.wrapped_key)
+            .map_err(|e| e.to_string())?;
+        if nonce.len() != NONCE_LEN {
+            return Err("Damaged vault key file".to_string());
+        }
+        let key = Aes256Gcm::new(&wrapping_key)
+            .decrypt(Nonce::from_slice(&nonce), wrapped.as_slice())
+            .map_err(|_| "Wrong password".to_string())?;
+        if key.len() != 32 {
+            return Err("Damaged vault key file".to_string());
+        }
+        Ok(*Key::<Aes256Gcm>::from_slice(&key))
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    set_key(Some(vault_key));
+    events::notes_reloaded(&app_handle)
+}
+
+// Forget the key. Notes can't be read or saved until the vault is unlocked again.
+#[tauri::command]
+pub async fn lock_vault(app_handle: AppHandle<Wry>) -> Result<(), String> {
+    set_key(None);
+    events::notes_reloaded(&app_handle)
+}
+
+#[tauri::command]
+pub async fn get_encryption_status(app_handle: AppHandle<Wry>) -> Result<EncryptionStatus, String> {
+    Ok(EncryptionStatus {
+        enabled: is_enabled(&app_handle),
+        unlocked: current_key().is_some(),
+    })
+}
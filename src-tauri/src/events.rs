@@ -0,0 +1,169 @@
+use crate::changes::{self, NoteChange};
+use crate::{annotate_metadata, get_note_path, read_note, Note, NoteMetadata, PeerDevice};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Wry};
+
+// Events that tell the frontend its lists are out of date. Each carries what
+// changed, so the frontend can update just that instead of fetching everything
+// again. `sync-notification` carries the new `SyncNotification` itself.
+//
+// Notes published as created or updated on the change bus are announced with
+// `note-created` or `note-updated` and their metadata; deleted ones with
+// `note-deleted`. `notes-updated` is left for changes that aren't about
+// particular notes (ordering, pins, tag colors, another notes directory), after
+// which everything should be fetched again. `sync-pause-changed` carries whether
+// sync is now paused by hand. `index-maintenance` reports how far `rebuild_index`
+// or `compact_index` has got.
+
+pub const NOTE_CREATED: &str = "note-created";
+pub const NOTE_UPDATED: &str = "note-updated";
+pub const NOTE_DELETED: &str = "note-deleted";
+pub const NOTES_UPDATED: &str = "notes-updated";
+pub const SYNC_NOTIFICATION: &str = "sync-notification";
+pub const PEERS_UPDATED: &str = "peers-updated";
+pub const INDEX_PROGRESS: &str = "index-progress";
+pub const OPEN_TARGETS: &str = "open-targets";
+pub const SYNC_PAUSE_CHANGED: &str = "sync-pause-changed";
+pub const INDEX_MAINTENANCE: &str = "index-maintenance";
+
+// Notes read between `index-progress` events
+pub const PROGRESS_BATCH: usize = 250;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct NoteDeleted {
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct PeersUpdated {
+    // Peers found, or whose addresses or name changed
+    pub updated: Vec<PeerDevice>,
+    // Ids of peers that went away
+    pub removed: Vec<String>,
+}
+
+// How far a scan of the notes directory has got. Vaults small enough to read in
+// one batch report nothing.
+#[derive(Debug, Serialize, Clone)]
+pub struct IndexProgress {
+    // `notes` while `get_notes` reads the vault, `index` while the note index is built
+    pub phase: &'static str,
+    pub scanned: usize,
+    pub total: usize,
+    // Notes read since the last event, so the list can fill in before the scan ends.
+    // Unsorted; only sent while reading notes.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<Note>,
+}
+
+// How far an index maintenance command has got
+#[derive(Debug, Serialize, Clone)]
+pub struct MaintenanceProgress {
+    // `rebuild` or `compact`
+    pub operation: &'static str,
+    // Notes read while rebuilding, steps done while compacting
+    pub done: usize,
+    pub total: usize,
+}
+
+impl IndexProgress {
+    pub fn notes(scanned: usize, total: usize) -> Self {
+        IndexProgress {
+            phase: "notes",
+            scanned,
+            total,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn index(scanned: usize, total: usize) -> Self {
+        IndexProgress {
+            phase: "index",
+            ..IndexProgress::notes(scanned, total)
+        }
+    }
+
+    pub fn with_notes(self, notes: &[Note]) -> Self {
+        IndexProgress {
+            notes: notes.to_vec(),
+            ..self
+        }
+    }
+}
+
+// Announce a note as it is now on disk
+fn note_saved(app_handle: &AppHandle<Wry>, note_id: &str, created: bool) -> Result<(), String> {
+    let note = read_note(app_handle, note_id, &get_note_path(app_handle, note_id))?;
+    let mut metadata = [NoteMetadata::from(&note)];
+    annotate_metadata(app_handle, &mut metadata);
+    let [metadata] = metadata;
+
+    let event = if created { NOTE_CREATED } else { NOTE_UPDATED };
+    app_handle.emit(event, metadata).map_err(|e| e.to_string())
+}
+
+fn note_deleted(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<(), String> {
+    app_handle
+        .emit(
+            NOTE_DELETED,
+            NoteDeleted {
+                id: note_id.to_string(),
+            },
+        )
+        .map_err(|e| e.to_string())
+}
+
+// Pass note changes on to the frontend
+pub fn spawn_forwarder(app_handle: &AppHandle<Wry>) {
+    changes::subscribe(app_handle, "Frontend events", |app_handle, change| {
+        let result = match &change {
+            NoteChange::Created(note) => note_saved(app_handle, &note.id, true),
+            NoteChange::Updated(note) => note_saved(app_handle, &note.id, false),
+            NoteChange::Deleted(id) => note_deleted(app_handle, id),
+            NoteChange::Shared { .. } => Ok(()),
+        };
+        if let Err(e) = result {
+            println!(
+                "Failed to announce change of note {}: {}",
+                change.note_id(),
+                e
+            );
+        }
+    });
+}
+
+// Everything about the notes list should be fetched again
+pub fn notes_reloaded(app_handle: &AppHandle<Wry>) -> Result<(), String> {
+    app_handle
+        .emit(NOTES_UPDATED, ())
+        .map_err(|e| e.to_string())
+}
+
+pub fn peers_updated(app_handle: &AppHandle<Wry>, update: PeersUpdated) -> Result<(), String> {
+    app_handle
+        .emit(PEERS_UPDATED, update)
+        .map_err(|e| e.to_string())
+}
+
+// Progress is only informational, so failing to send it isn't an error
+pub fn index_progress(app_handle: &AppHandle<Wry>, progress: IndexProgress) {
+    if let Err(e) = app_handle.emit(INDEX_PROGRESS, progress) {
+        println!("Failed to send index progress: {}", e);
+    }
+}
+
+pub fn maintenance_progress(
+    app_handle: &AppHandle<Wry>,
+    operation: &'static str,
+    done: usize,
+    total: usize,
+) {
+    let progress = MaintenanceProgress {
+        operation,
+        done,
+        total,
+    };
+    if let Err(e) = app_handle.emit(INDEX_MAINTENANCE, progress) {
+        println!("Failed to send index maintenance progress: {}", e);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::changes::{self, NoteChange};
+use crate::{blobs, get_note_path, get_notes_dir, load_notes, persist_note, storage, trash, Note};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Wry};
+
+// Notes can carry an expiry date in their frontmatter. Once it passes the note is
+// moved to the trash, or wiped from disk when it is marked sensitive:
+//
+// expires: 2026-11-01T09:00:00Z
+// expiry_action: wipe
+
+const EXPIRES_FIELD: &str = "expires";
+const ACTION_FIELD: &str = "expiry_action";
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// How many hours before expiry the frontend gets a `note-expiring` warning
+const WARNING_HOURS: i64 = 24;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ExpiryWarning {
+    note_id: String,
+    title: String,
+    expires_at: String,
+    wipe: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct NoteExpired {
+    note_id: String,
+    title: String,
+    wiped: bool,
+}
+
+// Accept a full RFC 3339 timestamp, or a plain date meaning the start of that day (UTC)
+fn parse_expiry(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+        return Some(at.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|at| at.and_utc())
+}
+
+fn expires_at(note: &Note) -> Option<DateTime<Utc>> {
+    note.fields
+        .get(EXPIRES_FIELD)
+        .and_then(Value::as_str)
+        .and_then(parse_expiry)
+}
+
+fn wipes(note: &Note) -> bool {
+    note.fields.get(ACTION_FIELD).and_then(Value::as_str) == Some("wipe")
+}
+
+// Overwrite a file with zeros before removing it, so a sensitive note doesn't linger
+// in free blocks. Best effort: copy-on-write and journaling file systems may still
+// keep old data around.
+fn shred(path: &Path) -> Result<(), String> {
+    let len = fs::metadata(path).map_err(|e| e.to_string())?.len();
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    let zeros = vec![0u8; 64 * 1024];
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..chunk]).map_err(|e| e.to_string())?;
+        remaining -= chunk as u64;
+    }
+    file.sync_all().map_err(|e| e.to_string())?;
+    drop(file);
+    fs::remove_file(path).map_err(|e| e.to_string())
+}
+
+fn wipe_note(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<(), String> {
+    storage::restore_note(app_handle, note_id)?;
+    storage::delete_archived(app_handle, note_id)?;
+
+    let attachments_dir = get_notes_dir(app_handle).join("attachments").join(note_id);
+    if attachments_dir.exists() {
+        // Contents other notes share can't be overwritten; they aren't this note's alone
+        let shared = blobs::release(app_handle, note_id);
+        for entry in fs::read_dir(&attachments_dir).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            if shared.contains(entry.file_name().to_string_lossy().as_ref()) {
+                fs::remove_file(&path).map_err(|e| e.to_string())?;
+            } else {
+                shred(&path)?;
+            }
+        }
+        fs::remove_dir_all(&attachments_dir).map_err(|e| e.to_string())?;
+    }
+
+    let note_path = get_note_path(app_handle, note_id);
+    if note_path.exists() {
+        shred(&note_path)?;
+    }
+    changes::publish(app_handle, NoteChange::Deleted(note_id.to_string()));
+    Ok(())
+}
+
+// Warn about notes expiring soon and remove the ones whose time has come.
+// `warned` remembers which notes were already warned about. Removed notes are
+// announced by `wipe_note` and `move_to_trash`.
+fn sweep(app_handle: &AppHandle<Wry>, warned: &mut HashSet<String>) -> Result<(), String> {
+    let now = Utc::now();
+
+    for note in load_notes(app_handle)? {
+        let Some(at) = expires_at(&note) else {
+            continue;
+        };
+        let wipe = wipes(&note);
+
+        if at <= now {
+            let result = if wipe {
+                wipe_note(app_handle, &note.id)
+            } else {
+                trash::move_to_trash(app_handle, &note.id)
+            };
+            match result {
+                Ok(()) => {
+                    println!("Note {} expired", note.id);
+                    warned.remove(&note.id);
+                    let _ = app_handle.emit(
+                        "note-expired",
+                        NoteExpired {
+                            note_id: note.id.clone(),
+                            title: note.title.clone(),
+                            wiped: wipe,
+                        },
+                    );
+                }
+                Err(e) => println!("Failed to expire note {}: {}", note.id, e),
+            }
+        } else if at - now <= chrono::Duration::hours(WARNING_HOURS)
+            && warned.insert(note.id.clone())
+        {
+            let _ = app_handle.emit(
+                "note-expiring",
+                ExpiryWarning {
+                    note_id: note.id.clone(),
+                    title: note.title.clone(),
+                    expires_at: at.to_rfc3339(),
+                    wipe,
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub fn spawn_expiry_scheduler(app_handle: AppHandle<Wry>) {
+    tauri::async_runtime::spawn(async move {
+        let warned = Arc::new(Mutex::new(HashSet::new()));
+        loop {
+            let handle = app_handle.clone();
+            let warned = warned.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let mut warned = warned.lock().map_err(|e| e.to_string())?;
+                sweep(&handle, &mut warned)
+            })
+            .await;
+            if let Ok(Err(e)) = result {
+                println!("Expiry check failed: {}", e);
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+// Set or clear a note's expiry. `wipe` removes the note for good instead of moving
+// it to the trash.
+#[tauri::command]
+pub async fn set_note_expiry(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    expires_at: Option<String>,
+    wipe: Option<bool>,
+) -> Result<Note, String> {
+    let mut note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    match expires_at
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+    {
+        Some(value) => {
+            let at =
+                parse_expiry(value).ok_or_else(|| format!("Invalid expiry date: {}", value))?;
+            if at <= Utc::now() {
+                return Err("Expiry date must be in the future".to_string());
+            }
+            note.fields.insert(
+                EXPIRES_FIELD.to_string(),
+                Value::String(at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+            );
+            if wipe.unwrap_or(false) {
+                note.fields
+                    .insert(ACTION_FIELD.to_string(), Value::String("wipe".to_string()));
+            } else {
+                note.fields.remove(ACTION_FIELD);
+            }
+        }
+        None => {
+            note.fields.remove(EXPIRES_FIELD);
+            note.fields.remove(ACTION_FIELD);
+        }
+    }
+
+    persist_note(&app_handle, note.clone()).await?;
+
+    Ok(note)
+}
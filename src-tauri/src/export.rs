@@ -0,0 +1,154 @@
+use crate::compression;
+use crate::markdown::{self, ATTACHMENT_SCHEME};
+use crate::render::render_note;
+use crate::{load_notes, render_note_file, Note};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Wry};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+// The attachment a markdown link target points at, if any. Notes reference
+// attachments as `attachment://name.png` or by their bare file name.
+pub fn referenced_attachment<'a>(target: &str, attachments: &'a [String]) -> Option<&'a String> {
+    let name = target.strip_prefix(ATTACHMENT_SCHEME).unwrap_or(target);
+    let decoded = name.replace("%20", " ");
+    attachments.iter().find(|a| *a == name || **a == decoded)
+}
+
+// Rewrite links that point at one of the note's attachments. `rewrite` receives the
+// attachment name and returns the new target; returning None leaves the link untouched.
+pub fn rewrite_attachment_links(
+    content: &str,
+    attachments: &[String],
+    rewrite: impl Fn(&str) -> Option<String>,
+) -> String {
+    markdown::rewrite_link_targets(content, |target| {
+        referenced_attachment(target, attachments).and_then(|name| rewrite(name))
+    })
+}
+
+// Inline each attachment as a data URI so the markdown needs nothing else
+fn embed_attachments(app_handle: &AppHandle<Wry>, note: &Note, content: &str) -> String {
+    rewrite_attachment_links(content, &note.attachments, |name| {
+        let data = compression::read_note_attachment(app_handle, &note.id, name).ok()?;
+        let mime = mime_guess::from_path(name).first_or_octet_stream();
+        Some(format!("data:{};base64,{}", mime, STANDARD.encode(data)))
+    })
+}
+
+// Export a single note as a self-contained markdown file
+#[tauri::command]
+pub async fn export_note_markdown(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    output_path: String,
+) -> Result<(), String> {
+    let mut note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    let rendered = render_note(&app_handle, note.clone()).await?;
+    note.content = embed_attachments(&app_handle, &note, &rendered);
+
+    fs::write(PathBuf::from(output_path), render_note_file(&note)).map_err(|e| e.to_string())
+}
+
+// A file name derived from the note title that is safe on every platform
+pub fn safe_file_name(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(100)
+        .collect();
+
+    let name = name.trim();
+    if name.is_empty() {
+        "note".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+// Write `files` (relative path, contents) either into a directory or, when `path`
+// ends in `.zip`, into a zip archive
+pub fn write_bundle(path: &Path, files: &[(String, Vec<u8>)]) -> Result<(), String> {
+    if path.extension().and_then(|e| e.to_str()) == Some("zip") {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut zip = ZipWriter::new(file);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for (name, data) in files {
+            zip.start_file(name.as_str(), options)
+                .map_err(|e| e.to_string())?;
+            zip.write_all(data).map_err(|e| e.to_string())?;
+        }
+
+        zip.finish().map_err(|e| e.to_string())?;
+    } else {
+        for (name, data) in files {
+            let dest = path.join(name);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::write(dest, data).map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+// The markdown file plus `attachments/` for one note, with links pointing into the bundle
+pub fn note_bundle_files(
+    app_handle: &AppHandle<Wry>,
+    note: &Note,
+    content: &str,
+    prefix: &str,
+) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut files = Vec::new();
+
+    for name in &note.attachments {
+        match compression::read_note_attachment(app_handle, &note.id, name) {
+            Ok(data) => files.push((format!("{}attachments/{}", prefix, name), data)),
+            Err(e) => println!("Skipping missing attachment {}: {}", name, e),
+        }
+    }
+
+    let mut exported = note.clone();
+    exported.content = rewrite_attachment_links(content, &note.attachments, |name| {
+        Some(format!("attachments/{}", name.replace(' ', "%20")))
+    });
+
+    files.push((
+        format!("{}{}.md", prefix, safe_file_name(&note.title)),
+        render_note_file(&exported).into_bytes(),
+    ));
+
+    Ok(files)
+}
+
+// Export a note as a folder (or `.zip`) holding the markdown and its attachments
+#[tauri::command]
+pub async fn export_note_bundle(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    path: String,
+) -> Result<(), String> {
+    let note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    let rendered = render_note(&app_handle, note.clone()).await?;
+    let files = note_bundle_files(&app_handle, &note, &rendered, "")?;
+    write_bundle(Path::new(&path), &files)
+}
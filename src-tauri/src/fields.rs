@@ -0,0 +1,163 @@
+use crate::settings::{load_vault_settings, save_vault_settings};
+use crate::{load_notes, persist_note, Note};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use tauri::{AppHandle, Wry};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    Text,
+    Number,
+    Boolean,
+    // ISO 8601 calendar date, e.g. 2024-05-01
+    Date,
+    List,
+    // One of `options`
+    Select,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FieldDefinition {
+    pub name: String,
+    pub field_type: FieldType,
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+fn is_valid_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn validate_definitions(definitions: &[FieldDefinition]) -> Result<(), String> {
+    let mut seen = HashSet::new();
+
+    for definition in definitions {
+        if !is_valid_field_name(&definition.name) {
+            return Err(format!("Invalid field name: {:?}", definition.name));
+        }
+        if !seen.insert(definition.name.as_str()) {
+            return Err(format!("Duplicate field definition: {}", definition.name));
+        }
+        if definition.field_type == FieldType::Select && definition.options.is_empty() {
+            return Err(format!(
+                "Select field {} needs at least one option",
+                definition.name
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_value(definition: &FieldDefinition, value: &Value) -> Result<(), String> {
+    let valid = match definition.field_type {
+        FieldType::Text => value.is_string(),
+        FieldType::Number => value.is_number(),
+        FieldType::Boolean => value.is_boolean(),
+        FieldType::Date => value
+            .as_str()
+            .map(|s| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok())
+            .unwrap_or(false),
+        FieldType::List => value
+            .as_array()
+            .map(|items| items.iter().all(Value::is_string))
+            .unwrap_or(false),
+        FieldType::Select => value
+            .as_str()
+            .map(|s| definition.options.iter().any(|option| option == s))
+            .unwrap_or(false),
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Value {} is not a valid {:?} for field {}",
+            value, definition.field_type, definition.name
+        ))
+    }
+}
+
+// Whether a note's field matches `expected`. List fields match if any item does.
+fn field_matches(actual: &Value, expected: &Value) -> bool {
+    match actual {
+        Value::Array(items) if !expected.is_array() => items.contains(expected),
+        _ => actual == expected,
+    }
+}
+
+#[tauri::command]
+pub async fn get_field_definitions(
+    app_handle: AppHandle<Wry>,
+) -> Result<Vec<FieldDefinition>, String> {
+    Ok(load_vault_settings(&app_handle).field_definitions)
+}
+
+#[tauri::command]
+pub async fn set_field_definitions(
+    app_handle: AppHandle<Wry>,
+    definitions: Vec<FieldDefinition>,
+) -> Result<(), String> {
+    validate_definitions(&definitions)?;
+
+    let mut settings = load_vault_settings(&app_handle);
+    settings.field_definitions = definitions;
+    save_vault_settings(&app_handle, &settings)
+}
+
+// Set (or with `value: null`, remove) a metadata field on a note
+#[tauri::command]
+pub async fn set_note_field(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    name: String,
+    value: Option<Value>,
+) -> Result<Note, String> {
+    if !is_valid_field_name(&name) {
+        return Err(format!("Invalid field name: {:?}", name));
+    }
+
+    let mut note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    match value {
+        Some(value) if !value.is_null() => {
+            let definitions = load_vault_settings(&app_handle).field_definitions;
+            if let Some(definition) = definitions.iter().find(|d| d.name == name) {
+                validate_value(definition, &value)?;
+            }
+            note.fields.insert(name, value);
+        }
+        _ => {
+            note.fields.remove(&name);
+        }
+    }
+
+    persist_note(&app_handle, note.clone()).await?;
+
+    Ok(note)
+}
+
+// Notes that have the field set, optionally restricted to a specific value
+#[tauri::command]
+pub async fn get_notes_by_field(
+    app_handle: AppHandle<Wry>,
+    name: String,
+    value: Option<Value>,
+) -> Result<Vec<Note>, String> {
+    Ok(load_notes(&app_handle)?
+        .into_iter()
+        .filter(|note| match (note.fields.get(&name), &value) {
+            (Some(actual), Some(expected)) => field_matches(actual, expected),
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+        .collect())
+}
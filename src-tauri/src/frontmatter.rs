@@ -0,0 +1,179 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+// Metadata stored in a YAML-style block at the top of a note file:
+//
+// ---
+// status: in-review
+// tags: [work, planning]
+// ---
+//
+// Only the subset of YAML we write ourselves is understood: `key: value` lines with
+// scalar, inline `[a, b]` or block `- item` list values.
+pub type Frontmatter = BTreeMap<String, Value>;
+
+const DELIMITER: &str = "---";
+
+// Split a note file into its frontmatter and the markdown that follows it
+pub fn split(raw: &str) -> (Frontmatter, &str) {
+    let mut fields = Frontmatter::new();
+
+    let Some(rest) = raw
+        .strip_prefix("---\n")
+        .or_else(|| raw.strip_prefix("---\r\n"))
+    else {
+        return (fields, raw);
+    };
+
+    // Find the closing delimiter line
+    let mut offset = 0;
+    let mut bounds = None;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == DELIMITER {
+            bounds = Some((offset, offset + line.len()));
+            break;
+        }
+        offset += line.len();
+    }
+    let Some((block_end, body_start)) = bounds else {
+        return (fields, raw);
+    };
+
+    let mut current_list: Option<String> = None;
+    for line in rest[..block_end].lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        // Items of a block list belong to the most recent key without a value
+        if let Some(item) = trimmed.strip_prefix('-') {
+            if let Some(Value::Array(items)) = current_list.as_ref().and_then(|k| fields.get_mut(k))
+            {
+                items.push(parse_value(item.trim()));
+            }
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().to_string();
+        let value = value.trim();
+
+        if value.is_empty() {
+            fields.insert(key.clone(), Value::Array(Vec::new()));
+            current_list = Some(key);
+        } else {
+            fields.insert(key, parse_value(value));
+            current_list = None;
+        }
+    }
+
+    // A key that never received list items had an empty value
+    if let Some(key) = current_list {
+        if fields.get(&key) == Some(&Value::Array(Vec::new())) {
+            fields.insert(key, Value::Null);
+        }
+    }
+
+    (fields, &rest[body_start..])
+}
+
+fn parse_value(value: &str) -> Value {
+    let value = value.trim();
+
+    if value.starts_with('"') {
+        return serde_json::from_str::<String>(value)
+            .map(Value::String)
+            .unwrap_or_else(|_| Value::String(value.trim_matches('"').to_string()));
+    }
+
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        return Value::String(value[1..value.len() - 1].replace("''", "'"));
+    }
+
+    if value.starts_with('[') && value.ends_with(']') {
+        if let Ok(parsed @ Value::Array(_)) = serde_json::from_str::<Value>(value) {
+            return parsed;
+        }
+        let inner = &value[1..value.len() - 1];
+        return Value::Array(
+            inner
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(parse_value)
+                .collect(),
+        );
+    }
+
+    if value.starts_with('{') {
+        if let Ok(parsed) = serde_json::from_str::<Value>(value) {
+            return parsed;
+        }
+    }
+
+    match value {
+        "" | "~" | "null" => return Value::Null,
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+
+    if let Ok(number) = value.parse::<i64>() {
+        return Value::from(number);
+    }
+    if let Some(number) = value
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+    {
+        return Value::Number(number);
+    }
+
+    Value::String(value.to_string())
+}
+
+// Whether a string can be written without quotes and still read back as the same string
+fn is_plain(value: &str) -> bool {
+    !value.is_empty()
+        && value.trim() == value
+        && !value.contains(['"', '\'', ',', '[', ']', '{', '}', '#', '\n', '\r'])
+        && !value.contains(": ")
+        && !value.starts_with(['-', '&', '*', '!', '|', '>', '%', '@', '`'])
+        && matches!(parse_value(value), Value::String(_))
+}
+
+fn render_value(value: &Value) -> String {
+    match value {
+        Value::String(s) if is_plain(s) => s.clone(),
+        Value::Array(items) => format!(
+            "[{}]",
+            items
+                .iter()
+                .map(render_value)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        // Quoted strings, numbers, booleans and maps share JSON's syntax
+        other => other.to_string(),
+    }
+}
+
+// Render fields as a frontmatter block, or nothing when there are none
+pub fn render(fields: &Frontmatter) -> String {
+    if fields.is_empty() {
+        return String::new();
+    }
+
+    let mut block = String::from("---\n");
+    for (key, value) in fields {
+        block.push_str(key);
+        block.push_str(": ");
+        block.push_str(&render_value(value));
+        block.push('\n');
+    }
+    block.push_str("---\n");
+    block
+}
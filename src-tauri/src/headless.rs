@@ -0,0 +1,161 @@
+use crate::ordering::folder_of;
+use crate::server::{self, BoxFuture, EventSink, Received, SyncHost, SyncStore};
+use crate::settings::device_settings_in;
+use crate::{
+    atomic, identity, lock, network, notebooks, overrides, pairing, render_note_file, tls,
+    AppState, SyncRequest,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+// `--headless-sync` runs the sync server and mDNS discovery without a window, so
+// sync can be tested end to end in CI or between local instances. Incoming notes
+// are saved straight into `<data dir>/notes` without asking, and every event the
+// app would send its frontend is printed to stdout as one line of JSON.
+//
+// Two instances on one machine need their own data directory and port, e.g.
+// `--headless-sync --data-dir /tmp/a --port 9000`.
+//
+// As in the app, notes are only taken from devices paired in the data directory's
+// `trusted-peers.json`, or from anyone when its `settings.json` has
+// `allow_unpaired_sync` on.
+
+// Prints events for a test harness to read
+pub struct StdoutEvents;
+
+impl EventSink for StdoutEvents {
+    fn emit(&self, event: &str, payload: Value) {
+        println!("{}", json!({ "event": event, "payload": payload }));
+    }
+}
+
+// Keeps incoming notes as plain files in a directory
+struct DirectoryStore {
+    data_dir: PathBuf,
+    notes_dir: PathBuf,
+}
+
+impl DirectoryStore {
+    fn save(&self, request: &SyncRequest) -> Result<(), String> {
+        let note = &request.note;
+        // Filed in its notebook as the app would, replacing the copy of a note that
+        // has moved notebooks since
+        let dir = folder_of(&note.fields)
+            .and_then(|name| notebooks::notebook_dir(&self.notes_dir, name))
+            .unwrap_or_else(|| self.notes_dir.clone());
+        let path = dir.join(format!("{}.md", note.id));
+        let previous = notebooks::find_note_file(&self.notes_dir, &note.id);
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        atomic::write(&path, render_note_file(note)).map_err(|e| e.to_string())?;
+        if let Some(previous) = previous.filter(|previous| *previous != path) {
+            let _ = fs::remove_file(previous);
+        }
+
+        let attachments_dir = self.notes_dir.join("attachments").join(&note.id);
+        fs::create_dir_all(&attachments_dir).map_err(|e| e.to_string())?;
+        for (file_name, file_data) in &request.attachments_data {
+            let name = Path::new(file_name)
+                .file_name()
+                .ok_or_else(|| format!("Not a file name: {}", file_name))?;
+            atomic::write(attachments_dir.join(name), file_data).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+impl SyncStore for DirectoryStore {
+    fn receive<'a>(&'a self, request: &'a SyncRequest) -> BoxFuture<'a, Received> {
+        Box::pin(async move {
+            match self.save(request) {
+                Ok(()) => Received::Saved,
+                Err(e) => {
+                    println!("Failed to save note {}: {}", request.note.id, e);
+                    Received::Failed
+                }
+            }
+        })
+    }
+
+    fn confirm_sent(&self, peer_id: &str, note_id: &str) {
+        println!("Peer {} accepted note {}", peer_id, note_id);
+    }
+
+    fn is_trusted(&self, peer_id: &str, token: Option<&str>) -> bool {
+        device_settings_in(&self.data_dir).allow_unpaired_sync
+            || pairing::is_paired(&self.data_dir, peer_id, token)
+    }
+}
+
+// Run the sync stack until interrupted
+pub fn run() {
+    let overrides = overrides::get();
+    let data_dir = overrides
+        .data_dir
+        .clone()
+        .unwrap_or_else(|| std::env::temp_dir().join("himoji-headless"));
+    let notes_dir = data_dir.join("notes");
+    if let Err(e) = fs::create_dir_all(&notes_dir) {
+        println!("Failed to create notes directory {:?}: {}", notes_dir, e);
+        return;
+    }
+    // Held until the service stops; the app opened on the same notes goes read-only
+    let vault = lock::acquire(&notes_dir, "headless sync");
+    if let Some(holder) = vault.holder() {
+        println!(
+            "Notes in {:?} are in use by {}, not starting",
+            notes_dir, holder
+        );
+        return;
+    }
+
+    let config = network::ServerConfig {
+        device_name: overrides
+            .device_name
+            .clone()
+            .unwrap_or_else(network::hostname),
+        first_port: overrides.port.unwrap_or(network::DEFAULT_PORT),
+        interfaces: Vec::new(),
+    };
+    let device_id = identity::load_device_id(&data_dir);
+    let cert = match tls::load_cert(&data_dir) {
+        Ok(cert) => cert,
+        Err(e) => {
+            println!("Failed to create the sync certificate: {}", e);
+            return;
+        }
+    };
+    println!(
+        "Headless sync as {} ({}), saving notes to {:?}",
+        config.device_name, device_id, notes_dir
+    );
+
+    let host = SyncHost {
+        state: Arc::new(Mutex::new(AppState {
+            device_id,
+            device_name: config.device_name.clone(),
+            peers: HashMap::new(),
+            sync_notifications: Vec::new(),
+        })),
+        store: Arc::new(DirectoryStore {
+            data_dir,
+            notes_dir,
+        }),
+        events: Arc::new(StdoutEvents),
+        cert,
+    };
+
+    let rt = tokio::runtime::Runtime::new().expect("Failed to start the async runtime");
+    rt.block_on(async {
+        let (stop, stopped) = watch::channel(false);
+        tokio::spawn(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            println!("Stopping headless sync");
+            stop.send_replace(true);
+        });
+        server::run(host, config, Vec::new(), stopped).await;
+    });
+}
@@ -0,0 +1,102 @@
+use crate::settings::{load_device_settings, save_device_settings};
+use crate::{network, overrides, tls, AppState};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, Wry};
+
+// This device as its peers know it. The id is made up on first launch and kept in
+// `identity.json` in the app data directory, so peers recognise the device after a
+// restart instead of meeting a new one every time. The name shown to peers is the
+// `device_name` device setting, or the hostname while that is unset.
+
+const IDENTITY_FILE: &str = "identity.json";
+
+#[derive(Serialize, Deserialize)]
+struct StoredIdentity {
+    device_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceInfo {
+    device_id: String,
+    // Name peers see
+    device_name: String,
+    hostname: String,
+    // Whether the name was chosen rather than taken from the hostname
+    custom_name: bool,
+    // Of the sync certificate, for comparing with what a peer sees
+    fingerprint: String,
+}
+
+// The device id kept in `data_dir`, created and stored on first use. An id that
+// can't be stored still works for this run.
+pub fn load_device_id(data_dir: &Path) -> String {
+    let path = data_dir.join(IDENTITY_FILE);
+    // A missing file just means this is the first launch
+    if let Ok(raw) = fs::read_to_string(&path) {
+        match serde_json::from_str::<StoredIdentity>(&raw) {
+            Ok(stored) if !stored.device_id.trim().is_empty() => return stored.device_id,
+            Ok(_) => println!("{:?} has no device id, creating a new one", path),
+            Err(e) => println!(
+                "Failed to parse {:?}, creating a new device id: {}",
+                path, e
+            ),
+        }
+    }
+
+    let stored = StoredIdentity {
+        device_id: uuid::Uuid::new_v4().to_string(),
+    };
+    let written = serde_json::to_string_pretty(&stored)
+        .map_err(|e| e.to_string())
+        .and_then(|raw| {
+            fs::create_dir_all(data_dir)
+                .and_then(|()| fs::write(&path, raw))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = written {
+        println!("Failed to store the device id in {:?}: {}", path, e);
+    }
+    stored.device_id
+}
+
+pub fn device_id(app_handle: &AppHandle<Wry>) -> String {
+    let data_dir = overrides::app_data_dir(app_handle).expect("Failed to get app data directory");
+    load_device_id(&data_dir)
+}
+
+#[tauri::command]
+pub async fn get_device_info(app_handle: AppHandle<Wry>) -> Result<DeviceInfo, String> {
+    let device_id = {
+        let state = app_handle.state::<Arc<Mutex<AppState>>>();
+        let state = state.lock().map_err(|e| e.to_string())?;
+        state.device_id.clone()
+    };
+    let settings = load_device_settings(&app_handle);
+    Ok(DeviceInfo {
+        device_id,
+        device_name: network::server_config(&app_handle).device_name,
+        hostname: network::hostname(),
+        custom_name: settings
+            .device_name
+            .is_some_and(|name| !name.trim().is_empty()),
+        fingerprint: app_handle.state::<tls::DeviceCert>().fingerprint.clone(),
+    })
+}
+
+// Rename this device for its peers; no name goes back to the hostname. The sync
+// server restarts to announce the new name.
+#[tauri::command]
+pub async fn set_device_name(
+    app_handle: AppHandle<Wry>,
+    name: Option<String>,
+) -> Result<DeviceInfo, String> {
+    let mut settings = load_device_settings(&app_handle);
+    settings.device_name = name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    save_device_settings(&app_handle, &settings)?;
+    get_device_info(app_handle).await
+}
@@ -0,0 +1,521 @@
+use crate::blobs;
+use crate::compression;
+use crate::document;
+use crate::frontmatter::Frontmatter;
+use crate::markdown::{self, attachment_link};
+use crate::plugins::{self, PluginHook};
+use crate::readonly;
+use crate::tables::{self, Alignment};
+use crate::{
+    frontmatter, get_attachments_dir, get_notes_dir, load_notes, new_note_id, overrides,
+    persist_note, writable_attachments_dir, Note,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Wry};
+
+const MAX_CSV_BYTES: u64 = 5 * 1024 * 1024;
+const MAX_CSV_ROWS: usize = 1000;
+const MAX_CSV_COLUMNS: usize = 50;
+const CSV_DELIMITERS: &[u8] = b",;\t|";
+const REGISTRY_FILE: &str = "imports.json";
+// New attachments of a re-import wait here until the updated note is saved
+const STAGING_DIR: &str = ".import-staging";
+
+// The local file a link in imported markdown refers to, if it exists. Web links,
+// anchors and links to other markdown documents are left alone.
+fn resolve_local_reference(target: &str, base_dir: &Path) -> Option<PathBuf> {
+    let target = target.trim().trim_start_matches('<').trim_end_matches('>');
+    if target.is_empty() || target.starts_with('#') || target.starts_with("data:") {
+        return None;
+    }
+
+    let path = match target.strip_prefix("file://") {
+        Some(path) => path,
+        None if target.contains("://") || target.starts_with("mailto:") => return None,
+        None => target,
+    };
+    let path = PathBuf::from(path.replace("%20", " "));
+
+    if path.extension().and_then(|e| e.to_str()) == Some("md") {
+        return None;
+    }
+
+    let path = if path.is_absolute() {
+        path
+    } else {
+        base_dir.join(path)
+    };
+    path.is_file().then_some(path)
+}
+
+// Pick a file name in `dir` that doesn't collide with an existing attachment
+pub fn unique_attachment_name(dir: &Path, file_name: &str) -> String {
+    if !compression::attachment_exists(dir, file_name) {
+        return file_name.to_string();
+    }
+
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) => (stem, format!(".{}", extension)),
+        None => (file_name, String::new()),
+    };
+    (1..)
+        .map(|n| format!("{}_{}{}", stem, n, extension))
+        .find(|candidate| !compression::attachment_exists(dir, candidate))
+        .unwrap_or_else(|| file_name.to_string())
+}
+
+// Copy every local file the markdown references into the note's attachments and
+// point the links at the copies. Returns the rewritten markdown and the new attachments.
+pub fn import_local_references(
+    content: &str,
+    base_dir: &Path,
+    attachments_dir: &Path,
+) -> (String, Vec<String>) {
+    let mut copied: HashMap<PathBuf, String> = HashMap::new();
+
+    let content = markdown::rewrite_link_targets(content, |target| {
+        let source = resolve_local_reference(target, base_dir)?;
+        if let Some(name) = copied.get(&source) {
+            return Some(attachment_link(name));
+        }
+
+        let file_name = source.file_name()?.to_str()?;
+        let name = unique_attachment_name(attachments_dir, file_name);
+        match fs::copy(&source, attachments_dir.join(&name)) {
+            Ok(_) => {
+                copied.insert(source, name.clone());
+                Some(attachment_link(&name))
+            }
+            Err(e) => {
+                println!("Failed to copy referenced file {:?}: {}", source, e);
+                None
+            }
+        }
+    });
+
+    (content, copied.into_values().collect())
+}
+
+// Title from the note's leading `# ` heading, falling back to the source file name
+fn imported_title(content: &str, source: &Path) -> String {
+    match content.trim_start().strip_prefix("# ") {
+        Some(rest) => rest.lines().next().unwrap_or("").trim().to_string(),
+        None => source
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("Untitled")
+            .to_string(),
+    }
+}
+
+// Every import is recorded by source path and content hash, so importing the same
+// file again (after fixing it up, or from a moved copy) finds the note it produced
+// instead of creating a duplicate.
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+struct ImportRecord {
+    note_id: String,
+    // sha256 of the source file as it was imported
+    hash: String,
+    // Attachments the import created, replaced when the note is updated
+    attachments: Vec<String>,
+}
+
+// Records by importer, then by canonical source path
+type ImportRegistry = HashMap<String, HashMap<String, ImportRecord>>;
+
+// What to do when a source was imported before and its note still exists
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAction {
+    // Keep the existing note as it is
+    Skip,
+    // Re-import into the existing note if the source changed since (the default)
+    Update,
+}
+
+struct ImportSource {
+    importer: &'static str,
+    key: String,
+    hash: String,
+}
+
+impl ImportSource {
+    fn new(importer: &'static str, path: &Path, data: &[u8]) -> Self {
+        let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        ImportSource {
+            importer,
+            key: key.to_string_lossy().into_owned(),
+            hash: hex::encode(Sha256::digest(data)),
+        }
+    }
+}
+
+enum ImportTarget {
+    // Nothing to import; this note already has the source's content
+    Existing(Note),
+    // Import into this note id, replacing `previous` when there is one
+    Note { id: String, previous: Option<Note> },
+}
+
+fn get_registry_path(app_handle: &AppHandle<Wry>) -> Result<PathBuf, String> {
+    let dir = overrides::app_data_dir(app_handle).map_err(|e| e.to_string())?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.join(REGISTRY_FILE))
+}
+
+fn load_registry(app_handle: &AppHandle<Wry>) -> ImportRegistry {
+    get_registry_path(app_handle)
+        .and_then(|path| fs::read_to_string(path).map_err(|e| e.to_string()))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_registry(app_handle: &AppHandle<Wry>, registry: &ImportRegistry) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(registry).map_err(|e| e.to_string())?;
+    fs::write(get_registry_path(app_handle)?, content).map_err(|e| e.to_string())
+}
+
+// Decide where an import goes. A source matches an earlier import by path, or by
+// content when the file was moved; records of deleted notes are ignored.
+fn import_target(
+    app_handle: &AppHandle<Wry>,
+    source: &ImportSource,
+    duplicates: Option<DuplicateAction>,
+) -> Result<ImportTarget, String> {
+    let registry = load_registry(app_handle);
+    let record = registry.get(source.importer).and_then(|records| {
+        records
+            .get(&source.key)
+            .or_else(|| records.values().find(|r| r.hash == source.hash))
+    });
+    let previous = match record {
+        Some(record) => load_notes(app_handle)?
+            .into_iter()
+            .find(|n| n.id == record.note_id),
+        None => None,
+    };
+    let (Some(record), Some(previous)) = (record, previous) else {
+        return Ok(ImportTarget::Note {
+            id: new_note_id(),
+            previous: None,
+        });
+    };
+
+    if duplicates == Some(DuplicateAction::Skip) || record.hash == source.hash {
+        println!("{} was imported before, skipping", source.key);
+        return Ok(ImportTarget::Existing(previous));
+    }
+
+    readonly::ensure_writable(app_handle, &previous.id).map_err(|e| e.to_string())?;
+    Ok(ImportTarget::Note {
+        id: previous.id.clone(),
+        previous: Some(previous),
+    })
+}
+
+fn staging_dir(app_handle: &AppHandle<Wry>, note_id: &str) -> PathBuf {
+    get_notes_dir(app_handle).join(STAGING_DIR).join(note_id)
+}
+
+// Where an import writes the note's attachments. A re-import stages them apart, so
+// the note keeps its current files until the update is saved.
+fn import_attachments_dir(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    previous: &Option<Note>,
+) -> Result<PathBuf, String> {
+    if previous.is_none() {
+        return writable_attachments_dir(app_handle, note_id);
+    }
+    let dir = staging_dir(app_handle, note_id);
+    // Leftovers of a re-import that failed
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+// The update is saved: the attachments of the previous import make way for the
+// staged ones
+fn swap_in_attachments(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    replaced: &[String],
+) -> Result<(), String> {
+    let staging = staging_dir(app_handle, note_id);
+    let attachments_dir = writable_attachments_dir(app_handle, note_id)?;
+    for name in replaced {
+        compression::release_attachment(app_handle, &attachments_dir, name);
+    }
+    for entry in fs::read_dir(&staging).map_err(|e| e.to_string())?.flatten() {
+        let dest = attachments_dir.join(entry.file_name());
+        fs::rename(entry.path(), &dest).map_err(|e| e.to_string())?;
+        if let Err(e) = blobs::share(app_handle, &dest) {
+            println!("Failed to deduplicate attachment {:?}: {}", dest, e);
+        }
+    }
+    let _ = fs::remove_dir_all(&staging);
+    Ok(())
+}
+
+async fn save_imported_note(
+    app_handle: &AppHandle<Wry>,
+    source: ImportSource,
+    mut note: Note,
+    previous: Option<Note>,
+) -> Result<Note, String> {
+    let imported_attachments = note.attachments.clone();
+    let mut registry = load_registry(app_handle);
+    // Attachments the previous import brought, which the new ones replace
+    let replaced: Vec<String> = registry
+        .get(source.importer)
+        .and_then(|records| records.values().find(|r| r.note_id == note.id))
+        .map(|record| record.attachments.clone())
+        .unwrap_or_default();
+    let updating = previous.is_some();
+
+    // An update keeps fields added since the first import and attachments the user added
+    if let Some(previous) = previous {
+        for (key, value) in previous.fields {
+            note.fields.entry(key).or_insert(value);
+        }
+        let attachments_dir = get_attachments_dir(app_handle, &note.id);
+        for name in previous.attachments {
+            let kept = compression::attachment_exists(&attachments_dir, &name)
+                && !replaced.contains(&name);
+            if kept && !note.attachments.contains(&name) {
+                note.attachments.push(name);
+            }
+        }
+    }
+
+    let note = plugins::run_hook(app_handle, PluginHook::Import, note).await;
+    if let Err(e) = persist_note(app_handle, note.clone()).await {
+        if updating {
+            let _ = fs::remove_dir_all(staging_dir(app_handle, &note.id));
+        }
+        return Err(e);
+    }
+    if updating {
+        swap_in_attachments(app_handle, &note.id, &replaced)?;
+    }
+
+    let records = registry.entry(source.importer.to_string()).or_default();
+    // A moved source replaces the record under its old path
+    records.retain(|_, record| record.note_id != note.id);
+    records.insert(
+        source.key,
+        ImportRecord {
+            note_id: note.id.clone(),
+            hash: source.hash,
+            attachments: imported_attachments,
+        },
+    );
+    save_registry(app_handle, &registry)?;
+
+    Ok(note)
+}
+
+// Import a markdown file as a new note, bringing along any local files it references.
+// A file that was imported before updates its note instead, per `duplicates`.
+#[tauri::command]
+pub async fn import_markdown(
+    app_handle: AppHandle<Wry>,
+    path: String,
+    duplicates: Option<DuplicateAction>,
+) -> Result<Note, String> {
+    let source = PathBuf::from(&path);
+    let raw = fs::read_to_string(&source).map_err(|e| e.to_string())?;
+    let base_dir = source.parent().unwrap_or(Path::new("."));
+    let (fields, body) = frontmatter::split(&raw);
+
+    let origin = ImportSource::new("markdown", &source, raw.as_bytes());
+    let (id, previous) = match import_target(&app_handle, &origin, duplicates)? {
+        ImportTarget::Existing(note) => return Ok(note),
+        ImportTarget::Note { id, previous } => (id, previous),
+    };
+    let attachments_dir = import_attachments_dir(&app_handle, &id, &previous)?;
+    let (content, attachments) = import_local_references(body, base_dir, &attachments_dir);
+
+    let note = Note {
+        id,
+        title: imported_title(&content, &source),
+        content: content.trim_start().to_string(),
+        datetime: chrono::Utc::now().timestamp().to_string(),
+        attachments,
+        fields,
+        ..Default::default()
+    };
+
+    save_imported_note(&app_handle, origin, note, previous).await
+}
+
+// Import a Word (.docx) or OpenDocument (.odt) file as a new note, with its
+// embedded images as attachments. Re-importing a document works as for markdown.
+#[tauri::command]
+pub async fn import_document(
+    app_handle: AppHandle<Wry>,
+    path: String,
+    duplicates: Option<DuplicateAction>,
+) -> Result<Note, String> {
+    let source = PathBuf::from(&path);
+    let data = fs::read(&source).map_err(|e| e.to_string())?;
+
+    let origin = ImportSource::new("document", &source, &data);
+    let (id, previous) = match import_target(&app_handle, &origin, duplicates)? {
+        ImportTarget::Existing(note) => return Ok(note),
+        ImportTarget::Note { id, previous } => (id, previous),
+    };
+    let attachments_dir = import_attachments_dir(&app_handle, &id, &previous)?;
+    let (content, attachments) =
+        document::convert_document(&app_handle, &source, &attachments_dir)?;
+
+    let note = Note {
+        id,
+        title: imported_title(&content, &source),
+        content,
+        datetime: chrono::Utc::now().timestamp().to_string(),
+        attachments,
+        fields: Frontmatter::new(),
+        ..Default::default()
+    };
+
+    save_imported_note(&app_handle, origin, note, previous).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CsvImportOptions {
+    // Detected from the file when not given
+    #[serde(default)]
+    delimiter: Option<char>,
+    #[serde(default = "default_has_header")]
+    has_header: bool,
+    // Character offset in the note content to insert at; the table is appended when absent
+    #[serde(default)]
+    cursor: Option<usize>,
+}
+
+fn default_has_header() -> bool {
+    true
+}
+
+fn csv_reader(data: &[u8], delimiter: u8) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(data)
+}
+
+// The delimiter that splits the first rows into the same number of (more than one) columns
+fn detect_delimiter(data: &[u8]) -> u8 {
+    CSV_DELIMITERS
+        .iter()
+        .copied()
+        .filter_map(|delimiter| {
+            let counts: Vec<usize> = csv_reader(data, delimiter)
+                .records()
+                .take(20)
+                .map(|record| record.map(|r| r.len()).unwrap_or(0))
+                .collect();
+            let columns = *counts.first()?;
+            (columns > 1 && counts.iter().all(|&c| c == columns)).then_some((delimiter, columns))
+        })
+        .max_by_key(|&(_, columns)| columns)
+        .map(|(delimiter, _)| delimiter)
+        .unwrap_or(b',')
+}
+
+fn csv_to_table(data: &[u8], options: &CsvImportOptions) -> Result<String, String> {
+    let delimiter = match options.delimiter {
+        Some(c) if c.is_ascii() => c as u8,
+        Some(c) => return Err(format!("Unsupported delimiter: {:?}", c)),
+        None => detect_delimiter(data),
+    };
+
+    let mut rows = Vec::new();
+    for record in csv_reader(data, delimiter).records() {
+        let record = record.map_err(|e| e.to_string())?;
+        if rows.len() == MAX_CSV_ROWS + 1 {
+            return Err(format!(
+                "CSV has more than {} rows; attach the file instead",
+                MAX_CSV_ROWS
+            ));
+        }
+        if record.len() > MAX_CSV_COLUMNS {
+            return Err(format!("CSV has more than {} columns", MAX_CSV_COLUMNS));
+        }
+        rows.push(record.iter().map(tables::escape_cell).collect::<Vec<_>>());
+    }
+
+    if rows.is_empty() {
+        return Err("CSV file is empty".to_string());
+    }
+
+    if !options.has_header {
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        rows.insert(0, (1..=columns).map(|n| format!("Column {}", n)).collect());
+    }
+
+    Ok(tables::render_table(&rows, &[Alignment::None]))
+}
+
+// Insert `block` at a character offset, keeping it separated from the text around it by blank lines
+fn insert_block(content: &str, cursor: Option<usize>, block: &str) -> String {
+    let offset = cursor
+        .and_then(|cursor| content.char_indices().nth(cursor).map(|(i, _)| i))
+        .unwrap_or(content.len());
+    let (before, after) = content.split_at(offset);
+
+    let mut result = before.to_string();
+    if !result.is_empty() {
+        while !result.ends_with("\n\n") {
+            result.push('\n');
+        }
+    }
+    result.push_str(block);
+    if !after.is_empty() {
+        if !after.starts_with('\n') {
+            result.push('\n');
+        }
+        result.push_str(after);
+    }
+    result
+}
+
+// Insert a CSV file into a note as a markdown table
+#[tauri::command]
+pub async fn import_csv_as_table(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    path: String,
+    options: CsvImportOptions,
+) -> Result<Note, String> {
+    let size = fs::metadata(&path).map_err(|e| e.to_string())?.len();
+    if size > MAX_CSV_BYTES {
+        return Err(format!(
+            "CSV file is larger than {} MB",
+            MAX_CSV_BYTES / 1024 / 1024
+        ));
+    }
+    let data = fs::read(&path).map_err(|e| e.to_string())?;
+    let table = csv_to_table(&data, &options)?;
+
+    readonly::ensure_writable(&app_handle, &note_id).map_err(|e| e.to_string())?;
+
+    let mut note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+    note.content = insert_block(&note.content, options.cursor, &table);
+
+    persist_note(&app_handle, note.clone()).await?;
+
+    Ok(note)
+}
@@ -0,0 +1,244 @@
+use crate::changes::{self, NoteChange};
+use crate::events::{self, IndexProgress};
+use crate::{
+    encryption, get_note_path, get_notes_dir, links, note_from_file, notebooks, tags, terms, Note,
+    NoteMetadata,
+};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tauri::{AppHandle, Manager, Wry};
+
+// In-memory index of the notes directory for features that compare notes with each
+// other. Refreshing only re-reads files whose modification time changed, so queries
+// don't rescan every note, and notes changed in the app are re-indexed as they are
+// saved. Archived notes are left out until they are restored.
+
+// Reading speed behind the reading time shown in listings
+const WORDS_PER_MINUTE: usize = 200;
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub struct IndexEntry {
+    pub title: String,
+    pub modified: Option<SystemTime>,
+    // Term counts of the note's plain text
+    pub terms: HashMap<String, usize>,
+    // Words in the note's plain text
+    pub words: usize,
+    pub tags: Vec<String>,
+    // Link targets as written: `[[Title]]` wikilinks and `notes://note/<id>` links
+    pub links: Vec<String>,
+    // Embedding of the note's text, filled in on demand by semantic search
+    pub embedding: Option<Vec<f32>>,
+}
+
+#[derive(Default)]
+pub struct NoteIndex {
+    pub entries: HashMap<String, IndexEntry>,
+    // Model the stored embeddings came from; switching models discards them
+    pub embedding_model: Option<String>,
+}
+
+impl NoteIndex {
+    // Inverse document frequency of every indexed term
+    pub fn idf(&self) -> HashMap<String, f64> {
+        let documents: Vec<HashMap<String, usize>> =
+            self.entries.values().map(|e| e.terms.clone()).collect();
+        terms::idf(&documents)
+    }
+
+    // The id of the note a link target points to, if it is in the index. Wikilinks
+    // name notes by title (or id), other note links by id.
+    pub fn link_resolver(&self) -> impl Fn(&str) -> Option<String> + '_ {
+        let by_title: HashMap<String, &str> = self
+            .entries
+            .iter()
+            .map(|(id, e)| (e.title.to_lowercase(), id.as_str()))
+            .collect();
+        move |target: &str| {
+            let id = match target.strip_prefix(links::NOTE_LINK_SCHEME) {
+                Some(id) => id,
+                None => {
+                    let title = target.strip_prefix("[[")?.strip_suffix("]]")?;
+                    by_title
+                        .get(&title.to_lowercase())
+                        .copied()
+                        .unwrap_or(title)
+                }
+            };
+            self.entries.contains_key(id).then(|| id.to_string())
+        }
+    }
+}
+
+fn index_file(id: &str, raw: &str, modified: Option<SystemTime>) -> IndexEntry {
+    index_note(note_from_file(id, raw, String::new(), Vec::new()), modified)
+}
+
+fn index_note(note: Note, modified: Option<SystemTime>) -> IndexEntry {
+    let text = terms::note_text(&note.content);
+    IndexEntry {
+        terms: terms::term_counts(&text),
+        words: text.split_whitespace().count(),
+        tags: tags::tags_of(&note),
+        links: links::note_links(&note.content)
+            .into_iter()
+            .map(|(_, target)| target)
+            .collect(),
+        title: note.title,
+        modified,
+        embedding: None,
+    }
+}
+
+// Bring the index up to date with the notes directory. Reading many files at
+// once, as when the index is first built, reports `index-progress`.
+pub fn refresh(app_handle: &AppHandle<Wry>, index: &mut NoteIndex) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    let mut stale = Vec::new();
+
+    for path in notebooks::note_files(&get_notes_dir(app_handle)) {
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        seen.insert(id.to_string());
+
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let current = index
+            .entries
+            .get(id)
+            .is_some_and(|e| modified.is_some() && e.modified == modified);
+        if !current {
+            stale.push((id.to_string(), path, modified));
+        }
+    }
+
+    let total = stale.len();
+    let report = total > events::PROGRESS_BATCH;
+    for (scanned, (id, path, modified)) in stale.into_iter().enumerate() {
+        match encryption::read_to_string(&path) {
+            Ok(raw) => {
+                let entry = index_file(&id, &raw, modified);
+                index.entries.insert(id, entry);
+            }
+            Err(e) => println!("Failed to index {:?}: {}", path, e),
+        }
+        if report && (scanned + 1) % events::PROGRESS_BATCH == 0 {
+            events::index_progress(app_handle, IndexProgress::index(scanned + 1, total));
+        }
+    }
+    if report && total % events::PROGRESS_BATCH != 0 {
+        events::index_progress(app_handle, IndexProgress::index(total, total));
+    }
+
+    index.entries.retain(|id, _| seen.contains(id));
+    Ok(())
+}
+
+// Keep the index current with notes changed in the app, so the next refresh finds
+// them unchanged
+pub fn spawn_indexer(app_handle: &AppHandle<Wry>) {
+    changes::subscribe(app_handle, "Note index", |app_handle, change| {
+        let result = with_cached_index(app_handle, |index| match change {
+            NoteChange::Created(note) | NoteChange::Updated(note) => {
+                let modified = fs::metadata(get_note_path(app_handle, &note.id))
+                    .and_then(|m| m.modified())
+                    .ok();
+                index
+                    .entries
+                    .insert(note.id.clone(), index_note(note, modified));
+            }
+            NoteChange::Deleted(id) => {
+                index.entries.remove(&id);
+            }
+            NoteChange::Shared { .. } => {}
+        });
+        if let Err(e) = result {
+            println!("Failed to update the note index: {}", e);
+        }
+    });
+}
+
+// Run `f` on the refreshed index
+pub fn with_index<T>(
+    app_handle: &AppHandle<Wry>,
+    f: impl FnOnce(&mut NoteIndex) -> T,
+) -> Result<T, String> {
+    let state = app_handle.state::<Mutex<NoteIndex>>();
+    let mut index = state.lock().map_err(|e| e.to_string())?;
+    refresh(app_handle, &mut index)?;
+    Ok(f(&mut index))
+}
+
+// Run `f` on the index as it is, without checking the notes directory for changes
+pub fn with_cached_index<T>(
+    app_handle: &AppHandle<Wry>,
+    f: impl FnOnce(&mut NoteIndex) -> T,
+) -> Result<T, String> {
+    let state = app_handle.state::<Mutex<NoteIndex>>();
+    let mut index = state.lock().map_err(|e| e.to_string())?;
+    Ok(f(&mut index))
+}
+
+// Minutes it takes to read this many words
+pub fn reading_minutes(words: usize) -> usize {
+    words.div_ceil(WORDS_PER_MINUTE)
+}
+
+impl IndexEntry {
+    // Minutes it takes to read the note, at least one unless it is empty
+    pub fn reading_minutes(&self) -> usize {
+        reading_minutes(self.words)
+    }
+
+    // Whole days since the note's file was last written
+    pub fn days_since_edit(&self) -> Option<u64> {
+        let age = self.modified?.elapsed().ok()?;
+        Some(age.as_secs() / DAY.as_secs())
+    }
+}
+
+// The note's entry, re-indexing just that note when its file changed since it was
+// indexed
+fn current_entry<'a>(
+    app_handle: &AppHandle<Wry>,
+    index: &'a mut NoteIndex,
+    id: &str,
+) -> Option<&'a IndexEntry> {
+    let path = get_note_path(app_handle, id);
+    let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+    let current = index
+        .entries
+        .get(id)
+        .is_some_and(|e| e.modified == Some(modified));
+    if !current {
+        match encryption::read_to_string(&path) {
+            Ok(raw) => {
+                let entry = index_file(id, &raw, Some(modified));
+                index.entries.insert(id.to_string(), entry);
+            }
+            Err(e) => {
+                println!("Failed to index {:?}: {}", path, e);
+                return None;
+            }
+        }
+    }
+    index.entries.get(id)
+}
+
+// Fill in reading time and staleness of listed notes. Only the listed notes are
+// checked against their files, as this runs for every note announced as saved.
+pub fn annotate(app_handle: &AppHandle<Wry>, notes: &mut [NoteMetadata]) {
+    let result = with_cached_index(app_handle, |index| {
+        for note in notes.iter_mut() {
+            if let Some(entry) = current_entry(app_handle, index, &note.id) {
+                note.reading_minutes = Some(entry.reading_minutes());
+                note.days_since_edit = entry.days_since_edit();
+            }
+        }
+    });
+    if let Err(e) = result {
+        println!("Failed to annotate notes from the index: {}", e);
+    }
+}
@@ -0,0 +1,32 @@
+// API keys for outside services are kept in the OS keychain (Keychain, Credential
+// Manager, Secret Service) rather than in settings.json, which is plain text and
+// may end up in backups.
+
+const SERVICE: &str = "himoji-notes";
+
+fn entry(account: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(SERVICE, account).map_err(|e| e.to_string())
+}
+
+// The stored key for `account`, if one was set
+pub fn get_key(account: &str) -> Result<Option<String>, String> {
+    match entry(account)?.get_password() {
+        Ok(key) => Ok(Some(key)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("Can't read key from the keychain: {}", e)),
+    }
+}
+
+// Store the key for `account`, or remove it when `key` is empty
+pub fn set_key(account: &str, key: Option<&str>) -> Result<(), String> {
+    let entry = entry(account)?;
+    match key.map(str::trim).filter(|k| !k.is_empty()) {
+        Some(key) => entry
+            .set_password(key)
+            .map_err(|e| format!("Can't store key in the keychain: {}", e)),
+        None => match entry.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Can't remove key from the keychain: {}", e)),
+        },
+    }
+}
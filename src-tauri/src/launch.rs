@@ -0,0 +1,123 @@
+use crate::events;
+use crate::get_notes_dir;
+use crate::links::NOTE_LINK_SCHEME;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager, Wry};
+
+// What the app was launched to open. Only one instance runs on the default data
+// directory: launching the app again focuses the running window and hands it the new
+// command line, so a second process never starts its own sync server or announces
+// itself over mDNS under a second identity. Instances started with `--data-dir` have
+// identities of their own and run alongside it. `notes://note/<id>` links open that
+// note; paths to files open them, as the note itself when the file is in the notes
+// directory.
+//
+// Targets from the first launch wait in `Pending` until the frontend asks for
+// them, since it isn't listening yet; later ones arrive as `open-targets` events.
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LaunchTarget {
+    Note { id: String },
+    File { path: String },
+}
+
+#[derive(Default)]
+pub struct Pending(Mutex<Vec<LaunchTarget>>);
+
+// Targets in a command line, skipping the program and any `--flags`. Relative paths
+// are taken from `cwd`, the directory the app was launched in.
+pub fn targets(app_handle: &AppHandle<Wry>, args: &[String], cwd: &Path) -> Vec<LaunchTarget> {
+    let notes_dir = get_notes_dir(app_handle);
+    let mut targets = Vec::new();
+
+    for arg in args.iter().skip(1).filter(|arg| !arg.starts_with("--")) {
+        if let Some(id) = arg.strip_prefix(NOTE_LINK_SCHEME) {
+            let id = id.trim_end_matches('/');
+            if !id.is_empty() {
+                targets.push(LaunchTarget::Note { id: id.to_string() });
+            }
+            continue;
+        }
+        if arg.starts_with("notes://") {
+            println!("Ignoring unsupported link {}", arg);
+            continue;
+        }
+
+        let path = cwd.join(arg);
+        if !path.is_file() {
+            println!("Ignoring launch argument {}: not a file", arg);
+            continue;
+        }
+        targets.push(target_for_file(&notes_dir, path));
+    }
+
+    targets
+}
+
+// A note file opens as the note, anything else as a file to import
+fn target_for_file(notes_dir: &Path, path: PathBuf) -> LaunchTarget {
+    let in_notes_dir = match (path.parent(), notes_dir.canonicalize()) {
+        (Some(parent), Ok(notes_dir)) => parent.canonicalize().is_ok_and(|p| p == notes_dir),
+        _ => false,
+    };
+    let id = path.file_stem().and_then(|s| s.to_str());
+    let is_note = path.extension().and_then(|e| e.to_str()) == Some("md");
+
+    match id {
+        Some(id) if in_notes_dir && is_note => LaunchTarget::Note { id: id.to_string() },
+        _ => LaunchTarget::File {
+            path: path.to_string_lossy().to_string(),
+        },
+    }
+}
+
+// Remember what the first instance was launched with
+pub fn init(app_handle: &AppHandle<Wry>) {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let args: Vec<String> = std::env::args().collect();
+    let targets = targets(app_handle, &args, &cwd);
+
+    let pending = app_handle.state::<Pending>();
+    if let Ok(mut queued) = pending.0.lock() {
+        *queued = targets;
+    };
+}
+
+// Bring the main window to the front
+pub fn focus_main_window(app_handle: &AppHandle<Wry>) {
+    if let Some(window) = app_handle.get_webview_window("main") {
+        let _ = window.unminimize();
+        let _ = window.show();
+        if let Err(e) = window.set_focus() {
+            println!("Failed to focus the window: {}", e);
+        }
+    }
+}
+
+// Have the main window open `targets`, as if the app had been launched with them
+pub fn open(app_handle: &AppHandle<Wry>, targets: Vec<LaunchTarget>) {
+    focus_main_window(app_handle);
+    if targets.is_empty() {
+        return;
+    }
+    if let Err(e) = app_handle.emit(events::OPEN_TARGETS, targets) {
+        println!("Failed to forward launch arguments: {}", e);
+    }
+}
+
+// Called in the running instance when the app is launched again
+pub fn forward(app_handle: &AppHandle<Wry>, args: Vec<String>, cwd: String) {
+    let targets = targets(app_handle, &args, Path::new(&cwd));
+    open(app_handle, targets);
+}
+
+// What the app was launched to open, once; later calls return nothing
+#[tauri::command]
+pub async fn take_launch_targets(app_handle: AppHandle<Wry>) -> Result<Vec<LaunchTarget>, String> {
+    let pending = app_handle.state::<Pending>();
+    let mut queued = pending.0.lock().map_err(|e| e.to_string())?;
+    Ok(std::mem::take(&mut *queued))
+}
@@ -0,0 +1,107 @@
+use crate::ordering::FOLDER_FIELD;
+use crate::schema::{markdown_files, StepReport};
+use crate::{
+    atomic, encryption, frontmatter, new_note_id, note_from_file, render_note_file, storage, Note,
+};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+// Schema version 1 -> 2. Early vaults were a flat pile of markdown files: no
+// frontmatter, notes filed in subdirectories for folders, titles sometimes only in
+// the file name, and whatever line endings the editor used. Version 2 keeps every
+// note at the top of the notes directory with its folder in the frontmatter and a
+// `# Title` heading as the first line. Version 3 files notes in directories again,
+// one per notebook, keeping the frontmatter folder (see `notebooks`).
+
+pub fn upgrade_note(note: &mut Note) {
+    if note.content.contains('\r') {
+        note.content = note.content.replace("\r\n", "\n");
+    }
+    if !note.content.starts_with("# ") {
+        note.content = format!("# {}\n\n{}", note.title, note.content);
+    }
+}
+
+fn needs_upgrade(notes_dir: &Path, file: &Path) -> bool {
+    if file.parent().is_some_and(|p| p != Path::new("")) {
+        return true;
+    }
+    // Notes of an encrypted vault can't be read before it is unlocked, and were
+    // written by a version that already uses this layout
+    let Ok(raw) = encryption::read_to_string(notes_dir.join(file)) else {
+        return false;
+    };
+    raw.contains('\r') || !frontmatter::split(&raw).1.starts_with("# ")
+}
+
+// Bring one legacy file into the current layout
+fn upgrade_file(notes_dir: &Path, file: &Path) -> Result<(), String> {
+    let source = notes_dir.join(file);
+    let raw = encryption::read_to_string(&source).map_err(|e| e.to_string())?;
+    let modified = fs::metadata(&source).and_then(|m| m.modified()).ok();
+    let stem = file
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or("File name isn't valid UTF-8")?;
+
+    let mut note = note_from_file(stem, &raw.replace("\r\n", "\n"), String::new(), Vec::new());
+    // Legacy notes without a heading were titled by their file name
+    if !note.content.starts_with("# ") {
+        note.title = stem.to_string();
+    }
+    upgrade_note(&mut note);
+
+    // Subdirectories become folders; the file moves to the top of the notes directory
+    let folder = file
+        .parent()
+        .map(|p| {
+            p.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .filter(|f| !f.is_empty());
+    let mut dest = source.clone();
+    if let Some(folder) = folder {
+        note.fields
+            .entry(FOLDER_FIELD.to_string())
+            .or_insert(Value::String(folder));
+        dest = notes_dir.join(file.file_name().ok_or("File has no name")?);
+        if dest.exists() {
+            dest = notes_dir.join(format!("{}.md", new_note_id()));
+        }
+    }
+
+    atomic::write(&dest, render_note_file(&note)).map_err(|e| e.to_string())?;
+    if dest != source {
+        fs::remove_file(&source).map_err(|e| e.to_string())?;
+    }
+    // Note dates come from the modification time
+    if let Some(modified) = modified {
+        storage::set_modified(&dest, modified);
+    }
+    Ok(())
+}
+
+pub fn upgrade_vault(notes_dir: &Path, report: &mut StepReport) -> Result<(), String> {
+    let files: Vec<_> = markdown_files(notes_dir)?
+        .into_iter()
+        .filter(|file| needs_upgrade(notes_dir, file))
+        .collect();
+
+    for file in &files {
+        match upgrade_file(notes_dir, file) {
+            Ok(()) => report.changed += 1,
+            Err(e) => report.errors.push(format!("{}: {}", file.display(), e)),
+        }
+    }
+
+    // Empty folder directories are left behind by the moves
+    for file in &files {
+        if let Some(parent) = file.parent().filter(|p| *p != Path::new("")) {
+            let _ = fs::remove_dir(notes_dir.join(parent));
+        }
+    }
+    Ok(())
+}
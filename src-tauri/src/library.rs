@@ -0,0 +1,533 @@
+use crate::protocol::SyncError;
+use crate::tombstones::{self, Tombstone};
+use crate::{
+    changes, clock, compression, conditions, delta, encryption, get_note_path, load_notes, lock,
+    merge, network, pairing, protocol, render_note_file, schema, timestamps, tls,
+    writable_attachments_dir, AppState, Note, PeerDevice, SyncRequest,
+};
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tauri::{AppHandle, Manager, Wry};
+
+// Two-way sync of the whole library with a paired peer. Both devices first swap
+// tombstones and a manifest of their notes (id, hash and modification time), then
+// only notes that differ travel, one request each:
+//
+// - a note only one device has is copied to the other;
+// - a note that changed on one device only since they last synced (the sync base
+//   `merge.rs` keeps for each peer) replaces the copy on the other;
+// - a note changed on both, or that differs without a common base, is left alone
+//   and reported as a conflict, to be shared on its own and merged or chosen there.
+//
+// Notes arriving this way are saved without a prompt; both devices asked for them
+// by pairing and starting the sync. A device serving a note records it as the base
+// only once the peer acknowledges having saved it.
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ManifestEntry {
+    id: String,
+    // SHA-256 of the note file without its clock, which changes on every save
+    hash: String,
+    // Milliseconds since the epoch
+    modified: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestRequest {
+    peer_id: String,
+    peer_name: String,
+    // Sync protocol version of the requesting peer
+    #[serde(default = "protocol::legacy_version")]
+    protocol: u32,
+    tombstones: Vec<Tombstone>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestReply {
+    notes: Vec<ManifestEntry>,
+    tombstones: Vec<Tombstone>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NoteQuery {
+    peer_id: String,
+    note_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NoteAck {
+    peer_id: String,
+    note_id: String,
+    // Hash of the version that was saved, as in the manifest
+    hash: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LibraryConflict {
+    note_id: String,
+    title: String,
+    // When each side last changed it, in milliseconds since the epoch
+    local_modified: Option<u64>,
+    peer_modified: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct LibrarySummary {
+    peer_name: String,
+    // Notes new or changed on this device
+    created: usize,
+    updated: usize,
+    // Notes new or changed on the peer
+    sent_created: usize,
+    sent_updated: usize,
+    // Notes left as they are on both devices
+    conflicts: Vec<LibraryConflict>,
+    // Notes that failed to go either way
+    failed: usize,
+}
+
+type Reply = (StatusCode, Json<Value>);
+
+fn failure(status: StatusCode, error: SyncError) -> Reply {
+    println!("Library sync request failed: {}", error);
+    (status, Json(error.body()))
+}
+
+fn internal(message: impl ToString) -> SyncError {
+    SyncError::new("internal", message.to_string(), true)
+}
+
+fn hash_text(text: &str) -> String {
+    hex::encode(Sha256::digest(text.as_bytes()))
+}
+
+fn modified_millis(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as u64)
+}
+
+fn manifest(app_handle: &AppHandle<Wry>) -> Result<HashMap<String, (ManifestEntry, Note)>, String> {
+    Ok(load_notes(app_handle)?
+        .into_iter()
+        .map(|note| {
+            let entry = ManifestEntry {
+                id: note.id.clone(),
+                hash: hash_text(&merge::merge_text(&note)),
+                modified: modified_millis(&get_note_path(app_handle, &note.id)),
+            };
+            (note.id.clone(), (entry, note))
+        })
+        .collect())
+}
+
+fn own_identity(app_handle: &AppHandle<Wry>) -> Result<(String, String), String> {
+    let state = app_handle.state::<Arc<Mutex<AppState>>>();
+    let state = state.lock().map_err(|e| e.to_string())?;
+    Ok((state.device_id.clone(), state.device_name.clone()))
+}
+
+// A note with its attachments, ready to send
+fn note_request(app_handle: &AppHandle<Wry>, note: Note) -> Result<SyncRequest, String> {
+    let (peer_id, peer_name) = own_identity(app_handle)?;
+    let mut attachments_data = HashMap::new();
+    for name in &note.attachments {
+        match compression::read_note_attachment(app_handle, &note.id, name) {
+            Ok(data) => {
+                attachments_data.insert(name.clone(), data);
+            }
+            Err(e) => println!("Skipping missing attachment {}: {}", name, e),
+        }
+    }
+    Ok(SyncRequest {
+        protocol: protocol::PROTOCOL_VERSION,
+        schema: schema::SCHEMA_VERSION,
+        peer_id,
+        peer_name,
+        note,
+        attachments_data,
+        tombstones: Vec::new(),
+    })
+}
+
+// Save a note as the peer that sent it has it, which is now the version both have in
+// common. Returns whether it is new here.
+fn save_incoming(app_handle: &AppHandle<Wry>, request: &SyncRequest) -> Result<bool, String> {
+    lock::ensure_writable(app_handle)?;
+    let note = &request.note;
+    clock::observe(app_handle, note);
+
+    let attachments_dir = writable_attachments_dir(app_handle, &note.id)?;
+    for (file_name, file_data) in &request.attachments_data {
+        let name = Path::new(file_name)
+            .file_name()
+            .ok_or_else(|| format!("Not a file name: {}", file_name))?;
+        compression::write_attachment(app_handle, &attachments_dir.join(name), file_data)?;
+    }
+
+    let path = get_note_path(app_handle, &note.id);
+    let created = !path.exists();
+    // Keep the dates the peer has for the note
+    let mut dated = note.clone();
+    timestamps::adopt(&mut dated);
+    let contents = render_note_file(&dated);
+    encryption::write(app_handle, &path, &contents)?;
+    merge::record_base(app_handle, &request.peer_id, &note.id, &contents);
+    changes::publish_saved(app_handle, &note.id, created)?;
+    Ok(created)
+}
+
+// Read a note sent by a peer, upgraded to our schema and checked like a sync request
+fn read_note_request(body: Value) -> Result<SyncRequest, SyncError> {
+    let request =
+        protocol::read_request(body).map_err(|e| SyncError::new("unreadable_request", e, false))?;
+    protocol::validate_request(&request)?;
+    Ok(request)
+}
+
+// Sync every note with a paired peer in both directions
+#[tauri::command]
+pub async fn sync_with_peer(
+    app_handle: AppHandle<Wry>,
+    peer_id: String,
+) -> Result<LibrarySummary, String> {
+    conditions::check_bulk_work(&app_handle).await?;
+    lock::ensure_writable(&app_handle)?;
+
+    let peer: PeerDevice = {
+        let state = app_handle.state::<Arc<Mutex<AppState>>>();
+        let state = state.lock().map_err(|e| e.to_string())?;
+        state.peers.get(&peer_id).cloned().ok_or("Peer not found")?
+    };
+    let client = tls::client(&app_handle, &peer)?;
+    let post = |path: &'static str, body: Value, timeout: Duration| {
+        let client = client.clone();
+        let addresses = peer.addresses();
+        let port = peer.port;
+        async move { network::post_to_peer(&client, &addresses, port, path, &body, timeout).await }
+    };
+
+    let (own_id, own_name) = own_identity(&app_handle)?;
+    let request = ManifestRequest {
+        peer_id: own_id.clone(),
+        peer_name: own_name,
+        protocol: protocol::PROTOCOL_VERSION,
+        tombstones: tombstones::current(&app_handle, peer.protocol),
+    };
+    let theirs: ManifestReply = post(
+        "/sync/library/manifest",
+        serde_json::to_value(&request).map_err(|e| e.to_string())?,
+        Duration::from_secs(30),
+    )
+    .await?
+    .json()
+    .await
+    .map_err(|e| e.to_string())?;
+
+    // Deletions on either side win, as with single notes
+    tombstones::apply(&app_handle, &theirs.tombstones)?;
+    let mut ours = manifest(&app_handle)?;
+
+    let mut summary = LibrarySummary {
+        peer_name: peer.name.clone(),
+        ..Default::default()
+    };
+    let mut pull = Vec::new();
+    let mut push = Vec::new();
+    for entry in theirs.notes {
+        if tombstones::is_deleted(&app_handle, &entry.id) {
+            continue;
+        }
+        let Some((local, note)) = ours.remove(&entry.id) else {
+            pull.push((entry.id, true));
+            continue;
+        };
+        if local.hash == entry.hash {
+            // The same on both, so a base for later syncs if there was none
+            if merge::base_note(&app_handle, &peer.id, &note.id).is_none() {
+                merge::record_base(&app_handle, &peer.id, &note.id, &render_note_file(&note));
+            }
+            continue;
+        }
+        let base = merge::base_note(&app_handle, &peer.id, &note.id)
+            .map(|base| hash_text(&merge::merge_text(&base)));
+        match base {
+            Some(base) if base == local.hash => pull.push((entry.id, false)),
+            Some(base) if base == entry.hash => push.push((note, false)),
+            _ => summary.conflicts.push(LibraryConflict {
+                note_id: note.id,
+                title: note.title,
+                local_modified: local.modified,
+                peer_modified: entry.modified,
+            }),
+        }
+    }
+    // Whatever is left only exists here
+    push.extend(ours.into_values().map(|(_, note)| (note, true)));
+
+    for (note_id, created) in pull {
+        let query = NoteQuery {
+            peer_id: own_id.clone(),
+            note_id: note_id.clone(),
+        };
+        let result = async {
+            let body: Value = post(
+                "/sync/library/get",
+                serde_json::to_value(&query).map_err(|e| e.to_string())?,
+                Duration::from_secs(60),
+            )
+            .await?
+            .json()
+            .await
+            .map_err(|e| e.to_string())?;
+            let request = read_note_request(body).map_err(|e| e.to_string())?;
+            if request.peer_id != peer.id {
+                return Err(format!("Note came from {} instead", request.peer_id));
+            }
+            let created = save_incoming(&app_handle, &request)?;
+
+            // Now the peer can take it as the version both have
+            let ack = NoteAck {
+                peer_id: own_id.clone(),
+                note_id: note_id.clone(),
+                hash: hash_text(&merge::merge_text(&request.note)),
+            };
+            if let Err(e) = post(
+                "/sync/library/ack",
+                serde_json::to_value(&ack).map_err(|e| e.to_string())?,
+                Duration::from_secs(10),
+            )
+            .await
+            {
+                println!("Failed to confirm note {} to {}: {}", note_id, peer.name, e);
+            }
+            Ok::<bool, String>(created)
+        }
+        .await;
+        match result {
+            Ok(_) if created => summary.created += 1,
+            Ok(_) => summary.updated += 1,
+            Err(e) => {
+                println!("Failed to fetch note {} from {}: {}", note_id, peer.name, e);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    for (note, created) in push {
+        let note_id = note.id.clone();
+        let contents = render_note_file(&note);
+        let result = async {
+            let mut request = note_request(&app_handle, note)?;
+            // The manifest says the note differs, but its attachments may not
+            delta::trim(
+                &client,
+                &peer,
+                &own_id,
+                &request.note,
+                &mut request.attachments_data,
+            )
+            .await;
+            post(
+                "/sync/library/put",
+                serde_json::to_value(&request).map_err(|e| e.to_string())?,
+                Duration::from_secs(60),
+            )
+            .await?;
+            Ok::<(), String>(())
+        }
+        .await;
+        match result {
+            Ok(()) => {
+                merge::record_base(&app_handle, &peer.id, &note_id, &contents);
+                if created {
+                    summary.sent_created += 1;
+                } else {
+                    summary.sent_updated += 1;
+                }
+            }
+            Err(e) => {
+                println!("Failed to send note {} to {}: {}", note_id, peer.name, e);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "Library sync with {}: {} created, {} updated here, {} created, {} updated there, {} conflicts",
+        peer.name,
+        summary.created,
+        summary.updated,
+        summary.sent_created,
+        summary.sent_updated,
+        summary.conflicts.len()
+    );
+    Ok(summary)
+}
+
+fn check_trusted(app_handle: &AppHandle<Wry>, headers: &HeaderMap, peer_id: &str) -> Option<Reply> {
+    if pairing::is_trusted(app_handle, peer_id, pairing::bearer_token(headers)) {
+        return None;
+    }
+    Some(failure(
+        StatusCode::FORBIDDEN,
+        SyncError::new(
+            "not_paired",
+            format!("{} isn't paired with this device", peer_id),
+            false,
+        ),
+    ))
+}
+
+// Library syncs wait while sync is paused here
+fn check_paused(app_handle: &AppHandle<Wry>) -> Option<Reply> {
+    conditions::is_sync_paused(app_handle)
+        .then(|| failure(StatusCode::SERVICE_UNAVAILABLE, SyncError::paused()))
+}
+
+async fn receive_manifest(
+    State(app_handle): State<AppHandle<Wry>>,
+    headers: HeaderMap,
+    Json(request): Json<ManifestRequest>,
+) -> Reply {
+    if let Some(rejected) =
+        check_trusted(&app_handle, &headers, &request.peer_id).or_else(|| check_paused(&app_handle))
+    {
+        return rejected;
+    }
+    println!("Library sync requested by {}", request.peer_name);
+
+    let result = tombstones::apply(&app_handle, &request.tombstones).and_then(|()| {
+        let notes = manifest(&app_handle)?
+            .into_values()
+            .map(|(entry, _)| entry)
+            .collect();
+        Ok(ManifestReply {
+            notes,
+            tombstones: tombstones::current(&app_handle, request.protocol),
+        })
+    });
+    match result.and_then(|reply| serde_json::to_value(reply).map_err(|e| e.to_string())) {
+        Ok(reply) => (StatusCode::OK, Json(reply)),
+        Err(e) => failure(StatusCode::INTERNAL_SERVER_ERROR, internal(e)),
+    }
+}
+
+async fn receive_get(
+    State(app_handle): State<AppHandle<Wry>>,
+    headers: HeaderMap,
+    Json(query): Json<NoteQuery>,
+) -> Reply {
+    if let Some(rejected) =
+        check_trusted(&app_handle, &headers, &query.peer_id).or_else(|| check_paused(&app_handle))
+    {
+        return rejected;
+    }
+    if let Err(invalid) = protocol::check_id("note_id", &query.note_id) {
+        return failure(StatusCode::BAD_REQUEST, invalid);
+    }
+
+    let note = match load_notes(&app_handle) {
+        Ok(notes) => notes.into_iter().find(|n| n.id == query.note_id),
+        Err(e) => return failure(StatusCode::INTERNAL_SERVER_ERROR, internal(e)),
+    };
+    let Some(note) = note else {
+        return failure(
+            StatusCode::NOT_FOUND,
+            SyncError::new("not_found", "No such note", false),
+        );
+    };
+    // The base waits for the peer to acknowledge saving it
+    let result = note_request(&app_handle, note)
+        .and_then(|request| serde_json::to_value(request).map_err(|e| e.to_string()));
+    match result {
+        Ok(body) => (StatusCode::OK, Json(body)),
+        Err(e) => failure(StatusCode::INTERNAL_SERVER_ERROR, internal(e)),
+    }
+}
+
+// The peer saved a note it fetched. If ours is still the version it got, that is the
+// version both have; if it changed since, the next sync sorts it out.
+async fn receive_ack(
+    State(app_handle): State<AppHandle<Wry>>,
+    headers: HeaderMap,
+    Json(ack): Json<NoteAck>,
+) -> Reply {
+    if let Some(rejected) = check_trusted(&app_handle, &headers, &ack.peer_id) {
+        return rejected;
+    }
+    if let Err(invalid) = protocol::check_id("note_id", &ack.note_id) {
+        return failure(StatusCode::BAD_REQUEST, invalid);
+    }
+
+    let note = match load_notes(&app_handle) {
+        Ok(notes) => notes.into_iter().find(|n| n.id == ack.note_id),
+        Err(e) => return failure(StatusCode::INTERNAL_SERVER_ERROR, internal(e)),
+    };
+    let recorded = note.is_some_and(|note| {
+        let current = hash_text(&merge::merge_text(&note)) == ack.hash;
+        if current {
+            merge::record_base(
+                &app_handle,
+                &ack.peer_id,
+                &note.id,
+                &render_note_file(&note),
+            );
+        }
+        current
+    });
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({ "success": true, "recorded": recorded })),
+    )
+}
+
+async fn receive_put(
+    State(app_handle): State<AppHandle<Wry>>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Reply {
+    let request = match read_note_request(body) {
+        Ok(request) => request,
+        Err(invalid) => return failure(StatusCode::BAD_REQUEST, invalid),
+    };
+    if let Some(rejected) =
+        check_trusted(&app_handle, &headers, &request.peer_id).or_else(|| check_paused(&app_handle))
+    {
+        return rejected;
+    }
+    if tombstones::is_deleted(&app_handle, &request.note.id) {
+        return (
+            StatusCode::OK,
+            Json(serde_json::json!({ "success": true, "deleted": true })),
+        );
+    }
+
+    match save_incoming(&app_handle, &request) {
+        Ok(_) => (StatusCode::OK, Json(serde_json::json!({ "success": true }))),
+        Err(e) => failure(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            SyncError::new("storage_failed", e, true),
+        ),
+    }
+}
+
+pub fn routes(app_handle: AppHandle<Wry>) -> axum::Router {
+    axum::Router::new()
+        .route(
+            "/sync/library/manifest",
+            axum::routing::post(receive_manifest),
+        )
+        .route("/sync/library/get", axum::routing::post(receive_get))
+        .route("/sync/library/put", axum::routing::post(receive_put))
+        .route("/sync/library/ack", axum::routing::post(receive_ack))
+        .with_state(app_handle)
+}
@@ -0,0 +1,256 @@
+use crate::proxy::{self, HttpFeature};
+use crate::{index, load_notes, markdown, Note};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tauri::{AppHandle, Wry};
+
+// Internal links to other notes, as written in markdown
+pub const NOTE_LINK_SCHEME: &str = "notes://note/";
+
+// Pause between requests to external sites so a long note doesn't hammer one server
+const EXTERNAL_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkScope {
+    All,
+    Note(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct BrokenLink {
+    // 1-based line in the note content
+    line: usize,
+    target: String,
+    reason: String,
+}
+
+// A note linking to another, or linked from it
+#[derive(Debug, Serialize)]
+pub struct LinkedNote {
+    // The link as written in the note, e.g. `[[Title]]`
+    target: String,
+    // Set when the link points to an existing note
+    note_id: Option<String>,
+    title: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NoteLinkReport {
+    note_id: String,
+    title: String,
+    broken: Vec<BrokenLink>,
+}
+
+// Targets of `[[Note title]]`, `[[Note title|alias]]` and `[[Note title#heading]]` links
+pub fn wikilinks(line: &str) -> Vec<String> {
+    let mut targets = Vec::new();
+    let mut rest = line;
+
+    while let Some(start) = rest.find("[[") {
+        let Some(len) = rest[start + 2..].find("]]") else {
+            break;
+        };
+        let inner = &rest[start + 2..start + 2 + len];
+        let target = inner.split(['|', '#']).next().unwrap_or("").trim();
+        if !target.is_empty() {
+            targets.push(target.to_string());
+        }
+        rest = &rest[start + 2 + len + 2..];
+    }
+
+    targets
+}
+
+// Every link in a note as (0-based line, target), skipping fenced code blocks
+pub fn note_links(content: &str) -> Vec<(usize, String)> {
+    let mut links = Vec::new();
+    let mut in_fence = false;
+
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+
+        for target in wikilinks(line) {
+            links.push((i, format!("[[{}]]", target)));
+        }
+        markdown::rewrite_link_targets(line, |target| {
+            links.push((i, target.to_string()));
+            None
+        });
+    }
+
+    links
+}
+
+async fn request_external(client: &reqwest::Client, url: &str) -> Option<String> {
+    let result = match client.head(url).send().await {
+        // Some servers don't implement HEAD; ask for the page instead
+        Ok(response) if response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED => {
+            client.get(url).send().await
+        }
+        other => other,
+    };
+
+    match result {
+        Ok(response)
+            if response.status().is_client_error() || response.status().is_server_error() =>
+        {
+            Some(format!("HTTP {}", response.status()))
+        }
+        Ok(_) => None,
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+// Check wikilinks and `notes://note/` links against existing notes and, when
+// `check_external` is set, web links by requesting them. Only notes with broken
+// links are included in the report.
+#[tauri::command]
+pub async fn check_links(
+    app_handle: AppHandle<Wry>,
+    scope: LinkScope,
+    check_external: Option<bool>,
+) -> Result<Vec<NoteLinkReport>, String> {
+    let notes = load_notes(&app_handle)?;
+    let ids: HashSet<&str> = notes.iter().map(|n| n.id.as_str()).collect();
+    let titles: HashSet<String> = notes.iter().map(|n| n.title.to_lowercase()).collect();
+
+    let checked: Vec<&Note> = match &scope {
+        LinkScope::All => notes.iter().collect(),
+        LinkScope::Note(id) => {
+            let note = notes.iter().find(|n| &n.id == id).ok_or("Note not found")?;
+            vec![note]
+        }
+    };
+
+    let client = proxy::config_for(&app_handle, HttpFeature::LinkCheck)
+        .apply(reqwest::Client::builder())?
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+    // Each external URL is only requested once per run
+    let mut external_results: HashMap<String, Option<String>> = HashMap::new();
+    let mut reports = Vec::new();
+
+    for note in checked {
+        let mut broken = Vec::new();
+
+        for (line, target) in note_links(&note.content) {
+            let reason =
+                if let Some(title) = target.strip_prefix("[[").and_then(|t| t.strip_suffix("]]")) {
+                    (!titles.contains(&title.to_lowercase()) && !ids.contains(title))
+                        .then(|| "No note with this title".to_string())
+                } else if let Some(id) = target.strip_prefix(NOTE_LINK_SCHEME) {
+                    (!ids.contains(id)).then(|| "Linked note no longer exists".to_string())
+                } else if check_external.unwrap_or(false)
+                    && (target.starts_with("http://") || target.starts_with("https://"))
+                {
+                    match external_results.get(&target) {
+                        Some(result) => result.clone(),
+                        None => {
+                            if !external_results.is_empty() {
+                                tokio::time::sleep(EXTERNAL_CHECK_INTERVAL).await;
+                            }
+                            let result = request_external(&client, &target).await;
+                            external_results.insert(target.clone(), result.clone());
+                            result
+                        }
+                    }
+                } else {
+                    None
+                };
+
+            if let Some(reason) = reason {
+                broken.push(BrokenLink {
+                    line: line + 1,
+                    target,
+                    reason,
+                });
+            }
+        }
+
+        if !broken.is_empty() {
+            reports.push(NoteLinkReport {
+                note_id: note.id.clone(),
+                title: note.title.clone(),
+                broken,
+            });
+        }
+    }
+
+    Ok(reports)
+}
+
+// Links from a note to others, each target once, in the order they first appear.
+// Links to notes that don't exist are included without an id.
+#[tauri::command]
+pub async fn get_outgoing_links(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+) -> Result<Vec<LinkedNote>, String> {
+    index::with_index(&app_handle, |index| -> Result<Vec<LinkedNote>, String> {
+        let entry = index.entries.get(&note_id).ok_or("Note not found")?;
+        let resolve = index.link_resolver();
+        let mut seen = HashSet::new();
+        Ok(entry
+            .links
+            .iter()
+            .filter(|target| is_note_link(target) && seen.insert(target.as_str()))
+            .map(|target| {
+                let linked = resolve(target);
+                LinkedNote {
+                    target: target.clone(),
+                    title: linked
+                        .as_ref()
+                        .and_then(|id| index.entries.get(id))
+                        .map(|e| e.title.clone()),
+                    note_id: linked,
+                }
+            })
+            .collect())
+    })?
+}
+
+// Notes that link to a note, sorted by title
+#[tauri::command]
+pub async fn get_backlinks(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+) -> Result<Vec<LinkedNote>, String> {
+    index::with_index(&app_handle, |index| -> Result<Vec<LinkedNote>, String> {
+        if !index.entries.contains_key(&note_id) {
+            return Err("Note not found".to_string());
+        }
+        let resolve = index.link_resolver();
+        let mut backlinks: Vec<LinkedNote> = index
+            .entries
+            .iter()
+            .filter(|(id, _)| **id != note_id)
+            .filter_map(|(id, entry)| {
+                let target = entry
+                    .links
+                    .iter()
+                    .find(|target| resolve(target).as_deref() == Some(note_id.as_str()))?;
+                Some(LinkedNote {
+                    target: target.clone(),
+                    note_id: Some(id.clone()),
+                    title: Some(entry.title.clone()),
+                })
+            })
+            .collect();
+        backlinks.sort_by_cached_key(|b| b.title.as_deref().unwrap_or("").to_lowercase());
+        Ok(backlinks)
+    })?
+}
+
+fn is_note_link(target: &str) -> bool {
+    target.starts_with("[[") || target.starts_with(NOTE_LINK_SCHEME)
+}
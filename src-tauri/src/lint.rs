@@ -0,0 +1,174 @@
+use crate::compression;
+use crate::markdown::{self, ATTACHMENT_SCHEME};
+use crate::tables;
+use crate::{get_attachments_dir, load_notes};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use tauri::{AppHandle, Wry};
+
+#[derive(Debug, Serialize)]
+pub struct LintWarning {
+    // 1-based line in the note content
+    line: usize,
+    kind: &'static str,
+    message: String,
+}
+
+fn warning(line: usize, kind: &'static str, message: String) -> LintWarning {
+    LintWarning {
+        line: line + 1,
+        kind,
+        message,
+    }
+}
+
+// Labels of reference definitions such as `[docs]: https://example.com`
+fn reference_definition(line: &str) -> Option<String> {
+    let rest = line.trim_start().strip_prefix('[')?;
+    let (label, after) = rest.split_once("]:")?;
+    (!label.is_empty() && !after.trim().is_empty()).then(|| label.to_lowercase())
+}
+
+// Labels used by `[text][label]` and `[label][]` links on a line
+fn reference_uses(line: &str) -> Vec<String> {
+    let mut labels = Vec::new();
+    let mut rest = line;
+
+    while let Some(pos) = rest.find("][") {
+        let before = &rest[..pos];
+        let after = &rest[pos + 2..];
+        let Some(end) = after.find(']') else {
+            break;
+        };
+
+        let label = match &after[..end] {
+            "" => before.rfind('[').map(|start| &before[start + 1..]),
+            label => Some(label),
+        };
+        if let Some(label) = label.filter(|l| !l.trim().is_empty()) {
+            labels.push(label.to_lowercase());
+        }
+        rest = &after[end + 1..];
+    }
+
+    labels
+}
+
+fn heading_text(line: &str) -> Option<String> {
+    let hashes = line.chars().take_while(|&c| c == '#').count();
+    if !(1..=6).contains(&hashes) || !line[hashes..].starts_with(' ') {
+        return None;
+    }
+    Some(
+        line[hashes..]
+            .trim()
+            .trim_end_matches('#')
+            .trim()
+            .to_lowercase(),
+    )
+}
+
+// Check a note's markdown for common mistakes. `attachment_exists` reports whether
+// an attachment of the note is present on disk.
+pub fn lint_markdown(content: &str, attachment_exists: impl Fn(&str) -> bool) -> Vec<LintWarning> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut warnings = Vec::new();
+
+    // Lines inside fenced code blocks are never markdown
+    let mut in_fence = false;
+    let prose: Vec<bool> = lines
+        .iter()
+        .map(|line| {
+            let fence =
+                line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~");
+            if fence {
+                in_fence = !in_fence;
+                return false;
+            }
+            !in_fence
+        })
+        .collect();
+    let prose_lines = || lines.iter().enumerate().filter(|(i, _)| prose[*i]);
+
+    let definitions: HashSet<String> = prose_lines()
+        .filter_map(|(_, line)| reference_definition(line))
+        .collect();
+    let mut headings: HashMap<String, usize> = HashMap::new();
+
+    for (i, line) in prose_lines() {
+        for label in reference_uses(line) {
+            if !definitions.contains(&label) {
+                warnings.push(warning(
+                    i,
+                    "broken-reference",
+                    format!("Reference link [{}] has no definition", label),
+                ));
+            }
+        }
+
+        if let Some(text) = heading_text(line) {
+            match headings.get(&text) {
+                Some(first) => warnings.push(warning(
+                    i,
+                    "duplicate-heading",
+                    format!("Duplicate heading, first used on line {}", first + 1),
+                )),
+                None => {
+                    headings.insert(text, i);
+                }
+            }
+        }
+
+        markdown::rewrite_link_targets(line, |target| {
+            if let Some(name) = target.strip_prefix(ATTACHMENT_SCHEME) {
+                let name = name.replace("%20", " ");
+                if !attachment_exists(&name) {
+                    warnings.push(warning(
+                        i,
+                        "missing-attachment",
+                        format!("Attachment {} does not exist", name),
+                    ));
+                }
+            }
+            None
+        });
+    }
+
+    let table_lines: Vec<&str> = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| if prose[i] { *line } else { "" })
+        .collect();
+    for (start, end) in tables::find_tables(&table_lines) {
+        let columns = tables::split_row(lines[start]).len();
+        for (i, line) in lines.iter().enumerate().take(end).skip(start + 1) {
+            let cells = tables::split_row(line).len();
+            if cells != columns {
+                warnings.push(warning(
+                    i,
+                    "malformed-table",
+                    format!("Row has {} cells but the header has {}", cells, columns),
+                ));
+            }
+        }
+    }
+
+    warnings.sort_by_key(|w| w.line);
+    warnings
+}
+
+#[tauri::command]
+pub async fn lint_note(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+) -> Result<Vec<LintWarning>, String> {
+    let note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    let attachments_dir = get_attachments_dir(&app_handle, &note.id);
+    Ok(lint_markdown(&note.content, |name| {
+        compression::attachment_exists(&attachments_dir, name)
+    }))
+}
@@ -0,0 +1,151 @@
+use crate::get_notes_dir;
+use serde::Serialize;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, Wry};
+
+// Advisory lock on the notes directory, so two instances (the app next to
+// `--headless-sync`, or two builds of the app sharing a notes directory) don't
+// write the same files. Launching the same app twice never gets this far; see
+// `launch`. The first instance holds an exclusive lock on `.himoji.lock` for as
+// long as it runs and writes who it is into it. Another app instance attaches
+// read-only: it lists and shows notes, but refuses to write them and doesn't start
+// the sync server or background jobs that would.
+//
+// The lock only keeps out instances of this app; other programs editing the notes
+// directory aren't affected.
+
+pub const LOCK_FILE: &str = ".himoji.lock";
+
+pub struct VaultLock {
+    dir: PathBuf,
+    // Open while we hold the lock; dropping it releases the lock
+    file: Option<File>,
+    // Who holds the lock when we don't, as written in the lock file
+    holder: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct VaultAccess {
+    pub read_only: bool,
+    pub notes_dir: String,
+    pub holder: Option<String>,
+}
+
+// Lock `dir` for this instance, described as `role` to instances that find it
+// locked. A lock file that can't be used at all leaves the notes writable, as
+// they were before locking existed.
+pub fn acquire(dir: &Path, role: &str) -> VaultLock {
+    let path = dir.join(LOCK_FILE);
+    let unlocked = |holder: Option<String>| VaultLock {
+        dir: dir.to_path_buf(),
+        file: None,
+        holder,
+    };
+
+    let mut file = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+    {
+        Ok(file) => file,
+        Err(e) => {
+            println!("Failed to open lock file {:?}: {}", path, e);
+            return unlocked(None);
+        }
+    };
+
+    match file.try_lock() {
+        Ok(()) => {
+            let owner = format!("{} (pid {})", role, std::process::id());
+            let written = file
+                .set_len(0)
+                .and_then(|()| file.write_all(owner.as_bytes()));
+            if let Err(e) = written {
+                println!("Failed to write lock file {:?}: {}", path, e);
+            }
+            VaultLock {
+                dir: dir.to_path_buf(),
+                file: Some(file),
+                holder: None,
+            }
+        }
+        Err(TryLockError::WouldBlock) => {
+            let mut owner = String::new();
+            let _ = file.read_to_string(&mut owner);
+            let owner = match owner.trim() {
+                "" => "another instance".to_string(),
+                owner => owner.to_string(),
+            };
+            println!(
+                "Notes in {:?} are locked by {}, opening read-only",
+                dir, owner
+            );
+            unlocked(Some(owner))
+        }
+        Err(TryLockError::Error(e)) => {
+            println!("Failed to lock {:?}: {}", path, e);
+            unlocked(None)
+        }
+    }
+}
+
+impl VaultLock {
+    pub fn holder(&self) -> Option<&str> {
+        self.holder.as_deref()
+    }
+}
+
+// Lock the notes directory the settings point at, releasing the one locked before
+pub fn lock_notes_dir(app_handle: &AppHandle<Wry>) {
+    let dir = get_notes_dir(app_handle);
+    match app_handle.try_state::<Mutex<VaultLock>>() {
+        Some(state) => {
+            if let Ok(mut current) = state.lock() {
+                // Release first, in case it's the same directory
+                current.file = None;
+                *current = acquire(&dir, "Himoji Notes");
+            }
+        }
+        None => {
+            app_handle.manage(Mutex::new(acquire(&dir, "Himoji Notes")));
+        }
+    }
+}
+
+// Who holds the notes directory, if another instance does
+pub fn holder(app_handle: &AppHandle<Wry>) -> Option<String> {
+    let state = app_handle.try_state::<Mutex<VaultLock>>()?;
+    let lock = state.lock().ok()?;
+    lock.holder().map(str::to_string)
+}
+
+pub fn is_read_only(app_handle: &AppHandle<Wry>) -> bool {
+    holder(app_handle).is_some()
+}
+
+// Refuse to write while another instance holds the notes directory
+pub fn ensure_writable(app_handle: &AppHandle<Wry>) -> Result<(), String> {
+    match holder(app_handle) {
+        Some(holder) => Err(format!(
+            "The notes are open in {}, so they are read-only here",
+            holder
+        )),
+        None => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub async fn get_vault_access(app_handle: AppHandle<Wry>) -> Result<VaultAccess, String> {
+    let state = app_handle.state::<Mutex<VaultLock>>();
+    let lock = state.lock().map_err(|e| e.to_string())?;
+    Ok(VaultAccess {
+        read_only: lock.holder().is_some(),
+        notes_dir: lock.dir.to_string_lossy().to_string(),
+        holder: lock.holder.clone(),
+    })
+}
@@ -1,33 +1,178 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use local_ip_address::local_ip;
-use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+mod anki;
+mod asset;
+mod atomic;
+mod attachments;
+mod automation;
+mod autosave;
+mod backup;
+mod blobs;
+mod capture;
+mod catalog;
+mod changes;
+mod clock;
+mod compression;
+mod conditions;
+mod covers;
+mod delta;
+mod document;
+mod embeddings;
+mod emoji;
+mod encryption;
+mod events;
+mod expiry;
+mod export;
+mod fields;
+mod frontmatter;
+mod headless;
+mod identity;
+mod import;
+mod index;
+mod keychain;
+mod launch;
+mod layout;
+mod library;
+mod links;
+mod lint;
+mod lock;
+mod markdown;
+mod merge;
+mod migrate;
+#[cfg(feature = "mock-peer")]
+mod mock_peer;
+mod network;
+mod normalize;
+mod notebooks;
+mod ordering;
+mod overrides;
+mod pairing;
+mod paste;
+mod pdf;
+mod peers;
+mod pins;
+mod plugins;
+mod protocol;
+mod proxy;
+mod query;
+mod readonly;
+mod related;
+mod render;
+mod schema;
+mod scratchpad;
+mod secret;
+mod server;
+mod session;
+mod settings;
+mod sketch;
+mod snippets;
+mod speech;
+mod stats;
+mod storage;
+mod summarize;
+mod switcher;
+mod tables;
+mod tags;
+mod terms;
+mod timestamps;
+mod tls;
+mod tombstones;
+mod translate;
+mod trash;
+mod tray;
+mod vault;
+mod webhooks;
+
+use changes::NoteChange;
+use plugins::PluginHook;
+use proxy::HttpFeature;
+use readonly::SaveError;
 use reqwest;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::Write;
-use std::net::{IpAddr, SocketAddr};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tauri::{AppHandle, Emitter, Manager, Wry};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 struct Note {
     id: String,
     title: String,
     content: String,
     datetime: String,
     attachments: Vec<String>,
+    // User-defined metadata, persisted in the note's frontmatter
+    #[serde(default)]
+    fields: BTreeMap<String, serde_json::Value>,
+    // Frontmatter tags and `#tags` in the content, filled in when the note is read
+    #[serde(default, skip_deserializing)]
+    tags: Vec<String>,
+    // The `folder` field, filled in like `tags`
+    #[serde(default, skip_deserializing)]
+    notebook: Option<String>,
+}
+
+// Everything about a note except its content, for listings that don't need the body
+#[derive(Debug, Serialize, Clone)]
+struct NoteMetadata {
+    id: String,
+    title: String,
+    datetime: String,
+    attachments: Vec<String>,
+    fields: BTreeMap<String, serde_json::Value>,
+    // Set when the note is pinned, filled in by `annotate_metadata`
+    pin: Option<pins::PinPosition>,
+    // The note's tags with their colors and icons, filled in by `annotate_metadata`
+    tags: Vec<tags::TagChip>,
+    #[serde(skip)]
+    tag_names: Vec<String>,
+    // Attachment used as the note's cover image
+    cover: Option<String>,
+    // Plain-text preview of the first paragraph
+    excerpt: String,
+    // Estimated minutes to read, filled in from the index by `annotate_metadata`
+    reading_minutes: Option<usize>,
+    // Whole days since the note was last edited, filled in like `reading_minutes`
+    days_since_edit: Option<u64>,
+}
+
+impl From<&Note> for NoteMetadata {
+    fn from(note: &Note) -> Self {
+        NoteMetadata {
+            id: note.id.clone(),
+            title: note.title.clone(),
+            datetime: note.datetime.clone(),
+            attachments: note.attachments.clone(),
+            fields: note.fields.clone(),
+            pin: None,
+            tags: Vec::new(),
+            tag_names: note.tags.clone(),
+            cover: covers::cover_of(note).map(str::to_string),
+            excerpt: markdown::excerpt(&note.content),
+            reading_minutes: None,
+            days_since_edit: None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct SyncRequest {
+    // Wire format version the request was written in
+    #[serde(default = "protocol::legacy_version")]
+    protocol: u32,
+    // Schema version of the sender's vault, so older notes can be upgraded on arrival
+    #[serde(default = "protocol::legacy_version")]
+    schema: u32,
     peer_id: String,
     peer_name: String,
     note: Note,
     attachments_data: HashMap<String, Vec<u8>>,
+    // Notes the sender deleted recently, so they are deleted here too
+    #[serde(default)]
+    tombstones: Vec<tombstones::Tombstone>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -36,6 +181,25 @@ struct PeerDevice {
     name: String,
     ip: IpAddr,
     port: u16,
+    // Every address the peer advertised, most likely reachable first
+    #[serde(default)]
+    addresses: Vec<IpAddr>,
+    // Sync protocol version the peer advertised
+    #[serde(default = "protocol::legacy_version")]
+    protocol: u32,
+    // Fingerprint of the certificate the peer serves sync on
+    #[serde(default)]
+    fingerprint: Option<String>,
+}
+
+impl PeerDevice {
+    fn addresses(&self) -> Vec<IpAddr> {
+        if self.addresses.is_empty() {
+            vec![self.ip]
+        } else {
+            self.addresses.clone()
+        }
+    }
 }
 
 // Structure to hold server binding information
@@ -50,6 +214,17 @@ enum SyncStatus {
     Pending,
     Accepted,
     Rejected,
+    // Saved next to our own copy as a new note
+    Copied,
+}
+
+// How to settle an incoming note, given in `respond_to_sync`
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SyncResolution {
+    KeepMine,
+    TakeTheirs,
+    KeepBoth,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -58,6 +233,11 @@ struct SyncNotification {
     from_peer: PeerDevice,
     note_title: String,
     status: SyncStatus,
+    #[serde(default)]
+    note_id: String,
+    // Our copy was edited too, so taking theirs would lose those edits
+    #[serde(default)]
+    conflict: bool,
 }
 
 // State to track discovered peers and sync notifications
@@ -69,145 +249,454 @@ struct AppState {
 }
 
 fn get_notes_dir(app_handle: &AppHandle<Wry>) -> PathBuf {
-    let mut path = app_handle
-        .path()
-        .app_data_dir()
-        .expect("Failed to get app data directory");
-    path.push("notes");
+    // A directory chosen in settings, or `notes` in the app data directory
+    let path = match settings::load_device_settings(app_handle).notes_dir {
+        Some(path) => path,
+        None => overrides::app_data_dir(app_handle)
+            .expect("Failed to get app data directory")
+            .join("notes"),
+    };
     fs::create_dir_all(&path).expect("Failed to create notes directory");
     path
 }
 
+// Where a note's attachments live. Only a lookup: the directory may not exist, and
+// the files of a note in cold storage stay packed (see `read_note_attachment`).
 fn get_attachments_dir(app_handle: &AppHandle<Wry>, note_id: &str) -> PathBuf {
     let mut path = get_notes_dir(app_handle);
     path.push("attachments");
     path.push(note_id);
-    fs::create_dir_all(&path).expect("Failed to create attachments directory");
     path
 }
 
+// A note's attachments directory to write into, created if needed. A note in cold
+// storage is unpacked first, so new files join the ones it has.
+fn writable_attachments_dir(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<PathBuf, String> {
+    storage::restore_note(app_handle, note_id)?;
+    let path = get_attachments_dir(app_handle, note_id);
+    fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+// Id for a new note. UUIDv7 ids sort by creation time; notes created before the
+// switch keep their v4 ids, which are accepted everywhere ids are.
+fn new_note_id() -> String {
+    uuid::Uuid::now_v7().to_string()
+}
+
+// Where a note's file is, in the directory of its notebook or at the top of the
+// notes directory. A note that doesn't exist yet starts at the top until it is
+// filed (see `notebooks::file_note`).
 fn get_note_path(app_handle: &AppHandle<Wry>, id: &str) -> PathBuf {
-    let mut path = get_notes_dir(app_handle);
-    path.push(format!("{}.md", id));
-    path
+    let notes_dir = get_notes_dir(app_handle);
+    let path = notes_dir.join(format!("{}.md", id));
+    if path.exists() {
+        return path;
+    }
+    notebooks::find_note_file(&notes_dir, id).unwrap_or(path)
 }
 
-#[tauri::command]
-async fn get_notes(app_handle: AppHandle<Wry>) -> Result<Vec<Note>, String> {
-    let notes_dir = get_notes_dir(&app_handle);
-    let mut notes = Vec::new();
+// Where a note received from a peer waits, as `<id>.md.sync`, until it is accepted
+fn get_sync_path(app_handle: &AppHandle<Wry>, id: &str) -> PathBuf {
+    let mut path = get_note_path(app_handle, id).into_os_string();
+    path.push(".sync");
+    PathBuf::from(path)
+}
 
-    for entry in fs::read_dir(notes_dir).map_err(|e| e.to_string())? {
-        let entry = entry.map_err(|e| e.to_string())?;
+// Build a note from the raw contents of its file
+fn note_from_file(id: &str, raw: &str, datetime: String, attachments: Vec<String>) -> Note {
+    let (fields, body) = frontmatter::split(raw);
+    let content = body.to_string();
+
+    // Parse the first line as title if it starts with #
+    let mut lines = content.lines();
+    let title = lines
+        .next()
+        .and_then(|line| {
+            if line.starts_with("# ") {
+                Some(line[2..].to_string())
+            } else {
+                None
+            }
+        })
+        .unwrap_or_else(|| "Untitled".to_string());
+
+    let mut note = Note {
+        id: id.to_string(),
+        title,
+        content,
+        // Notes saved before timestamps were kept go by the file's time
+        datetime: timestamps::datetime_of(&fields).unwrap_or(datetime),
+        attachments,
+        fields,
+        ..Default::default()
+    };
+    note.tags = tags::tags_of(&note);
+    note.notebook = ordering::folder_of(&note.fields).map(str::to_string);
+    note
+}
 
-        let path = entry.path();
-        if path.extension().and_then(|s| s.to_str()) == Some("md") {
-            if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
-                let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
-
-                // Get attachments for this note
-                let attachments_dir = get_attachments_dir(&app_handle, id);
-                let mut attachments = Vec::new();
-                if attachments_dir.exists() {
-                    for attachment in fs::read_dir(attachments_dir).map_err(|e| e.to_string())? {
-                        if let Ok(attachment) = attachment {
-                            if let Some(name) = attachment.file_name().to_str() {
-                                attachments.push(name.to_string());
-                            }
-                        }
+// Read a note's file along with the names of its attachments
+fn read_note(app_handle: &AppHandle<Wry>, id: &str, path: &Path) -> Result<Note, String> {
+    let raw = encryption::read_to_string(path).map_err(|e| e.to_string())?;
+
+    // Get attachments for this note
+    let attachments_dir = get_attachments_dir(app_handle, id);
+    let mut attachments = Vec::new();
+    if attachments_dir.exists() {
+        for attachment in fs::read_dir(attachments_dir).map_err(|e| e.to_string())? {
+            if let Ok(attachment) = attachment {
+                if let Some(name) = attachment.file_name().to_str() {
+                    if atomic::is_temp(name) {
+                        continue;
+                    }
+                    // Compressed attachments are listed under their own name
+                    let name = compression::attachment_name(name).to_string();
+                    if !attachments.contains(&name) {
+                        attachments.push(name);
                     }
                 }
-
-                // Parse the first line as title if it starts with #
-                let mut lines = content.lines();
-                let title = lines
-                    .next()
-                    .and_then(|line| {
-                        if line.starts_with("# ") {
-                            Some(line[2..].to_string())
-                        } else {
-                            None
-                        }
-                    })
-                    .unwrap_or_else(|| "Untitled".to_string());
-
-                let note = Note {
-                    id: id.to_string(),
-                    title,
-                    content,
-                    datetime: entry
-                        .metadata()
-                        .map_err(|e| e.to_string())?
-                        .modified()
-                        .map_err(|e| e.to_string())?
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .map_err(|e| e.to_string())?
-                        .as_secs_f64()
-                        .to_string(),
-                    attachments,
-                };
-                notes.push(note);
             }
         }
     }
 
-    notes.sort_by(|a, b| b.datetime.partial_cmp(&a.datetime).unwrap());
+    let datetime = fs::metadata(path)
+        .map_err(|e| e.to_string())?
+        .modified()
+        .map_err(|e| e.to_string())?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs_f64()
+        .to_string();
+    Ok(note_from_file(id, &raw, datetime, attachments))
+}
+
+// Read every note from disk, newest first
+fn load_notes(app_handle: &AppHandle<Wry>) -> Result<Vec<Note>, String> {
+    scan_notes(app_handle, |_, _| {})
+}
+
+// `load_notes`, calling `progress` with each batch of notes read and how far the
+// scan has got
+fn scan_notes(
+    app_handle: &AppHandle<Wry>,
+    mut progress: impl FnMut(&[Note], events::IndexProgress),
+) -> Result<Vec<Note>, String> {
+    let paths = notebooks::note_files(&get_notes_dir(app_handle));
+
+    let total = paths.len();
+    let mut notes = Vec::with_capacity(total);
+    let mut reported = 0;
+    for path in paths {
+        if let Some(id) = path.file_stem().and_then(|s| s.to_str()) {
+            notes.push(read_note(app_handle, id, &path)?);
+        }
+        if notes.len() - reported >= events::PROGRESS_BATCH {
+            progress(
+                &notes[reported..],
+                events::IndexProgress::notes(notes.len(), total),
+            );
+            reported = notes.len();
+        }
+    }
+    if reported > 0 && reported < notes.len() {
+        progress(
+            &notes[reported..],
+            events::IndexProgress::notes(notes.len(), total),
+        );
+    }
+
+    // Archived notes are listed like any other
+    notes.extend(storage::load_archived_notes(app_handle)?);
+
+    sort_newest_first(&mut notes);
+    Ok(notes)
+}
+
+fn sort_newest_first(notes: &mut [Note]) {
+    // Notes modified in the same instant list the most recently created first
+    notes.sort_by(|a, b| {
+        b.datetime
+            .partial_cmp(&a.datetime)
+            .unwrap()
+            .then_with(|| b.id.cmp(&a.id))
+    });
+}
+
+#[tauri::command]
+async fn get_notes(app_handle: AppHandle<Wry>) -> Result<Vec<Note>, String> {
+    // Large vaults take a while to read, so the frontend gets what has been read
+    // so far along with the progress
+    let mut notes = scan_notes(&app_handle, |batch, progress| {
+        events::index_progress(&app_handle, progress.with_notes(batch));
+    })?;
+    let sort_mode = settings::load_vault_settings(&app_handle).sort_mode;
+    ordering::sort_notes(&app_handle, &mut notes, sort_mode);
+    pins::pinned_first(&app_handle, &mut notes);
+    secret::redact(&mut notes);
     Ok(notes)
 }
 
+// Just the given notes, for refreshing the ones a `notes-updated` event names.
+// Notes that no longer exist are left out.
 #[tauri::command]
-async fn save_note(app_handle: AppHandle<Wry>, note: Note) -> Result<(), String> {
-    let path = get_note_path(&app_handle, &note.id);
-    let note_content = format!("# {}\n\n{}", note.title, note.content); // Prepend title as markdown header
-    fs::write(path, note_content).map_err(|e| e.to_string())?;
+async fn get_notes_by_id(
+    app_handle: AppHandle<Wry>,
+    note_ids: Vec<String>,
+) -> Result<Vec<Note>, String> {
+    let mut notes = Vec::new();
+    let mut missing = Vec::new();
+    for id in note_ids {
+        let path = get_note_path(&app_handle, &id);
+        if path.exists() {
+            notes.push(read_note(&app_handle, &id, &path)?);
+        } else {
+            missing.push(id);
+        }
+    }
+    // Archived notes only come as part of the whole archive
+    if !missing.is_empty() {
+        let archived = storage::load_archived_notes(&app_handle)?;
+        notes.extend(archived.into_iter().filter(|n| missing.contains(&n.id)));
+    }
+    secret::redact(&mut notes);
+    Ok(notes)
+}
+
+// A single note with its full content
+#[tauri::command]
+async fn get_note(app_handle: AppHandle<Wry>, note_id: String) -> Result<Note, String> {
+    get_notes_by_id(app_handle, vec![note_id])
+        .await?
+        .pop()
+        .ok_or_else(|| "Note not found".to_string())
+}
+
+// Every note without its content, sorted by `sort_mode`. The parts that come from
+// settings are left for `annotate_metadata`.
+fn list_metadata(
+    app_handle: &AppHandle<Wry>,
+    sort_mode: ordering::SortMode,
+) -> Result<Vec<NoteMetadata>, String> {
+    // From the catalog, which only reads notes changed since the last listing. An
+    // encrypted vault has none.
+    let listing = match catalog::list(app_handle) {
+        Ok(Some(mut listing)) => {
+            listing
+                .notes
+                .extend(storage::load_archived_notes(app_handle)?);
+            listing
+        }
+        result => {
+            if let Err(e) = result {
+                println!("Note catalog unavailable, reading every note: {}", e);
+            }
+            catalog::Listing {
+                notes: load_notes(app_handle)?,
+                excerpts: HashMap::new(),
+            }
+        }
+    };
+    let catalog::Listing {
+        mut notes,
+        excerpts,
+    } = listing;
+
+    sort_newest_first(&mut notes);
+    ordering::sort_notes(app_handle, &mut notes, sort_mode);
+
+    let metadata = notes
+        .iter()
+        .map(|note| {
+            let mut metadata = NoteMetadata::from(note);
+            if let Some(excerpt) = excerpts.get(&note.id) {
+                metadata.excerpt = excerpt.clone();
+            }
+            secret::redact_metadata(&mut metadata, note);
+            metadata
+        })
+        .collect();
+    Ok(metadata)
+}
+
+// Every note without its content, in the same order as `get_notes`, for the sidebar
+#[tauri::command]
+async fn get_notes_metadata(app_handle: AppHandle<Wry>) -> Result<Vec<NoteMetadata>, String> {
+    let sort_mode = settings::load_vault_settings(&app_handle).sort_mode;
+    let mut metadata = list_metadata(&app_handle, sort_mode)?;
+    annotate_metadata(&app_handle, &mut metadata);
+    Ok(metadata)
+}
+
+// What the note list shows of a note
+#[derive(Debug, Serialize)]
+struct NotePreview {
+    id: String,
+    title: String,
+    // Plain text from the start of the body
+    preview: String,
+    attachment_count: usize,
+    // Seconds since the epoch, like `Note::datetime`
+    created: f64,
+    updated: f64,
+}
+
+// Previews of every note, in the vault's sort order, for list views that don't need
+// the content
+#[tauri::command]
+async fn get_note_previews(app_handle: AppHandle<Wry>) -> Result<Vec<NotePreview>, String> {
+    let sort_mode = settings::load_vault_settings(&app_handle).sort_mode;
+    Ok(list_metadata(&app_handle, sort_mode)?
+        .into_iter()
+        .map(|note| NotePreview {
+            created: timestamps::created_of(&note.fields, &note.datetime),
+            updated: note.datetime.parse().unwrap_or_default(),
+            attachment_count: note.attachments.len(),
+            preview: note.excerpt,
+            title: note.title,
+            id: note.id,
+        })
+        .collect())
+}
+
+#[derive(Debug, Serialize)]
+struct NotePage {
+    notes: Vec<NoteMetadata>,
+    // Notes in the whole listing, to tell when the last page has been reached
+    total: usize,
+}
+
+// A page of the note listing, without content, for opening large libraries without
+// reading every note. `sort` defaults to the vault's sort mode; bodies are loaded
+// one at a time with `get_note`.
+#[tauri::command]
+async fn list_notes(
+    app_handle: AppHandle<Wry>,
+    offset: usize,
+    limit: usize,
+    sort: Option<ordering::SortMode>,
+) -> Result<NotePage, String> {
+    let sort_mode = sort.unwrap_or(settings::load_vault_settings(&app_handle).sort_mode);
+    let notes = list_metadata(&app_handle, sort_mode)?;
+    let total = notes.len();
+    let mut page: Vec<NoteMetadata> = notes.into_iter().skip(offset).take(limit).collect();
+    annotate_metadata(&app_handle, &mut page);
+    Ok(NotePage { notes: page, total })
+}
+
+// Fill in the parts of a metadata listing that come from settings rather than the notes
+fn annotate_metadata(app_handle: &AppHandle<Wry>, notes: &mut [NoteMetadata]) {
+    pins::annotate(app_handle, notes);
+    tags::annotate(app_handle, notes);
+    index::annotate(app_handle, notes);
+}
+
+// Serialize a note to its on-disk form: frontmatter, a title heading, then the body.
+// `content` may already start with the title heading (as returned by `get_notes`),
+// in which case it is replaced rather than duplicated. A first heading that isn't
+// the title is part of the body and stays.
+fn render_note_file(note: &Note) -> String {
+    let (first, rest) = note.content.split_once('\n').unwrap_or((&note.content, ""));
+    let body = match first.strip_prefix("# ") {
+        Some(heading) if heading.trim() == note.title.trim() => rest,
+        _ => note.content.as_str(),
+    };
+
+    format!(
+        "{}# {}\n\n{}",
+        frontmatter::render(&note.fields),
+        note.title,
+        body.trim_start_matches('\n')
+    )
+}
+
+// Write a note to disk, running save plugins and webhooks along the way. A note
+// that is read-only or locked with a passphrase is refused.
+async fn persist_note(app_handle: &AppHandle<Wry>, note: Note) -> Result<(), String> {
+    readonly::ensure_writable(app_handle, &note.id).map_err(|e| e.to_string())?;
+    persist_note_unchecked(app_handle, note).await
+}
+
+// `persist_note` for the writers that have to change protected notes: sync, moving
+// a whole notebook, and locking, unlocking or unprotecting a note itself
+async fn persist_note_unchecked(app_handle: &AppHandle<Wry>, note: Note) -> Result<(), String> {
+    lock::ensure_writable(app_handle)?;
+    let mut note = plugins::run_hook(app_handle, PluginHook::Save, note).await;
+    clock::stamp(app_handle, &mut note);
+    storage::restore_note(app_handle, &note.id)?;
+    let path = get_note_path(app_handle, &note.id);
+    let is_new = !path.exists();
+    timestamps::stamp(&mut note, &path);
+    encryption::write(app_handle, path, render_note_file(&note))?;
+    notebooks::file_note(app_handle, &note.id, ordering::folder_of(&note.fields))?;
+
+    // Webhooks, the frontend and the index hear about it from here
+    let change = if is_new {
+        NoteChange::Created(note)
+    } else {
+        NoteChange::Updated(note)
+    };
+    changes::publish(app_handle, change);
     Ok(())
 }
 
+// Save a note from the editor. With `expected_revision`, the revision the edit
+// started from, a note changed meanwhile is refused with a conflict instead of
+// being overwritten. Returns the note's new revision.
 #[tauri::command]
-async fn delete_note(app_handle: AppHandle<Wry>, note_id: String) -> Result<(), String> {
-    // Delete the note file
-    let note_path = get_note_path(&app_handle, &note_id);
-    if note_path.exists() {
-        fs::remove_file(note_path).map_err(|e| e.to_string())?;
+async fn save_note(
+    app_handle: AppHandle<Wry>,
+    mut note: Note,
+    expected_revision: Option<String>,
+) -> Result<Option<String>, SaveError> {
+    readonly::ensure_writable(&app_handle, &note.id)?;
+    if let Some(expected) = &expected_revision {
+        readonly::ensure_current(&app_handle, &note.id, expected)?;
     }
 
-    // Delete attachments directory
-    let attachments_dir = get_attachments_dir(&app_handle, &note_id);
-    if attachments_dir.exists() {
-        fs::remove_dir_all(attachments_dir).map_err(|e| e.to_string())?;
+    let settings = settings::load_vault_settings(&app_handle);
+    if settings.format_tables_on_save {
+        note.content = tables::format_all_tables(&note.content);
     }
+    if settings.normalize_markdown_on_save && !normalize::is_opted_out(&note) {
+        note.content = normalize::normalize_markdown(&note.content);
+    }
+    let id = note.id.clone();
+    persist_note(&app_handle, note).await?;
 
-    Ok(())
+    let saved = read_note(&app_handle, &id, &get_note_path(&app_handle, &id))?;
+    Ok(readonly::revision_of(&saved))
+}
+
+#[tauri::command]
+async fn delete_note(app_handle: AppHandle<Wry>, note_id: String) -> Result<(), String> {
+    // Move the note and its attachments to the trash, from where it can be restored
+    storage::restore_note(&app_handle, &note_id)?;
+    if get_note_path(&app_handle, &note_id).exists() {
+        trash::move_to_trash(&app_handle, &note_id)?;
+    }
+    // Peers delete their copy too instead of sending it back
+    tombstones::record(&app_handle, &note_id)
 }
 
 #[tauri::command]
 async fn save_attachment(
     app_handle: AppHandle<Wry>,
     note_id: String,
-    source_path: Option<String>, // Path from file or image blob
+    source_path: Option<String>, // Path of a file picked by the user
     image_data: Option<Vec<u8>>, // Optional binary data for pasted images
+    file_name: Option<String>,   // Name the data came with, if any
 ) -> Result<String, String> {
-    let attachments_dir = get_attachments_dir(&app_handle, &note_id);
-
-    // Set up a unique filename with timestamp
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map_err(|e| e.to_string())?
-        .as_secs();
-    let file_name = format!("{}_image.png", timestamp);
-    let dest_path = attachments_dir.join(&file_name);
-
     if let Some(data) = image_data {
-        // Save the image data directly if provided
-        std::fs::write(dest_path.clone(), data).map_err(|e| e.to_string())?;
+        attachments::store(&app_handle, &note_id, file_name.as_deref(), &data)
     } else if let Some(path) = source_path {
         let source_path = PathBuf::from(path);
-        std::fs::copy(source_path, dest_path.clone()).map_err(|e| e.to_string())?;
+        let data = fs::read(&source_path).map_err(|e| e.to_string())?;
+        let name = source_path.file_name().and_then(|n| n.to_str());
+        attachments::store(&app_handle, &note_id, file_name.as_deref().or(name), &data)
     } else {
-        return Err("No valid image source provided".to_string());
+        Err("No attachment source provided".to_string())
     }
-
-    Ok(file_name) // Return the saved filename
 }
 
 #[tauri::command]
@@ -217,31 +706,13 @@ async fn save_clipboard_image(
     file_name: String,
     image_data: Vec<u8>,
 ) -> Result<String, String> {
-    let attachment_dir = get_attachments_dir(&app_handle, &note_id);
+    let attachment_dir = writable_attachments_dir(&app_handle, &note_id)?;
     let file_path = attachment_dir.join(&file_name);
-
-    File::create(&file_path)
-        .and_then(|mut file| file.write_all(&image_data))
-        .map_err(|e| e.to_string())?;
+    compression::write_attachment(&app_handle, &file_path, &image_data)?;
 
     Ok(file_name)
 }
 
-#[tauri::command]
-async fn serve_attachment(
-    app: tauri::AppHandle,
-    note_id: String,
-    file_name: String,
-) -> Result<Vec<u8>, String> {
-    let attachment_path = get_attachments_dir(&app, &note_id).join(&file_name);
-
-    if !attachment_path.exists() {
-        return Err("File not found".into());
-    }
-
-    fs::read(&attachment_path).map_err(|e| e.to_string())
-}
-
 // Network discovery functions
 #[tauri::command]
 async fn get_peers(app_handle: AppHandle<Wry>) -> Result<Vec<PeerDevice>, String> {
@@ -251,12 +722,34 @@ async fn get_peers(app_handle: AppHandle<Wry>) -> Result<Vec<PeerDevice>, String
     Ok(app_state.peers.values().cloned().collect())
 }
 
+// Let the user know a note or an answer didn't get through to a peer
+fn report_sync_failure(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    peer_id: &str,
+    error: protocol::SyncError,
+) {
+    println!(
+        "Sync with {} failed for note {}: {}",
+        peer_id, note_id, error
+    );
+    let _ = app_handle.emit(
+        "sync-failed",
+        serde_json::json!({
+            "note_id": note_id,
+            "peer_id": peer_id,
+            "error": error,
+        }),
+    );
+}
+
 #[tauri::command]
 async fn share_note(
     app_handle: AppHandle<Wry>,
     note_id: String,
     peer_id: String,
 ) -> Result<(), String> {
+    conditions::check_sync_paused(&app_handle)?;
     let state = app_handle.state::<Arc<Mutex<AppState>>>();
 
     // Get the peer device - we need to drop the mutex guard before await
@@ -278,14 +771,10 @@ async fn share_note(
 
     // Read attachments data
     let mut attachments_data = HashMap::new();
-    let attachments_dir = get_attachments_dir(&app_handle, &note_id);
-
     for attachment_name in &note.attachments {
-        let attachment_path = attachments_dir.join(attachment_name);
-        if attachment_path.exists() {
-            if let Ok(data) = fs::read(&attachment_path) {
-                attachments_data.insert(attachment_name.clone(), data);
-            }
+        if let Ok(data) = compression::read_note_attachment(&app_handle, &note_id, attachment_name)
+        {
+            attachments_data.insert(attachment_name.clone(), data);
         }
     }
 
@@ -297,27 +786,36 @@ async fn share_note(
 
     // Create the sync request
     let sync_request = SyncRequest {
+        protocol: protocol::negotiate(peer.protocol),
+        schema: schema::SCHEMA_VERSION,
         peer_id: device_id,
         peer_name: device_name,  // Use our local device name
         note: note.clone(),
         attachments_data,
+        tombstones: tombstones::current(&app_handle, peer.protocol),
     };
 
+    changes::publish(
+        &app_handle,
+        NoteChange::Shared {
+            note: note.clone(),
+            peer_id: peer_id.clone(),
+        },
+    );
+    merge::record_sent(&app_handle, &peer.id, note);
+
     // Send the sync request to the peer
-    let client = reqwest::Client::new();
-    let url = format!("http://{}:{}/sync/request", peer.ip, peer.port);
+    let client = tls::client(&app_handle, &peer)?;
 
     tokio::spawn(async move {
-        let result = client
-            .post(&url)
-            .json(&sync_request)
-            .timeout(Duration::from_secs(5))
-            .send()
-            .await;
-
-        if let Err(e) = result {
-            println!("Failed to send sync request: {}", e);
-        }
+        send_note(
+            &app_handle,
+            &client,
+            &peer,
+            sync_request,
+            Duration::from_secs(5),
+        )
+        .await;
     });
 
     Ok(())
@@ -330,6 +828,18 @@ async fn share_notes(
     peer_id: String,
 ) -> Result<(), String> {
     println!("Sharing {} notes with peer {}", note_ids.len(), peer_id);
+
+    conditions::check_sync_paused(&app_handle)?;
+    // Sending many notes at once waits out metered connections and low battery
+    if note_ids.len() > 1 && conditions::check_bulk_work(&app_handle).await.is_err() {
+        println!(
+            "Queued {} notes for {} until sync can resume",
+            note_ids.len(),
+            peer_id
+        );
+        conditions::defer_share(&app_handle, &peer_id, note_ids);
+        return Ok(());
+    }
     
     let state = app_handle.state::<Arc<Mutex<AppState>>>();
 
@@ -353,10 +863,14 @@ async fn share_notes(
 
     // Find the notes
     let all_notes = get_notes(app_handle.clone()).await?;
-    let client = reqwest::Client::new();
-    let url = format!("http://{}:{}/sync/request", peer.ip, peer.port);
+    let tombstones = tombstones::current(&app_handle, peer.protocol);
+    let sync_proxy = proxy::config_for(&app_handle, HttpFeature::Sync);
+    let peer_addresses = peer.addresses();
     
-    println!("Will send requests to URL: {}", url);
+    println!(
+        "Will send requests to {:?} on port {}",
+        peer_addresses, peer.port
+    );
     println!("Our device: {} ({})", device_name, device_id);
 
     // Process each note
@@ -374,70 +888,118 @@ async fn share_notes(
 
         // Read attachments data
         let mut attachments_data = HashMap::new();
-        let attachments_dir = get_attachments_dir(&app_handle, &note_id);
-
         for attachment_name in &note.attachments {
-            let attachment_path = attachments_dir.join(attachment_name);
-            if attachment_path.exists() {
-                if let Ok(data) = fs::read(&attachment_path) {
-                    attachments_data.insert(attachment_name.clone(), data.clone());
-                    println!("Added attachment: {}, size: {} bytes", attachment_name, data.len());
-                }
+            if let Ok(data) =
+                compression::read_note_attachment(&app_handle, &note_id, attachment_name)
+            {
+                println!(
+                    "Added attachment: {}, size: {} bytes",
+                    attachment_name,
+                    data.len()
+                );
+                attachments_data.insert(attachment_name.clone(), data);
             }
         }
 
         // Create the sync request with correct device info
         let sync_request = SyncRequest {
+            protocol: protocol::negotiate(peer.protocol),
+            schema: schema::SCHEMA_VERSION,
             peer_id: device_id.clone(),
             peer_name: device_name.clone(),  // Our own device name, not peer.name
             note: note.clone(),
             attachments_data,
+            tombstones: tombstones.clone(),
         };
+        let sync_tls = pairing::authorize(
+            &app_handle,
+            &peer.id,
+            tls::for_peer(reqwest::Client::builder(), &peer)?,
+        );
 
         // Send the sync request to the peer - create a new client with custom settings for each request
-        // to avoid payload size issues
-        let url_clone = url.clone();
+        // to avoid payload size issues. A client that can't be built fails the share
+        // rather than falling back to one that doesn't check the peer's certificate.
+        let custom_client = sync_proxy
+            .apply(sync_tls)?
+            .pool_max_idle_per_host(0) // Don't reuse connections
+            .tcp_keepalive(None) // Disable keepalive
+            .tcp_nodelay(true) // Prioritize low latency
+            .build()
+            .map_err(|e| e.to_string())?;
 
-        tokio::spawn(async move {
-            println!("Sending sync request for note: {}", note.id);
+        changes::publish(
+            &app_handle,
+            NoteChange::Shared {
+                note: note.clone(),
+                peer_id: peer_id.clone(),
+            },
+        );
+        merge::record_sent(&app_handle, &peer.id, &note);
 
-            // Create a custom client with larger limits
-            let custom_client = reqwest::Client::builder()
-                .pool_max_idle_per_host(0) // Don't reuse connections
-                .tcp_keepalive(None) // Disable keepalive
-                .tcp_nodelay(true) // Prioritize low latency
-                .build()
-                .unwrap_or_else(|_| reqwest::Client::new());
+        let peer = peer.clone();
+        let app_handle = app_handle.clone();
 
+        tokio::spawn(async move {
+            println!("Sending sync request for note: {}", note.id);
             // Use a longer timeout for larger payloads
-            let result = custom_client
-                .post(&url_clone)
-                .json(&sync_request)
-                .timeout(Duration::from_secs(60)) // Increase timeout to 60 seconds
-                .send()
-                .await;
-
-            match result {
-                Ok(response) => {
-                    println!(
-                        "Sync request sent successfully for note: {}, status: {}",
-                        note.id,
-                        response.status()
-                    );
-                    if let Ok(text) = response.text().await {
-                        println!("Response body: {}", text);
-                    }
-                }
-                Err(e) => {
-                    println!("Failed to send sync request for note {}: {}", note.id, e);
-                }
-            }
+            send_note(
+                &app_handle,
+                &custom_client,
+                &peer,
+                sync_request,
+                Duration::from_secs(60),
+            )
+            .await;
         });
     }
 
     Ok(())
 }
 
+// Send a note to a peer, leaving out what the peer already has. A note the peer
+// holds as it is isn't sent at all, and is confirmed as synced right away.
+async fn send_note(
+    app_handle: &AppHandle<Wry>,
+    client: &reqwest::Client,
+    peer: &PeerDevice,
+    mut sync_request: SyncRequest,
+    timeout: Duration,
+) {
+    let note_id = sync_request.note.id.clone();
+    let current = delta::trim(
+        client,
+        peer,
+        &sync_request.peer_id,
+        &sync_request.note,
+        &mut sync_request.attachments_data,
+    )
+    .await;
+    if current {
+        println!("{} already has note {}", peer.name, note_id);
+        merge::confirm_sent(app_handle, &peer.id, &note_id);
+        return;
+    }
+
+    let result = network::post_to_peer(
+        client,
+        &peer.addresses(),
+        peer.port,
+        "/sync/request",
+        &sync_request,
+        timeout,
+    )
+    .await;
+    match result {
+        Ok(response) => {
+            if let Ok(text) = response.text().await {
+                merge::confirm_if_merged(app_handle, &peer.id, &note_id, &text);
+            }
+        }
+        Err(e) => report_sync_failure(app_handle, &note_id, &peer.id, e),
+    }
+}
+
 #[tauri::command]
 async fn get_sync_notifications(
     app_handle: AppHandle<Wry>,
@@ -448,131 +1010,176 @@ async fn get_sync_notifications(
     Ok(app_state.sync_notifications.clone())
 }
 
+// Move the files in `from` into a note's attachments, replacing any of the same
+// name, and return their names
+fn move_attachments(
+    app_handle: &AppHandle<Wry>,
+    from: &Path,
+    note_id: &str,
+) -> Result<Vec<String>, String> {
+    let to = writable_attachments_dir(app_handle, note_id)?;
+    let mut names = Vec::new();
+    for entry in fs::read_dir(from).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let target = to.join(entry.file_name());
+        // What's there may share its file with other notes, so it is replaced,
+        // never written into
+        blobs::unshare_file(app_handle, &target);
+        let _ = fs::remove_file(&target);
+        // The app data and notes directories may be on different drives
+        fs::rename(entry.path(), &target)
+            .or_else(|_| fs::copy(entry.path(), &target).map(|_| ()))
+            .map_err(|e| e.to_string())?;
+        if let Err(e) = blobs::share(app_handle, &target) {
+            println!("Failed to deduplicate attachment {:?}: {}", target, e);
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            let name = compression::attachment_name(name).to_string();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    let _ = fs::remove_dir_all(from);
+    Ok(names)
+}
+
 #[tauri::command]
 async fn respond_to_sync(
     app_handle: AppHandle<Wry>,
     notification_id: String,
     accept: bool,
+    resolution: Option<SyncResolution>,
 ) -> Result<(), String> {
+    // Without a resolution, accepting takes their version and rejecting keeps ours
+    let resolution = resolution.unwrap_or(if accept {
+        SyncResolution::TakeTheirs
+    } else {
+        SyncResolution::KeepMine
+    });
     let state = app_handle.state::<Arc<Mutex<AppState>>>();
 
     // Extract needed data and release mutex before await
-    let (peer, note_id, _notification_status) = {
+    let (peer, note_id, note_title, conflict) = {
         let mut app_state = state.lock().map_err(|e| e.to_string())?;
 
         // Find the notification
-        let notification_index = app_state
+        let notification = app_state
             .sync_notifications
-            .iter()
-            .position(|n| n.id == notification_id)
+            .iter_mut()
+            .find(|n| n.id == notification_id)
             .ok_or("Notification not found")?;
 
-        let notification = &mut app_state.sync_notifications[notification_index];
-        let peer = notification.from_peer.clone();
-
-        // Get the note ID from the temporary sync file
-        let notes_dir = get_notes_dir(&app_handle);
-        let mut note_id = String::new();
-
-        for entry in fs::read_dir(notes_dir).map_err(|e| e.to_string())? {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(ext) = path.extension() {
-                    if ext == "sync" {
-                        if let Some(stem) = path.file_stem() {
-                            if let Some(stem_str) = stem.to_str() {
-                                note_id = stem_str.to_string();
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
         // Update the notification status
-        notification.status = if accept {
-            SyncStatus::Accepted
-        } else {
-            SyncStatus::Rejected
+        notification.status = match resolution {
+            SyncResolution::TakeTheirs => SyncStatus::Accepted,
+            SyncResolution::KeepMine => SyncStatus::Rejected,
+            SyncResolution::KeepBoth => SyncStatus::Copied,
         };
 
-        (peer, note_id, notification.status.clone())
+        (
+            notification.from_peer.clone(),
+            notification.note_id.clone(),
+            notification.note_title.clone(),
+            notification.conflict,
+        )
     };
 
-    // Handle the sync file based on accept/reject decision
-    if accept && !note_id.is_empty() {
-        // Move the temporary .sync file to a regular note file
-        let notes_dir = get_notes_dir(&app_handle);
-        let sync_path = notes_dir.join(format!("{}.sync", note_id));
-        let note_path = notes_dir.join(format!("{}.md", note_id));
-
-        if sync_path.exists() {
-            if let Err(e) = fs::rename(&sync_path, &note_path) {
-                println!("Failed to rename sync file: {}", e);
-                // Try copy instead of rename
-                if let Ok(content) = fs::read_to_string(&sync_path) {
-                    let _ = fs::write(&note_path, content);
-                    let _ = fs::remove_file(&sync_path);
+    let sync_path = get_sync_path(&app_handle, &note_id);
+    let note_path = get_note_path(&app_handle, &note_id);
+    // Attachments that came with the note were set aside instead of replacing ours
+    let incoming_attachments = merge::incoming_attachments_dir(&app_handle, &note_id)?;
+
+    // Handle the sync file based on the resolution
+    match resolution {
+        SyncResolution::TakeTheirs => {
+            // Move the temporary .sync file to a regular note file
+            let is_new = !note_path.exists();
+
+            if sync_path.exists() {
+                if let Err(e) = fs::rename(&sync_path, &note_path) {
+                    println!("Failed to rename sync file: {}", e);
+                    // Try copy instead of rename
+                    if let Ok(content) = fs::read(&sync_path) {
+                        let _ = atomic::write(&note_path, content);
+                        let _ = fs::remove_file(&sync_path);
+                    }
                 }
             }
-        }
 
-        // The attachments should already be in place from when we received the sync request
+            move_attachments(&app_handle, &incoming_attachments, &note_id)?;
 
-        // Notify frontend to refresh notes
-        app_handle
-            .emit("notes-updated", ())
-            .map_err(|e| e.to_string())?;
-    } else if !note_id.is_empty() {
-        // Delete the temporary file and attachments if rejected
-        let notes_dir = get_notes_dir(&app_handle);
-        let sync_path = notes_dir.join(format!("{}.sync", note_id));
-        if sync_path.exists() {
-            let _ = fs::remove_file(sync_path);
-        }
+            // Both devices have this version now; later concurrent edits merge against it
+            if let Ok(content) = encryption::read_to_string(&note_path) {
+                merge::record_base(&app_handle, &peer.id, &note_id, &content);
+            }
 
-        // Also consider cleaning up any attachments that were pre-saved
-        let attachments_dir = get_attachments_dir(&app_handle, &note_id);
-        if attachments_dir.exists() {
-            let _ = fs::remove_dir_all(attachments_dir);
+            // Notify frontend of the accepted note
+            changes::publish_saved(&app_handle, &note_id, is_new)?;
+        }
+        SyncResolution::KeepBoth => {
+            // Their version becomes a note of its own, and ours stays as it is
+            if !conflict {
+                return Err("Only a conflicting note can be kept as a copy".to_string());
+            }
+            let raw = encryption::read_to_string(&sync_path).map_err(|e| e.to_string())?;
+            let mut copy = note_from_file(&note_id, &raw, String::new(), Vec::new());
+            copy.id = new_note_id();
+            copy.title = format!("{} ({})", note_title, peer.name);
+            copy.attachments = move_attachments(&app_handle, &incoming_attachments, &copy.id)?;
+
+            persist_note(&app_handle, copy).await?;
+            let _ = fs::remove_file(&sync_path);
+        }
+        SyncResolution::KeepMine => {
+            // Delete the temporary file and the attachments that came with it
+            if sync_path.exists() {
+                let _ = fs::remove_file(&sync_path);
+            }
+            let _ = fs::remove_dir_all(&incoming_attachments);
         }
     }
 
     // Notify the peer about the response
-    let client = reqwest::Client::new();
-    let url = format!("http://{}:{}/sync/response", peer.ip, peer.port);
+    let client = tls::client(&app_handle, &peer)?;
 
+    let device_id = state.lock().map_err(|e| e.to_string())?.device_id.clone();
     let response = serde_json::json!({
         "notification_id": notification_id,
-        "accepted": accept
+        "peer_id": device_id,
+        "note_id": note_id,
+        "accepted": resolution == SyncResolution::TakeTheirs,
+        "protocol": protocol::negotiate(peer.protocol)
     });
 
     tokio::spawn(async move {
-        let result = client
-            .post(&url)
-            .json(&response)
-            .timeout(Duration::from_secs(5))
-            .send()
-            .await;
+        let result = network::post_to_peer(
+            &client,
+            &peer.addresses(),
+            peer.port,
+            "/sync/response",
+            &response,
+            Duration::from_secs(5),
+        )
+        .await;
 
         if let Err(e) = result {
-            println!("Failed to send sync response: {}", e);
+            report_sync_failure(&app_handle, &note_id, &peer.id, e);
         }
     });
 
     Ok(())
 }
 
-#[tauri::command]
-async fn open_notes_dir(app_handle: AppHandle<Wry>) -> Result<(), String> {
-    let path = get_notes_dir(&app_handle);
+// Open a file or directory the way the system would
+fn open_externally(path: &Path) -> Result<(), String> {
+    let path = path.to_str().ok_or("Path isn't valid UTF-8")?;
 
     #[cfg(target_os = "windows")]
     {
         use std::process::Command;
         Command::new("explorer")
-            .args([path.to_str().unwrap()])
+            .args([path])
             .spawn()
             .map_err(|e| e.to_string())?;
     }
@@ -581,7 +1188,7 @@ async fn open_notes_dir(app_handle: AppHandle<Wry>) -> Result<(), String> {
     {
         use std::process::Command;
         Command::new("open")
-            .args([path.to_str().unwrap()])
+            .args([path])
             .spawn()
             .map_err(|e| e.to_string())?;
     }
@@ -590,7 +1197,7 @@ async fn open_notes_dir(app_handle: AppHandle<Wry>) -> Result<(), String> {
     {
         use std::process::Command;
         Command::new("xdg-open")
-            .args([path.to_str().unwrap()])
+            .args([path])
             .spawn()
             .map_err(|e| e.to_string())?;
     }
@@ -598,431 +1205,318 @@ async fn open_notes_dir(app_handle: AppHandle<Wry>) -> Result<(), String> {
     Ok(())
 }
 
+#[tauri::command]
+async fn open_notes_dir(app_handle: AppHandle<Wry>) -> Result<(), String> {
+    open_externally(&get_notes_dir(&app_handle))
+}
+
+// The sync server and mDNS registration, run until the mDNS browser stops. Saved
+// settings that change the device name, port or interfaces stop both so they can be
+// started again with the new values.
+async fn run_network(
+    app_handle: AppHandle<Wry>,
+    config: network::ServerConfig,
+) -> network::Restart {
+    let host = server::SyncHost {
+        state: app_handle.state::<Arc<Mutex<AppState>>>().inner().clone(),
+        store: Arc::new(app_handle.clone()),
+        events: Arc::new(app_handle.clone()),
+        cert: app_handle.state::<tls::DeviceCert>().inner().clone(),
+    };
+
+    // Interfaces chosen in settings, or every usable one
+    let listen_ips = network::listen_addresses(&app_handle);
+
+    let (stop, stopped) = tokio::sync::watch::channel(false);
+    let watcher = {
+        let app_handle = app_handle.clone();
+        let config = config.clone();
+        let stop = stop.clone();
+        let mut changes = settings::subscribe(&app_handle);
+        tokio::spawn(async move {
+            let restart = network::wait_for_restart(&app_handle, &config, &mut changes).await;
+            stop.send_replace(true);
+            restart
+        })
+    };
+
+    server::run(host, config, listen_ips, stopped).await;
+
+    // Stopped by the watcher, or else by itself, in which case whatever still runs
+    // of it (the HTTP server) is stopped too
+    if *stop.borrow() {
+        watcher.await.unwrap_or(network::Restart::Failed)
+    } else {
+        watcher.abort();
+        stop.send_replace(true);
+        network::Restart::Failed
+    }
+}
+
 fn main() {
-    // Generate a unique device ID and name
-    let device_id = uuid::Uuid::new_v4().to_string();
-    let device_name = hostname::get()
-        .map(|h| h.to_string_lossy().into_owned())
-        .unwrap_or_else(|_| "Unknown Device".to_string());
-
-    // Initialize app state
-    let app_state = Arc::new(Mutex::new(AppState {
-        device_id,
-        device_name,
-        peers: HashMap::new(),
-        sync_notifications: Vec::new(),
-    }));
+    overrides::init_logging();
+
+    // Only the sync stack, without the GUI
+    if overrides::get().headless_sync {
+        headless::run();
+        return;
+    }
+    #[cfg(feature = "mock-peer")]
+    if std::env::args().any(|arg| arg == "--mock-peer") {
+        mock_peer::run();
+        return;
+    }
 
     // Create builder and manage state
-    tauri::Builder::default()
-        .manage(app_state)
+    let mut builder = tauri::Builder::default();
+    // Registered first, so a second launch hands over its arguments and exits before
+    // anything else starts. Instances given a data directory of their own run side
+    // by side, so only the default one is kept single.
+    if overrides::get().data_dir.is_none() {
+        builder = builder.plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            launch::forward(app, args, cwd);
+        }));
+    }
+    builder
+        .plugin(tauri_plugin_clipboard_manager::init())
+        .plugin(capture::plugin())
+        .register_asynchronous_uri_scheme_protocol(asset::SCHEME, asset::handle)
+        .manage(changes::ChangeBus::default())
+        .manage(autosave::Autosave::default())
+        .manage(Mutex::new(index::NoteIndex::default()))
+        .manage(Mutex::new(speech::Speech::default()))
+        .manage(Mutex::new(migrate::Incoming::default()))
+        .manage(Mutex::new(pairing::Pairing::default()))
+        .manage(Mutex::new(None::<schema::MigrationReport>))
+        .manage(settings::LiveSettings::default())
+        .manage(launch::Pending::default())
+        .manage(catalog::Catalog::default())
+        .manage(conditions::SyncPause::default())
+        .manage(conditions::DeferredShares::default())
         .invoke_handler(tauri::generate_handler![
             get_notes,
+            get_notes_metadata,
             save_note,
             delete_note,
             save_attachment,
             save_clipboard_image,
-            serve_attachment,
             get_peers,
             share_note,
             share_notes,
             get_sync_notifications,
             respond_to_sync,
-            open_notes_dir
+            open_notes_dir,
+            settings::get_vault_settings,
+            settings::update_vault_settings,
+            settings::get_device_settings,
+            settings::update_device_settings,
+            plugins::list_plugins,
+            plugins::enable_plugin,
+            automation::run_automation,
+            snippets::expand_snippet,
+            snippets::get_snippets,
+            snippets::save_snippets,
+            snippets::list_templates,
+            snippets::create_note_from_template,
+            fields::get_field_definitions,
+            fields::set_field_definitions,
+            fields::set_note_field,
+            fields::get_notes_by_field,
+            query::query_notes,
+            render::render_markdown,
+            export::export_note_markdown,
+            export::export_note_bundle,
+            import::import_markdown,
+            import::import_document,
+            import::import_csv_as_table,
+            tables::format_table,
+            lint::lint_note,
+            links::check_links,
+            paste::convert_html_to_markdown,
+            paste::paste_html,
+            storage::get_storage_policy,
+            storage::set_storage_policy,
+            storage::get_storage_report,
+            vault::find_orphaned_attachments,
+            vault::cleanup_orphans,
+            vault::verify_vault,
+            vault::repair_vault,
+            network::list_network_interfaces,
+            readonly::set_note_readonly,
+            expiry::set_note_expiry,
+            scratchpad::get_scratchpad,
+            scratchpad::save_scratchpad,
+            scratchpad::promote_scratchpad_to_note,
+            session::get_session,
+            session::save_session,
+            trash::get_recently_deleted,
+            trash::restore_notes,
+            ordering::set_note_order,
+            ordering::reorder_notes,
+            pins::pin_note,
+            pins::unpin_note,
+            pins::set_pin_layout,
+            emoji::set_note_emoji,
+            switcher::quick_switch,
+            covers::set_cover,
+            covers::get_cover_banner,
+            tags::get_tag_tree,
+            tags::get_notes_by_tag,
+            tags::rename_tag,
+            tags::update_tag_meta,
+            tags::tag_notes,
+            tags::suggest_tags_for_note,
+            tags::get_tags,
+            related::get_related_notes,
+            embeddings::semantic_search,
+            summarize::summarize_note,
+            summarize::set_summarizer_key,
+            translate::translate_note,
+            translate::set_translator_key,
+            speech::speak_note,
+            speech::stop_speaking,
+            sketch::save_sketch,
+            sketch::get_sketch,
+            migrate::send_vault,
+            migrate::set_vault_receiving,
+            schema::get_migration_report,
+            compression::compress_existing_attachments,
+            get_notes_by_id,
+            autosave::autosave_note,
+            autosave::flush_autosave,
+            lock::get_vault_access,
+            launch::take_launch_targets,
+            anki::export_anki,
+            identity::get_device_info,
+            identity::set_device_name,
+            catalog::rebuild_index,
+            catalog::compact_index,
+            notebooks::get_notebooks,
+            notebooks::create_notebook,
+            notebooks::rename_notebook,
+            notebooks::move_note_to_notebook,
+            pairing::pair_with_peer,
+            pairing::confirm_pairing,
+            pairing::list_paired_peers,
+            pairing::unpair_peer,
+            library::sync_with_peer,
+            attachments::get_attachment_info,
+            attachments::open_attachment,
+            blobs::deduplicate_attachments,
+            pdf::export_note_pdf,
+            backup::export_library,
+            backup::import_library,
+            encryption::enable_vault_encryption,
+            encryption::unlock_vault,
+            encryption::lock_vault,
+            encryption::get_encryption_status,
+            secret::lock_note,
+            secret::unlock_note,
+            list_notes,
+            get_note,
+            get_note_previews,
+            stats::get_note_stats,
+            links::get_outgoing_links,
+            links::get_backlinks,
+            capture::quick_capture,
+            conditions::get_sync_paused,
+            conditions::set_sync_paused
         ])
         .setup(|app| {
+            // The same device to peers on every launch
+            let device_id = identity::device_id(app.handle());
+            let device_name = network::server_config(app.handle()).device_name;
+
+            // Orders this device's changes against its peers'
+            app.manage(Mutex::new(clock::Clock::new(&device_id)));
+
+            app.manage(Arc::new(Mutex::new(AppState {
+                device_id,
+                device_name,
+                peers: HashMap::new(),
+                sync_notifications: Vec::new(),
+            })));
+            // What sync with peers is encrypted with
+            app.manage(tls::device_cert(app.handle())?);
+
+            // Another instance holding the notes leaves this one read-only, without
+            // anything that would write to them
+            lock::lock_notes_dir(app.handle());
+            let read_only = lock::is_read_only(app.handle());
+
+            // Notes or files the app was launched to open
+            launch::init(app.handle());
+
+            // Jotting something down from anywhere
+            capture::register(app.handle());
+
+            // Upgrade an older vault format before anything reads it
+            if !read_only {
+                schema::run_at_startup(app.handle());
+            }
+
             let app_handle = app.handle().clone();
 
             // Spawn a separate thread for networking
             std::thread::spawn(move || {
+                if read_only {
+                    return;
+                }
                 let rt = tokio::runtime::Runtime::new().unwrap();
                 rt.block_on(async {
-                    // Try to bind to a port
-                    let mut bound_listener = None;
-                    let mut bound_port = 0;
-                    let mut bound_ip = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
-
-                    // Try different ports
-                    for port in 8000..8020 {
-                        // Try to get the local IP address - with fallback options for macOS
-                        let mut attempted_local_ip = false;
-                        
-                        if let Ok(local_ip) = local_ip() {
-                            attempted_local_ip = true;
-                            let addr = SocketAddr::new(local_ip, port);
-                            if let Ok(listener) = tokio::net::TcpListener::bind(addr).await {
-                                bound_listener = Some(listener);
-                                bound_port = port;
-                                bound_ip = local_ip;
-                                break;
-                            }
-                        }
-                        
-                        // If local_ip failed or couldn't bind, try with explicit IP addresses
-                        if !attempted_local_ip || bound_listener.is_none() {
-                            // Try with IPv4 loopback first
-                            let addr = SocketAddr::new(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), port);
-                            if let Ok(listener) = tokio::net::TcpListener::bind(addr).await {
-                                bound_listener = Some(listener);
-                                bound_port = port;
-                                bound_ip = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
-                                break;
-                            }
-                            
-                            // Try with 0.0.0.0 (bind to all interfaces)
-                            let addr = SocketAddr::new(IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)), port);
-                            if let Ok(listener) = tokio::net::TcpListener::bind(addr).await {
-                                bound_listener = Some(listener);
-                                bound_port = port;
-                                // Use 127.0.0.1 for services even though bound to 0.0.0.0
-                                bound_ip = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
-                                break;
-                            }
+                    // Start over whenever settings or the network change, the machine
+                    // wakes up, or the server stops by itself
+                    let mut failures = 0;
+                    loop {
+                        let config = network::server_config(&app_handle);
+                        let started = std::time::Instant::now();
+                        let restart = run_network(app_handle.clone(), config.clone()).await;
+                        if restart != network::Restart::Failed {
+                            println!("{}, restarting sync server", restart);
+                            failures = 0;
+                            continue;
                         }
-                    }
-
-                    // Check if binding succeeded
-                    if bound_listener.is_none() {
-                        println!("Failed to bind to any port");
-                        return;
-                    }
 
-                    let listener = bound_listener.unwrap();
-                    println!("HTTP server listening on {}:{}", bound_ip, bound_port);
-
-                    // Clone the device ID and name for mDNS
-                    let device_id;
-                    let device_name;
-
-                    // Properly scoped to avoid temporary value issues
-                    {
-                        let state_arc = app_handle.state::<Arc<Mutex<AppState>>>();
-                        let guard = match state_arc.lock() {
-                            Ok(guard) => guard,
-                            Err(_) => {
-                                println!("Failed to lock app state");
-                                return;
+                        if started.elapsed() >= network::STABLE_RUN {
+                            failures = 0;
+                        }
+                        let delay = network::retry_delay(failures);
+                        failures += 1;
+                        println!("Sync server stopped, restarting in {:?}", delay);
+                        let mut changes = settings::subscribe(&app_handle);
+                        tokio::select! {
+                            _ = tokio::time::sleep(delay) => {}
+                            restart = network::wait_for_restart(&app_handle, &config, &mut changes) => {
+                                println!("{}, restarting sync server", restart);
+                                failures = 0;
                             }
-                        };
-                        device_id = guard.device_id.clone();
-                        device_name = guard.device_name.clone();
+                        }
                     }
+                });
+            });
 
-                    // Start HTTP server and create two separate handles for the router
-                    let request_handle = app_handle.clone();
-                    let response_handle = app_handle.clone();
-
-                    tokio::spawn(async move {
-                        // Set up the HTTP server using axum with increased limits
-                        let router = axum::Router::new()
-                            .route(
-                                "/sync/request",
-                                axum::routing::post(
-                                    move |req: axum::extract::Json<SyncRequest>| {
-                                        let app = request_handle.clone();
-                                        async move {
-                                            let sync_request = req.0;
-                                            println!(
-                                                "Received sync request from peer: {}",
-                                                sync_request.peer_id
-                                            );
-
-                                            // Properly scope the state access
-                                            let peer;
-                                            let notification_id;
-                                            let note_title;
-
-                                            {
-                                                let state_arc = app.state::<Arc<Mutex<AppState>>>();
-                                                let mut guard = match state_arc.lock() {
-                                                    Ok(guard) => guard,
-                                                    Err(_) => {
-                                                        println!("Failed to lock app state");
-                                                        return axum::Json(serde_json::json!({
-                                                            "success": false,
-                                                            "error": "Failed to lock app state"
-                                                        }));
-                                                    }
-                                                };
-
-                                                // When sharing notes, we don't require the peer to be in the peers list
-                                                // Instead, we'll use the peer_id from the sync request
-                                                let peer_info = guard.peers.get(&sync_request.peer_id);
-                                                
-                                                if let Some(p) = peer_info {
-                                                    println!("Found peer in peers list: {}", p.name);
-                                                    peer = p.clone();
-                                                } else {
-                                                    println!("Peer not in peers list, creating temporary peer entry");
-                                                    // Create a temporary peer device entry
-                                                    peer = PeerDevice {
-                                                        id: sync_request.peer_id.clone(),
-                                                        name: sync_request.peer_name.clone(), // Use the name from the request
-                                                        ip: std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
-                                                        port: 0, // We don't know the port
-                                                    };
-                                                }
-
-                                                // Create notification
-                                                notification_id = uuid::Uuid::new_v4().to_string();
-                                                note_title = sync_request.note.title.clone();
-
-                                                println!("Creating notification: {} for note: {}", notification_id, note_title);
-
-                                                // Store the notification
-                                                guard.sync_notifications.push(SyncNotification {
-                                                    id: notification_id.clone(),
-                                                    from_peer: peer.clone(),
-                                                    note_title: note_title.clone(),
-                                                    status: SyncStatus::Pending,
-                                                });
-                                                
-                                                println!("Current notifications count: {}", guard.sync_notifications.len());
-                                            }
-
-                                            // Store the note temporarily
-                                            let path = get_note_path(&app, &sync_request.note.id);
-                                            if let Some(path_str) = path.to_str() {
-                                                let note = sync_request.note.clone();
-                                                let note_content =
-                                                    format!("# {}\n\n{}", note.title, note.content);
-                                                let sync_path = format!("{}.sync", path_str);
-                                                println!("Writing sync file to: {}", sync_path);
-
-                                                if let Err(e) = fs::write(&sync_path, note_content)
-                                                {
-                                                    println!("Failed to write sync file: {}", e);
-                                                } else {
-                                                    println!("Successfully wrote sync file");
-                                                }
-
-                                                // Save any attachment files that were included
-                                                for (file_name, file_data) in
-                                                    &sync_request.attachments_data
-                                                {
-                                                    let attachments_dir =
-                                                        get_attachments_dir(&app, &note.id);
-                                                    let attachment_path =
-                                                        attachments_dir.join(file_name);
-                                                    println!(
-                                                        "Saving attachment: {} to path: {:?}",
-                                                        file_name, attachment_path
-                                                    );
-
-                                                    if let Err(e) =
-                                                        fs::write(&attachment_path, file_data)
-                                                    {
-                                                        println!(
-                                                            "Failed to write attachment file: {}",
-                                                            e
-                                                        );
-                                                    } else {
-                                                        println!(
-                                                            "Successfully wrote attachment file"
-                                                        );
-                                                    }
-                                                }
-                                            }
-
-                                            // Notify the frontend
-                                            println!(
-                                                "Emitting sync-notification event to frontend"
-                                            );
-                                            match app.emit("sync-notification", ()) {
-                                                Ok(_) => println!(
-                                                    "Successfully emitted sync-notification event"
-                                                ),
-                                                Err(e) => println!(
-                                                    "Failed to emit sync-notification event: {}",
-                                                    e
-                                                ),
-                                            }
-
-                                            // Return success
-                                            axum::Json(serde_json::json!({ "success": true }))
-                                        }
-                                    },
-                                ),
-                            )
-                            .route(
-                                "/sync/response",
-                                axum::routing::post(
-                                    move |req: axum::extract::Json<serde_json::Value>| {
-                                        let app_handle = response_handle.clone();
-                                        async move {
-                                            let response = req.0;
-
-                                            let notification_id =
-                                                response["notification_id"].as_str().unwrap_or("");
-                                            let accepted =
-                                                response["accepted"].as_bool().unwrap_or(false);
-
-                                            // Notify the frontend
-                                            let _ = app_handle.emit(
-                                                "sync-response",
-                                                serde_json::json!({
-                                                    "notification_id": notification_id,
-                                                    "accepted": accepted,
-                                                }),
-                                            );
-
-                                            // Return success
-                                            axum::Json(serde_json::json!({ "success": true }))
-                                        }
-                                    },
-                                ),
-                            );
-
-                        // Configure the router with proper limits for large attachments
-                        let app = router.layer(
-                            tower::ServiceBuilder::new()
-                                .layer(axum::extract::DefaultBodyLimit::max(50 * 1024 * 1024)), // 50 MB limit
-                        );
-
-                        if let Err(e) = axum::serve(listener, app).await {
-                            println!("HTTP server error: {}", e);
-                        }
-                    });
-
-                    // Try to set up mDNS service with the bound port
-                    let mdns = match ServiceDaemon::new() {
-                        Ok(daemon) => daemon,
-                        Err(e) => {
-                            println!("Failed to create mDNS daemon: {}", e);
-                            return;
-                        }
-                    };
-
-                    // Convert IP to IPv4 for mDNS
-                    let ipv4_addr = match bound_ip {
-                        IpAddr::V4(addr) => addr,
-                        IpAddr::V6(_) => {
-                            println!("IPv6 not supported for mDNS");
-                            return;
-                        }
-                    };
-
-                    // Create service info
-                    let service_type = "_notes-sync._tcp.local.";
-                    let instance_name = format!("{}_{}", device_name, device_id);
-
-                    let properties = HashMap::from([
-                        ("id".into(), device_id.clone().into()),
-                        ("name".into(), device_name.clone().into()),
-                    ]);
-
-                    let service_info = match ServiceInfo::new(
-                        service_type,
-                        &instance_name,
-                        "local.", // Use a fixed domain name instead of hostname-based one
-                        ipv4_addr,
-                        bound_port,
-                        Some(properties),
-                    ) {
-                        Ok(info) => info,
-                        Err(e) => {
-                            println!("Failed to create mDNS service info: {}", e);
-                            return;
-                        }
-                    };
+            // Subsystems that follow note changes
+            events::spawn_forwarder(app.handle());
+            webhooks::spawn_dispatcher(app.handle());
+            index::spawn_indexer(app.handle());
+            catalog::spawn_cataloger(app.handle());
 
-                    // Register service
-                    if let Err(e) = mdns.register(service_info) {
-                        println!("Failed to register mDNS service: {}", e);
-                        return;
-                    }
+            // Check peers configured by hostname, which mDNS won't find
+            peers::spawn_static_peers(app.handle().clone());
 
-                    println!("mDNS service registered successfully");
+            // Recent notes and sync status from the system tray
+            tray::init(app.handle());
 
-                    // Browse for other services
-                    let browser = match mdns.browse(service_type) {
-                        Ok(browser) => browser,
-                        Err(e) => {
-                            println!("Failed to browse mDNS: {}", e);
-                            return;
-                        }
-                    };
+            // Notes shared while sync was held back go out once it may run again
+            conditions::spawn_deferred_sender(app.handle().clone());
 
-                    // Store device_id for comparing in the mDNS events
-                    let device_id_for_compare = device_id.clone();
-                    let app_handle_for_events = app_handle.clone();
+            if !read_only {
+                // Move long-untouched notes into cold storage in the background
+                storage::spawn_archiver(app.handle().clone());
 
-                    // Handle mDNS events
-                    loop {
-                        match browser.recv() {
-                            Ok(ServiceEvent::ServiceResolved(info)) => {
-                                // Skip our own service
-                                if let Some(peer_id) =
-                                    info.get_property("id").and_then(|id| id.to_string().into())
-                                {
-                                    if peer_id == device_id_for_compare {
-                                        continue;
-                                    }
-
-                                    let peer_name = info
-                                        .get_property("name")
-                                        .and_then(|name| name.to_string().into())
-                                        .unwrap_or_else(|| "Unknown".to_string());
-
-                                    // Get IP address
-                                    if let Some(addr) = info.get_addresses().iter().next() {
-                                        let peer = PeerDevice {
-                                            id: peer_id.clone(),
-                                            name: peer_name,
-                                            ip: IpAddr::V4(*addr),
-                                            port: info.get_port(),
-                                        };
-
-                                        // Get a copy of state to update
-                                        let app_state =
-                                            app_handle_for_events.state::<Arc<Mutex<AppState>>>();
-
-                                        // Add the peer
-                                        {
-                                            if let Ok(mut state) = app_state.lock() {
-                                                state.peers.insert(peer_id, peer);
-                                            }
-                                        }
-
-                                        // Notify frontend - outside of lock scope
-                                        let _ = app_handle_for_events.emit("peers-updated", ());
-                                    }
-                                }
-                            }
-                            Ok(ServiceEvent::ServiceRemoved(_service_type, instance_name)) => {
-                                // Extract the ID from the instance name
-                                if let Some(id_part) = instance_name.split('_').last() {
-                                    let peer_id = id_part.to_string();
-                                    let removed;
-
-                                    // Get a copy of state to update
-                                    let app_state =
-                                        app_handle_for_events.state::<Arc<Mutex<AppState>>>();
-
-                                    // Remove the peer
-                                    {
-                                        if let Ok(mut state) = app_state.lock() {
-                                            removed = state.peers.remove(&peer_id).is_some();
-                                        } else {
-                                            removed = false;
-                                        }
-                                    }
-
-                                    // Notify frontend if needed - outside of lock scope
-                                    if removed {
-                                        let _ = app_handle_for_events.emit("peers-updated", ());
-                                    }
-                                }
-                            }
-                            Ok(_) => { /* Ignore other events */ }
-                            Err(e) => {
-                                println!("Error receiving mDNS event: {:?}", e);
-                                break;
-                            }
-                        }
-                    }
-                });
-            });
+                // Trash or wipe notes whose expiry date has passed
+                expiry::spawn_expiry_scheduler(app.handle().clone());
+            }
 
             Ok(())
         })
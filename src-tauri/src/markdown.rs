@@ -0,0 +1,207 @@
+// Helpers for working with the markdown text of notes
+
+// How notes link to files in their attachments directory
+pub const ATTACHMENT_SCHEME: &str = "attachment://";
+
+// A link target for an attachment, as inserted by the editor
+pub fn attachment_link(name: &str) -> String {
+    format!("{}{}", ATTACHMENT_SCHEME, name.replace(' ', "%20"))
+}
+
+// Rewrite the target of every `[text](target)` / `![alt](target)` in `content`.
+// `rewrite` receives the bare target (without any `"title"` part) and returns its
+// replacement, or None to leave the link untouched.
+pub fn rewrite_link_targets(
+    content: &str,
+    mut rewrite: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(start) = rest.find("](") {
+        let target_start = start + 2;
+        let Some(len) = rest[target_start..].find(')') else {
+            break;
+        };
+
+        // Allow an optional title: ![alt](file.png "title")
+        let inner = &rest[target_start..target_start + len];
+        let (target, suffix) = match inner.find(' ') {
+            Some(space) => (&inner[..space], &inner[space..]),
+            None => (inner, ""),
+        };
+
+        output.push_str(&rest[..target_start]);
+        match rewrite(target) {
+            Some(new_target) => {
+                output.push_str(&new_target);
+                output.push_str(suffix);
+            }
+            None => output.push_str(inner),
+        }
+        output.push(')');
+        rest = &rest[target_start + len + 1..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+// Inline markdown reduced to the text a reader sees: link text without targets,
+// no images, emphasis or code markers
+pub fn strip_inline(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut text = String::with_capacity(line.len());
+    let mut i = 0;
+
+    // Index of the `)` closing a `(target)` that starts at `open`
+    let closing_paren = |open: usize| {
+        (open < chars.len() && chars[open] == '(')
+            .then(|| chars[open..].iter().position(|&c| c == ')'))
+            .flatten()
+            .map(|len| open + len)
+    };
+
+    while i < chars.len() {
+        match chars[i] {
+            '!' if chars.get(i + 1) == Some(&'[') => {
+                // Images don't contribute any text
+                let close = chars[i..].iter().position(|&c| c == ']').map(|p| i + p);
+                match close.and_then(|close| closing_paren(close + 1)) {
+                    Some(end) => i = end + 1,
+                    None => {
+                        text.push('!');
+                        i += 1;
+                    }
+                }
+            }
+            '[' if chars.get(i + 1) == Some(&'[') => {
+                // [[target]] or [[target|label]]
+                let inner_start = i + 2;
+                match chars[inner_start..]
+                    .windows(2)
+                    .position(|w| w == [']', ']'])
+                {
+                    Some(len) => {
+                        let inner: String = chars[inner_start..inner_start + len].iter().collect();
+                        text.push_str(inner.rsplit('|').next().unwrap_or(&inner));
+                        i = inner_start + len + 2;
+                    }
+                    None => {
+                        text.push('[');
+                        i += 1;
+                    }
+                }
+            }
+            '[' => {
+                let close = chars[i..].iter().position(|&c| c == ']').map(|p| i + p);
+                match close.and_then(|close| closing_paren(close + 1).map(|end| (close, end))) {
+                    Some((close, end)) => {
+                        let label: String = chars[i + 1..close].iter().collect();
+                        text.push_str(&strip_inline(&label));
+                        i = end + 1;
+                    }
+                    None => {
+                        text.push('[');
+                        i += 1;
+                    }
+                }
+            }
+            '<' => {
+                // Drop inline HTML tags, keep a lone `<`
+                match chars[i..].iter().position(|&c| c == '>') {
+                    Some(len)
+                        if chars
+                            .get(i + 1)
+                            .is_some_and(|c| c.is_alphabetic() || *c == '/') =>
+                    {
+                        i += len + 1;
+                    }
+                    _ => {
+                        text.push('<');
+                        i += 1;
+                    }
+                }
+            }
+            '*' | '`' => i += 1,
+            '~' if chars.get(i + 1) == Some(&'~') => i += 2,
+            // Underscores inside words (snake_case) are text, around them emphasis
+            '_' if i == 0
+                || i + 1 == chars.len()
+                || !chars[i - 1].is_alphanumeric()
+                || !chars[i + 1].is_alphanumeric() =>
+            {
+                i += 1
+            }
+            c => {
+                text.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    text
+}
+
+const MAX_EXCERPT_CHARS: usize = 200;
+
+// A short plain-text preview of a note: its first paragraph that isn't a heading,
+// code block, table or rule, with markdown removed
+pub fn excerpt(content: &str) -> String {
+    let mut words: Vec<String> = Vec::new();
+    let mut in_fence = false;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        let skipped = in_fence
+            || trimmed.starts_with('#')
+            || trimmed.starts_with('|')
+            || (trimmed.len() >= 3 && trimmed.chars().all(|c| matches!(c, '-' | '*' | '_' | ' ')));
+
+        if trimmed.is_empty() || skipped {
+            if !words.is_empty() {
+                break;
+            }
+            continue;
+        }
+
+        // Drop block markers: quotes, list bullets and numbers, task boxes
+        let mut block = trimmed.trim_start_matches('>').trim_start();
+        if let Some(rest) = block
+            .strip_prefix("- ")
+            .or_else(|| block.strip_prefix("* "))
+            .or_else(|| block.strip_prefix("+ "))
+        {
+            block = rest;
+        } else if let Some(dot) = block.find(". ") {
+            if dot > 0 && block[..dot].chars().all(|c| c.is_ascii_digit()) {
+                block = &block[dot + 2..];
+            }
+        }
+        for task in ["[ ] ", "[x] ", "[X] "] {
+            if let Some(rest) = block.strip_prefix(task) {
+                block = rest;
+            }
+        }
+
+        let text = strip_inline(block);
+        words.extend(text.split_whitespace().map(str::to_string));
+    }
+
+    let text = words.join(" ");
+    if text.chars().count() <= MAX_EXCERPT_CHARS {
+        return text;
+    }
+
+    // Cut at the last word boundary that fits
+    let cut: String = text.chars().take(MAX_EXCERPT_CHARS).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(space) if space > MAX_EXCERPT_CHARS / 2 => &cut[..space],
+        _ => cut.as_str(),
+    };
+    format!("{}…", cut.trim_end())
+}
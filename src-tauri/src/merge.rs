@@ -0,0 +1,375 @@
+use crate::{
+    clock, compression, encryption, get_note_path, load_notes, note_from_file, overrides,
+    persist_note_unchecked, protocol, render_note_file, timestamps, writable_attachments_dir, Note,
+    SyncRequest,
+};
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use tauri::{AppHandle, Emitter, Wry};
+
+// Automatic merging of concurrent edits to a synced note. The version this device
+// and a peer last agreed on is kept for every note synced with that peer, in
+// `sync-base/<peer id>/`; when a note comes in that was also edited here since then,
+// a three-way line merge against that base is tried. Only edits that overlap fall
+// back to the sync prompt. Each peer has bases of its own, as syncing with one
+// device says nothing about what another has.
+//
+// The sender keeps what it sent as `<id>.sent` until the peer accepts or merges it,
+// so a rejected note never becomes the base.
+//
+// Overlapping edits are a conflict: the prompt then offers keeping our copy, taking
+// theirs or keeping both. The attachments that come with any note waiting for an
+// answer wait in `sync-incoming/<id>`, so ours aren't overwritten before the user
+// chooses.
+
+const BASE_DIR: &str = "sync-base";
+const INCOMING_DIR: &str = "sync-incoming";
+// Largest diff table (lines x lines) worth computing before asking instead
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SyncMerged {
+    note_id: String,
+    title: String,
+    peer_name: String,
+}
+
+// Lines `start..end` of the base replaced by `lines`
+#[derive(Debug, PartialEq)]
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+fn base_dir(app_handle: &AppHandle<Wry>) -> Result<PathBuf, String> {
+    Ok(overrides::app_data_dir(app_handle)
+        .map_err(|e| e.to_string())?
+        .join(BASE_DIR))
+}
+
+fn base_path(
+    app_handle: &AppHandle<Wry>,
+    peer_id: &str,
+    file_name: &str,
+) -> Result<PathBuf, String> {
+    // Peer ids come from the peers themselves
+    protocol::check_id("peer_id", peer_id).map_err(|e| e.to_string())?;
+    let dir = base_dir(app_handle)?.join(peer_id);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.join(file_name))
+}
+
+// Remember a note file's contents as the version this device and the peer have
+pub fn record_base(app_handle: &AppHandle<Wry>, peer_id: &str, note_id: &str, contents: &str) {
+    let result = base_path(app_handle, peer_id, &format!("{}.md", note_id))
+        .and_then(|path| encryption::write(app_handle, path, contents));
+    if let Err(e) = result {
+        println!("Failed to record sync base of {}: {}", note_id, e);
+    }
+}
+
+// Remember the version of a note being sent to a peer, until the peer takes it
+pub fn record_sent(app_handle: &AppHandle<Wry>, peer_id: &str, note: &Note) {
+    let result = base_path(app_handle, peer_id, &format!("{}.sent", note.id))
+        .and_then(|path| encryption::write(app_handle, path, render_note_file(note)));
+    if let Err(e) = result {
+        println!("Failed to record sent version of {}: {}", note.id, e);
+    }
+}
+
+// Drop the versions kept of a note for merging, with every peer
+pub fn forget(app_handle: &AppHandle<Wry>, note_id: &str) {
+    let Ok(dir) = base_dir(app_handle) else {
+        return;
+    };
+    let mut dirs = vec![dir.clone()];
+    if let Ok(entries) = fs::read_dir(&dir) {
+        dirs.extend(entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()));
+    }
+    for dir in dirs {
+        let _ = fs::remove_file(dir.join(format!("{}.md", note_id)));
+        let _ = fs::remove_file(dir.join(format!("{}.sent", note_id)));
+    }
+}
+
+// The peer accepted or merged the version we sent, so it is the new base
+pub fn confirm_sent(app_handle: &AppHandle<Wry>, peer_id: &str, note_id: &str) {
+    let (Ok(sent), Ok(base)) = (
+        base_path(app_handle, peer_id, &format!("{}.sent", note_id)),
+        base_path(app_handle, peer_id, &format!("{}.md", note_id)),
+    ) else {
+        return;
+    };
+    if sent.exists() {
+        if let Err(e) = fs::rename(&sent, &base) {
+            println!("Failed to record sync base of {}: {}", note_id, e);
+        }
+    }
+}
+
+// Read the peer's reply to a sync request, which says whether it merged the note
+pub fn confirm_if_merged(app_handle: &AppHandle<Wry>, peer_id: &str, note_id: &str, reply: &str) {
+    let merged = serde_json::from_str::<serde_json::Value>(reply)
+        .is_ok_and(|reply| reply["merged"].as_bool() == Some(true));
+    if merged {
+        confirm_sent(app_handle, peer_id, note_id);
+    }
+}
+
+// Changes turning `base` into `other`, from a longest common subsequence of lines
+fn diff<'a>(base: &[&str], other: &[&'a str]) -> Option<Vec<Hunk<'a>>> {
+    // Only the middle that differs needs the table
+    let prefix = base.iter().zip(other).take_while(|(a, b)| a == b).count();
+    let suffix = base[prefix..]
+        .iter()
+        .rev()
+        .zip(other[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &base[prefix..base.len() - suffix];
+    let new = &other[prefix..other.len() - suffix];
+    let (n, m) = (old.len(), new.len());
+    if (n + 1).saturating_mul(m + 1) > MAX_DIFF_CELLS {
+        return None;
+    }
+
+    // lcs[i * (m + 1) + j] is the common subsequence length of old[i..] and new[j..]
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[at(i, j)] = if old[i] == new[j] {
+                lcs[at(i + 1, j + 1)] + 1
+            } else {
+                lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+            };
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let mut current: Option<Hunk> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            hunks.extend(current.take());
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let hunk = current.get_or_insert(Hunk {
+            start: prefix + i,
+            end: prefix + i,
+            lines: Vec::new(),
+        });
+        if j < m && (i == n || lcs[at(i, j + 1)] >= lcs[at(i + 1, j)]) {
+            hunk.lines.push(new[j]);
+            j += 1;
+        } else {
+            i += 1;
+            hunk.end = prefix + i;
+        }
+    }
+    hunks.extend(current);
+    Some(hunks)
+}
+
+// Three-way line merge. None when the two sides changed the same or adjacent lines.
+fn merge_lines(base: &str, ours: &str, theirs: &str) -> Option<String> {
+    let base: Vec<&str> = base.lines().collect();
+    let our_lines: Vec<&str> = ours.lines().collect();
+    let their_lines: Vec<&str> = theirs.lines().collect();
+
+    let mut hunks = diff(&base, &our_lines)?;
+    hunks.extend(diff(&base, &their_lines)?);
+    hunks.sort_by_key(|h| (h.start, h.end));
+    // The same edit made on both devices
+    hunks.dedup();
+    // Hunks of one side never touch, so touching hunks come from both
+    if hunks.windows(2).any(|pair| pair[1].start <= pair[0].end) {
+        return None;
+    }
+
+    let mut merged = Vec::new();
+    let mut position = 0;
+    for hunk in &hunks {
+        merged.extend_from_slice(&base[position..hunk.start]);
+        merged.extend_from_slice(&hunk.lines);
+        position = hunk.end;
+    }
+    merged.extend_from_slice(&base[position..]);
+
+    let mut text = merged.join("\n");
+    if ours.ends_with('\n') {
+        text.push('\n');
+    }
+    Some(text)
+}
+
+// The version of a note this device and the peer last had in common. Bases kept
+// before they were kept per peer stand in until the peer has one of its own.
+pub fn base_note(app_handle: &AppHandle<Wry>, peer_id: &str, note_id: &str) -> Option<Note> {
+    let file_name = format!("{}.md", note_id);
+    let raw = base_path(app_handle, peer_id, &file_name)
+        .and_then(|path| encryption::read_to_string(path).map_err(|e| e.to_string()))
+        .or_else(|_| {
+            let legacy = base_dir(app_handle)?.join(&file_name);
+            encryption::read_to_string(legacy).map_err(|e| e.to_string())
+        })
+        .ok()?;
+    Some(note_from_file(note_id, &raw, String::new(), Vec::new()))
+}
+
+// Whether an incoming note would overwrite edits made here: our copy differs from it
+// and changed since this device and the peer last had the same version, or they
+// never had
+pub fn is_conflict(app_handle: &AppHandle<Wry>, peer_id: &str, incoming: &Note) -> bool {
+    let Ok(raw) = encryption::read_to_string(get_note_path(app_handle, &incoming.id)) else {
+        return false;
+    };
+    let local = note_from_file(&incoming.id, &raw, String::new(), Vec::new());
+    let ours = merge_text(&local);
+    if ours == merge_text(incoming) {
+        return false;
+    }
+    !base_note(app_handle, peer_id, &incoming.id).is_some_and(|base| merge_text(&base) == ours)
+}
+
+// Where the attachments of an incoming note wait until the user answers
+pub fn incoming_attachments_dir(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+) -> Result<PathBuf, String> {
+    let dir = overrides::app_data_dir(app_handle)
+        .map_err(|e| e.to_string())?
+        .join(INCOMING_DIR)
+        .join(note_id);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+// The note file as merged, without its clock or update time: every save stamps
+// new ones, which would make each side's edits overlap at those lines
+pub fn merge_text(note: &Note) -> String {
+    let mut note = note.clone();
+    note.fields.remove(clock::CLOCK_FIELD);
+    note.fields.remove(timestamps::UPDATED_FIELD);
+    render_note_file(&note)
+}
+
+// The local note with an incoming version merged in, when both were edited since
+// they were last in sync and the edits don't overlap
+pub fn merge_incoming(app_handle: &AppHandle<Wry>, peer_id: &str, incoming: &Note) -> Option<Note> {
+    let local = load_notes(app_handle)
+        .ok()?
+        .into_iter()
+        .find(|n| n.id == incoming.id)?;
+    let base = merge_text(&base_note(app_handle, peer_id, &incoming.id)?);
+    let (ours, theirs) = (merge_text(&local), merge_text(incoming));
+    // Edits on one side only are a plain update, left to the prompt as before
+    if ours == base || theirs == base || ours == theirs {
+        return None;
+    }
+
+    let merged = merge_lines(&base, &ours, &theirs)?;
+    let mut attachments = local.attachments.clone();
+    for name in &incoming.attachments {
+        if !attachments.contains(name) {
+            attachments.push(name.clone());
+        }
+    }
+    Some(note_from_file(
+        &local.id,
+        &merged,
+        local.datetime.clone(),
+        attachments,
+    ))
+}
+
+// Save a merged note along with the attachments that came with the request
+pub async fn apply_merge(
+    app_handle: &AppHandle<Wry>,
+    request: &SyncRequest,
+    merged: Note,
+) -> Result<(), String> {
+    let attachments_dir = writable_attachments_dir(app_handle, &merged.id)?;
+    for (file_name, file_data) in &request.attachments_data {
+        compression::write_attachment(app_handle, &attachments_dir.join(file_name), file_data)?;
+    }
+
+    persist_note_unchecked(app_handle, merged.clone()).await?;
+    // Both devices now have the incoming version in common
+    record_base(
+        app_handle,
+        &request.peer_id,
+        &merged.id,
+        &render_note_file(&request.note),
+    );
+
+    println!(
+        "Merged concurrent edits of {} from {}",
+        merged.id, request.peer_name
+    );
+    app_handle
+        .emit(
+            "sync-merged",
+            SyncMerged {
+                note_id: merged.id,
+                title: merged.title,
+                peer_name: request.peer_name.clone(),
+            },
+        )
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_finds_replaced_and_deleted_lines() {
+        let base = ["a", "b", "c"];
+        assert_eq!(diff(&base, &base), Some(Vec::new()));
+        assert_eq!(
+            diff(&base, &["a", "x", "c"]),
+            Some(vec![Hunk {
+                start: 1,
+                end: 2,
+                lines: vec!["x"],
+            }])
+        );
+        assert_eq!(
+            diff(&base, &["a", "c"]),
+            Some(vec![Hunk {
+                start: 1,
+                end: 2,
+                lines: Vec::new(),
+            }])
+        );
+    }
+
+    #[test]
+    fn disjoint_edits_merge() {
+        let merged = merge_lines("a\nb\nc\nd\n", "A\nb\nc\nd\n", "a\nb\nc\nD\n");
+        assert_eq!(merged.as_deref(), Some("A\nb\nc\nD\n"));
+    }
+
+    #[test]
+    fn the_same_edit_on_both_sides_merges() {
+        let merged = merge_lines("a\nb\nc\n", "a\nB\nc\n", "a\nB\nc\n");
+        assert_eq!(merged.as_deref(), Some("a\nB\nc\n"));
+    }
+
+    #[test]
+    fn overlapping_edits_conflict() {
+        assert_eq!(merge_lines("a\nb\nc\n", "a\nB\nc\n", "a\nX\nc\n"), None);
+        // Edits to neighbouring lines touch, too
+        assert_eq!(merge_lines("a\nb\nc\n", "A\nb\nc\n", "a\nB\nc\n"), None);
+    }
+
+    #[test]
+    fn insertions_at_the_same_line_conflict() {
+        assert_eq!(merge_lines("a\nb\n", "a\nx\nb\n", "a\ny\nb\n"), None);
+    }
+}
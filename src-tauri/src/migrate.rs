@@ -0,0 +1,557 @@
+use crate::events;
+use crate::settings::{self, load_device_settings, save_device_settings};
+use crate::{
+    atomic, blobs, conditions, get_notes_dir, lock, network, overrides, pairing, protocol, storage,
+    tls, AppState,
+};
+use axum::body::Bytes;
+use axum::extract::rejection::BytesRejection;
+use axum::extract::{DefaultBodyLimit, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager, Wry};
+
+// Moving everything to a new device: the whole notes directory (notes, attachments,
+// trash, cold storage, ordering) and settings, which include static peers and the
+// rest of the configuration. The new device has to be paired with the sender and
+// opt in to receiving first. Files
+// are staged and checked against the sender's hashes, and only a complete, verified
+// transfer is moved into place.
+
+// Paths in a transfer are relative to the app data directory, using `/`
+const SETTINGS_PATH: &str = "settings.json";
+const NOTES_PREFIX: &str = "notes/";
+const STAGING_DIR: &str = "vault-incoming";
+// Largest single file accepted, well above the 50 MB limit for note sync
+const MAX_FILE_BYTES: usize = 1024 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VaultFile {
+    path: String,
+    size: u64,
+    sha256: String,
+    // Modification time in milliseconds since the epoch, kept because note dates
+    // and cold storage go by it
+    modified: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct VaultOffer {
+    transfer_id: String,
+    peer_id: String,
+    peer_name: String,
+    files: Vec<VaultFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileQuery {
+    transfer_id: String,
+    path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FinishRequest {
+    transfer_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VaultProgress {
+    transfer_id: String,
+    files_done: usize,
+    files_total: usize,
+    bytes_done: u64,
+    bytes_total: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VaultReport {
+    files: usize,
+    bytes: u64,
+}
+
+struct IncomingTransfer {
+    id: String,
+    peer_id: String,
+    peer_name: String,
+    files: HashMap<String, VaultFile>,
+    received: HashSet<String>,
+    bytes_received: u64,
+}
+
+// Receiving side of a transfer
+#[derive(Default)]
+pub struct Incoming {
+    accepting: bool,
+    transfer: Option<IncomingTransfer>,
+}
+
+type HandlerError = (StatusCode, String);
+
+fn app_data_dir(app_handle: &AppHandle<Wry>) -> Result<PathBuf, String> {
+    overrides::app_data_dir(app_handle).map_err(|e| e.to_string())
+}
+
+// Only plain relative paths into the notes directory, or the settings file
+fn is_valid_path(path: &str) -> bool {
+    if path == SETTINGS_PATH {
+        return true;
+    }
+    path.starts_with(NOTES_PREFIX)
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+fn sha256_file(path: &Path) -> Result<String, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    Ok(hex::encode(Sha256::digest(&data)))
+}
+
+fn modified_millis(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_millis() as u64)
+}
+
+fn collect_dir(dir: &Path, notes_dir: &Path, files: &mut Vec<VaultFile>) -> Result<(), String> {
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        // Attachments go as they are; the receiver builds its own store of them
+        if path == notes_dir.join(blobs::BLOBS_DIR) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_dir(&path, notes_dir, files)?;
+            continue;
+        }
+        // Notes staged by a pending sync haven't been accepted here either
+        if path.extension().and_then(|e| e.to_str()) == Some("sync") {
+            continue;
+        }
+        // The receiver holds its own lock
+        if path.file_name().and_then(|n| n.to_str()) == Some(lock::LOCK_FILE) {
+            continue;
+        }
+        let Ok(relative) = path.strip_prefix(notes_dir) else {
+            continue;
+        };
+        // Paths are sent as if the notes were in the app data directory
+        let relative = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.push(VaultFile {
+            path: format!("{}{}", NOTES_PREFIX, relative),
+            size: fs::metadata(&path).map_err(|e| e.to_string())?.len(),
+            sha256: sha256_file(&path)?,
+            modified: modified_millis(&path),
+        });
+    }
+    Ok(())
+}
+
+// Every file that makes up the vault, with hashes for the receiver to check
+fn collect_files(app_handle: &AppHandle<Wry>) -> Result<Vec<VaultFile>, String> {
+    let app_dir = app_data_dir(app_handle)?;
+    let mut files = Vec::new();
+    let notes_dir = get_notes_dir(app_handle);
+    collect_dir(&notes_dir, &notes_dir, &mut files)?;
+
+    let settings = app_dir.join(SETTINGS_PATH);
+    if settings.exists() {
+        files.push(VaultFile {
+            path: SETTINGS_PATH.to_string(),
+            size: fs::metadata(&settings).map_err(|e| e.to_string())?.len(),
+            sha256: sha256_file(&settings)?,
+            modified: modified_millis(&settings),
+        });
+    }
+    Ok(files)
+}
+
+// Send the whole vault to a peer that is waiting to receive it. Progress is reported
+// with `vault-send-progress` events after each file.
+#[tauri::command]
+pub async fn send_vault(
+    app_handle: AppHandle<Wry>,
+    peer_id: String,
+) -> Result<VaultReport, String> {
+    conditions::check_bulk_work(&app_handle).await?;
+
+    let (peer, device_id, device_name) = {
+        let state = app_handle.state::<Arc<Mutex<AppState>>>();
+        let app_state = state.lock().map_err(|e| e.to_string())?;
+        let peer = app_state
+            .peers
+            .get(&peer_id)
+            .cloned()
+            .ok_or("Peer not found")?;
+        (
+            peer,
+            app_state.device_id.clone(),
+            app_state.device_name.clone(),
+        )
+    };
+
+    let files = {
+        let app_handle = app_handle.clone();
+        tokio::task::spawn_blocking(move || collect_files(&app_handle))
+            .await
+            .map_err(|e| e.to_string())??
+    };
+    let offer = VaultOffer {
+        transfer_id: uuid::Uuid::new_v4().to_string(),
+        peer_id: device_id,
+        peer_name: device_name,
+        files: files.clone(),
+    };
+
+    let client = tls::client(&app_handle, &peer)?;
+    let response = network::post_to_peer(
+        &client,
+        &peer.addresses(),
+        peer.port,
+        "/vault/offer",
+        &offer,
+        Duration::from_secs(10),
+    )
+    .await
+    .map_err(|e| format!("Peer didn't accept the transfer: {}", e))?;
+    // The rest of the transfer goes to whichever address answered
+    let address = response
+        .remote_addr()
+        .ok_or("Can't tell which address the peer answered on")?;
+
+    let app_dir = app_data_dir(&app_handle)?;
+    let mut progress = VaultProgress {
+        transfer_id: offer.transfer_id.clone(),
+        files_done: 0,
+        files_total: files.len(),
+        bytes_done: 0,
+        bytes_total: files.iter().map(|f| f.size).sum(),
+    };
+
+    for file in &files {
+        let data = fs::read(app_dir.join(&file.path)).map_err(|e| e.to_string())?;
+        client
+            .post(format!("https://{}/vault/file", address))
+            .query(&[
+                ("transfer_id", offer.transfer_id.as_str()),
+                ("path", file.path.as_str()),
+            ])
+            .body(data)
+            .timeout(Duration::from_secs(300))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("Failed to send {}: {}", file.path, e))?;
+
+        progress.files_done += 1;
+        progress.bytes_done += file.size;
+        let _ = app_handle.emit("vault-send-progress", &progress);
+    }
+
+    let report: VaultReport = client
+        .post(format!("https://{}/vault/finish", address))
+        .json(&FinishRequest {
+            transfer_id: offer.transfer_id.clone(),
+        })
+        .timeout(Duration::from_secs(120))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Peer couldn't complete the transfer: {}", e))?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if report.files != files.len() {
+        return Err(format!(
+            "Peer stored {} of {} files",
+            report.files,
+            files.len()
+        ));
+    }
+    Ok(report)
+}
+
+// Allow (or stop allowing) a peer to send its vault to this device
+#[tauri::command]
+pub async fn set_vault_receiving(app_handle: AppHandle<Wry>, enabled: bool) -> Result<(), String> {
+    let state = app_handle.state::<Mutex<Incoming>>();
+    let mut incoming = state.lock().map_err(|e| e.to_string())?;
+    incoming.accepting = enabled;
+    if !enabled && incoming.transfer.take().is_some() {
+        // Files of the abandoned transfer
+        let _ = fs::remove_dir_all(app_data_dir(&app_handle)?.join(STAGING_DIR));
+    }
+    Ok(())
+}
+
+// Offers and finish requests are read like sync requests, so a body that is too
+// large or isn't JSON is refused like any other bad offer
+fn read_body<T: DeserializeOwned>(body: Result<Bytes, BytesRejection>) -> Result<T, HandlerError> {
+    protocol::read_body(body).map_err(|(status, error)| (status, error.message))
+}
+
+fn forbidden(message: &str) -> HandlerError {
+    (StatusCode::FORBIDDEN, message.to_string())
+}
+
+fn failed(message: impl ToString) -> HandlerError {
+    (StatusCode::INTERNAL_SERVER_ERROR, message.to_string())
+}
+
+// Like notes, a vault is only taken from a paired device
+fn check_paired(
+    app_handle: &AppHandle<Wry>,
+    headers: &HeaderMap,
+    peer_id: &str,
+) -> Result<(), HandlerError> {
+    if pairing::is_trusted(app_handle, peer_id, pairing::bearer_token(headers)) {
+        Ok(())
+    } else {
+        Err(forbidden(&format!(
+            "{} isn't paired with this device",
+            peer_id
+        )))
+    }
+}
+
+async fn receive_offer(
+    State(app_handle): State<AppHandle<Wry>>,
+    headers: HeaderMap,
+    body: Result<Bytes, BytesRejection>,
+) -> Result<Json<serde_json::Value>, HandlerError> {
+    let offer: VaultOffer = read_body(body)?;
+    check_paired(&app_handle, &headers, &offer.peer_id)?;
+    if let Some(file) = offer.files.iter().find(|f| !is_valid_path(&f.path)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid path: {}", file.path),
+        ));
+    }
+
+    let staging = app_data_dir(&app_handle).map_err(failed)?.join(STAGING_DIR);
+    {
+        let state = app_handle.state::<Mutex<Incoming>>();
+        let mut incoming = state.lock().map_err(failed)?;
+        if !incoming.accepting {
+            return Err(forbidden("This device isn't waiting for a vault"));
+        }
+        if let Some(transfer) = &incoming.transfer {
+            return Err(forbidden(&format!(
+                "Already receiving a vault from {}",
+                transfer.peer_name
+            )));
+        }
+        incoming.transfer = Some(IncomingTransfer {
+            id: offer.transfer_id.clone(),
+            peer_id: offer.peer_id.clone(),
+            peer_name: offer.peer_name.clone(),
+            files: offer
+                .files
+                .iter()
+                .map(|f| (f.path.clone(), f.clone()))
+                .collect(),
+            received: HashSet::new(),
+            bytes_received: 0,
+        });
+    }
+
+    // Leftovers of an earlier, interrupted transfer
+    let _ = fs::remove_dir_all(&staging);
+    fs::create_dir_all(&staging).map_err(failed)?;
+
+    let _ = app_handle.emit(
+        "vault-offer",
+        serde_json::json!({
+            "peer_name": offer.peer_name,
+            "files": offer.files.len(),
+            "bytes": offer.files.iter().map(|f| f.size).sum::<u64>(),
+        }),
+    );
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+async fn receive_file(
+    State(app_handle): State<AppHandle<Wry>>,
+    headers: HeaderMap,
+    Query(query): Query<FileQuery>,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>, HandlerError> {
+    let (expected, peer_id) = {
+        let state = app_handle.state::<Mutex<Incoming>>();
+        let incoming = state.lock().map_err(failed)?;
+        let transfer = incoming
+            .transfer
+            .as_ref()
+            .filter(|t| t.id == query.transfer_id)
+            .ok_or_else(|| forbidden("Unknown transfer"))?;
+        let expected = transfer
+            .files
+            .get(&query.path)
+            .cloned()
+            .ok_or_else(|| forbidden("File isn't part of the transfer"))?;
+        (expected, transfer.peer_id.clone())
+    };
+    check_paired(&app_handle, &headers, &peer_id)?;
+
+    if body.len() as u64 != expected.size || hex::encode(Sha256::digest(&body)) != expected.sha256 {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{} doesn't match its hash", query.path),
+        ));
+    }
+
+    let dest = app_data_dir(&app_handle)
+        .map_err(failed)?
+        .join(STAGING_DIR)
+        .join(&query.path);
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).map_err(failed)?;
+    }
+    atomic::write(&dest, &body).map_err(failed)?;
+    if let Some(millis) = expected.modified {
+        storage::set_modified(&dest, UNIX_EPOCH + Duration::from_millis(millis));
+    }
+
+    let progress = {
+        let state = app_handle.state::<Mutex<Incoming>>();
+        let mut incoming = state.lock().map_err(failed)?;
+        let transfer = incoming
+            .transfer
+            .as_mut()
+            .filter(|t| t.id == query.transfer_id)
+            .ok_or_else(|| forbidden("Unknown transfer"))?;
+        if transfer.received.insert(query.path.clone()) {
+            transfer.bytes_received += expected.size;
+        }
+        VaultProgress {
+            transfer_id: transfer.id.clone(),
+            files_done: transfer.received.len(),
+            files_total: transfer.files.len(),
+            bytes_done: transfer.bytes_received,
+            bytes_total: transfer.files.values().map(|f| f.size).sum(),
+        }
+    };
+    let _ = app_handle.emit("vault-receive-progress", &progress);
+
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+// Move the verified files from staging into place
+fn install(app_dir: &Path, notes_dir: &Path, files: &[VaultFile]) -> Result<(), String> {
+    let staging = app_dir.join(STAGING_DIR);
+    for file in files {
+        let source = staging.join(&file.path);
+        let dest = match file.path.strip_prefix(NOTES_PREFIX) {
+            Some(relative) => notes_dir.join(relative),
+            None => app_dir.join(&file.path),
+        };
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        fs::rename(&source, &dest).map_err(|e| format!("{}: {}", file.path, e))?;
+    }
+    let _ = fs::remove_dir_all(staging);
+    Ok(())
+}
+
+async fn receive_finish(
+    State(app_handle): State<AppHandle<Wry>>,
+    headers: HeaderMap,
+    body: Result<Bytes, BytesRejection>,
+) -> Result<Json<VaultReport>, HandlerError> {
+    let request: FinishRequest = read_body(body)?;
+    let transfer = {
+        let state = app_handle.state::<Mutex<Incoming>>();
+        let mut incoming = state.lock().map_err(failed)?;
+        let transfer = incoming
+            .transfer
+            .as_ref()
+            .filter(|t| t.id == request.transfer_id)
+            .ok_or_else(|| forbidden("Unknown transfer"))?;
+        check_paired(&app_handle, &headers, &transfer.peer_id)?;
+        let missing = transfer.files.len() - transfer.received.len();
+        if missing > 0 {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("{} files haven't arrived", missing),
+            ));
+        }
+        // One vault per opt-in
+        incoming.accepting = false;
+        incoming.transfer.take().unwrap()
+    };
+
+    let files: Vec<VaultFile> = transfer.files.into_values().collect();
+    let app_dir = app_data_dir(&app_handle).map_err(failed)?;
+    let notes_dir = get_notes_dir(&app_handle);
+    let local = load_device_settings(&app_handle);
+    {
+        let files = files.clone();
+        tokio::task::spawn_blocking(move || install(&app_dir, &notes_dir, &files))
+            .await
+            .map_err(failed)?
+            .map_err(failed)?;
+    }
+
+    // The sender's settings came along, but where this device keeps its notes, how
+    // it shows up on the network and who may sync with it stay its own
+    settings::reload_device_settings(&app_handle);
+    let mut received = load_device_settings(&app_handle);
+    received.notes_dir = local.notes_dir;
+    received.device_name = local.device_name;
+    received.sync_port = local.sync_port;
+    received.network_interfaces = local.network_interfaces;
+    received.allow_unpaired_sync = local.allow_unpaired_sync;
+    save_device_settings(&app_handle, &received).map_err(failed)?;
+
+    let report = VaultReport {
+        files: files.len(),
+        bytes: files.iter().map(|f| f.size).sum(),
+    };
+    println!(
+        "Received vault from {}: {} files",
+        transfer.peer_name, report.files
+    );
+    let _ = events::notes_reloaded(&app_handle);
+    let _ = app_handle.emit("vault-received", &report);
+
+    Ok(Json(report))
+}
+
+// Routes of the receiving side, served next to the sync endpoints
+pub fn routes(app_handle: AppHandle<Wry>) -> axum::Router {
+    axum::Router::new()
+        .route("/vault/offer", axum::routing::post(receive_offer))
+        .route("/vault/file", axum::routing::post(receive_file))
+        .route("/vault/finish", axum::routing::post(receive_finish))
+        .layer(DefaultBodyLimit::max(MAX_FILE_BYTES))
+        .with_state(app_handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_notes_and_settings_are_valid() {
+        assert!(is_valid_path(SETTINGS_PATH));
+        assert!(is_valid_path("notes/note.md"));
+        assert!(is_valid_path("notes/Work/note.md"));
+        assert!(!is_valid_path("other/note.md"));
+        assert!(!is_valid_path("notes/../settings.json"));
+        assert!(!is_valid_path("notes/../../outside"));
+        assert!(!is_valid_path("/notes/note.md"));
+    }
+}
@@ -0,0 +1,387 @@
+use crate::headless::StdoutEvents;
+use crate::server::{self, BoxFuture, Received, SyncHost, SyncStore};
+use crate::{network, overrides, protocol, schema, tls, AppState, Note, PeerDevice, SyncRequest};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+// A fake peer for integration tests of sync, built with `--features mock-peer`. It
+// runs the real sync server and mDNS advertisement, records every note sent to it
+// and answers shares the way a test asks: accepting, rejecting or never answering,
+// after a delay that stands in for a slow link, and dropping off the network after
+// a number of requests.
+//
+// Start one in-process with `MockPeer::start`, or as its own process with
+// `--mock-peer` and the flags read by `run`. It can't pair, so the app it talks to
+// needs `allow_unpaired_sync` on.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Answer {
+    #[default]
+    Accept,
+    Reject,
+    // Leave the share pending forever
+    Ignore,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Behavior {
+    pub answer: Answer,
+    // How long each request takes before it is answered
+    pub delay: Duration,
+    // Stop serving after this many requests, as if the device left the network
+    pub disconnect_after: Option<usize>,
+}
+
+struct MockStore {
+    behavior: Behavior,
+    state: Arc<Mutex<AppState>>,
+    received: Arc<Mutex<Vec<SyncRequest>>>,
+    stop: watch::Sender<bool>,
+}
+
+impl SyncStore for MockStore {
+    fn receive<'a>(&'a self, request: &'a SyncRequest) -> BoxFuture<'a, Received> {
+        Box::pin(async move {
+            tokio::time::sleep(self.behavior.delay).await;
+
+            let count = match self.received.lock() {
+                Ok(mut received) => {
+                    received.push(request.clone());
+                    received.len()
+                }
+                Err(_) => return Received::Failed,
+            };
+            if self
+                .behavior
+                .disconnect_after
+                .is_some_and(|limit| count >= limit)
+            {
+                println!("Mock peer disconnecting after {} requests", count);
+                self.stop.send_replace(true);
+            }
+
+            let accepted = match self.behavior.answer {
+                Answer::Accept => true,
+                Answer::Reject => false,
+                Answer::Ignore => return Received::Pending,
+            };
+            tokio::spawn(answer(
+                self.state.clone(),
+                request.peer_id.clone(),
+                request.note.id.clone(),
+                accepted,
+            ));
+            Received::Pending
+        })
+    }
+
+    fn confirm_sent(&self, peer_id: &str, note_id: &str) {
+        println!("Peer {} accepted note {}", peer_id, note_id);
+    }
+}
+
+// Answer a share like `respond_to_sync` does, once the sender has been found
+async fn answer(state: Arc<Mutex<AppState>>, peer_id: String, note_id: String, accepted: bool) {
+    let peer = state
+        .lock()
+        .ok()
+        .and_then(|state| state.peers.get(&peer_id).cloned());
+    let Some(peer) = peer else {
+        println!("Mock peer hasn't discovered {}, not answering", peer_id);
+        return;
+    };
+
+    let client = match tls::for_peer(reqwest::Client::builder(), &peer)
+        .and_then(|builder| builder.build().map_err(|e| e.to_string()))
+    {
+        Ok(client) => client,
+        Err(e) => {
+            println!("Mock peer can't answer {}: {}", peer_id, e);
+            return;
+        }
+    };
+
+    let device_id = match state.lock() {
+        Ok(state) => state.device_id.clone(),
+        Err(_) => return,
+    };
+    let response = json!({
+        "notification_id": uuid::Uuid::new_v4().to_string(),
+        "peer_id": device_id,
+        "note_id": note_id,
+        "accepted": accepted,
+    });
+    let result = network::post_to_peer(
+        &client,
+        &peer.addresses(),
+        peer.port,
+        "/sync/response",
+        &response,
+        Duration::from_secs(5),
+    )
+    .await;
+    if let Err(e) = result {
+        println!("Mock peer failed to answer {}: {}", peer_id, e);
+    }
+}
+
+pub struct MockPeer {
+    pub id: String,
+    pub name: String,
+    // Of the certificate it serves sync on, for pinning it
+    pub fingerprint: String,
+    state: Arc<Mutex<AppState>>,
+    received: Arc<Mutex<Vec<SyncRequest>>>,
+    stop: watch::Sender<bool>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl MockPeer {
+    // Start serving on the first free port from `port`. The server runs on its own
+    // thread, since mDNS browsing blocks.
+    pub fn start(name: &str, port: u16, behavior: Behavior) -> MockPeer {
+        let id = uuid::Uuid::new_v4().to_string();
+        let state = Arc::new(Mutex::new(AppState {
+            device_id: id.clone(),
+            device_name: name.to_string(),
+            peers: HashMap::new(),
+            sync_notifications: Vec::new(),
+        }));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let (stop, stopped) = watch::channel(false);
+        // A new certificate every run, like the id
+        let cert = tls::DeviceCert::generate().expect("Failed to create the sync certificate");
+        let fingerprint = cert.fingerprint.clone();
+
+        let host = SyncHost {
+            state: state.clone(),
+            store: Arc::new(MockStore {
+                behavior,
+                state: state.clone(),
+                received: received.clone(),
+                stop: stop.clone(),
+            }),
+            events: Arc::new(StdoutEvents),
+            cert,
+        };
+        let config = network::ServerConfig {
+            device_name: name.to_string(),
+            first_port: port,
+            interfaces: Vec::new(),
+        };
+        let thread = std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().expect("Failed to start the async runtime");
+            rt.block_on(server::run(host, config, Vec::new(), stopped));
+        });
+
+        MockPeer {
+            id,
+            name: name.to_string(),
+            fingerprint,
+            state,
+            received,
+            stop,
+            thread,
+        }
+    }
+
+    // Sync requests received so far, oldest first
+    pub fn received(&self) -> Vec<SyncRequest> {
+        self.received
+            .lock()
+            .map(|received| received.clone())
+            .unwrap_or_default()
+    }
+
+    // Devices found over mDNS
+    pub fn peers(&self) -> Vec<PeerDevice> {
+        self.state
+            .lock()
+            .map(|state| state.peers.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Share a note with a peer like `share_note` does, returning the peer's reply
+    pub async fn share(&self, peer: &PeerDevice, note: Note) -> Result<Value, String> {
+        let request = SyncRequest {
+            protocol: protocol::negotiate(peer.protocol),
+            schema: schema::SCHEMA_VERSION,
+            peer_id: self.id.clone(),
+            peer_name: self.name.clone(),
+            note,
+            attachments_data: HashMap::new(),
+            tombstones: Vec::new(),
+        };
+        let client = tls::for_peer(reqwest::Client::builder(), peer)?
+            .build()
+            .map_err(|e| e.to_string())?;
+        network::post_to_peer(
+            &client,
+            &peer.addresses(),
+            peer.port,
+            "/sync/request",
+            &request,
+            Duration::from_secs(5),
+        )
+        .await?
+        .json()
+        .await
+        .map_err(|e| e.to_string())
+    }
+
+    // Drop off the network: the server stops and the mDNS service goes away
+    pub fn disconnect(self) {
+        self.stop.send_replace(true);
+        let _ = self.thread.join();
+    }
+}
+
+// `--mock-peer`: serve as a mock peer until interrupted. `--port` and
+// `--device-name` apply as usual, and `--mock-answer accept|reject|ignore`,
+// `--mock-delay-ms <ms>` and `--mock-disconnect-after <count>` set its behavior.
+pub fn run() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let number = |name: &str| overrides::flag(&args, name).and_then(|v| v.parse::<u64>().ok());
+    let answer = match overrides::flag(&args, "--mock-answer").as_deref() {
+        Some("reject") => Answer::Reject,
+        Some("ignore") => Answer::Ignore,
+        _ => Answer::Accept,
+    };
+    let behavior = Behavior {
+        answer,
+        delay: Duration::from_millis(number("--mock-delay-ms").unwrap_or(0)),
+        disconnect_after: number("--mock-disconnect-after").map(|n| n as usize),
+    };
+
+    let overrides = overrides::get();
+    let name = overrides
+        .device_name
+        .clone()
+        .unwrap_or_else(|| "Mock Peer".to_string());
+    let peer = MockPeer::start(
+        &name,
+        overrides.port.unwrap_or(network::DEFAULT_PORT),
+        behavior.clone(),
+    );
+    println!(
+        "Mock peer {} ({}) running with {:?}",
+        name, peer.id, behavior
+    );
+
+    let rt = tokio::runtime::Runtime::new().expect("Failed to start the async runtime");
+    let mut stopped = peer.stop.subscribe();
+    rt.block_on(async {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            // Disconnected on its own
+            _ = stopped.wait_for(|stop| *stop) => {}
+        }
+    });
+    peer.disconnect();
+}
+
+// Run with `cargo test --features mock-peer`. Peers are addressed directly on the
+// loopback interface rather than found over mDNS, which CI machines may not have.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, TcpListener};
+    use std::time::Instant;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    // A port nothing listens on right now
+    fn free_port() -> u16 {
+        let listener = TcpListener::bind((LOCALHOST, 0)).expect("No free port");
+        listener.local_addr().expect("No local address").port()
+    }
+
+    fn start(name: &str, behavior: Behavior) -> (MockPeer, PeerDevice) {
+        let port = free_port();
+        let peer = MockPeer::start(name, port, behavior);
+        let device = PeerDevice {
+            id: peer.id.clone(),
+            name: peer.name.clone(),
+            ip: LOCALHOST,
+            port,
+            addresses: Vec::new(),
+            protocol: protocol::PROTOCOL_VERSION,
+            fingerprint: Some(peer.fingerprint.clone()),
+        };
+        (peer, device)
+    }
+
+    fn note(id: &str) -> Note {
+        Note {
+            id: id.to_string(),
+            title: "Shared".to_string(),
+            content: "# Shared\n\nFrom the other device".to_string(),
+            datetime: "1700000000".to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Share `note` once the target's server is up, which takes a moment after start
+    async fn share_when_up(from: &MockPeer, to: &PeerDevice, note: Note) -> Result<Value, String> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match from.share(to, note.clone()).await {
+                Err(_) if Instant::now() < deadline => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn shared_note_is_received() {
+        let ignore = Behavior {
+            answer: Answer::Ignore,
+            ..Default::default()
+        };
+        let (receiver, receiver_device) = start("Receiver", ignore);
+        let (sender, _) = start("Sender", Behavior::default());
+
+        let reply = share_when_up(&sender, &receiver_device, note("note-1"))
+            .await
+            .expect("Share failed");
+        assert_eq!(reply["success"], json!(true));
+
+        let received = receiver.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].peer_id, sender.id);
+        assert_eq!(received[0].peer_name, "Sender");
+        assert_eq!(received[0].note.id, "note-1");
+        assert_eq!(received[0].note.title, "Shared");
+
+        sender.disconnect();
+        receiver.disconnect();
+    }
+
+    #[tokio::test]
+    async fn peer_drops_off_after_its_limit() {
+        let behavior = Behavior {
+            answer: Answer::Ignore,
+            disconnect_after: Some(1),
+            ..Default::default()
+        };
+        let (receiver, receiver_device) = start("Receiver", behavior);
+        let (sender, _) = start("Sender", Behavior::default());
+
+        share_when_up(&sender, &receiver_device, note("note-1"))
+            .await
+            .expect("First share failed");
+        // The server finishes the request it is on before it stops
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let second = sender.share(&receiver_device, note("note-2")).await;
+        assert!(second.is_err(), "Peer still answered: {:?}", second);
+        assert_eq!(receiver.received().len(), 1);
+
+        sender.disconnect();
+        receiver.disconnect();
+    }
+}
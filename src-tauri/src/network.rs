@@ -0,0 +1,313 @@
+use crate::overrides;
+use crate::protocol::{self, SyncError};
+use crate::settings::load_device_settings;
+use local_ip_address::{list_afinet_netifas, local_ip};
+use serde::Serialize;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Range;
+use std::time::{Duration, Instant, SystemTime};
+use tauri::{AppHandle, Wry};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+// How long one peer address gets to answer before the next one is tried alongside it
+const ADDRESS_HEAD_START: Duration = Duration::from_millis(300);
+pub const DEFAULT_PORT: u16 = 8000;
+// How many ports from the first one are tried while they are taken
+const PORT_ATTEMPTS: u16 = 20;
+// How often to look for a resume from sleep or changed addresses
+const NETWORK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// How much further the wall clock may run than the monotonic clock between two
+// checks before we take it that the machine was asleep
+const SLEEP_THRESHOLD: Duration = Duration::from_secs(10);
+// Waits before restarting a sync server that stopped by itself, the last one
+// repeating for as long as it keeps failing
+const RETRY_DELAYS: [Duration; 5] = [
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(15),
+    Duration::from_secs(30),
+    Duration::from_secs(60),
+];
+// A server that ran this long before failing had started fine, so the next restart
+// doesn't wait longer than the first
+pub const STABLE_RUN: Duration = Duration::from_secs(60);
+
+// What the sync server and mDNS registration are started with. They are restarted
+// when saved settings change any of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub device_name: String,
+    pub first_port: u16,
+    pub interfaces: Vec<String>,
+}
+
+// Why the sync server and mDNS registration stopped and start over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Restart {
+    Settings,
+    // The machine woke from sleep, after which the mDNS sockets are often dead
+    Resumed,
+    NetworkChanged,
+    // The server or mDNS stopped by itself, e.g. a failed bind or browse
+    Failed,
+}
+
+impl fmt::Display for Restart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Restart::Settings => "Network settings changed",
+            Restart::Resumed => "Woke from sleep",
+            Restart::NetworkChanged => "Network addresses changed",
+            Restart::Failed => "Sync server stopped",
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NetworkInterface {
+    name: String,
+    address: String,
+    ipv4: bool,
+    loopback: bool,
+    // Whether the interface is one of those chosen in settings
+    selected: bool,
+}
+
+pub fn hostname() -> String {
+    hostname::get()
+        .map(|h| h.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "Unknown Device".to_string())
+}
+
+pub fn server_config(app_handle: &AppHandle<Wry>) -> ServerConfig {
+    let settings = load_device_settings(app_handle);
+    let overrides = overrides::get();
+    ServerConfig {
+        device_name: overrides
+            .device_name
+            .clone()
+            .or(settings.device_name)
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(hostname),
+        first_port: overrides
+            .port
+            .or(settings.sync_port)
+            .unwrap_or(DEFAULT_PORT),
+        interfaces: settings.network_interfaces,
+    }
+}
+
+pub fn ports(config: &ServerConfig) -> Range<u16> {
+    config.first_port..config.first_port.saturating_add(PORT_ATTEMPTS)
+}
+
+// Resolves once saved settings no longer match `config`
+async fn wait_for_change(
+    app_handle: &AppHandle<Wry>,
+    config: &ServerConfig,
+    changes: &mut watch::Receiver<()>,
+) {
+    while server_config(app_handle) == *config {
+        if changes.changed().await.is_err() {
+            // Settings can't change anymore
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+// Resolves once the machine has woken from sleep, or once its usable addresses
+// have changed and it has any. Sleep is noticed by the wall clock jumping ahead of
+// the monotonic one, which doesn't count time asleep on Linux and macOS; elsewhere
+// a resume only shows if it changed the addresses.
+pub async fn wait_for_network_change() -> Restart {
+    let mut addresses = usable_addresses();
+    loop {
+        let (wall, monotonic) = (SystemTime::now(), Instant::now());
+        tokio::time::sleep(NETWORK_CHECK_INTERVAL).await;
+        let asleep = wall
+            .elapsed()
+            .unwrap_or_default()
+            .saturating_sub(monotonic.elapsed());
+        if asleep > SLEEP_THRESHOLD {
+            return Restart::Resumed;
+        }
+
+        let current = usable_addresses();
+        if current != addresses {
+            // Without any address there is nothing to restart on; wait for one
+            if !current.is_empty() {
+                return Restart::NetworkChanged;
+            }
+            addresses = current;
+        }
+    }
+}
+
+// Resolves with the first reason to restart a server started with `config`
+pub async fn wait_for_restart(
+    app_handle: &AppHandle<Wry>,
+    config: &ServerConfig,
+    changes: &mut watch::Receiver<()>,
+) -> Restart {
+    tokio::select! {
+        _ = wait_for_change(app_handle, config, changes) => Restart::Settings,
+        restart = wait_for_network_change() => restart,
+    }
+}
+
+// How long to wait before the next restart after `failures` failed ones in a row
+pub fn retry_delay(failures: usize) -> Duration {
+    RETRY_DELAYS[failures.min(RETRY_DELAYS.len() - 1)]
+}
+
+fn interfaces() -> Vec<(String, IpAddr)> {
+    list_afinet_netifas().unwrap_or_else(|e| {
+        println!("Failed to list network interfaces: {}", e);
+        Vec::new()
+    })
+}
+
+// IPv4 addresses of the interfaces chosen in settings that are currently up.
+// Empty when no interfaces are configured, leaving the choice to the OS.
+pub fn selected_addresses(app_handle: &AppHandle<Wry>) -> Vec<IpAddr> {
+    let names = load_device_settings(app_handle).network_interfaces;
+    if names.is_empty() {
+        return Vec::new();
+    }
+
+    interfaces()
+        .into_iter()
+        .filter(|(name, ip)| ip.is_ipv4() && names.contains(name))
+        .map(|(_, ip)| ip)
+        .collect()
+}
+
+// Every non-loopback IPv4 address of this machine, the OS's preferred one first
+fn usable_addresses() -> Vec<IpAddr> {
+    let primary = local_ip().ok();
+    let mut addresses: Vec<IpAddr> = interfaces()
+        .into_iter()
+        .map(|(_, ip)| ip)
+        .filter(|ip| ip.is_ipv4() && !ip.is_loopback())
+        .collect();
+    addresses.sort_by_key(|ip| Some(*ip) != primary);
+    addresses.dedup();
+    addresses
+}
+
+// Addresses to listen on and advertise to peers: the interfaces chosen in
+// settings, or otherwise every usable address
+pub fn listen_addresses(app_handle: &AppHandle<Wry>) -> Vec<IpAddr> {
+    let selected = selected_addresses(app_handle);
+    if selected.is_empty() {
+        usable_addresses()
+    } else {
+        selected
+    }
+}
+
+// Order a peer's advertised addresses so those on one of our own /24 subnets come first
+pub fn order_peer_addresses(mut addresses: Vec<IpAddr>) -> Vec<IpAddr> {
+    let local = interfaces();
+    let same_subnet = |ip: &IpAddr| match ip {
+        IpAddr::V4(ip) => local.iter().any(|(_, own)| match own {
+            IpAddr::V4(own) => own.octets()[..3] == ip.octets()[..3],
+            IpAddr::V6(_) => false,
+        }),
+        IpAddr::V6(_) => false,
+    };
+    addresses.sort_by_key(|ip| !same_subnet(ip));
+    addresses
+}
+
+// POST JSON to a peer that may be reachable on any of several addresses. Addresses
+// are tried in order, each getting a short head start before the next one races it
+// (happy eyeballs); the first address that answers wins. An answer with a failing
+// status is the peer's error and isn't retried on other addresses.
+pub async fn post_to_peer(
+    client: &reqwest::Client,
+    addresses: &[IpAddr],
+    port: u16,
+    path: &str,
+    body: &impl Serialize,
+    timeout: Duration,
+) -> Result<reqwest::Response, SyncError> {
+    let body =
+        serde_json::to_vec(body).map_err(|e| SyncError::new("internal", e.to_string(), false))?;
+    let mut attempts = JoinSet::new();
+    let mut last_error = "Peer has no known addresses".to_string();
+
+    for ip in addresses {
+        let url = format!("https://{}{}", SocketAddr::new(*ip, port), path);
+        let client = client.clone();
+        let body = body.clone();
+        attempts.spawn(async move {
+            client
+                .post(&url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .timeout(timeout)
+                .send()
+                .await
+                .map_err(|e| format!("{}: {}", url, e))
+        });
+
+        let head_start = tokio::time::sleep(ADDRESS_HEAD_START);
+        tokio::pin!(head_start);
+        loop {
+            tokio::select! {
+                Some(result) = attempts.join_next() => match result {
+                    Ok(Ok(response)) => return check_status(response).await,
+                    // A quick failure moves straight on to the next address
+                    Ok(Err(e)) => {
+                        last_error = e;
+                        break;
+                    }
+                    Err(e) => {
+                        last_error = e.to_string();
+                        break;
+                    }
+                },
+                _ = &mut head_start => break,
+            }
+        }
+    }
+
+    while let Some(result) = attempts.join_next().await {
+        match result {
+            Ok(Ok(response)) => return check_status(response).await,
+            Ok(Err(e)) => last_error = e,
+            Err(e) => last_error = e.to_string(),
+        }
+    }
+    Err(SyncError::unreachable(last_error))
+}
+
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, SyncError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(protocol::read_error(status.as_u16(), &body))
+}
+
+#[tauri::command]
+pub async fn list_network_interfaces(
+    app_handle: AppHandle<Wry>,
+) -> Result<Vec<NetworkInterface>, String> {
+    let selected = load_device_settings(&app_handle).network_interfaces;
+
+    Ok(interfaces()
+        .into_iter()
+        .map(|(name, ip)| NetworkInterface {
+            selected: selected.contains(&name),
+            name,
+            address: ip.to_string(),
+            ipv4: ip.is_ipv4(),
+            loopback: ip.is_loopback(),
+        })
+        .collect())
+}
@@ -0,0 +1,90 @@
+use crate::Note;
+use serde_json::Value;
+
+// Frontmatter key a note can set to `false` to keep its markdown exactly as written
+const OPT_OUT_FIELD: &str = "normalize";
+
+pub fn is_opted_out(note: &Note) -> bool {
+    note.fields.get(OPT_OUT_FIELD) == Some(&Value::Bool(false))
+}
+
+fn is_heading(line: &str) -> bool {
+    let hashes = line.chars().take_while(|&c| c == '#').count();
+    (1..=6).contains(&hashes) && line[hashes..].starts_with([' ', '\t'])
+}
+
+fn is_thematic_break(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3 && (marks.chars().all(|c| c == '*') || marks.chars().all(|c| c == '-'))
+}
+
+fn normalize_line(line: &str) -> String {
+    // Two trailing spaces are a hard line break, so keep exactly those
+    let hard_break = line.ends_with("  ") && !line.trim().is_empty();
+    let line = line.trim_end();
+
+    let mut normalized = if is_heading(line) {
+        let hashes = line.chars().take_while(|&c| c == '#').count();
+        format!("{} {}", &line[..hashes], line[hashes..].trim_start())
+    } else {
+        let indent_len = line.len() - line.trim_start().len();
+        let (indent, rest) = line.split_at(indent_len);
+        match rest.strip_prefix("* ").or_else(|| rest.strip_prefix("+ ")) {
+            Some(item) if !is_thematic_break(rest) => format!("{}- {}", indent, item),
+            _ => line.to_string(),
+        }
+    };
+
+    if hard_break && !is_heading(line) {
+        normalized.push_str("  ");
+    }
+    normalized
+}
+
+// Tidy markdown so files diff cleanly: one space after heading markers, blank lines
+// around headings, `-` list markers, no trailing whitespace or repeated blank lines,
+// and a single final newline. Fenced code blocks are left untouched.
+pub fn normalize_markdown(content: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut fence: Option<&str> = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+            lines.push(line.to_string());
+            continue;
+        }
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(&trimmed[..3]);
+            lines.push(line.trim_end().to_string());
+            continue;
+        }
+
+        let line = normalize_line(line);
+        let previous_blank = lines.last().map(String::is_empty).unwrap_or(true);
+        let previous_heading = lines.last().is_some_and(|l| is_heading(l));
+
+        if line.is_empty() {
+            if !previous_blank {
+                lines.push(line);
+            }
+            continue;
+        }
+        if (is_heading(&line) || previous_heading) && !previous_blank {
+            lines.push(String::new());
+        }
+        lines.push(line);
+    }
+
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+
+    let mut normalized = lines.join("\n");
+    normalized.push('\n');
+    normalized
+}
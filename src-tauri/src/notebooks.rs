@@ -0,0 +1,364 @@
+use crate::events;
+use crate::ordering::{self, folder_of, FOLDER_FIELD};
+use crate::schema::StepReport;
+use crate::settings::{load_vault_settings, save_vault_settings};
+use crate::tags::is_within;
+use crate::{
+    encryption, frontmatter, get_note_path, get_notes_dir, get_sync_path, load_notes, persist_note,
+    persist_note_unchecked, Note,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Wry};
+
+// Notebooks group notes, e.g. work and personal. Each is a subdirectory of the notes
+// directory, and nests with slashes (`work/clients` is `notes/work/clients/`). A
+// note's notebook is also kept in its `folder` frontmatter field, so it stays filed
+// when synced, and every write moves the file to the directory the field names.
+// Notebooks whose names can't be directory names keep their notes at the top.
+// Notebooks created in the app are remembered in the vault settings too.
+
+// Holds attachments at the top of the notes directory, so it is never a notebook
+const ATTACHMENTS_DIR: &str = "attachments";
+
+#[derive(Debug, Serialize)]
+pub struct Notebook {
+    // Full path, e.g. `work/clients`
+    name: String,
+    // Notes filed directly in this notebook
+    count: usize,
+}
+
+// Tidy a notebook name as typed: no surrounding whitespace or empty segments
+fn normalize_notebook(name: &str) -> String {
+    name.split('/')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// A notebook's directory, or None when a segment of its name can't be a directory
+// name on every system (`.drafts`, `a:b`) or would be the attachments
+pub fn notebook_dir(notes_dir: &Path, notebook: &str) -> Option<PathBuf> {
+    let name = normalize_notebook(notebook);
+    if name.is_empty() {
+        return None;
+    }
+    let mut dir = notes_dir.to_path_buf();
+    for (i, segment) in name.split('/').enumerate() {
+        let usable = !segment.starts_with('.')
+            && !(i == 0 && segment == ATTACHMENTS_DIR)
+            && !segment
+                .chars()
+                .any(|c| c.is_control() || r#"\:*?"<>|"#.contains(c));
+        if !usable {
+            return None;
+        }
+        dir.push(segment);
+    }
+    Some(dir)
+}
+
+// The notes directory followed by every notebook directory in it, parents before
+// the notebooks nested in them. Directories the app manages itself (attachments,
+// trash, cold storage) are left out.
+pub fn note_dirs(notes_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![notes_dir.to_path_buf()];
+    let mut next = 0;
+    while next < dirs.len() {
+        let top = next == 0;
+        if let Ok(entries) = fs::read_dir(&dirs[next]) {
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if entry.file_type().is_ok_and(|t| t.is_dir())
+                    && !name.starts_with('.')
+                    && !(top && name == ATTACHMENTS_DIR)
+                {
+                    dirs.push(entry.path());
+                }
+            }
+        }
+        next += 1;
+    }
+    dirs
+}
+
+// Every file at the top of the notes directory and in notebooks, such as notes and
+// the copies of them staged by a pending sync
+pub fn note_dir_files(notes_dir: &Path) -> Vec<PathBuf> {
+    note_dirs(notes_dir)
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+// Every note file, at the top of the notes directory and in notebooks
+pub fn note_files(notes_dir: &Path) -> Vec<PathBuf> {
+    note_dir_files(notes_dir)
+        .into_iter()
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("md"))
+        .collect()
+}
+
+// Where the file of an existing note is, in whichever notebook it is filed
+pub fn find_note_file(notes_dir: &Path, id: &str) -> Option<PathBuf> {
+    let name = format!("{}.md", id);
+    note_dirs(notes_dir)
+        .into_iter()
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+}
+
+// Move a note's file into the directory of its notebook, or to the top of the notes
+// directory without one, along with a copy of it waiting from a peer
+pub fn file_note(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    notebook: Option<&str>,
+) -> Result<(), String> {
+    let notes_dir = get_notes_dir(app_handle);
+    let current = get_note_path(app_handle, note_id);
+    if !current.exists() {
+        return Ok(());
+    }
+    let dir = notebook
+        .and_then(|name| notebook_dir(&notes_dir, name))
+        .unwrap_or(notes_dir);
+    let target = dir.join(format!("{}.md", note_id));
+    if target == current {
+        return Ok(());
+    }
+
+    let staged = get_sync_path(app_handle, note_id);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    fs::rename(&current, &target).map_err(|e| e.to_string())?;
+    if staged.exists() {
+        fs::rename(&staged, get_sync_path(app_handle, note_id)).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// The notebook a directory under the notes directory stands for
+fn notebook_of_dir(notes_dir: &Path, dir: &Path) -> Option<String> {
+    let relative = dir.strip_prefix(notes_dir).ok()?;
+    let name = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    (!name.is_empty()).then_some(name)
+}
+
+// Every notebook, with notes or created empty, sorted by name. Parents of nested
+// notebooks are listed too.
+fn notebooks(app_handle: &AppHandle<Wry>) -> Result<Vec<Notebook>, String> {
+    let notes_dir = get_notes_dir(app_handle);
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for name in load_vault_settings(app_handle).notebooks {
+        counts.entry(name).or_default();
+    }
+    for dir in note_dirs(&notes_dir) {
+        if let Some(name) = notebook_of_dir(&notes_dir, &dir) {
+            counts.entry(name).or_default();
+        }
+    }
+    for note in load_notes(app_handle)? {
+        if let Some(name) = folder_of(&note.fields).map(normalize_notebook) {
+            *counts.entry(name).or_default() += 1;
+        }
+    }
+
+    let parents: Vec<String> = counts
+        .keys()
+        .flat_map(|name| {
+            name.match_indices('/')
+                .map(|(i, _)| name[..i].to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for parent in parents {
+        counts.entry(parent).or_default();
+    }
+    counts.remove("");
+
+    Ok(counts
+        .into_iter()
+        .map(|(name, count)| Notebook { name, count })
+        .collect())
+}
+
+#[tauri::command]
+pub async fn get_notebooks(app_handle: AppHandle<Wry>) -> Result<Vec<Notebook>, String> {
+    notebooks(&app_handle)
+}
+
+#[tauri::command]
+pub async fn create_notebook(
+    app_handle: AppHandle<Wry>,
+    name: String,
+) -> Result<Vec<Notebook>, String> {
+    let name = normalize_notebook(&name);
+    if name.is_empty() {
+        return Err("Notebook name can't be empty".to_string());
+    }
+    if notebooks(&app_handle)?.iter().any(|n| n.name == name) {
+        return Err(format!("Notebook {:?} already exists", name));
+    }
+
+    if let Some(dir) = notebook_dir(&get_notes_dir(&app_handle), &name) {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let mut settings = load_vault_settings(&app_handle);
+    settings.notebooks.push(name);
+    save_vault_settings(&app_handle, &settings)?;
+    events::notes_reloaded(&app_handle)?;
+    notebooks(&app_handle)
+}
+
+// Rename a notebook along with the notebooks nested in it. Renaming onto an
+// existing notebook merges the two. Returns the number of notes moved.
+#[tauri::command]
+pub async fn rename_notebook(
+    app_handle: AppHandle<Wry>,
+    from: String,
+    to: String,
+) -> Result<usize, String> {
+    let from = normalize_notebook(&from);
+    let to = normalize_notebook(&to);
+    if from.is_empty() || to.is_empty() {
+        return Err("Notebook name can't be empty".to_string());
+    }
+    if from == to {
+        return Ok(0);
+    }
+    if is_within(&to, &from) {
+        return Err("A notebook can't be moved into itself".to_string());
+    }
+    let rename = |name: &str| {
+        let name = normalize_notebook(name);
+        is_within(&name, &from).then(|| format!("{}{}", to, &name[from.len()..]))
+    };
+
+    let mut moved = 0;
+    for mut note in load_notes(&app_handle)? {
+        let Some(renamed) = folder_of(&note.fields).and_then(rename) else {
+            continue;
+        };
+        note.fields
+            .insert(FOLDER_FIELD.to_string(), Value::String(renamed));
+        persist_note_unchecked(&app_handle, note).await?;
+        moved += 1;
+    }
+    move_notebook_dirs(&get_notes_dir(&app_handle), &from, &rename);
+
+    let mut settings = load_vault_settings(&app_handle);
+    let mut renamed: Vec<String> = Vec::new();
+    for name in &settings.notebooks {
+        let name = rename(name).unwrap_or_else(|| name.clone());
+        if !renamed.contains(&name) {
+            renamed.push(name);
+        }
+    }
+    if renamed != settings.notebooks {
+        settings.notebooks = renamed;
+        save_vault_settings(&app_handle, &settings)?;
+    }
+    ordering::rename_folders(&app_handle, rename)?;
+
+    events::notes_reloaded(&app_handle)?;
+    Ok(moved)
+}
+
+// Carry the directories of a renamed notebook and the ones nested in it, emptied
+// of notes by now, over to the new name, so empty notebooks survive the rename
+fn move_notebook_dirs(notes_dir: &Path, from: &str, rename: impl Fn(&str) -> Option<String>) {
+    let Some(from_dir) = notebook_dir(notes_dir, from).filter(|dir| dir.is_dir()) else {
+        return;
+    };
+    let dirs = note_dirs(&from_dir);
+    for dir in &dirs {
+        let Some(to_dir) = notebook_of_dir(notes_dir, dir)
+            .and_then(|name| rename(&name))
+            .and_then(|name| notebook_dir(notes_dir, &name))
+        else {
+            continue;
+        };
+        if let Err(e) = fs::create_dir_all(&to_dir) {
+            println!("Failed to create notebook directory {:?}: {}", to_dir, e);
+        }
+    }
+    // Nested directories go first; anything other than notes left in one keeps it
+    for dir in dirs.iter().rev() {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+// Schema version 2 -> 3: notes filed in a notebook move from the top of the notes
+// directory into the notebook's directory. Notes that can't be read yet, as in a
+// locked encrypted vault, stay where they are until they are next saved.
+pub fn upgrade_vault(notes_dir: &Path, report: &mut StepReport) -> Result<(), String> {
+    for entry in fs::read_dir(notes_dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("md") {
+            continue;
+        }
+        let Ok(raw) = encryption::read_to_string(&path) else {
+            continue;
+        };
+        let (fields, _) = frontmatter::split(&raw);
+        let Some(dir) = folder_of(&fields).and_then(|name| notebook_dir(notes_dir, name)) else {
+            continue;
+        };
+        let Some(file_name) = path.file_name() else {
+            continue;
+        };
+        let dest = dir.join(file_name);
+        if dest.exists() {
+            println!("Leaving {:?} in place, {:?} is taken", path, dest);
+            continue;
+        }
+        match fs::create_dir_all(&dir).and_then(|_| fs::rename(&path, &dest)) {
+            Ok(()) => report.changed += 1,
+            Err(e) => report.errors.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    Ok(())
+}
+
+// Notes received from an older device carry their notebook in the frontmatter
+// already, and are filed when they are saved
+pub fn upgrade_note(_note: &mut Note) {}
+
+// File a note in a notebook, or take it out of any with no notebook
+#[tauri::command]
+pub async fn move_note_to_notebook(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    notebook: Option<String>,
+) -> Result<(), String> {
+    let mut note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    let notebook = notebook
+        .map(|name| normalize_notebook(&name))
+        .filter(|name| !name.is_empty());
+    if folder_of(&note.fields) == notebook.as_deref() {
+        return Ok(());
+    }
+    match notebook {
+        Some(name) => note
+            .fields
+            .insert(FOLDER_FIELD.to_string(), Value::String(name)),
+        None => note.fields.remove(FOLDER_FIELD),
+    };
+    persist_note(&app_handle, note).await
+}
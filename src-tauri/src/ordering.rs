@@ -0,0 +1,155 @@
+use crate::frontmatter::Frontmatter;
+use crate::{atomic, events};
+use crate::{get_notes_dir, load_notes, timestamps, Note};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use tauri::{AppHandle, Wry};
+
+// Notes are grouped into folders by their `folder` frontmatter field. In manual sort
+// mode each folder keeps the order the user dragged its notes into, stored in
+// `notes/.order.json` as folder -> note ids. Notes at the top level use "".
+
+pub const FOLDER_FIELD: &str = "folder";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortMode {
+    // Most recently modified first
+    #[default]
+    Modified,
+    Manual,
+    // Most recently created first
+    Created,
+    // Alphabetically by title
+    Title,
+}
+
+type FolderOrder = HashMap<String, Vec<String>>;
+
+pub fn folder_of(fields: &Frontmatter) -> Option<&str> {
+    fields.get(FOLDER_FIELD).and_then(Value::as_str)
+}
+
+fn get_order_path(app_handle: &AppHandle<Wry>) -> PathBuf {
+    get_notes_dir(app_handle).join(".order.json")
+}
+
+fn load_order(app_handle: &AppHandle<Wry>) -> FolderOrder {
+    fs::read_to_string(get_order_path(app_handle))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+// Sort notes by their folder's manual order. Notes that were never placed keep their
+// newest-first order after the placed ones.
+pub fn apply_manual_order(app_handle: &AppHandle<Wry>, notes: &mut [Note]) {
+    let order = load_order(app_handle);
+    notes.sort_by_cached_key(|note| {
+        let folder = folder_of(&note.fields).unwrap_or("").to_string();
+        let position = order
+            .get(&folder)
+            .and_then(|ids| ids.iter().position(|id| *id == note.id))
+            .unwrap_or(usize::MAX);
+        (folder, position)
+    });
+}
+
+// Sort notes that are in newest-first order by the given mode
+pub fn sort_notes(app_handle: &AppHandle<Wry>, notes: &mut [Note], mode: SortMode) {
+    match mode {
+        SortMode::Modified => {}
+        SortMode::Manual => apply_manual_order(app_handle, notes),
+        SortMode::Created => notes.sort_by(|a, b| {
+            timestamps::created_of(&b.fields, &b.datetime)
+                .partial_cmp(&timestamps::created_of(&a.fields, &a.datetime))
+                .unwrap_or(Ordering::Equal)
+        }),
+        SortMode::Title => notes.sort_by_cached_key(|note| note.title.to_lowercase()),
+    }
+}
+
+#[tauri::command]
+pub async fn set_note_order(
+    app_handle: AppHandle<Wry>,
+    folder: String,
+    ordered_ids: Vec<String>,
+) -> Result<(), String> {
+    let notes = load_notes(&app_handle)?;
+    save_note_order(&app_handle, &notes, folder, ordered_ids)
+}
+
+// `set_note_order` for the folder the notes are in, so a list can be reordered
+// without naming it. Every note has to be in the same folder.
+#[tauri::command]
+pub async fn reorder_notes(
+    app_handle: AppHandle<Wry>,
+    ordered_ids: Vec<String>,
+) -> Result<(), String> {
+    let notes = load_notes(&app_handle)?;
+    let Some(first) = ordered_ids.first() else {
+        return Ok(());
+    };
+    let folder = notes
+        .iter()
+        .find(|n| n.id == *first)
+        .map(|n| folder_of(&n.fields).unwrap_or("").to_string())
+        .ok_or_else(|| format!("Note {} not found", first))?;
+    save_note_order(&app_handle, &notes, folder, ordered_ids)
+}
+
+fn save_note_order(
+    app_handle: &AppHandle<Wry>,
+    notes: &[Note],
+    folder: String,
+    ordered_ids: Vec<String>,
+) -> Result<(), String> {
+    let mut ids: Vec<String> = Vec::new();
+    for id in ordered_ids {
+        let in_folder = notes
+            .iter()
+            .any(|n| n.id == id && folder_of(&n.fields).unwrap_or("") == folder);
+        if !in_folder {
+            return Err(format!("Note {} is not in folder {:?}", id, folder));
+        }
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    let mut order = load_order(app_handle);
+    if ids.is_empty() {
+        order.remove(&folder);
+    } else {
+        order.insert(folder, ids);
+    }
+    save_order(app_handle, &order)?;
+
+    events::notes_reloaded(app_handle)
+}
+
+fn save_order(app_handle: &AppHandle<Wry>, order: &FolderOrder) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(order).map_err(|e| e.to_string())?;
+    atomic::write(get_order_path(app_handle), content).map_err(|e| e.to_string())
+}
+
+// Carry the manual order of folders along when they are renamed. `rename` gets
+// each folder and returns its new name, or None to leave it.
+pub fn rename_folders(
+    app_handle: &AppHandle<Wry>,
+    rename: impl Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    let order = load_order(app_handle);
+    if !order.keys().any(|folder| rename(folder).is_some()) {
+        return Ok(());
+    }
+    let renamed: FolderOrder = order
+        .into_iter()
+        .map(|(folder, ids)| (rename(&folder).unwrap_or(folder), ids))
+        .collect();
+    save_order(app_handle, &renamed)
+}
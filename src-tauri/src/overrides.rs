@@ -0,0 +1,92 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tauri::{AppHandle, Manager, Wry};
+
+// Startup configuration that can be given on the command line or in the environment,
+// for running several instances side by side, scripted setups and debugging. Command
+// line flags win over environment variables, and both win over saved settings:
+//
+//   --data-dir <path>      HIMOJI_DATA_DIR      app data directory (cache goes inside it)
+//   --port <port>          HIMOJI_PORT          first port the sync server tries
+//   --device-name <name>   HIMOJI_DEVICE_NAME   name shown to peers
+//   --log-level <level>    HIMOJI_LOG           error, warn, info, debug or trace
+//   --headless-sync        HIMOJI_HEADLESS_SYNC run only the sync stack, without the GUI
+
+static OVERRIDES: OnceLock<Overrides> = OnceLock::new();
+
+#[derive(Debug, Default, Clone)]
+pub struct Overrides {
+    pub data_dir: Option<PathBuf>,
+    pub port: Option<u16>,
+    pub device_name: Option<String>,
+    pub log_level: Option<String>,
+    pub headless_sync: bool,
+}
+
+// The value of `--name value` or `--name=value`
+pub fn flag(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == name {
+            args.get(i + 1).cloned()
+        } else {
+            arg.strip_prefix(&prefix).map(str::to_string)
+        }
+    })
+}
+
+fn setting(args: &[String], flag_name: &str, var: &str) -> Option<String> {
+    flag(args, flag_name)
+        .or_else(|| env::var(var).ok())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn parse(args: &[String]) -> Overrides {
+    let port = setting(args, "--port", "HIMOJI_PORT").and_then(|port| {
+        port.parse()
+            .map_err(|_| println!("Ignoring invalid port override: {}", port))
+            .ok()
+    });
+    Overrides {
+        data_dir: setting(args, "--data-dir", "HIMOJI_DATA_DIR").map(PathBuf::from),
+        port,
+        device_name: setting(args, "--device-name", "HIMOJI_DEVICE_NAME"),
+        log_level: setting(args, "--log-level", "HIMOJI_LOG"),
+        headless_sync: args.iter().any(|arg| arg == "--headless-sync")
+            || env::var("HIMOJI_HEADLESS_SYNC").is_ok_and(|v| v == "1" || v == "true"),
+    }
+}
+
+// Read once, on first use
+pub fn get() -> &'static Overrides {
+    OVERRIDES.get_or_init(|| parse(&env::args().skip(1).collect::<Vec<_>>()))
+}
+
+// Route dependency logging through the chosen level; off unless one is given
+pub fn init_logging() {
+    let Some(level) = &get().log_level else {
+        return;
+    };
+    match level.parse::<log::LevelFilter>() {
+        Ok(filter) => {
+            env_logger::Builder::new().filter_level(filter).init();
+        }
+        Err(_) => println!("Ignoring invalid log level: {}", level),
+    }
+}
+
+pub fn app_data_dir(app_handle: &AppHandle<Wry>) -> tauri::Result<PathBuf> {
+    match &get().data_dir {
+        Some(dir) => Ok(dir.clone()),
+        None => app_handle.path().app_data_dir(),
+    }
+}
+
+pub fn app_cache_dir(app_handle: &AppHandle<Wry>) -> tauri::Result<PathBuf> {
+    match &get().data_dir {
+        Some(dir) => Ok(dir.join("cache")),
+        None => app_handle.path().app_cache_dir(),
+    }
+}
@@ -0,0 +1,396 @@
+use crate::settings::load_device_settings;
+use crate::{atomic, network, overrides, tls, AppState, PeerDevice};
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, Wry};
+
+// Pairing decides which devices may send this one notes. One device asks to pair
+// with `pair_with_peer` and shows a PIN; the user types that PIN on the other
+// device (`confirm_pairing`), which proves both screens are in front of them.
+// The device that showed the PIN then hands out a token, and both keep it in
+// `trusted-peers.json` in the app data directory along with the other device's
+// certificate fingerprint. The token goes along with every sync request and answer,
+// as `Authorization: Bearer <token>`, but only to a device presenting the
+// certificate it paired with, and the server turns away devices without one unless
+// `allow_unpaired_sync` is on in settings.
+
+const TRUSTED_FILE: &str = "trusted-peers.json";
+// How long a PIN can be typed in, and how many wrong tries it survives
+const PIN_LIFETIME: Duration = Duration::from_secs(5 * 60);
+const PIN_ATTEMPTS: u32 = 5;
+
+pub const PAIR_REQUEST: &str = "pair-request";
+pub const PEER_PAIRED: &str = "peer-paired";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct TrustedPeer {
+    name: String,
+    token: String,
+    paired_at: String,
+    // Certificate fingerprint of the peer when it paired. Pairings from before it
+    // was kept go by the fingerprint the peer advertises.
+    #[serde(default)]
+    fingerprint: Option<String>,
+}
+
+// A device this one is paired with, as listed to the frontend
+#[derive(Debug, Serialize, Clone)]
+pub struct PairedPeer {
+    id: String,
+    name: String,
+    paired_at: String,
+}
+
+struct PendingPin {
+    pin: String,
+    expires: Instant,
+    attempts: u32,
+}
+
+// Pairings in progress
+#[derive(Default)]
+pub struct Pairing {
+    // PINs this device is showing, by the id of the peer that should type them
+    pins: HashMap<String, PendingPin>,
+}
+
+// A device asking to pair, or confirming with the PIN it was shown
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct PairRequest {
+    peer_id: String,
+    peer_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pin: Option<String>,
+    // Fingerprint of the sender's sync certificate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PairReply {
+    token: String,
+}
+
+type HandlerError = (StatusCode, String);
+
+fn trusted_path(app_handle: &AppHandle<Wry>) -> Result<PathBuf, String> {
+    let dir = overrides::app_data_dir(app_handle).map_err(|e| e.to_string())?;
+    Ok(dir.join(TRUSTED_FILE))
+}
+
+fn load_trusted(app_handle: &AppHandle<Wry>) -> HashMap<String, TrustedPeer> {
+    match trusted_path(app_handle) {
+        Ok(path) => read_trusted(&path),
+        Err(_) => HashMap::new(),
+    }
+}
+
+fn read_trusted(path: &Path) -> HashMap<String, TrustedPeer> {
+    // A missing file just means nothing is paired yet
+    match fs::read_to_string(path) {
+        Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+            println!("Failed to parse {:?}: {}", path, e);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    }
+}
+
+fn save_trusted(
+    app_handle: &AppHandle<Wry>,
+    trusted: &HashMap<String, TrustedPeer>,
+) -> Result<(), String> {
+    let raw = serde_json::to_string_pretty(trusted).map_err(|e| e.to_string())?;
+    // The tokens let anyone holding them send this device notes
+    atomic::write_private(trusted_path(app_handle)?, raw).map_err(|e| e.to_string())
+}
+
+fn trust(
+    app_handle: &AppHandle<Wry>,
+    id: &str,
+    name: &str,
+    token: String,
+    fingerprint: Option<String>,
+) -> Result<(), String> {
+    let paired_at = chrono::Utc::now().to_rfc3339();
+    let mut trusted = load_trusted(app_handle);
+    trusted.insert(
+        id.to_string(),
+        TrustedPeer {
+            name: name.to_string(),
+            token,
+            paired_at: paired_at.clone(),
+            fingerprint,
+        },
+    );
+    save_trusted(app_handle, &trusted)?;
+    let _ = app_handle.emit(
+        PEER_PAIRED,
+        PairedPeer {
+            id: id.to_string(),
+            name: name.to_string(),
+            paired_at,
+        },
+    );
+    Ok(())
+}
+
+fn random_bytes() -> [u8; 16] {
+    *uuid::Uuid::new_v4().as_bytes()
+}
+
+fn new_pin() -> String {
+    let bytes = random_bytes();
+    let n = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    format!("{:06}", n % 1_000_000)
+}
+
+fn new_token() -> String {
+    format!(
+        "{}{}",
+        hex::encode(random_bytes()),
+        hex::encode(random_bytes())
+    )
+}
+
+// The token of the sender of a request, if it sent one
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+// Whether a sync request from `peer_id` carrying `token` may be taken
+pub fn is_trusted(app_handle: &AppHandle<Wry>, peer_id: &str, token: Option<&str>) -> bool {
+    if load_device_settings(app_handle).allow_unpaired_sync {
+        return true;
+    }
+    overrides::app_data_dir(app_handle).is_ok_and(|dir| is_paired(&dir, peer_id, token))
+}
+
+// Whether `token` is the one `peer_id` was paired with, going by the pairings kept
+// in `data_dir`
+pub fn is_paired(data_dir: &Path, peer_id: &str, token: Option<&str>) -> bool {
+    match (
+        read_trusted(&data_dir.join(TRUSTED_FILE)).get(peer_id),
+        token,
+    ) {
+        (Some(trusted), Some(token)) => same_token(&trusted.token, token),
+        _ => false,
+    }
+}
+
+// Compare tokens in time that doesn't depend on where they differ
+fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+// Send our token to `peer_id` with every request, once paired. The connection is
+// pinned to the certificate the peer paired with, so a device that merely
+// advertises the peer's id doesn't get the token.
+pub fn authorize(
+    app_handle: &AppHandle<Wry>,
+    peer_id: &str,
+    builder: reqwest::ClientBuilder,
+) -> reqwest::ClientBuilder {
+    let Some(trusted) = load_trusted(app_handle).remove(peer_id) else {
+        return builder;
+    };
+    let builder = match trusted.fingerprint {
+        Some(fingerprint) => tls::pinned(builder, fingerprint),
+        None => builder,
+    };
+    // reqwest has its own copy of the header types
+    let value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", trusted.token));
+    match value {
+        Ok(value) => builder.default_headers(reqwest::header::HeaderMap::from_iter([(
+            reqwest::header::AUTHORIZATION,
+            value,
+        )])),
+        Err(_) => builder,
+    }
+}
+
+fn find_peer(app_handle: &AppHandle<Wry>, peer_id: &str) -> Result<PeerDevice, String> {
+    let state = app_handle.state::<Arc<Mutex<AppState>>>();
+    let state = state.lock().map_err(|e| e.to_string())?;
+    state
+        .peers
+        .get(peer_id)
+        .cloned()
+        .ok_or_else(|| "Peer not found".to_string())
+}
+
+fn own_request(app_handle: &AppHandle<Wry>, pin: Option<String>) -> Result<PairRequest, String> {
+    let fingerprint = app_handle.state::<tls::DeviceCert>().fingerprint.clone();
+    let state = app_handle.state::<Arc<Mutex<AppState>>>();
+    let state = state.lock().map_err(|e| e.to_string())?;
+    Ok(PairRequest {
+        peer_id: state.device_id.clone(),
+        peer_name: state.device_name.clone(),
+        pin,
+        fingerprint: Some(fingerprint),
+    })
+}
+
+// Start pairing with a peer: it is asked to show a prompt for the PIN returned
+// here, which the user reads off this device and types in over there
+#[tauri::command]
+pub async fn pair_with_peer(app_handle: AppHandle<Wry>, peer_id: String) -> Result<String, String> {
+    let peer = find_peer(&app_handle, &peer_id)?;
+    let pin = new_pin();
+    {
+        let state = app_handle.state::<Mutex<Pairing>>();
+        let mut pairing = state.lock().map_err(|e| e.to_string())?;
+        pairing.pins.insert(
+            peer_id.clone(),
+            PendingPin {
+                pin: pin.clone(),
+                expires: Instant::now() + PIN_LIFETIME,
+                attempts: 0,
+            },
+        );
+    }
+
+    let client = tls::client(&app_handle, &peer)?;
+    network::post_to_peer(
+        &client,
+        &peer.addresses(),
+        peer.port,
+        "/sync/pair",
+        &own_request(&app_handle, None)?,
+        Duration::from_secs(5),
+    )
+    .await
+    .map_err(|e| format!("Couldn't reach {}: {}", peer.name, e))?;
+    Ok(pin)
+}
+
+// Finish pairing with a peer that asked, using the PIN it shows
+#[tauri::command]
+pub async fn confirm_pairing(
+    app_handle: AppHandle<Wry>,
+    peer_id: String,
+    pin: String,
+) -> Result<(), String> {
+    let peer = find_peer(&app_handle, &peer_id)?;
+    let client = tls::client(&app_handle, &peer)?;
+    let reply: PairReply = network::post_to_peer(
+        &client,
+        &peer.addresses(),
+        peer.port,
+        "/sync/pair/confirm",
+        &own_request(&app_handle, Some(pin.trim().to_string()))?,
+        Duration::from_secs(5),
+    )
+    .await
+    .map_err(|e| format!("Pairing with {} failed: {}", peer.name, e))?
+    .json()
+    .await
+    .map_err(|e| e.to_string())?;
+    // The client only connected to the device presenting this certificate
+    trust(
+        &app_handle,
+        &peer.id,
+        &peer.name,
+        reply.token,
+        peer.fingerprint.clone(),
+    )
+}
+
+#[tauri::command]
+pub async fn list_paired_peers(app_handle: AppHandle<Wry>) -> Result<Vec<PairedPeer>, String> {
+    let mut peers: Vec<PairedPeer> = load_trusted(&app_handle)
+        .into_iter()
+        .map(|(id, trusted)| PairedPeer {
+            id,
+            name: trusted.name,
+            paired_at: trusted.paired_at,
+        })
+        .collect();
+    peers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(peers)
+}
+
+// Forget a peer; it has to pair again before it can send notes
+#[tauri::command]
+pub async fn unpair_peer(app_handle: AppHandle<Wry>, peer_id: String) -> Result<(), String> {
+    let mut trusted = load_trusted(&app_handle);
+    if trusted.remove(&peer_id).is_none() {
+        return Err("Peer isn't paired".to_string());
+    }
+    save_trusted(&app_handle, &trusted)
+}
+
+// Another device asks to pair; the frontend prompts for the PIN it shows
+async fn receive_pair(
+    State(app_handle): State<AppHandle<Wry>>,
+    Json(request): Json<PairRequest>,
+) -> Result<Json<serde_json::Value>, HandlerError> {
+    println!("Pairing requested by {}", request.peer_name);
+    let _ = app_handle.emit(PAIR_REQUEST, &request);
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+// The other device typed in the PIN this one shows
+async fn receive_confirm(
+    State(app_handle): State<AppHandle<Wry>>,
+    Json(request): Json<PairRequest>,
+) -> Result<Json<PairReply>, HandlerError> {
+    let forbidden = |message: &str| (StatusCode::FORBIDDEN, message.to_string());
+    {
+        let state = app_handle.state::<Mutex<Pairing>>();
+        let mut pairing = state
+            .lock()
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let Some(pending) = pairing.pins.get_mut(&request.peer_id) else {
+            return Err(forbidden("No pairing in progress with this device"));
+        };
+        if pending.expires < Instant::now() || pending.attempts >= PIN_ATTEMPTS {
+            pairing.pins.remove(&request.peer_id);
+            return Err(forbidden("The PIN expired; start pairing again"));
+        }
+        if request.pin.as_deref() != Some(pending.pin.as_str()) {
+            pending.attempts += 1;
+            return Err(forbidden("Wrong PIN"));
+        }
+        pairing.pins.remove(&request.peer_id);
+    }
+
+    // Older devices don't send their fingerprint; the one they advertise will do
+    let fingerprint = request.fingerprint.clone().or_else(|| {
+        find_peer(&app_handle, &request.peer_id)
+            .ok()
+            .and_then(|peer| peer.fingerprint)
+    });
+    let token = new_token();
+    trust(
+        &app_handle,
+        &request.peer_id,
+        &request.peer_name,
+        token.clone(),
+        fingerprint,
+    )
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    Ok(Json(PairReply { token }))
+}
+
+pub fn routes(app_handle: AppHandle<Wry>) -> axum::Router {
+    axum::Router::new()
+        .route("/sync/pair", axum::routing::post(receive_pair))
+        .route("/sync/pair/confirm", axum::routing::post(receive_confirm))
+        .with_state(app_handle)
+}
@@ -0,0 +1,171 @@
+use crate::markdown::{self, attachment_link};
+use crate::proxy::{self, HttpFeature};
+use crate::settings::load_device_settings;
+use crate::{compression, writable_attachments_dir};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tauri::{AppHandle, Wry};
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "svg", "bmp"];
+const MAX_REMOTE_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+#[derive(Debug, Serialize)]
+pub struct PasteResult {
+    markdown: String,
+    // Attachments created for images found in the pasted content
+    attachments: Vec<String>,
+}
+
+pub fn html_to_markdown(html: &str) -> String {
+    let markdown = html2md::parse_html(html);
+
+    // Word and some browsers leave runs of blank lines and non-breaking spaces behind
+    let mut cleaned = String::with_capacity(markdown.len());
+    let mut blank_lines = 0;
+    for line in markdown.replace('\u{a0}', " ").lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_lines += 1;
+            if blank_lines > 1 {
+                continue;
+            }
+        } else {
+            blank_lines = 0;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+
+    cleaned.trim().to_string()
+}
+
+fn is_remote_image(target: &str) -> bool {
+    (target.starts_with("http://") || target.starts_with("https://"))
+        && target
+            .split(['?', '#'])
+            .next()
+            .and_then(|path| path.rsplit_once('.'))
+            .is_some_and(|(_, ext)| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+// Decode a `data:image/png;base64,...` URI into its extension and bytes
+fn decode_data_uri(target: &str) -> Option<(String, Vec<u8>)> {
+    let rest = target.strip_prefix("data:image/")?;
+    let (meta, data) = rest.split_once(',')?;
+    let subtype = meta.strip_suffix(";base64")?;
+    let extension = match subtype {
+        "jpeg" => "jpg",
+        "svg+xml" => "svg",
+        other => other,
+    };
+    if !extension.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let bytes = STANDARD.decode(data.trim()).ok()?;
+    Some((extension.to_string(), bytes))
+}
+
+async fn download_image(client: &reqwest::Client, url: &str) -> Result<Vec<u8>, String> {
+    let response = client
+        .get(url)
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .error_for_status()
+        .map_err(|e| e.to_string())?;
+
+    if response
+        .content_length()
+        .is_some_and(|len| len as usize > MAX_REMOTE_IMAGE_BYTES)
+    {
+        return Err("Image too large".to_string());
+    }
+
+    let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+    if bytes.len() > MAX_REMOTE_IMAGE_BYTES {
+        return Err("Image too large".to_string());
+    }
+    Ok(bytes.to_vec())
+}
+
+#[tauri::command]
+pub async fn convert_html_to_markdown(html: String) -> Result<String, String> {
+    Ok(html_to_markdown(&html))
+}
+
+// Convert pasted HTML to markdown, saving embedded (and, if enabled, remote)
+// images as attachments of the note so the result doesn't depend on the source page
+#[tauri::command]
+pub async fn paste_html(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    html: String,
+) -> Result<PasteResult, String> {
+    let markdown = html_to_markdown(&html);
+    let download_remote = load_device_settings(&app_handle).paste_download_remote_images;
+
+    // Collect image targets first; downloads can't happen inside the rewrite pass
+    let mut targets = Vec::new();
+    markdown::rewrite_link_targets(&markdown, |target| {
+        if target.starts_with("data:image/") || (download_remote && is_remote_image(target)) {
+            targets.push(target.to_string());
+        }
+        None
+    });
+
+    if targets.is_empty() {
+        return Ok(PasteResult {
+            markdown,
+            attachments: Vec::new(),
+        });
+    }
+
+    let attachments_dir = writable_attachments_dir(&app_handle, &note_id)?;
+    let timestamp = chrono::Utc::now().timestamp_millis();
+    let client = proxy::client(&app_handle, HttpFeature::Paste)?;
+    let mut saved: HashMap<String, String> = HashMap::new();
+
+    for (index, target) in targets.iter().enumerate() {
+        if saved.contains_key(target) {
+            continue;
+        }
+
+        let image = match decode_data_uri(target) {
+            Some(image) => Ok(image),
+            None => download_image(&client, target).await.map(|bytes| {
+                let extension = target
+                    .split(['?', '#'])
+                    .next()
+                    .and_then(|path| path.rsplit_once('.'))
+                    .map(|(_, ext)| ext.to_lowercase())
+                    .unwrap_or_else(|| "png".to_string());
+                (extension, bytes)
+            }),
+        };
+
+        match image {
+            Ok((extension, bytes)) => {
+                let file_name = format!("pasted_{}_{}.{}", timestamp, index, extension);
+                let path = attachments_dir.join(&file_name);
+                if let Err(e) = compression::write_attachment(&app_handle, &path, &bytes) {
+                    println!("Failed to save pasted image: {}", e);
+                    continue;
+                }
+                saved.insert(target.clone(), file_name);
+            }
+            Err(e) => println!("Failed to capture pasted image: {}", e),
+        }
+    }
+
+    let markdown = markdown::rewrite_link_targets(&markdown, |target| {
+        saved.get(target).map(|name| attachment_link(name))
+    });
+
+    Ok(PasteResult {
+        markdown,
+        attachments: saved.into_values().collect(),
+    })
+}
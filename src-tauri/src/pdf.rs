@@ -0,0 +1,586 @@
+use crate::export::referenced_attachment;
+use crate::render::render_note;
+use crate::{compression, load_notes, Note};
+use printpdf::image_crate::{self, DynamicImage};
+use printpdf::{
+    Color, Greyscale, Image, ImageTransform, IndirectFontRef, Line, Mm, PdfDocument,
+    PdfDocumentReference, PdfLayerReference, Point,
+};
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::fs::File;
+use std::io::BufWriter;
+use tauri::{AppHandle, Wry};
+
+// Export of a note to PDF, laid out here rather than printed from the webview:
+// headings, paragraphs, lists, quotes, code blocks and rules, with image
+// attachments embedded at up to the width of the page. Text is set in DejaVu Sans
+// and DejaVu Sans Mono, bundled from `fonts/` and embedded in the PDF as styles are
+// used, so text beyond Latin-1 comes out as written.
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+const CONTENT_WIDTH: f32 = PAGE_WIDTH - 2.0 * MARGIN;
+const BODY_SIZE: f32 = 11.0;
+const CODE_SIZE: f32 = 9.5;
+const LINE_SPACING: f32 = 1.4;
+// Space after a block, in millimetres
+const BLOCK_GAP: f32 = 3.0;
+const INDENT: f32 = 6.0;
+const PT_TO_MM: f32 = 0.3528;
+// Resolution images are placed at when they fit the page
+const IMAGE_DPI: f32 = 150.0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+    Code,
+}
+
+impl Style {
+    fn with(self, bold: bool, italic: bool) -> Style {
+        match (bold, italic) {
+            (true, true) => Style::BoldItalic,
+            (true, false) => Style::Bold,
+            (false, true) => Style::Italic,
+            (false, false) => Style::Regular,
+        }
+    }
+
+    fn slot(self) -> usize {
+        match self {
+            Style::Regular => 0,
+            Style::Bold => 1,
+            Style::Italic => 2,
+            Style::BoldItalic => 3,
+            Style::Code => 4,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Span {
+    text: String,
+    style: Style,
+}
+
+enum Block {
+    Heading(u8, Vec<Span>),
+    Paragraph {
+        spans: Vec<Span>,
+        indent: f32,
+        // List marker, drawn in the indent
+        marker: Option<String>,
+        quoted: bool,
+    },
+    Code(String),
+    Rule,
+    Image {
+        target: String,
+        alt: String,
+    },
+}
+
+// Font files for each style, in the order of `Style::slot`
+const FONT_FILES: [&[u8]; 5] = [
+    include_bytes!("../fonts/DejaVuSans.ttf"),
+    include_bytes!("../fonts/DejaVuSans-Bold.ttf"),
+    include_bytes!("../fonts/DejaVuSans-Oblique.ttf"),
+    include_bytes!("../fonts/DejaVuSans-BoldOblique.ttf"),
+    include_bytes!("../fonts/DejaVuSansMono.ttf"),
+];
+
+// `text` without the tabs and control characters the fonts don't draw
+fn pdf_text(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            '\t' => Some("    ".to_string()),
+            '\n' => Some("\n".to_string()),
+            c if c.is_control() => None,
+            c => Some(c.to_string()),
+        })
+        .collect()
+}
+
+// The markdown of a note as blocks to lay out
+fn parse(content: &str) -> Vec<Block> {
+    let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut blocks = Vec::new();
+    let mut spans: Vec<Span> = Vec::new();
+    let (mut bold, mut italic, mut quote_depth) = (false, false, 0);
+    // Each open list, with the number of its next item if it is ordered
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut marker: Option<String> = None;
+    let mut heading: Option<u8> = None;
+    let mut code: Option<String> = None;
+    let mut image: Option<(String, String)> = None;
+
+    let flush = |spans: &mut Vec<Span>,
+                 marker: &mut Option<String>,
+                 blocks: &mut Vec<Block>,
+                 lists: &[Option<u64>],
+                 quote_depth: usize| {
+        if spans.iter().all(|s| s.text.trim().is_empty()) && marker.is_none() {
+            spans.clear();
+            return;
+        }
+        blocks.push(Block::Paragraph {
+            spans: std::mem::take(spans),
+            indent: (lists.len() + quote_depth) as f32 * INDENT,
+            marker: marker.take(),
+            quoted: quote_depth > 0,
+        });
+    };
+
+    for event in Parser::new_ext(content, options) {
+        if let Some((_, alt)) = image.as_mut() {
+            match event {
+                Event::Text(text) | Event::Code(text) => alt.push_str(&text),
+                Event::End(TagEnd::Image) => {
+                    flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+                    let (target, alt) = image.take().unwrap_or_default();
+                    blocks.push(Block::Image { target, alt });
+                }
+                _ => {}
+            }
+            continue;
+        }
+        if let Some(text) = code.as_mut() {
+            match event {
+                Event::Text(chunk) => text.push_str(&chunk),
+                Event::End(TagEnd::CodeBlock) => {
+                    blocks.push(Block::Code(code.take().unwrap_or_default()));
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+                heading = Some(match level {
+                    HeadingLevel::H1 => 1,
+                    HeadingLevel::H2 => 2,
+                    HeadingLevel::H3 => 3,
+                    _ => 4,
+                });
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(level) = heading.take() {
+                    blocks.push(Block::Heading(level, std::mem::take(&mut spans)));
+                }
+            }
+            Event::Start(Tag::BlockQuote) => {
+                flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+                quote_depth += 1;
+            }
+            Event::End(TagEnd::BlockQuote) => {
+                flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+                quote_depth = quote_depth.saturating_sub(1);
+            }
+            Event::Start(Tag::List(start)) => {
+                flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+                lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+                marker = Some(match lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}.", *n - 1)
+                    }
+                    _ => "-".to_string(),
+                });
+            }
+            Event::TaskListMarker(done) => {
+                let text = if done { "[x] " } else { "[ ] " };
+                spans.push(Span {
+                    text: text.to_string(),
+                    style: Style::Code,
+                });
+            }
+            Event::End(TagEnd::Paragraph) | Event::End(TagEnd::Item) => {
+                flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+            }
+            // Query blocks were expanded already; other fences are shown as written
+            Event::Start(Tag::CodeBlock(_)) => {
+                flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+                code = Some(String::new());
+            }
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                image = Some((dest_url.to_string(), String::new()));
+            }
+            Event::Start(Tag::Strong) => bold = true,
+            Event::End(TagEnd::Strong) => bold = false,
+            Event::Start(Tag::Emphasis) => italic = true,
+            Event::End(TagEnd::Emphasis) => italic = false,
+            Event::Text(text) => spans.push(Span {
+                text: text.to_string(),
+                style: Style::Regular.with(bold || heading.is_some(), italic),
+            }),
+            Event::Code(text) => spans.push(Span {
+                text: text.to_string(),
+                style: Style::Code,
+            }),
+            Event::SoftBreak => spans.push(Span {
+                text: " ".to_string(),
+                style: Style::Regular,
+            }),
+            Event::HardBreak => spans.push(Span {
+                text: "\n".to_string(),
+                style: Style::Regular,
+            }),
+            Event::Rule => {
+                flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+                blocks.push(Block::Rule);
+            }
+            _ => {}
+        }
+    }
+    flush(&mut spans, &mut marker, &mut blocks, &lists, quote_depth);
+    blocks
+}
+
+// The bundled fonts, each embedded in the document the first time it is used, as
+// every one adds its whole file to the PDF
+struct Fonts {
+    faces: Vec<ttf_parser::Face<'static>>,
+    embedded: [Option<IndirectFontRef>; 5],
+}
+
+impl Fonts {
+    fn load() -> Result<Fonts, String> {
+        let faces = FONT_FILES
+            .iter()
+            .map(|data| ttf_parser::Face::parse(data, 0).map_err(|e| e.to_string()))
+            .collect::<Result<_, _>>()?;
+        Ok(Fonts {
+            faces,
+            embedded: Default::default(),
+        })
+    }
+
+    fn get(&mut self, doc: &PdfDocumentReference, style: Style) -> Result<IndirectFontRef, String> {
+        let slot = &mut self.embedded[style.slot()];
+        if let Some(font) = slot {
+            return Ok(font.clone());
+        }
+        let font = doc
+            .add_external_font(FONT_FILES[style.slot()])
+            .map_err(|e| e.to_string())?;
+        *slot = Some(font.clone());
+        Ok(font)
+    }
+
+    // Width of `text` in millimetres. Characters the font lacks take the width of
+    // the box drawn for them.
+    fn width(&self, text: &str, style: Style, size: f32) -> f32 {
+        let face = &self.faces[style.slot()];
+        let units: u32 = text
+            .chars()
+            .map(|c| {
+                let glyph = face.glyph_index(c).unwrap_or(ttf_parser::GlyphId(0));
+                face.glyph_hor_advance(glyph).unwrap_or(0) as u32
+            })
+            .sum();
+        units as f32 / face.units_per_em() as f32 * size * PT_TO_MM
+    }
+}
+
+// Places blocks top to bottom, starting new pages as they fill
+struct Writer {
+    doc: PdfDocumentReference,
+    layer: PdfLayerReference,
+    fonts: Fonts,
+    // Distance of the next line's top from the bottom of the page, in millimetres
+    y: f32,
+}
+
+impl Writer {
+    fn new(title: &str) -> Result<Writer, String> {
+        let (doc, page, layer) =
+            PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Content");
+        let fonts = Fonts::load()?;
+        let layer = doc.get_page(page).get_layer(layer);
+        Ok(Writer {
+            doc,
+            layer,
+            fonts,
+            y: PAGE_HEIGHT - MARGIN,
+        })
+    }
+
+    // Start a new page unless `height` still fits on this one
+    fn ensure(&mut self, height: f32) {
+        if self.y - height >= MARGIN {
+            return;
+        }
+        let (page, layer) = self
+            .doc
+            .add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Content");
+        self.layer = self.doc.get_page(page).get_layer(layer);
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    fn line(&self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        self.layer
+            .set_outline_color(Color::Greyscale(Greyscale::new(0.7, None)));
+        self.layer.set_outline_thickness(0.5);
+        self.layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(x1), Mm(y1)), false),
+                (Point::new(Mm(x2), Mm(y2)), false),
+            ],
+            is_closed: false,
+        });
+    }
+
+    // Lay out `spans` as wrapped lines starting `indent` in from the margin
+    fn text(
+        &mut self,
+        spans: &[Span],
+        size: f32,
+        indent: f32,
+        marker: Option<&str>,
+        quoted: bool,
+    ) -> Result<(), String> {
+        let left = MARGIN + indent;
+        let width = CONTENT_WIDTH - indent;
+        let line_height = size * PT_TO_MM * LINE_SPACING;
+        let grey = if quoted { 0.35 } else { 0.0 };
+
+        // Words, each with the style of the span it came from; `None` breaks the line
+        let mut words: Vec<Option<(String, Style)>> = Vec::new();
+        for span in spans {
+            for (i, line) in pdf_text(&span.text).split('\n').enumerate() {
+                if i > 0 {
+                    words.push(None);
+                }
+                let mut word = String::new();
+                for c in line.chars() {
+                    word.push(c);
+                    if c == ' ' {
+                        words.push(Some((std::mem::take(&mut word), span.style)));
+                    }
+                }
+                if !word.is_empty() {
+                    words.push(Some((word, span.style)));
+                }
+            }
+        }
+
+        let mut lines: Vec<Vec<(String, Style)>> = vec![Vec::new()];
+        let mut x = 0.0;
+        for word in words {
+            let Some((word, style)) = word else {
+                lines.push(Vec::new());
+                x = 0.0;
+                continue;
+            };
+            let advance = self.fonts.width(&word, style, size);
+            let fits = x + self.fonts.width(word.trim_end(), style, size) <= width;
+            if !fits && x > 0.0 {
+                lines.push(Vec::new());
+                x = 0.0;
+                if word.trim().is_empty() {
+                    continue;
+                }
+            }
+            x += advance;
+            if let Some(line) = lines.last_mut() {
+                line.push((word, style));
+            }
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            self.ensure(line_height);
+            let baseline = self.y - size * PT_TO_MM;
+            self.layer
+                .set_fill_color(Color::Greyscale(Greyscale::new(grey, None)));
+            if let (0, Some(marker)) = (i, marker) {
+                let marker_x = left - self.fonts.width(marker, Style::Regular, size) - 1.5;
+                let font = self.fonts.get(&self.doc, Style::Regular)?;
+                self.layer
+                    .use_text(marker, size, Mm(marker_x), Mm(baseline), &font);
+            }
+            let mut x = left;
+            for (word, style) in line {
+                let font = self.fonts.get(&self.doc, *style)?;
+                self.layer
+                    .use_text(word.as_str(), size, Mm(x), Mm(baseline), &font);
+                x += self.fonts.width(word, *style, size);
+            }
+            if quoted {
+                self.line(left - 3.0, self.y, left - 3.0, self.y - line_height);
+            }
+            self.y -= line_height;
+        }
+        self.layer
+            .set_fill_color(Color::Greyscale(Greyscale::new(0.0, None)));
+        Ok(())
+    }
+
+    fn code(&mut self, text: &str) -> Result<(), String> {
+        let char_width = self.fonts.width("m", Style::Code, CODE_SIZE);
+        let per_line = ((CONTENT_WIDTH - INDENT) / char_width).floor().max(1.0) as usize;
+        let mut spans = Vec::new();
+        for line in pdf_text(text.trim_end_matches('\n')).split('\n') {
+            // Long lines are broken anywhere rather than at spaces
+            let chars: Vec<char> = line.chars().collect();
+            let chunks: Vec<String> = if chars.is_empty() {
+                vec![String::new()]
+            } else {
+                chars.chunks(per_line).map(|c| c.iter().collect()).collect()
+            };
+            for chunk in chunks {
+                spans.push(Span {
+                    // Spaces would be dropped at the start of wrapped lines
+                    text: format!("{}\n", chunk.replace(' ', "\u{A0}")),
+                    style: Style::Code,
+                });
+            }
+        }
+        if let Some(last) = spans.last_mut() {
+            last.text.pop();
+        }
+        self.text(&spans, CODE_SIZE, INDENT / 2.0, None, false)
+    }
+
+    fn image(&mut self, image: &DynamicImage) {
+        let (width_px, height_px) = (image.width() as f32, image.height() as f32);
+        let natural_width = width_px / IMAGE_DPI * 25.4;
+        let natural_height = height_px / IMAGE_DPI * 25.4;
+        let scale = (CONTENT_WIDTH / natural_width)
+            .min((PAGE_HEIGHT - 2.0 * MARGIN) / natural_height)
+            .min(1.0);
+        let height = natural_height * scale;
+        self.ensure(height);
+        // Transparency isn't carried over; images are flattened to RGB
+        let flattened = DynamicImage::ImageRgb8(image.to_rgb8());
+        Image::from_dynamic_image(&flattened).add_to_layer(
+            self.layer.clone(),
+            ImageTransform {
+                translate_x: Some(Mm(MARGIN)),
+                translate_y: Some(Mm(self.y - height)),
+                dpi: Some(IMAGE_DPI),
+                scale_x: Some(scale),
+                scale_y: Some(scale),
+                ..Default::default()
+            },
+        );
+        self.y -= height;
+    }
+
+    fn block(
+        &mut self,
+        app_handle: &AppHandle<Wry>,
+        note: &Note,
+        block: &Block,
+    ) -> Result<(), String> {
+        match block {
+            Block::Heading(level, spans) => {
+                let size = match level {
+                    1 => 22.0,
+                    2 => 18.0,
+                    3 => 15.0,
+                    _ => 13.0,
+                };
+                // Keep a heading with at least a line of what follows it
+                self.ensure(size * PT_TO_MM * LINE_SPACING + BODY_SIZE * PT_TO_MM * 2.0);
+                self.y -= BLOCK_GAP;
+                self.text(spans, size, 0.0, None, false)?;
+            }
+            Block::Paragraph {
+                spans,
+                indent,
+                marker,
+                quoted,
+            } => self.text(spans, BODY_SIZE, *indent, marker.as_deref(), *quoted)?,
+            Block::Code(text) => self.code(text)?,
+            Block::Rule => {
+                self.ensure(BLOCK_GAP * 2.0);
+                self.line(
+                    MARGIN,
+                    self.y - BLOCK_GAP,
+                    PAGE_WIDTH - MARGIN,
+                    self.y - BLOCK_GAP,
+                );
+                self.y -= BLOCK_GAP * 2.0;
+            }
+            Block::Image { target, alt } => match load_image(app_handle, note, target) {
+                Some(image) => self.image(&image),
+                // Remote or unreadable images are named instead
+                None => {
+                    let text = format!("[Image: {}]", if alt.is_empty() { target } else { alt });
+                    let span = Span {
+                        text,
+                        style: Style::Italic,
+                    };
+                    self.text(&[span], BODY_SIZE, 0.0, None, false)?;
+                }
+            },
+        }
+        self.y -= BLOCK_GAP;
+        Ok(())
+    }
+}
+
+fn load_image(app_handle: &AppHandle<Wry>, note: &Note, target: &str) -> Option<DynamicImage> {
+    let name = referenced_attachment(target, &note.attachments)?;
+    let data = compression::read_note_attachment(app_handle, &note.id, name)
+        .map_err(|e| println!("Failed to read image {}: {}", name, e))
+        .ok()?;
+    image_crate::load_from_memory(&data)
+        .map_err(|e| println!("Failed to decode image {}: {}", name, e))
+        .ok()
+}
+
+fn write_pdf(
+    app_handle: &AppHandle<Wry>,
+    note: &Note,
+    content: &str,
+    output_path: &str,
+) -> Result<(), String> {
+    let mut writer = Writer::new(&note.title)?;
+    let title = Span {
+        text: note.title.clone(),
+        style: Style::Bold,
+    };
+    writer.text(&[title], 24.0, 0.0, None, false)?;
+    writer.y -= BLOCK_GAP * 2.0;
+    for block in parse(content) {
+        writer.block(app_handle, note, &block)?;
+    }
+
+    let file = File::create(output_path).map_err(|e| e.to_string())?;
+    writer
+        .doc
+        .save(&mut BufWriter::new(file))
+        .map_err(|e| e.to_string())
+}
+
+// Export a note as a PDF, rendered the way it is displayed
+#[tauri::command]
+pub async fn export_note_pdf(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    output_path: String,
+) -> Result<(), String> {
+    let note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+    let rendered = render_note(&app_handle, note.clone()).await?;
+
+    tokio::task::spawn_blocking(move || write_pdf(&app_handle, &note, &rendered, &output_path))
+        .await
+        .map_err(|e| e.to_string())?
+}
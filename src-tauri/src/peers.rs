@@ -0,0 +1,171 @@
+use crate::events::{self, PeersUpdated};
+use crate::proxy::{self, HttpFeature};
+use crate::settings::{self, load_device_settings};
+use crate::{protocol, tls, AppState, PeerDevice};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, Wry};
+
+// Peers configured by hostname, for networks where mDNS doesn't reach such as
+// Tailscale or other VPNs. They are checked at startup, periodically and whenever
+// settings change, and show up next to discovered peers while they answer. Their
+// certificate fingerprint is the one they present when checked.
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StaticPeer {
+    // Hostname or address, e.g. a MagicDNS name like `laptop.tailnet-name.ts.net`
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+}
+
+fn default_port() -> u16 {
+    8000
+}
+
+// What a device reports about itself on `/sync/info`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeerInfo {
+    pub id: String,
+    pub name: String,
+    // Sync protocol version the peer speaks
+    #[serde(default = "protocol::legacy_version")]
+    pub protocol: u32,
+}
+
+async fn resolve(peer: &StaticPeer) -> Result<Vec<IpAddr>, String> {
+    let mut addresses: Vec<IpAddr> = tokio::net::lookup_host((peer.host.as_str(), peer.port))
+        .await
+        .map_err(|e| format!("Failed to resolve {}: {}", peer.host, e))?
+        .map(|addr| addr.ip())
+        .collect();
+    addresses.dedup();
+    Ok(addresses)
+}
+
+// Resolve a static peer and ask it who it is, trying its addresses in order
+async fn check(client: &reqwest::Client, peer: &StaticPeer) -> Result<PeerDevice, String> {
+    let addresses = resolve(peer).await?;
+    let mut last_error = format!("{} has no addresses", peer.host);
+
+    for ip in &addresses {
+        let url = format!(
+            "https://{}/sync/info",
+            std::net::SocketAddr::new(*ip, peer.port)
+        );
+        let result = client
+            .get(&url)
+            .timeout(HEALTH_CHECK_TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(response) => {
+                let fingerprint = tls::peer_fingerprint(&response);
+                let info: PeerInfo = response.json().await.map_err(|e| e.to_string())?;
+                return Ok(PeerDevice {
+                    id: info.id,
+                    name: info.name,
+                    ip: *ip,
+                    port: peer.port,
+                    addresses: addresses.clone(),
+                    protocol: info.protocol,
+                    fingerprint,
+                });
+            }
+            Err(e) => last_error = format!("{}: {}", url, e),
+        }
+    }
+
+    Err(last_error)
+}
+
+// Health-check the configured static peers, adding those that answer to the peer
+// list and dropping those that stopped answering
+async fn refresh(app_handle: &AppHandle<Wry>, added: &mut HashMap<String, String>) {
+    let static_peers = load_device_settings(app_handle).static_peers;
+    let client = proxy::config_for(app_handle, HttpFeature::Sync)
+        .apply(tls::unpinned(reqwest::Client::builder()))
+        .and_then(|builder| builder.build().map_err(|e| e.to_string()));
+    let client = match client {
+        Ok(client) => client,
+        Err(e) => {
+            println!("Failed to create client for static peers: {}", e);
+            return;
+        }
+    };
+
+    let mut healthy = Vec::new();
+    for peer in &static_peers {
+        match check(&client, peer).await {
+            Ok(device) => healthy.push((peer.host.clone(), device)),
+            Err(e) => println!("Static peer {} is unreachable: {}", peer.host, e),
+        }
+    }
+
+    let update = {
+        let state = app_handle.state::<Arc<Mutex<AppState>>>();
+        let Ok(mut state) = state.lock() else {
+            return;
+        };
+        let mut update = PeersUpdated::default();
+
+        // Hosts that are gone from settings or no longer answer
+        let stale: Vec<String> = added
+            .keys()
+            .filter(|host| !healthy.iter().any(|(h, _)| h == *host))
+            .cloned()
+            .collect();
+        for host in stale {
+            if let Some(id) = added.remove(&host) {
+                if state.peers.remove(&id).is_some() {
+                    update.removed.push(id);
+                }
+            }
+        }
+
+        for (host, device) in healthy {
+            if device.id == state.device_id {
+                continue;
+            }
+            let known = state.peers.get(&device.id).is_some_and(|p| {
+                p.ip == device.ip && p.port == device.port && p.fingerprint == device.fingerprint
+            });
+            added.insert(host, device.id.clone());
+            if !known {
+                state.peers.insert(device.id.clone(), device.clone());
+                update.updated.push(device);
+            }
+        }
+        update
+    };
+
+    if !update.updated.is_empty() || !update.removed.is_empty() {
+        let _ = events::peers_updated(app_handle, update);
+    }
+}
+
+pub fn spawn_static_peers(app_handle: AppHandle<Wry>) {
+    tauri::async_runtime::spawn(async move {
+        // Host -> id of the peer it resolved to on the last check
+        let mut added = HashMap::new();
+        let mut changes = settings::subscribe(&app_handle);
+        loop {
+            refresh(&app_handle, &mut added).await;
+            let interval = load_device_settings(&app_handle)
+                .static_peer_interval
+                .map_or(HEALTH_CHECK_INTERVAL, Duration::from_secs);
+            // Edited peers are checked right away
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = changes.changed() => {}
+            }
+        }
+    });
+}
@@ -0,0 +1,141 @@
+use crate::events;
+use crate::settings::{load_vault_settings, save_vault_settings};
+use crate::{load_notes, Note, NoteMetadata};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Wry};
+
+// Pinned notes are kept in settings as named sections, each listing its notes in the
+// order the user arranged them. The section with an empty name holds pins that
+// haven't been grouped.
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct PinSection {
+    pub name: String,
+    pub note_ids: Vec<String>,
+}
+
+// Where a note sits among the pins, for the sidebar
+#[derive(Debug, Serialize, Clone)]
+pub struct PinPosition {
+    section: String,
+    // Index of the section, then of the note within it
+    section_index: usize,
+    position: usize,
+}
+
+fn position_in(sections: &[PinSection], note_id: &str) -> Option<PinPosition> {
+    sections
+        .iter()
+        .enumerate()
+        .find_map(|(section_index, section)| {
+            let position = section.note_ids.iter().position(|id| id == note_id)?;
+            Some(PinPosition {
+                section: section.name.clone(),
+                section_index,
+                position,
+            })
+        })
+}
+
+// Fill in the pin position of every note in a metadata listing
+pub fn annotate(app_handle: &AppHandle<Wry>, notes: &mut [NoteMetadata]) {
+    let sections = load_vault_settings(app_handle).pinned;
+    for note in notes {
+        note.pin = position_in(&sections, &note.id);
+    }
+}
+
+// Move pinned notes to the front, in the order of their sections and positions.
+// The rest keep the order they had.
+pub fn pinned_first(app_handle: &AppHandle<Wry>, notes: &mut [Note]) {
+    let sections = load_vault_settings(app_handle).pinned;
+    notes.sort_by_cached_key(|note| {
+        position_in(&sections, &note.id).map_or((1, 0, 0), |p| (0, p.section_index, p.position))
+    });
+}
+
+fn save_sections(app_handle: &AppHandle<Wry>, sections: Vec<PinSection>) -> Result<(), String> {
+    let mut settings = load_vault_settings(app_handle);
+    settings.pinned = sections;
+    save_vault_settings(app_handle, &settings)?;
+    events::notes_reloaded(app_handle)
+}
+
+// Pin a note at the end of a section, creating the section if needed. A note that
+// is already pinned moves to the new section.
+#[tauri::command]
+pub async fn pin_note(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    section: Option<String>,
+) -> Result<(), String> {
+    if !load_notes(&app_handle)?.iter().any(|n| n.id == note_id) {
+        return Err("Note not found".to_string());
+    }
+
+    let name = section.unwrap_or_default().trim().to_string();
+    let mut sections = load_vault_settings(&app_handle).pinned;
+    for section in &mut sections {
+        section.note_ids.retain(|id| *id != note_id);
+    }
+    match sections.iter_mut().find(|s| s.name == name) {
+        Some(section) => section.note_ids.push(note_id),
+        None => sections.push(PinSection {
+            name,
+            note_ids: vec![note_id],
+        }),
+    }
+
+    save_sections(&app_handle, sections)
+}
+
+#[tauri::command]
+pub async fn unpin_note(app_handle: AppHandle<Wry>, note_id: String) -> Result<(), String> {
+    let mut sections = load_vault_settings(&app_handle).pinned;
+    for section in &mut sections {
+        section.note_ids.retain(|id| *id != note_id);
+    }
+    save_sections(&app_handle, sections)
+}
+
+// Replace the whole pin layout: reorder pins, move them between sections, and
+// rename, reorder or drop sections. Empty sections are kept so the user can fill
+// them later.
+#[tauri::command]
+pub async fn set_pin_layout(
+    app_handle: AppHandle<Wry>,
+    sections: Vec<PinSection>,
+) -> Result<(), String> {
+    let ids: Vec<String> = load_notes(&app_handle)?.into_iter().map(|n| n.id).collect();
+    let mut seen_names = Vec::new();
+    let mut seen_ids = Vec::new();
+
+    for section in &sections {
+        if seen_names.contains(&section.name.trim()) {
+            return Err(format!("Duplicate pin section: {:?}", section.name));
+        }
+        seen_names.push(section.name.trim());
+
+        // Pins of deleted notes may still be in the layout the frontend sends back
+        for id in section.note_ids.iter().filter(|id| ids.contains(id)) {
+            if seen_ids.contains(&id) {
+                return Err(format!("Note {} is pinned more than once", id));
+            }
+            seen_ids.push(id);
+        }
+    }
+
+    let sections = sections
+        .into_iter()
+        .map(|s| PinSection {
+            name: s.name.trim().to_string(),
+            note_ids: s
+                .note_ids
+                .into_iter()
+                .filter(|id| ids.contains(id))
+                .collect(),
+        })
+        .collect();
+    save_sections(&app_handle, sections)
+}
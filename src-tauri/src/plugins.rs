@@ -0,0 +1,258 @@
+use crate::overrides;
+use crate::secret;
+use crate::settings::{load_device_settings, save_device_settings};
+use crate::Note;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tauri::{AppHandle, Wry};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const MANIFEST_FILE: &str = "plugin.json";
+const MAX_OUTPUT_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginHook {
+    Save,
+    Render,
+    Import,
+}
+
+// Contents of `plugins/<name>/plugin.json`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct PluginManifest {
+    name: String,
+    #[serde(default)]
+    description: String,
+    // Executable to run, relative to the plugin directory
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    hooks: Vec<PluginHook>,
+    #[serde(default = "default_timeout_secs")]
+    timeout_secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    5
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PluginInfo {
+    name: String,
+    description: String,
+    hooks: Vec<PluginHook>,
+    enabled: bool,
+    path: String,
+}
+
+// What a plugin receives on stdin
+#[derive(Debug, Serialize)]
+struct PluginInput<'a> {
+    hook: PluginHook,
+    note: &'a Note,
+}
+
+// What a plugin may print on stdout; missing fields leave the note untouched
+#[derive(Debug, Deserialize, Default)]
+struct PluginOutput {
+    title: Option<String>,
+    content: Option<String>,
+}
+
+fn get_plugins_dir(app_handle: &AppHandle<Wry>) -> PathBuf {
+    let mut path = overrides::app_data_dir(app_handle).expect("Failed to get app data directory");
+    path.push("plugins");
+    fs::create_dir_all(&path).expect("Failed to create plugins directory");
+    path
+}
+
+fn discover_plugins(app_handle: &AppHandle<Wry>) -> Vec<(PathBuf, PluginManifest)> {
+    let mut plugins = Vec::new();
+
+    let entries = match fs::read_dir(get_plugins_dir(app_handle)) {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("Failed to read plugins directory: {}", e);
+            return plugins;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let manifest_path = dir.join(MANIFEST_FILE);
+        if !manifest_path.is_file() {
+            continue;
+        }
+
+        match fs::read_to_string(&manifest_path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                serde_json::from_str::<PluginManifest>(&content).map_err(|e| e.to_string())
+            }) {
+            Ok(manifest) => plugins.push((dir, manifest)),
+            Err(e) => println!("Skipping plugin at {:?}: {}", dir, e),
+        }
+    }
+
+    plugins.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+    plugins
+}
+
+// Run a single plugin. Plugins are untrusted, so they get a cleared environment,
+// their own directory as cwd, a hard timeout and a cap on how much they can print.
+async fn run_plugin(
+    dir: &Path,
+    manifest: &PluginManifest,
+    hook: PluginHook,
+    note: &Note,
+) -> Result<PluginOutput, String> {
+    let command = Path::new(&manifest.command);
+    if command
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return Err("Plugin command must live inside the plugin directory".to_string());
+    }
+    let executable = dir.join(command);
+
+    let input = serde_json::to_vec(&PluginInput { hook, note }).map_err(|e| e.to_string())?;
+
+    let mut child = tokio::process::Command::new(&executable)
+        .args(&manifest.args)
+        .current_dir(dir)
+        .env_clear()
+        .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    let (Some(mut stdin), Some(stdout), Some(stderr)) =
+        (child.stdin.take(), child.stdout.take(), child.stderr.take())
+    else {
+        return Err("Plugin pipes aren't available".to_string());
+    };
+
+    // The input is written on its own task, within the timeout, so a plugin that
+    // doesn't read it can't hold up the save. Closing stdin when done marks the end.
+    let feed = tokio::spawn(async move {
+        let _ = stdin.write_all(&input).await;
+    });
+    let limit = MAX_OUTPUT_BYTES as u64 + 1;
+    let run = async {
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let (out_read, err_read, status) = tokio::join!(
+            stdout.take(limit).read_to_end(&mut out),
+            stderr.take(limit).read_to_end(&mut err),
+            child.wait(),
+        );
+        out_read
+            .and(err_read)
+            .and(status)
+            .map(|status| (status, out, err))
+    };
+    let finished = tokio::time::timeout(Duration::from_secs(manifest.timeout_secs), run).await;
+    feed.abort();
+    let (status, stdout, stderr) = match finished {
+        Ok(result) => result.map_err(|e| e.to_string())?,
+        Err(_) => {
+            if let Err(e) = child.kill().await {
+                println!("Failed to stop plugin {}: {}", manifest.name, e);
+            }
+            return Err(format!("Plugin timed out after {}s", manifest.timeout_secs));
+        }
+    };
+
+    if !status.success() {
+        return Err(format!(
+            "Plugin exited with {}: {}",
+            status,
+            String::from_utf8_lossy(&stderr).trim()
+        ));
+    }
+
+    if stdout.len() > MAX_OUTPUT_BYTES {
+        return Err("Plugin output too large".to_string());
+    }
+
+    if stdout.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(PluginOutput::default());
+    }
+
+    serde_json::from_slice(&stdout).map_err(|e| e.to_string())
+}
+
+// Pass the note through every enabled plugin registered for `hook`, in name order.
+// A failing plugin is logged and skipped rather than blocking the operation. Notes
+// locked with a passphrase are left alone: their body is the sealed fence, which a
+// plugin rewriting it would make impossible to unlock.
+pub async fn run_hook(app_handle: &AppHandle<Wry>, hook: PluginHook, mut note: Note) -> Note {
+    let enabled = load_device_settings(app_handle).enabled_plugins;
+    if enabled.is_empty() || secret::is_locked(&note) {
+        return note;
+    }
+
+    for (dir, manifest) in discover_plugins(app_handle) {
+        if !enabled.contains(&manifest.name) || !manifest.hooks.contains(&hook) {
+            continue;
+        }
+
+        match run_plugin(&dir, &manifest, hook, &note).await {
+            Ok(output) => {
+                if let Some(title) = output.title {
+                    note.title = title;
+                }
+                if let Some(content) = output.content {
+                    note.content = content;
+                }
+            }
+            Err(e) => println!("Plugin {} failed on {:?}: {}", manifest.name, hook, e),
+        }
+    }
+
+    note
+}
+
+#[tauri::command]
+pub async fn list_plugins(app_handle: AppHandle<Wry>) -> Result<Vec<PluginInfo>, String> {
+    let enabled = load_device_settings(&app_handle).enabled_plugins;
+
+    Ok(discover_plugins(&app_handle)
+        .into_iter()
+        .map(|(dir, manifest)| PluginInfo {
+            enabled: enabled.contains(&manifest.name),
+            name: manifest.name,
+            description: manifest.description,
+            hooks: manifest.hooks,
+            path: dir.to_string_lossy().into_owned(),
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub async fn enable_plugin(
+    app_handle: AppHandle<Wry>,
+    name: String,
+    enabled: bool,
+) -> Result<(), String> {
+    if !discover_plugins(&app_handle)
+        .iter()
+        .any(|(_, manifest)| manifest.name == name)
+    {
+        return Err("Plugin not found".to_string());
+    }
+
+    let mut settings = load_device_settings(&app_handle);
+    settings.enabled_plugins.retain(|n| n != &name);
+    if enabled {
+        settings.enabled_plugins.push(name);
+    }
+    save_device_settings(&app_handle, &settings)
+}
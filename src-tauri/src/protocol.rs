@@ -0,0 +1,355 @@
+use crate::ordering::folder_of;
+use crate::schema::{self, SCHEMA_VERSION};
+use crate::{tags, SyncRequest};
+use axum::body::Bytes;
+use axum::extract::rejection::BytesRejection;
+use axum::http::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+
+// Version of the sync wire format. Peers advertise the version they speak over mDNS
+// and `/sync/info`, and requests carry the version they were written in. A sender
+// talks to a peer in the highest version both support; a receiver reads any
+// version, ignoring fields newer than it knows, so builds of different ages keep
+// syncing instead of rejecting each other's requests.
+//
+// 1: the original format, with no version information
+// 2: adds `protocol` and `schema` to sync requests
+// 3: adds `restored` to tombstones
+
+pub const PROTOCOL_VERSION: u32 = 3;
+// The first version whose peers know restored tombstones
+pub const RESTORES_VERSION: u32 = 3;
+// Peers and requests that don't state a version predate versioning
+pub const LEGACY_VERSION: u32 = 1;
+
+pub fn legacy_version() -> u32 {
+    LEGACY_VERSION
+}
+
+// The version to talk to a peer in
+pub fn negotiate(peer_version: u32) -> u32 {
+    peer_version.clamp(LEGACY_VERSION, PROTOCOL_VERSION)
+}
+
+// A version advertised as text, e.g. in an mDNS property
+pub fn parse_version(value: Option<&str>) -> u32 {
+    value
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(LEGACY_VERSION)
+}
+
+// Read a sync request of any version. The note is brought up to this build's schema,
+// and what isn't sent but read from the note file (its tags and notebook) is filled
+// in as if it had been read here.
+pub fn read_request(body: Value) -> Result<SyncRequest, String> {
+    let version = body
+        .get("protocol")
+        .and_then(Value::as_u64)
+        .map_or(LEGACY_VERSION, |v| v as u32);
+    if version > PROTOCOL_VERSION {
+        println!(
+            "Sync request uses protocol {} (we speak {}), reading the fields we know",
+            version, PROTOCOL_VERSION
+        );
+    }
+
+    let mut request: SyncRequest = serde_json::from_value(body)
+        .map_err(|e| format!("Unreadable sync request (protocol {}): {}", version, e))?;
+    if request.schema < SCHEMA_VERSION {
+        schema::upgrade_note(&mut request.note, request.schema);
+    }
+    request.note.tags = tags::tags_of(&request.note);
+    request.note.notebook = folder_of(&request.note.fields).map(str::to_string);
+    Ok(request)
+}
+
+// What the sync endpoints send back when they fail, with a matching HTTP status:
+// `{"success": false, "error": {"code", "message", "retryable"}}`. Peers that
+// predate it answered 200 with a bare error string, which `read_error` handles too.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SyncError {
+    // Stable reason for programs to match on, e.g. `invalid_request`
+    pub code: String,
+    pub message: String,
+    // Whether sending the same request again later may work
+    pub retryable: bool,
+    // The refused part of the request, e.g. `note.title`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl SyncError {
+    pub fn new(code: &str, message: impl Into<String>, retryable: bool) -> Self {
+        SyncError {
+            code: code.to_string(),
+            message: message.into(),
+            retryable,
+            field: None,
+        }
+    }
+
+    // The peer couldn't be reached at all
+    pub fn unreachable(message: impl Into<String>) -> Self {
+        SyncError::new("unreachable", message, true)
+    }
+
+    // Sync is paused by hand on this device; the peer can try again later
+    pub fn paused() -> Self {
+        SyncError::new("sync_paused", "Sync is paused on this device", true)
+    }
+
+    pub fn body(&self) -> Value {
+        json!({
+            "success": false,
+            "error": self,
+            "protocol": PROTOCOL_VERSION
+        })
+    }
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl From<SyncError> for String {
+    fn from(error: SyncError) -> String {
+        error.to_string()
+    }
+}
+
+// The error in a peer's reply with a failing status
+pub fn read_error(status: u16, body: &str) -> SyncError {
+    let body: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+    if let Ok(error) = serde_json::from_value::<SyncError>(body["error"].clone()) {
+        return error;
+    }
+    let message = body["error"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("Peer answered with HTTP {}", status));
+    // Server-side trouble may pass; a refused request won't
+    SyncError::new("http_error", message, status >= 500)
+}
+
+// Body of a request to a sync endpoint. It is read by hand rather than through
+// axum's `Json`, whose rejections are plain text, so a body that is too large or
+// isn't JSON is refused with a `SyncError` like any other request.
+pub fn read_body<T: DeserializeOwned>(
+    body: Result<Bytes, BytesRejection>,
+) -> Result<T, (StatusCode, SyncError)> {
+    let body = body.map_err(|rejection| {
+        let code = if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            "too_large"
+        } else {
+            "unreadable_request"
+        };
+        (
+            rejection.status(),
+            SyncError::new(code, rejection.body_text(), false),
+        )
+    })?;
+    serde_json::from_slice(&body).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            SyncError::new("unreadable_request", e.to_string(), false),
+        )
+    })
+}
+
+// Limits on what a sync request may carry. Ids and attachment names become file
+// paths, so a request outside them is refused before anything is written.
+const MAX_ID_LEN: usize = 64;
+const MAX_NAME_LEN: usize = 256;
+const MAX_TITLE_LEN: usize = 1024;
+const MAX_CONTENT_BYTES: usize = 10 * 1024 * 1024;
+const MAX_ATTACHMENTS: usize = 200;
+const MAX_FILE_NAME_BYTES: usize = 255;
+const MAX_FIELDS: usize = 200;
+const MAX_TOMBSTONES: usize = 100_000;
+
+fn invalid(field: &str, error: impl fmt::Display) -> SyncError {
+    SyncError {
+        field: Some(field.to_string()),
+        ..SyncError::new("invalid_request", format!("{} {}", field, error), false)
+    }
+}
+
+pub fn check_id(field: &str, id: &str) -> Result<(), SyncError> {
+    if id.is_empty() || id.len() > MAX_ID_LEN {
+        return Err(invalid(field, "must be 1 to 64 characters"));
+    }
+    if id.starts_with('.')
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(invalid(
+            field,
+            "may only contain letters, digits, '-', '_' and '.'",
+        ));
+    }
+    Ok(())
+}
+
+fn check_text(field: &str, text: &str, max_chars: usize) -> Result<(), SyncError> {
+    if text.chars().count() > max_chars {
+        return Err(invalid(
+            field,
+            format!("is longer than {} characters", max_chars),
+        ));
+    }
+    if text.chars().any(char::is_control) {
+        return Err(invalid(field, "contains control characters"));
+    }
+    Ok(())
+}
+
+pub fn check_file_name(field: &str, name: &str) -> Result<(), SyncError> {
+    if name.is_empty() || name.len() > MAX_FILE_NAME_BYTES {
+        return Err(invalid(field, "must be 1 to 255 bytes"));
+    }
+    if name == "."
+        || name == ".."
+        || name.contains(['/', '\\'])
+        || name.chars().any(char::is_control)
+    {
+        return Err(invalid(
+            field,
+            format!("is not a plain file name: {:?}", name),
+        ));
+    }
+    Ok(())
+}
+
+// Check a request semantically before it is stored. Unknown fields were already
+// skipped when it was read, so newer peers aren't refused for them.
+pub fn validate_request(request: &SyncRequest) -> Result<(), SyncError> {
+    check_id("peer_id", &request.peer_id)?;
+    check_text("peer_name", &request.peer_name, MAX_NAME_LEN)?;
+
+    let note = &request.note;
+    check_id("note.id", &note.id)?;
+    check_text("note.title", &note.title, MAX_TITLE_LEN)?;
+    check_text("note.datetime", &note.datetime, MAX_ID_LEN)?;
+    if note.content.len() > MAX_CONTENT_BYTES {
+        return Err(invalid("note.content", "is larger than 10 MB"));
+    }
+    if note.fields.len() > MAX_FIELDS {
+        return Err(invalid("note.fields", "has too many fields"));
+    }
+    for key in note.fields.keys() {
+        check_text("note.fields", key, MAX_NAME_LEN)?;
+    }
+
+    if note.attachments.len() > MAX_ATTACHMENTS || request.attachments_data.len() > MAX_ATTACHMENTS
+    {
+        return Err(invalid(
+            "attachments_data",
+            format!("has more than {} attachments", MAX_ATTACHMENTS),
+        ));
+    }
+    for name in &note.attachments {
+        check_file_name("note.attachments", name)?;
+    }
+    for name in request.attachments_data.keys() {
+        check_file_name("attachments_data", name)?;
+    }
+
+    if request.tombstones.len() > MAX_TOMBSTONES {
+        return Err(invalid("tombstones", "has too many entries"));
+    }
+    for tombstone in &request.tombstones {
+        check_id("tombstones.note_id", &tombstone.note_id)?;
+        if let Some(hlc) = &tombstone.hlc {
+            check_text("tombstones.hlc", hlc, MAX_NAME_LEN)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tombstones::Tombstone;
+    use crate::Note;
+    use std::collections::HashMap;
+
+    fn request() -> SyncRequest {
+        SyncRequest {
+            protocol: PROTOCOL_VERSION,
+            schema: SCHEMA_VERSION,
+            peer_id: "peer-1".to_string(),
+            peer_name: "Laptop".to_string(),
+            note: Note {
+                id: "note-1".to_string(),
+                title: "Shared".to_string(),
+                datetime: "1700000000".to_string(),
+                ..Default::default()
+            },
+            attachments_data: HashMap::new(),
+            tombstones: Vec::new(),
+        }
+    }
+
+    fn refused_field(request: &SyncRequest) -> Option<String> {
+        validate_request(request)
+            .err()
+            .and_then(|error| error.field)
+    }
+
+    #[test]
+    fn accepts_a_plain_request() {
+        assert!(validate_request(&request()).is_ok());
+    }
+
+    #[test]
+    fn refuses_ids_that_are_not_file_names() {
+        let mut traversal = request();
+        traversal.note.id = "../outside".to_string();
+        assert_eq!(refused_field(&traversal).as_deref(), Some("note.id"));
+
+        let mut long = request();
+        long.note.id = "a".repeat(MAX_ID_LEN + 1);
+        assert_eq!(refused_field(&long).as_deref(), Some("note.id"));
+        long.note.id = "a".repeat(MAX_ID_LEN);
+        assert!(validate_request(&long).is_ok());
+    }
+
+    #[test]
+    fn refuses_attachment_paths() {
+        let mut request = request();
+        request
+            .attachments_data
+            .insert("../secret".to_string(), Vec::new());
+        assert_eq!(refused_field(&request).as_deref(), Some("attachments_data"));
+    }
+
+    #[test]
+    fn refuses_oversized_content_and_control_characters() {
+        let mut large = request();
+        large.note.content = "x".repeat(MAX_CONTENT_BYTES + 1);
+        assert_eq!(refused_field(&large).as_deref(), Some("note.content"));
+
+        let mut title = request();
+        title.note.title = "Line\nbreak".to_string();
+        assert_eq!(refused_field(&title).as_deref(), Some("note.title"));
+    }
+
+    #[test]
+    fn refuses_too_many_tombstones() {
+        let tombstone = Tombstone {
+            note_id: "note-2".to_string(),
+            deleted_at: 1_700_000_000,
+            restored: false,
+            hlc: None,
+        };
+        let mut request = request();
+        request.tombstones = vec![tombstone; MAX_TOMBSTONES + 1];
+        assert_eq!(refused_field(&request).as_deref(), Some("tombstones"));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::settings::{load_device_settings, DeviceSettings};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Wry};
+
+// Outbound HTTP requests go through the proxy configured in settings. Each feature
+// can override the default, e.g. to reach LAN peers directly while webhooks use
+// the corporate proxy.
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyMode {
+    // Use the HTTP(S)_PROXY environment variables and the OS proxy settings
+    #[default]
+    System,
+    // Never use a proxy
+    Direct,
+    // Use `url`, with basic auth taken from it if present
+    Manual,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub mode: ProxyMode,
+    pub url: Option<String>,
+    // Comma-separated hosts, domains and CIDR ranges that bypass a manual proxy
+    pub no_proxy: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HttpFeature {
+    Sync,
+    Webhooks,
+    Paste,
+    LinkCheck,
+    Embeddings,
+    Summarize,
+    Translate,
+}
+
+impl HttpFeature {
+    const ALL: [HttpFeature; 7] = [
+        HttpFeature::Sync,
+        HttpFeature::Webhooks,
+        HttpFeature::Paste,
+        HttpFeature::LinkCheck,
+        HttpFeature::Embeddings,
+        HttpFeature::Summarize,
+        HttpFeature::Translate,
+    ];
+
+    // Key of the feature in `proxy_overrides`
+    fn key(self) -> &'static str {
+        match self {
+            HttpFeature::Sync => "sync",
+            HttpFeature::Webhooks => "webhooks",
+            HttpFeature::Paste => "paste",
+            HttpFeature::LinkCheck => "link_check",
+            HttpFeature::Embeddings => "embeddings",
+            HttpFeature::Summarize => "summarize",
+            HttpFeature::Translate => "translate",
+        }
+    }
+}
+
+impl ProxyConfig {
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder, String> {
+        match self.mode {
+            ProxyMode::System => Ok(builder),
+            ProxyMode::Direct => Ok(builder.no_proxy()),
+            ProxyMode::Manual => {
+                let url = self
+                    .url
+                    .as_deref()
+                    .filter(|url| !url.trim().is_empty())
+                    .ok_or("Manual proxy mode needs a proxy URL")?;
+                let proxy = reqwest::Proxy::all(url.trim())
+                    .map_err(|e| format!("Invalid proxy URL: {}", e))?
+                    .no_proxy(
+                        self.no_proxy
+                            .as_deref()
+                            .and_then(reqwest::NoProxy::from_string),
+                    );
+                Ok(builder.proxy(proxy))
+            }
+        }
+    }
+}
+
+fn config_in(settings: &DeviceSettings, feature: HttpFeature) -> ProxyConfig {
+    settings
+        .proxy_overrides
+        .get(feature.key())
+        .unwrap_or(&settings.proxy)
+        .clone()
+}
+
+pub fn config_for(app_handle: &AppHandle<Wry>, feature: HttpFeature) -> ProxyConfig {
+    config_in(&load_device_settings(app_handle), feature)
+}
+
+pub fn client(
+    app_handle: &AppHandle<Wry>,
+    feature: HttpFeature,
+) -> Result<reqwest::Client, String> {
+    config_for(app_handle, feature)
+        .apply(reqwest::Client::builder())?
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+// Reject proxy settings that couldn't be used to build a client
+pub fn validate(settings: &DeviceSettings) -> Result<(), String> {
+    if let Some(key) = settings
+        .proxy_overrides
+        .keys()
+        .find(|key| !HttpFeature::ALL.iter().any(|f| f.key() == key.as_str()))
+    {
+        return Err(format!("Unknown proxy override: {}", key));
+    }
+
+    for feature in HttpFeature::ALL {
+        config_in(settings, feature).apply(reqwest::Client::builder())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,369 @@
+use crate::tags::tags_of;
+use crate::{annotate_metadata, load_notes, Note, NoteMetadata};
+use serde_json::Value;
+use std::cmp::Ordering;
+use tauri::{AppHandle, Wry};
+
+// A small boolean query language over note metadata, e.g.
+//
+//   status = in-review and (tags contains planning or priority >= 2)
+//   not archived and modified > 2024-01-01
+//
+// The left side of a comparison names a field: `id`, `title`, `content`, `modified`
+// (YYYY-MM-DD), `tags` (including `#tags` in the text) or any frontmatter field
+// (optionally written as `fields.<name>`).
+// Bare words on the right side are literals, so `status = done` needs no quotes.
+// A field on its own is true when it is set to something other than false/empty.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Op(CompareOp),
+    Word(String),
+    Literal(Value),
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(String, CompareOp, Value),
+    Truthy(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        // Two-character operators first
+        let next = chars.get(i + 1).copied();
+        let two = match (c, next) {
+            ('=', Some('=')) => Some(Token::Op(CompareOp::Eq)),
+            ('!', Some('=')) => Some(Token::Op(CompareOp::Ne)),
+            ('<', Some('=')) => Some(Token::Op(CompareOp::Le)),
+            ('>', Some('=')) => Some(Token::Op(CompareOp::Ge)),
+            ('&', Some('&')) => Some(Token::And),
+            ('|', Some('|')) => Some(Token::Or),
+            _ => None,
+        };
+        if let Some(token) = two {
+            tokens.push(token);
+            i += 2;
+            continue;
+        }
+
+        let single = match c {
+            '(' => Some(Token::LParen),
+            ')' => Some(Token::RParen),
+            '=' => Some(Token::Op(CompareOp::Eq)),
+            '<' => Some(Token::Op(CompareOp::Lt)),
+            '>' => Some(Token::Op(CompareOp::Gt)),
+            '~' => Some(Token::Op(CompareOp::Contains)),
+            '!' => Some(Token::Not),
+            _ => None,
+        };
+        if let Some(token) = single {
+            tokens.push(token);
+            i += 1;
+            continue;
+        }
+
+        if c == '"' || c == '\'' {
+            let quote = c;
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i).copied() {
+                    None => return Err("Unterminated string in query".to_string()),
+                    Some(ch) if ch == quote => break,
+                    Some('\\') => {
+                        if let Some(&escaped) = chars.get(i + 1) {
+                            value.push(escaped);
+                        }
+                        i += 2;
+                        continue;
+                    }
+                    Some(ch) => value.push(ch),
+                }
+                i += 1;
+            }
+            tokens.push(Token::Literal(Value::String(value)));
+            i += 1;
+            continue;
+        }
+
+        let is_word_char =
+            |ch: char| ch.is_alphanumeric() || matches!(ch, '_' | '-' | '.' | '/' | ':');
+        if !is_word_char(c) {
+            return Err(format!("Unexpected character '{}' in query", c));
+        }
+
+        let start = i;
+        while i < chars.len() && is_word_char(chars[i]) {
+            i += 1;
+        }
+        let word: String = chars[start..i].iter().collect();
+
+        tokens.push(match word.to_lowercase().as_str() {
+            "and" => Token::And,
+            "or" => Token::Or,
+            "not" => Token::Not,
+            "contains" => Token::Op(CompareOp::Contains),
+            "true" => Token::Literal(Value::Bool(true)),
+            "false" => Token::Literal(Value::Bool(false)),
+            "today" => Token::Literal(Value::String(
+                chrono::Local::now().format("%Y-%m-%d").to_string(),
+            )),
+            _ => match word
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+            {
+                Some(number) => Token::Literal(Value::Number(number)),
+                None => Token::Word(word),
+            },
+        });
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            left = Expr::And(Box::new(left), Box::new(self.parse_not()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err("Expected ')' in query".to_string()),
+                }
+            }
+            Some(Token::Word(field)) => {
+                let field = field
+                    .strip_prefix("fields.")
+                    .map(str::to_string)
+                    .unwrap_or(field);
+
+                let Some(Token::Op(op)) = self.peek().cloned() else {
+                    return Ok(Expr::Truthy(field));
+                };
+                self.next();
+
+                let value = match self.next() {
+                    Some(Token::Literal(value)) => value,
+                    Some(Token::Word(word)) => Value::String(word),
+                    _ => return Err(format!("Expected a value after operator on '{}'", field)),
+                };
+                Ok(Expr::Compare(field, op, value))
+            }
+            Some(token) => Err(format!("Unexpected {:?} in query", token)),
+            None => Err("Unexpected end of query".to_string()),
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        position: 0,
+    };
+
+    let expr = parser.parse_or()?;
+    if parser.position < parser.tokens.len() {
+        return Err(format!(
+            "Unexpected {:?} in query",
+            parser.tokens[parser.position]
+        ));
+    }
+    Ok(expr)
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+        Value::Number(_) => true,
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::String(a), Value::String(b)) => a.eq_ignore_ascii_case(b),
+        (Value::Number(_), _) | (_, Value::Number(_)) => {
+            matches!((as_number(left), as_number(right)), (Some(a), Some(b)) if a == b)
+        }
+        _ => left == right,
+    }
+}
+
+fn compare(left: &Value, op: CompareOp, right: &Value) -> bool {
+    // Lists match when any of their items does
+    if let Value::Array(items) = left {
+        return match op {
+            CompareOp::Ne => !items.iter().any(|item| values_equal(item, right)),
+            CompareOp::Eq | CompareOp::Contains => {
+                items.iter().any(|item| values_equal(item, right))
+            }
+            _ => items.iter().any(|item| compare(item, op, right)),
+        };
+    }
+
+    match op {
+        CompareOp::Eq => values_equal(left, right),
+        CompareOp::Ne => !values_equal(left, right),
+        CompareOp::Contains => match (left, right) {
+            (Value::String(a), Value::String(b)) => a.to_lowercase().contains(&b.to_lowercase()),
+            _ => values_equal(left, right),
+        },
+        CompareOp::Lt | CompareOp::Le | CompareOp::Gt | CompareOp::Ge => {
+            let ordering = match (as_number(left), as_number(right)) {
+                (Some(a), Some(b)) => a.partial_cmp(&b),
+                // Strings (including YYYY-MM-DD dates) compare lexically
+                _ => match (left, right) {
+                    (Value::String(a), Value::String(b)) => Some(a.as_str().cmp(b.as_str())),
+                    _ => None,
+                },
+            };
+            match ordering {
+                Some(Ordering::Less) => matches!(op, CompareOp::Lt | CompareOp::Le),
+                Some(Ordering::Equal) => matches!(op, CompareOp::Le | CompareOp::Ge),
+                Some(Ordering::Greater) => matches!(op, CompareOp::Gt | CompareOp::Ge),
+                None => false,
+            }
+        }
+    }
+}
+
+pub fn evaluate(expr: &Expr, resolve: &dyn Fn(&str) -> Value) -> bool {
+    match expr {
+        Expr::And(a, b) => evaluate(a, resolve) && evaluate(b, resolve),
+        Expr::Or(a, b) => evaluate(a, resolve) || evaluate(b, resolve),
+        Expr::Not(inner) => !evaluate(inner, resolve),
+        Expr::Compare(field, op, value) => compare(&resolve(field), *op, value),
+        Expr::Truthy(field) => is_truthy(&resolve(field)),
+    }
+}
+
+// Look up a query field on a note
+pub fn resolve_field(note: &Note, field: &str) -> Value {
+    match field {
+        "id" => Value::String(note.id.clone()),
+        "title" => Value::String(note.title.clone()),
+        "content" => Value::String(note.content.clone()),
+        // Tags written in the text as well as those in the frontmatter
+        "tags" => Value::Array(tags_of(note).into_iter().map(Value::String).collect()),
+        "modified" => note
+            .datetime
+            .parse::<f64>()
+            .ok()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs as i64, 0))
+            .map(|dt| {
+                Value::String(
+                    dt.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d")
+                        .to_string(),
+                )
+            })
+            .unwrap_or(Value::Null),
+        _ => note.fields.get(field).cloned().unwrap_or(Value::Null),
+    }
+}
+
+pub fn filter_notes(notes: Vec<Note>, expr: &Expr) -> Vec<Note> {
+    notes
+        .into_iter()
+        .filter(|note| evaluate(expr, &|field| resolve_field(note, field)))
+        .collect()
+}
+
+#[tauri::command]
+pub async fn query_notes(
+    app_handle: AppHandle<Wry>,
+    expr: String,
+) -> Result<Vec<NoteMetadata>, String> {
+    let expr = parse(&expr)?;
+
+    let mut notes: Vec<NoteMetadata> = filter_notes(load_notes(&app_handle)?, &expr)
+        .iter()
+        .map(NoteMetadata::from)
+        .collect();
+    annotate_metadata(&app_handle, &mut notes);
+    Ok(notes)
+}
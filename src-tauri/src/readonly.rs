@@ -0,0 +1,130 @@
+use crate::frontmatter::{self, Frontmatter};
+use crate::{
+    clock, encryption, get_note_path, load_notes, lock, persist_note_unchecked, read_note, secret,
+    storage, Note,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use tauri::{AppHandle, Wry};
+
+// Frontmatter key that marks a note as reference material. Being part of the note's
+// metadata, the flag travels with the note when it is synced to a peer.
+const READONLY_FIELD: &str = "readonly";
+
+// Why a note couldn't be saved, so the frontend can tell a protected note apart
+// from a failed write
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SaveError {
+    ReadOnly { note_id: String },
+    // The note changed since the version the edit started from, e.g. in another
+    // window or through sync. `current` is the stored note, to show or merge with.
+    Conflict { note_id: String, current: Note },
+    // Another instance holds the notes directory, so this one is read-only
+    Locked { holder: String },
+    // The note is locked with a passphrase and has to be unlocked first
+    PasswordLocked { note_id: String },
+    Failed { message: String },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::ReadOnly { note_id } => write!(f, "Note {} is read-only", note_id),
+            SaveError::Locked { holder } => write!(f, "The notes are open in {}", holder),
+            SaveError::PasswordLocked { note_id } => {
+                write!(f, "Note {} is locked with a passphrase", note_id)
+            }
+            SaveError::Conflict { note_id, .. } => {
+                write!(f, "Note {} was changed since it was opened", note_id)
+            }
+            SaveError::Failed { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for SaveError {
+    fn from(message: String) -> Self {
+        SaveError::Failed { message }
+    }
+}
+
+fn is_readonly(fields: &Frontmatter) -> bool {
+    fields.get(READONLY_FIELD) == Some(&Value::Bool(true))
+}
+
+// Refuse changes to a note whose stored copy is read-only. The stored file decides,
+// so an editor holding a stale copy without the flag can't overwrite the note.
+pub fn ensure_writable(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<(), SaveError> {
+    if let Some(holder) = lock::holder(app_handle) {
+        return Err(SaveError::Locked { holder });
+    }
+    // An archived note is about to be unpacked anyway, and its flag lives in the archive
+    storage::restore_note(app_handle, note_id)?;
+    let Ok(raw) = encryption::read_to_string(get_note_path(app_handle, note_id)) else {
+        return Ok(());
+    };
+    let fields = frontmatter::split(&raw).0;
+    if is_readonly(&fields) {
+        return Err(SaveError::ReadOnly {
+            note_id: note_id.to_string(),
+        });
+    }
+    // The editor only has the title of a locked note
+    if fields.contains_key(secret::LOCKED_FIELD) {
+        return Err(SaveError::PasswordLocked {
+            note_id: note_id.to_string(),
+        });
+    }
+    Ok(())
+}
+
+// The revision of a note: the clock stamp of its last save
+pub fn revision_of(note: &Note) -> Option<String> {
+    clock::timestamp_of(note).map(|timestamp| timestamp.to_string())
+}
+
+// Refuse to overwrite a note whose stored revision isn't the one the edit started
+// from. Notes saved before revisions existed have none and are never in conflict.
+pub fn ensure_current(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    expected: &str,
+) -> Result<(), SaveError> {
+    let path = get_note_path(app_handle, note_id);
+    if !path.exists() {
+        return Ok(());
+    }
+    let current = read_note(app_handle, note_id, &path)?;
+    match revision_of(&current) {
+        Some(revision) if revision != expected => Err(SaveError::Conflict {
+            note_id: note_id.to_string(),
+            current,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub async fn set_note_readonly(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    readonly: bool,
+) -> Result<Note, String> {
+    let mut note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    if readonly {
+        note.fields
+            .insert(READONLY_FIELD.to_string(), Value::Bool(true));
+    } else {
+        note.fields.remove(READONLY_FIELD);
+    }
+
+    persist_note_unchecked(&app_handle, note.clone()).await?;
+
+    Ok(note)
+}
@@ -0,0 +1,76 @@
+use crate::{index, terms};
+use serde::Serialize;
+use tauri::{AppHandle, Wry};
+
+// Notes similar to a given note, scored from the index by shared rare terms, shared
+// tags and links between the two
+
+// Added for each tag the notes have in common
+const SHARED_TAG_SCORE: f64 = 0.15;
+// Added when either note links to the other
+const LINK_SCORE: f64 = 0.4;
+const MIN_SCORE: f64 = 0.05;
+
+#[derive(Debug, Serialize)]
+pub struct RelatedNote {
+    id: String,
+    title: String,
+    score: f64,
+    shared_tags: Vec<String>,
+    linked: bool,
+}
+
+#[tauri::command]
+pub async fn get_related_notes(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    limit: Option<usize>,
+) -> Result<Vec<RelatedNote>, String> {
+    index::with_index(&app_handle, |index| -> Result<Vec<RelatedNote>, String> {
+        let note = index.entries.get(&note_id).ok_or("Note not found")?;
+        let idf = index.idf();
+        let target = terms::weights(&note.terms, &idf);
+
+        let resolve = index.link_resolver();
+        let links_to = |entry: &index::IndexEntry, id: &str| {
+            entry
+                .links
+                .iter()
+                .any(|target| resolve(target).as_deref() == Some(id))
+        };
+
+        let mut related: Vec<RelatedNote> = index
+            .entries
+            .iter()
+            .filter(|(id, _)| **id != note_id)
+            .map(|(id, other)| {
+                let shared_tags: Vec<String> = other
+                    .tags
+                    .iter()
+                    .filter(|tag| note.tags.contains(tag))
+                    .cloned()
+                    .collect();
+                let linked = links_to(note, id) || links_to(other, &note_id);
+
+                let mut score = terms::cosine(&target, &terms::weights(&other.terms, &idf));
+                score += SHARED_TAG_SCORE * shared_tags.len() as f64;
+                if linked {
+                    score += LINK_SCORE;
+                }
+
+                RelatedNote {
+                    id: id.clone(),
+                    title: other.title.clone(),
+                    score,
+                    shared_tags,
+                    linked,
+                }
+            })
+            .filter(|r| r.score >= MIN_SCORE)
+            .collect();
+
+        related.sort_by(|a, b| b.score.total_cmp(&a.score));
+        related.truncate(limit.unwrap_or(10));
+        Ok(related)
+    })?
+}
@@ -0,0 +1,219 @@
+use crate::links::NOTE_LINK_SCHEME;
+use crate::plugins::{self, PluginHook};
+use crate::query::{self, resolve_field};
+use crate::{load_notes, Note};
+use serde_json::Value;
+use std::cmp::Ordering;
+use tauri::{AppHandle, Wry};
+
+// Fenced blocks like
+//
+// ```notes-query
+// status = in-review and tags contains planning
+// format: table
+// fields: status, owner
+// sort: modified desc
+// limit: 10
+// ```
+//
+// are replaced with a list or table of matching notes when a note is rendered.
+const QUERY_FENCE: &str = "```notes-query";
+
+struct QueryBlock {
+    expr: String,
+    table: bool,
+    fields: Vec<String>,
+    sort: Option<(String, bool)>,
+    limit: Option<usize>,
+}
+
+fn parse_block(lines: &[&str]) -> QueryBlock {
+    let mut block = QueryBlock {
+        expr: String::new(),
+        table: false,
+        fields: Vec::new(),
+        sort: None,
+        limit: None,
+    };
+    let mut expr_lines = Vec::new();
+
+    for line in lines {
+        match line.split_once(':').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("format", value)) => block.table = value.eq_ignore_ascii_case("table"),
+            Some(("fields", value)) => {
+                block.fields = value
+                    .split(',')
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty())
+                    .collect()
+            }
+            Some(("sort", value)) => {
+                let mut parts = value.split_whitespace();
+                if let Some(field) = parts.next() {
+                    let descending = parts.next().is_some_and(|d| d.eq_ignore_ascii_case("desc"));
+                    block.sort = Some((field.to_string(), descending));
+                }
+            }
+            Some(("limit", value)) => block.limit = value.parse().ok(),
+            _ => expr_lines.push(line.trim()),
+        }
+    }
+
+    block.expr = expr_lines.join(" ");
+    block
+}
+
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(display_value)
+            .collect::<Vec<_>>()
+            .join(", "),
+        other => other.to_string(),
+    }
+}
+
+// Numbers sort numerically, everything else by its displayed text
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => display_value(a).cmp(&display_value(b)),
+    }
+}
+
+fn escape_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn note_link(note: &Note) -> String {
+    format!(
+        "[{}]({}{})",
+        note.title.replace(']', "\\]"),
+        NOTE_LINK_SCHEME,
+        note.id
+    )
+}
+
+fn render_block(block: &QueryBlock, notes: &[Note]) -> String {
+    let expr = match query::parse(&block.expr) {
+        Ok(expr) => expr,
+        Err(e) => return format!("> Query error: {}\n", e),
+    };
+
+    let mut matches = query::filter_notes(notes.to_vec(), &expr);
+
+    if let Some((field, descending)) = &block.sort {
+        matches.sort_by(|a, b| {
+            let ordering = compare_values(&resolve_field(a, field), &resolve_field(b, field));
+            if *descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    } else {
+        matches.sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()));
+    }
+
+    if let Some(limit) = block.limit {
+        matches.truncate(limit);
+    }
+
+    if matches.is_empty() {
+        return "_No matching notes_\n".to_string();
+    }
+
+    let mut output = String::new();
+    if block.table {
+        output.push_str("| Note |");
+        for field in &block.fields {
+            output.push_str(&format!(" {} |", escape_cell(field)));
+        }
+        output.push_str("\n| --- |");
+        output.push_str(&" --- |".repeat(block.fields.len()));
+        output.push('\n');
+
+        for note in &matches {
+            output.push_str(&format!("| {} |", escape_cell(&note_link(note))));
+            for field in &block.fields {
+                let value = display_value(&resolve_field(note, field));
+                output.push_str(&format!(" {} |", escape_cell(&value)));
+            }
+            output.push('\n');
+        }
+    } else {
+        for note in &matches {
+            output.push_str(&format!("- {}\n", note_link(note)));
+        }
+    }
+
+    output
+}
+
+// Replace every `notes-query` block in `content` with its current results
+pub fn expand_query_blocks(content: &str, notes: &[Note]) -> String {
+    if !content.contains(QUERY_FENCE) {
+        return content.to_string();
+    }
+
+    let mut output = String::with_capacity(content.len());
+    let mut lines = content.lines();
+
+    while let Some(line) = lines.next() {
+        if line.trim() != QUERY_FENCE {
+            output.push_str(line);
+            output.push('\n');
+            continue;
+        }
+
+        let mut block_lines = Vec::new();
+        let mut closed = false;
+        for inner in lines.by_ref() {
+            if inner.trim() == "```" {
+                closed = true;
+                break;
+            }
+            block_lines.push(inner);
+        }
+
+        if closed {
+            output.push_str(&render_block(&parse_block(&block_lines), notes));
+        } else {
+            // Leave an unterminated block exactly as written
+            output.push_str(line);
+            output.push('\n');
+            for inner in block_lines {
+                output.push_str(inner);
+                output.push('\n');
+            }
+        }
+    }
+
+    if !content.ends_with('\n') {
+        output.pop();
+    }
+    output
+}
+
+// The markdown to display for a note: render plugins applied and query blocks expanded
+pub async fn render_note(app_handle: &AppHandle<Wry>, note: Note) -> Result<String, String> {
+    let note = plugins::run_hook(app_handle, PluginHook::Render, note).await;
+    let notes = load_notes(app_handle)?;
+    Ok(expand_query_blocks(&note.content, &notes))
+}
+
+#[tauri::command]
+pub async fn render_markdown(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+) -> Result<String, String> {
+    let note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    render_note(&app_handle, note).await
+}
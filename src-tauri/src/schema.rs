@@ -0,0 +1,216 @@
+use crate::{get_notes_dir, layout, notebooks, overrides, Note};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager, Wry};
+
+// The on-disk format of the vault has a version, recorded in the notes directory.
+// Each change to the format adds a migration from the previous version to the
+// registry below. At startup the vault is upgraded one version at a time, after a
+// backup of every note, so an old vault always takes the same path to the current
+// format. Notes from a device on an older version go through the same registry one
+// note at a time.
+
+pub const SCHEMA_VERSION: u32 = 3;
+const VERSION_FILE: &str = ".schema.json";
+
+pub struct Migration {
+    // Version this migration upgrades from, leaving the vault at `from + 1`
+    from: u32,
+    description: &'static str,
+    // Upgrades the files in the notes directory
+    vault: fn(&Path, &mut StepReport) -> Result<(), String>,
+    // Upgrades a single note, e.g. one received over sync
+    note: fn(&mut Note),
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        description: "Flat legacy layout to frontmatter folders and title headings",
+        vault: layout::upgrade_vault,
+        note: layout::upgrade_note,
+    },
+    Migration {
+        from: 2,
+        description: "Notes filed in notebooks move into notebook directories",
+        vault: notebooks::upgrade_vault,
+        note: notebooks::upgrade_note,
+    },
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct VersionFile {
+    version: u32,
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct StepReport {
+    pub description: String,
+    // Notes the step rewrote
+    pub changed: usize,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MigrationReport {
+    from_version: u32,
+    to_version: u32,
+    backup: Option<PathBuf>,
+    steps: Vec<StepReport>,
+}
+
+// A vault without a version file predates versioning
+fn read_version(notes_dir: &Path) -> u32 {
+    fs::read_to_string(notes_dir.join(VERSION_FILE))
+        .ok()
+        .and_then(|raw| serde_json::from_str::<VersionFile>(&raw).ok())
+        .map_or(1, |file| file.version)
+}
+
+fn write_version(notes_dir: &Path, version: u32) -> Result<(), String> {
+    let raw = serde_json::to_string_pretty(&VersionFile { version }).map_err(|e| e.to_string())?;
+    fs::write(notes_dir.join(VERSION_FILE), raw).map_err(|e| e.to_string())
+}
+
+// Markdown files of the vault relative to the notes directory. Directories the app
+// manages itself (attachments, trash, cold storage) hold no notes to migrate.
+pub fn markdown_files(notes_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    fn walk(notes_dir: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+        for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if path.is_dir() {
+                if name.starts_with('.') || (dir == notes_dir && name == "attachments") {
+                    continue;
+                }
+                walk(notes_dir, &path, files)?;
+            } else if path.extension().and_then(|e| e.to_str()) == Some("md") {
+                if let Ok(relative) = path.strip_prefix(notes_dir) {
+                    files.push(relative.to_path_buf());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    walk(notes_dir, notes_dir, &mut files)?;
+    Ok(files)
+}
+
+fn backup(
+    app_handle: &AppHandle<Wry>,
+    notes_dir: &Path,
+    files: &[PathBuf],
+    version: u32,
+) -> Result<PathBuf, String> {
+    let dir = overrides::app_data_dir(app_handle)
+        .map_err(|e| e.to_string())?
+        .join("backups")
+        .join(format!(
+            "schema-v{}-{}",
+            version,
+            chrono::Utc::now().format("%Y%m%d-%H%M%S")
+        ));
+    for file in files {
+        let dest = dir.join(file);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        fs::copy(notes_dir.join(file), &dest).map_err(|e| e.to_string())?;
+    }
+    Ok(dir)
+}
+
+// Upgrade the vault to the current version. Returns None when it already was.
+pub fn migrate(app_handle: &AppHandle<Wry>) -> Result<Option<MigrationReport>, String> {
+    let notes_dir = get_notes_dir(app_handle);
+    let from_version = read_version(&notes_dir);
+    if from_version > SCHEMA_VERSION {
+        return Err(format!(
+            "Vault is at version {}, newer than this app understands ({})",
+            from_version, SCHEMA_VERSION
+        ));
+    }
+    if from_version == SCHEMA_VERSION {
+        return Ok(None);
+    }
+
+    let files = markdown_files(&notes_dir)?;
+    // A new, empty vault starts out current
+    if files.is_empty() {
+        write_version(&notes_dir, SCHEMA_VERSION)?;
+        return Ok(None);
+    }
+
+    let mut report = MigrationReport {
+        from_version,
+        to_version: from_version,
+        backup: Some(backup(app_handle, &notes_dir, &files, from_version)?),
+        steps: Vec::new(),
+    };
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= from_version) {
+        let mut step = StepReport {
+            description: migration.description.to_string(),
+            ..Default::default()
+        };
+        let result = (migration.vault)(&notes_dir, &mut step);
+        if let Err(e) = result {
+            step.errors.push(e);
+        }
+        let failed = !step.errors.is_empty();
+        report.steps.push(step);
+
+        // A failed step leaves the vault at its version so the next start retries it
+        if failed {
+            break;
+        }
+        report.to_version = migration.from + 1;
+        write_version(&notes_dir, report.to_version)?;
+    }
+    Ok(Some(report))
+}
+
+// Bring a note written under an older version up to the current format
+pub fn upgrade_note(note: &mut Note, from_version: u32) {
+    for migration in MIGRATIONS.iter().filter(|m| m.from >= from_version) {
+        (migration.note)(note);
+    }
+}
+
+// Run the migrations at startup, logging the result and keeping it for the frontend
+pub fn run_at_startup(app_handle: &AppHandle<Wry>) {
+    match migrate(app_handle) {
+        Ok(Some(report)) => {
+            println!(
+                "Migrated vault from version {} to {}, backup in {:?}",
+                report.from_version, report.to_version, report.backup
+            );
+            for step in &report.steps {
+                println!("{}: {} notes changed", step.description, step.changed);
+                for error in &step.errors {
+                    println!("Failed to migrate {}", error);
+                }
+            }
+            let _ = app_handle.emit("vault-migrated", &report);
+            if let Ok(mut state) = app_handle.state::<Mutex<Option<MigrationReport>>>().lock() {
+                *state = Some(report);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => println!("Failed to migrate vault: {}", e),
+    }
+}
+
+// The report of a migration done at this startup, for the frontend to show once it
+// is ready to listen
+#[tauri::command]
+pub async fn get_migration_report(
+    app_handle: AppHandle<Wry>,
+) -> Result<Option<MigrationReport>, String> {
+    let state = app_handle.state::<Mutex<Option<MigrationReport>>>();
+    let report = state.lock().map_err(|e| e.to_string())?;
+    Ok(report.clone())
+}
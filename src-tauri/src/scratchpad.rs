@@ -0,0 +1,73 @@
+use crate::{new_note_id, overrides, persist_note, Note};
+use std::fs;
+use std::path::PathBuf;
+use tauri::{AppHandle, Wry};
+
+// A single buffer for quick jottings. It lives next to the notes directory rather
+// than in it, so it never shows up in the note list until it is promoted.
+
+fn get_scratchpad_path(app_handle: &AppHandle<Wry>) -> PathBuf {
+    let mut path = overrides::app_data_dir(app_handle).expect("Failed to get app data directory");
+    fs::create_dir_all(&path).expect("Failed to create app data directory");
+    path.push("scratchpad.md");
+    path
+}
+
+// Write through a temporary file so a crash mid-write can't truncate the buffer
+fn write_scratchpad(app_handle: &AppHandle<Wry>, content: &str) -> Result<(), String> {
+    let path = get_scratchpad_path(app_handle);
+    let tmp = path.with_extension("md.tmp");
+    fs::write(&tmp, content).map_err(|e| e.to_string())?;
+    fs::rename(&tmp, &path).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_scratchpad(app_handle: AppHandle<Wry>) -> Result<String, String> {
+    let path = get_scratchpad_path(&app_handle);
+    if !path.exists() {
+        write_scratchpad(&app_handle, "")?;
+    }
+    fs::read_to_string(path).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn save_scratchpad(app_handle: AppHandle<Wry>, content: String) -> Result<(), String> {
+    write_scratchpad(&app_handle, &content)
+}
+
+// Turn the scratchpad into a regular note and start over with an empty buffer
+#[tauri::command]
+pub async fn promote_scratchpad_to_note(
+    app_handle: AppHandle<Wry>,
+    title: Option<String>,
+) -> Result<Note, String> {
+    let content = fs::read_to_string(get_scratchpad_path(&app_handle)).unwrap_or_default();
+    if content.trim().is_empty() {
+        return Err("Scratchpad is empty".to_string());
+    }
+
+    // A leading "# Heading" becomes the title unless one is given
+    let heading = content
+        .trim_start()
+        .strip_prefix("# ")
+        .and_then(|rest| rest.lines().next())
+        .map(|line| line.trim().to_string());
+    let title = title
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .or(heading)
+        .unwrap_or_else(|| "Scratchpad".to_string());
+
+    let note = Note {
+        id: new_note_id(),
+        title,
+        content: content.trim_start().to_string(),
+        datetime: chrono::Utc::now().timestamp().to_string(),
+        ..Default::default()
+    };
+
+    persist_note(&app_handle, note.clone()).await?;
+    write_scratchpad(&app_handle, "")?;
+
+    Ok(note)
+}
@@ -0,0 +1,200 @@
+use crate::frontmatter::Frontmatter;
+use crate::{
+    blobs, compression, encryption, get_note_path, merge, persist_note, persist_note_unchecked,
+    read_note, readonly, writable_attachments_dir, Note, NoteMetadata,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use tauri::{AppHandle, Wry};
+
+// Single notes can be locked with a passphrase of their own, apart from encrypting
+// the whole vault. A locked note keeps its frontmatter and title heading, so it is
+// still listed, synced and organised like any other, but its body is replaced by
+// a `locked` code block holding the encrypted original, and each attachment by an
+// encrypted `<name>.locked` file. The key comes from the passphrase with Argon2id;
+// its settings and salt are kept in the note's `locked` field.
+//
+// Listings only show the title of a locked note, and the editor can't save one
+// until it is unlocked. Commands that change only metadata keep the encrypted body
+// as it is. Locking drops the copies sync keeps for merging, which would give the
+// body away.
+
+pub const LOCKED_FIELD: &str = "locked";
+const LOCKED_SUFFIX: &str = ".locked";
+const FENCE_OPEN: &str = "```locked";
+const FENCE_CLOSE: &str = "```";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct LockParams {
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+fn params_of(fields: &Frontmatter) -> Option<LockParams> {
+    serde_json::from_value(fields.get(LOCKED_FIELD)?.clone()).ok()
+}
+
+pub fn is_locked(note: &Note) -> bool {
+    note.fields.contains_key(LOCKED_FIELD)
+}
+
+// What listings show of locked notes: the title and nothing else
+pub fn redact(notes: &mut [Note]) {
+    for note in notes.iter_mut().filter(|note| is_locked(note)) {
+        note.content = format!("# {}\n", note.title);
+        note.attachments.clear();
+    }
+}
+
+pub fn redact_metadata(metadata: &mut NoteMetadata, note: &Note) {
+    if is_locked(note) {
+        metadata.excerpt.clear();
+        metadata.attachments.clear();
+        metadata.cover = None;
+    }
+}
+
+// The body of a note as written below its title heading
+fn body_of(note: &Note) -> &str {
+    match note.content.strip_prefix("# ") {
+        Some(rest) => rest.split_once('\n').map(|(_, body)| body).unwrap_or(""),
+        None => note.content.as_str(),
+    }
+}
+
+fn derive(
+    passphrase: &str,
+    params: &LockParams,
+) -> Result<aes_gcm::Key<aes_gcm::Aes256Gcm>, String> {
+    let salt = BASE64.decode(&params.salt).map_err(|e| e.to_string())?;
+    encryption::derive_key(
+        passphrase,
+        &salt,
+        params.memory_kib,
+        params.iterations,
+        params.parallelism,
+    )
+}
+
+// Encrypt a note's body and attachments with a passphrase
+#[tauri::command]
+pub async fn lock_note(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    passphrase: String,
+) -> Result<(), String> {
+    readonly::ensure_writable(&app_handle, &note_id).map_err(|e| e.to_string())?;
+    let mut note = read_note(&app_handle, &note_id, &get_note_path(&app_handle, &note_id))?;
+    if is_locked(&note) {
+        return Err("The note is already locked".to_string());
+    }
+    if passphrase.chars().count() < encryption::MIN_PASSWORD_LEN {
+        return Err(format!(
+            "The passphrase needs at least {} characters",
+            encryption::MIN_PASSWORD_LEN
+        ));
+    }
+
+    let (kdf, salt) = encryption::new_kdf();
+    let params = LockParams {
+        salt: BASE64.encode(salt),
+        memory_kib: kdf.m_cost(),
+        iterations: kdf.t_cost(),
+        parallelism: kdf.p_cost(),
+    };
+    let key = tokio::task::spawn_blocking({
+        let params = params.clone();
+        move || derive(&passphrase, &params)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    // The settings are saved first: without them the attachments encrypted below
+    // could never be decrypted again
+    let attachments: Vec<String> = note
+        .attachments
+        .iter()
+        .filter(|n| !n.ends_with(LOCKED_SUFFIX))
+        .cloned()
+        .collect();
+    let sealed = encryption::encrypt_with(&key, body_of(&note).as_bytes())?;
+    note.content = format!(
+        "# {}\n\n{}\n{}\n{}\n",
+        note.title,
+        FENCE_OPEN,
+        BASE64.encode(sealed),
+        FENCE_CLOSE
+    );
+    note.fields.insert(
+        LOCKED_FIELD.to_string(),
+        serde_json::to_value(&params).map_err(|e| e.to_string())?,
+    );
+    persist_note(&app_handle, note).await?;
+    merge::forget(&app_handle, &note_id);
+
+    let attachments_dir = writable_attachments_dir(&app_handle, &note_id)?;
+    for name in &attachments {
+        let data = compression::read_attachment(&attachments_dir, name)
+            .map_err(|e| format!("Can't read attachment {}: {}", name, e))?;
+        let sealed = encryption::encrypt_with(&key, &data)?;
+        let locked = attachments_dir.join(format!("{}{}", name, LOCKED_SUFFIX));
+        encryption::write(&app_handle, &locked, sealed)?;
+        blobs::unshare_file(&app_handle, &attachments_dir.join(name));
+        blobs::unshare_file(
+            &app_handle,
+            &attachments_dir.join(format!("{}{}", name, compression::COMPRESSED_SUFFIX)),
+        );
+        compression::remove_attachment(&attachments_dir, name);
+    }
+    Ok(())
+}
+
+// Decrypt a locked note with its passphrase and store it unlocked again
+#[tauri::command]
+pub async fn unlock_note(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    passphrase: String,
+) -> Result<Note, String> {
+    let mut note = read_note(&app_handle, &note_id, &get_note_path(&app_handle, &note_id))?;
+    let params = params_of(&note.fields).ok_or("The note isn't locked")?;
+    let key = tokio::task::spawn_blocking(move || derive(&passphrase, &params))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let encoded = body_of(&note)
+        .trim()
+        .strip_prefix(FENCE_OPEN)
+        .and_then(|rest| rest.strip_suffix(FENCE_CLOSE))
+        .ok_or("The locked note is damaged")?;
+    let sealed = BASE64
+        .decode(encoded.trim())
+        .map_err(|_| "The locked note is damaged".to_string())?;
+    let body = encryption::decrypt_with(&key, &sealed).ok_or("Wrong passphrase")?;
+    let body = String::from_utf8(body).map_err(|e| e.to_string())?;
+
+    let attachments_dir = writable_attachments_dir(&app_handle, &note_id)?;
+    let mut attachments = Vec::new();
+    for name in note
+        .attachments
+        .iter()
+        .filter_map(|n| n.strip_suffix(LOCKED_SUFFIX))
+    {
+        let locked = attachments_dir.join(format!("{}{}", name, LOCKED_SUFFIX));
+        let sealed = encryption::read(&locked).map_err(|e| e.to_string())?;
+        let data = encryption::decrypt_with(&key, &sealed)
+            .ok_or_else(|| format!("Attachment {} failed to decrypt", name))?;
+        compression::write_attachment(&app_handle, &attachments_dir.join(name), &data)?;
+        fs::remove_file(&locked).map_err(|e| e.to_string())?;
+        attachments.push(name.to_string());
+    }
+
+    note.content = format!("# {}\n{}", note.title, body);
+    note.attachments = attachments;
+    note.fields.remove(LOCKED_FIELD);
+    persist_note_unchecked(&app_handle, note.clone()).await?;
+    Ok(note)
+}
@@ -0,0 +1,714 @@
+use crate::events::{self, PeersUpdated};
+use crate::protocol::SyncError;
+use crate::{
+    clock, compression, conditions, delta, encryption, get_sync_path, library, merge, migrate,
+    network, pairing, peers, protocol, render_note_file, timestamps, tls, tombstones, AppState,
+    NoteMetadata, PeerDevice, SyncNotification, SyncRequest, SyncStatus,
+};
+use axum::body::Bytes;
+use axum::extract::rejection::BytesRejection;
+use axum::http::{HeaderMap, StatusCode};
+use local_ip_address::local_ip;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter, Wry};
+use tokio::sync::watch;
+
+// The sync server and mDNS discovery. Nothing here depends on the GUI: where
+// incoming notes go and who hears about sync events are injected through `SyncStore`
+// and `EventSink`, so the same stack runs inside the app and in `--headless-sync`
+// mode (see `headless.rs`). The server only speaks HTTPS, with the device
+// certificate from `tls.rs`.
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// Receives the events the app would send its frontend
+pub trait EventSink: Send + Sync {
+    fn emit(&self, event: &str, payload: Value);
+}
+
+// What happened to a note a peer sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Received {
+    // Deleted here, so the note was dropped
+    Deleted,
+    // Older than the version we have
+    Stale,
+    // Merged into our copy without asking
+    Merged,
+    // Kept aside until the user accepts or rejects it
+    Pending,
+    // Kept aside like `Pending`, but our copy was edited too, so taking it would
+    // lose those edits
+    Conflict,
+    // Saved right away
+    Saved,
+    // Couldn't be stored
+    Failed,
+}
+
+// Where notes coming in from peers are kept
+pub trait SyncStore: Send + Sync {
+    fn receive<'a>(&'a self, request: &'a SyncRequest) -> BoxFuture<'a, Received>;
+
+    // The peer took the version of a note we sent
+    fn confirm_sent(&self, peer_id: &str, note_id: &str);
+
+    // Whether to take requests from `peer_id`, which sent `token` if paired.
+    // Stores that keep no pairings take everyone's.
+    fn is_trusted(&self, _peer_id: &str, _token: Option<&str>) -> bool {
+        true
+    }
+
+    // Whether incoming notes are turned away for now
+    fn is_paused(&self) -> bool {
+        false
+    }
+
+    // Further routes served next to the sync endpoints
+    fn routes(&self) -> axum::Router {
+        axum::Router::new()
+    }
+}
+
+#[derive(Clone)]
+pub struct SyncHost {
+    pub state: Arc<Mutex<AppState>>,
+    pub store: Arc<dyn SyncStore>,
+    pub events: Arc<dyn EventSink>,
+    pub cert: tls::DeviceCert,
+}
+
+impl EventSink for AppHandle<Wry> {
+    fn emit(&self, event: &str, payload: Value) {
+        if let Err(e) = Emitter::emit(self, event, payload) {
+            println!("Failed to emit {} event: {}", event, e);
+        }
+    }
+}
+
+impl SyncStore for AppHandle<Wry> {
+    fn receive<'a>(&'a self, request: &'a SyncRequest) -> BoxFuture<'a, Received> {
+        Box::pin(async move {
+            // Deletions on the peer win over our copies, and ours over the note it sent
+            if let Err(e) = tombstones::apply(self, &request.tombstones) {
+                println!("Failed to apply tombstones: {}", e);
+            }
+            if tombstones::is_deleted(self, &request.note.id) {
+                println!("Dropping deleted note {}", request.note.id);
+                return Received::Deleted;
+            }
+
+            // Versions we already have are dropped by their clocks, not by file times
+            clock::observe(self, &request.note);
+            if clock::is_stale(self, &request.peer_id, &request.note) {
+                println!("Dropping stale version of {}", request.note.id);
+                return Received::Stale;
+            }
+
+            // Edits to different parts of a note changed on both sides are merged without asking
+            if let Some(merged) = merge::merge_incoming(self, &request.peer_id, &request.note) {
+                match merge::apply_merge(self, request, merged).await {
+                    Ok(()) => return Received::Merged,
+                    Err(e) => println!("Automatic merge failed: {}", e),
+                }
+            }
+
+            // Our copy was edited too, so the prompt offers keeping both
+            let conflict = merge::is_conflict(self, &request.peer_id, &request.note);
+
+            // The note and its attachments wait apart from ours until the user
+            // answers, keeping the dates the peer has for it. A note that can't be
+            // stored isn't asked about.
+            let note = &request.note;
+            let staged = match merge::incoming_attachments_dir(self, &note.id) {
+                Ok(dir) => dir,
+                Err(e) => {
+                    println!("Failed to set aside incoming attachments: {}", e);
+                    return Received::Failed;
+                }
+            };
+            let mut dated = note.clone();
+            timestamps::adopt(&mut dated);
+            let sync_path = get_sync_path(self, &note.id);
+            if let Err(e) = encryption::write(self, &sync_path, render_note_file(&dated)) {
+                println!("Failed to write sync file {:?}: {}", sync_path, e);
+                return Received::Failed;
+            }
+
+            for (file_name, file_data) in &request.attachments_data {
+                let attachment_path = staged.join(file_name);
+                if let Err(e) = compression::write_attachment(self, &attachment_path, file_data) {
+                    println!("Failed to write attachment {:?}: {}", attachment_path, e);
+                }
+            }
+
+            // Attachments the peer knew we have stayed behind; the set-aside
+            // version needs its own copy of them
+            if conflict {
+                stage_kept_attachments(self, request);
+                Received::Conflict
+            } else {
+                Received::Pending
+            }
+        })
+    }
+
+    fn confirm_sent(&self, peer_id: &str, note_id: &str) {
+        merge::confirm_sent(self, peer_id, note_id);
+    }
+
+    fn is_trusted(&self, peer_id: &str, token: Option<&str>) -> bool {
+        pairing::is_trusted(self, peer_id, token)
+    }
+
+    fn is_paused(&self) -> bool {
+        conditions::is_sync_paused(self)
+    }
+
+    // Receiving a whole vault from another device, pairing with one, syncing the
+    // library with it and telling it what we already have
+    fn routes(&self) -> axum::Router {
+        migrate::routes(self.clone())
+            .merge(pairing::routes(self.clone()))
+            .merge(library::routes(self.clone()))
+            .merge(delta::routes(self.clone()))
+    }
+}
+
+// Copy attachments of a conflicting note that weren't sent, because we already
+// had them, next to the ones that were
+fn stage_kept_attachments(app_handle: &AppHandle<Wry>, request: &SyncRequest) {
+    let note = &request.note;
+    let staged = match merge::incoming_attachments_dir(app_handle, &note.id) {
+        Ok(dir) => dir,
+        Err(e) => {
+            println!("Failed to set aside incoming attachments: {}", e);
+            return;
+        }
+    };
+    for name in &note.attachments {
+        if request.attachments_data.contains_key(name) {
+            continue;
+        }
+        let copied = compression::read_note_attachment(app_handle, &note.id, name)
+            .map_err(|e| e.to_string())
+            .and_then(|data| compression::write_attachment(app_handle, &staged.join(name), &data));
+        if let Err(e) = copied {
+            println!("Failed to set aside attachment {}: {}", name, e);
+        }
+    }
+}
+
+// Send an event with a typed payload
+fn notify(host: &SyncHost, event: &str, payload: &impl Serialize) {
+    match serde_json::to_value(payload) {
+        Ok(payload) => host.events.emit(event, payload),
+        Err(e) => println!("Failed to serialize {} event: {}", event, e),
+    }
+}
+
+// Resolves once `stop` turns true
+async fn stopped(mut stop: watch::Receiver<bool>) {
+    if stop.wait_for(|stop| *stop).await.is_err() {
+        // Nothing can stop us anymore
+        std::future::pending::<()>().await;
+    }
+}
+
+// Status and body of an endpoint's reply
+type Reply = (StatusCode, Value);
+
+// Request body as received, read with `protocol::read_body`
+type RawBody = Result<Bytes, BytesRejection>;
+
+fn failure(status: StatusCode, error: SyncError) -> Reply {
+    println!("Sync request failed: {}", error);
+    (status, error.body())
+}
+
+// Reply to a sync request
+fn reply(received: Received) -> Reply {
+    let mut reply = json!({
+        "success": true,
+        "protocol": protocol::PROTOCOL_VERSION
+    });
+    match received {
+        Received::Deleted => reply["deleted"] = json!(true),
+        Received::Stale => reply["stale"] = json!(true),
+        Received::Merged => reply["merged"] = json!(true),
+        Received::Conflict => reply["conflict"] = json!(true),
+        Received::Failed => {
+            return failure(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                SyncError::new("storage_failed", "Failed to store the note", true),
+            );
+        }
+        Received::Pending | Received::Saved => {}
+    }
+    (StatusCode::OK, reply)
+}
+
+fn not_paired(peer_id: &str) -> Reply {
+    failure(
+        StatusCode::FORBIDDEN,
+        SyncError::new(
+            "not_paired",
+            format!("{} isn't paired with this device", peer_id),
+            false,
+        ),
+    )
+}
+
+async fn handle_request(host: &SyncHost, token: Option<&str>, body: Value) -> Reply {
+    // Requests from older and newer builds are read too
+    let sync_request = match protocol::read_request(body) {
+        Ok(request) => request,
+        Err(e) => {
+            return failure(
+                StatusCode::BAD_REQUEST,
+                SyncError::new("unreadable_request", e, false),
+            );
+        }
+    };
+    println!("Received sync request from peer: {}", sync_request.peer_id);
+
+    // Nothing from a malformed request is written
+    if let Err(invalid) = protocol::validate_request(&sync_request) {
+        return failure(StatusCode::BAD_REQUEST, invalid);
+    }
+    if !host.store.is_trusted(&sync_request.peer_id, token) {
+        return not_paired(&sync_request.peer_id);
+    }
+    if host.store.is_paused() {
+        return failure(StatusCode::SERVICE_UNAVAILABLE, SyncError::paused());
+    }
+
+    store_request(host, &sync_request).await
+}
+
+async fn store_request(host: &SyncHost, sync_request: &SyncRequest) -> Reply {
+    let received = host.store.receive(sync_request).await;
+    match received {
+        Received::Deleted | Received::Stale | Received::Merged | Received::Failed => {
+            return reply(received)
+        }
+        Received::Saved => {
+            // Stores that save right away don't tell new notes from updated ones
+            let metadata = NoteMetadata::from(&sync_request.note);
+            notify(host, events::NOTE_UPDATED, &metadata);
+            return reply(received);
+        }
+        Received::Pending | Received::Conflict => {}
+    }
+
+    let notification = {
+        let mut guard = match host.state.lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                return failure(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    SyncError::new("internal", "Failed to lock app state", true),
+                );
+            }
+        };
+
+        // When sharing notes, we don't require the peer to be in the peers list
+        // Instead, we'll use the peer_id from the sync request
+        let peer = match guard.peers.get(&sync_request.peer_id) {
+            Some(p) => {
+                println!("Found peer in peers list: {}", p.name);
+                p.clone()
+            }
+            None => {
+                println!("Peer not in peers list, creating temporary peer entry");
+                PeerDevice {
+                    id: sync_request.peer_id.clone(),
+                    name: sync_request.peer_name.clone(), // Use the name from the request
+                    ip: IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
+                    port: 0, // We don't know the port
+                    addresses: Vec::new(),
+                    protocol: sync_request.protocol,
+                    fingerprint: None,
+                }
+            }
+        };
+
+        let notification = SyncNotification {
+            id: uuid::Uuid::new_v4().to_string(),
+            from_peer: peer,
+            note_title: sync_request.note.title.clone(),
+            status: SyncStatus::Pending,
+            note_id: sync_request.note.id.clone(),
+            conflict: received == Received::Conflict,
+        };
+        println!(
+            "Creating notification: {} for note: {}",
+            notification.id, notification.note_title
+        );
+
+        guard.sync_notifications.push(notification.clone());
+        println!(
+            "Current notifications count: {}",
+            guard.sync_notifications.len()
+        );
+        notification
+    };
+
+    // Notify the frontend
+    println!("Emitting sync-notification event to frontend");
+    notify(host, events::SYNC_NOTIFICATION, &notification);
+    reply(received)
+}
+
+fn handle_response(host: &SyncHost, token: Option<&str>, response: Value) -> Reply {
+    let peer_id = response["peer_id"].as_str().unwrap_or("");
+    if !host.store.is_trusted(peer_id, token) {
+        return not_paired(peer_id);
+    }
+    let notification_id = response["notification_id"].as_str().unwrap_or("");
+    let Some(accepted) = response["accepted"].as_bool() else {
+        return failure(
+            StatusCode::BAD_REQUEST,
+            SyncError::new("invalid_response", "accepted is missing", false),
+        );
+    };
+
+    // What we sent is now the version both devices share
+    if let (true, Some(note_id)) = (accepted, response["note_id"].as_str()) {
+        if let Err(invalid) = protocol::check_id("note_id", note_id) {
+            return failure(StatusCode::BAD_REQUEST, invalid);
+        }
+        host.store.confirm_sent(peer_id, note_id);
+    }
+
+    // Notify the frontend
+    host.events.emit(
+        "sync-response",
+        json!({
+            "notification_id": notification_id,
+            "accepted": accepted,
+        }),
+    );
+
+    (StatusCode::OK, json!({ "success": true }))
+}
+
+// Bind the first free port on one of `listen_ips`, or anywhere usable when empty.
+// Returns the listener and the address to advertise.
+async fn bind(
+    config: &network::ServerConfig,
+    listen_ips: &[IpAddr],
+) -> Option<(tokio::net::TcpListener, IpAddr, u16)> {
+    for port in network::ports(config) {
+        if !listen_ips.is_empty() {
+            // A single address is bound directly; several need all interfaces
+            let ip = if listen_ips.len() == 1 {
+                listen_ips[0]
+            } else {
+                IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED)
+            };
+            if let Ok(listener) = tokio::net::TcpListener::bind(SocketAddr::new(ip, port)).await {
+                return Some((listener, listen_ips[0], port));
+            }
+            continue;
+        }
+
+        // Try to get the local IP address - with fallback options for macOS
+        if let Ok(local_ip) = local_ip() {
+            let addr = SocketAddr::new(local_ip, port);
+            if let Ok(listener) = tokio::net::TcpListener::bind(addr).await {
+                return Some((listener, local_ip, port));
+            }
+        }
+
+        // If local_ip failed or couldn't bind, try with IPv4 loopback first
+        let loopback = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+        if let Ok(listener) = tokio::net::TcpListener::bind(SocketAddr::new(loopback, port)).await {
+            return Some((listener, loopback, port));
+        }
+
+        // Try with 0.0.0.0 (bind to all interfaces)
+        let addr = SocketAddr::new(IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED), port);
+        if let Ok(listener) = tokio::net::TcpListener::bind(addr).await {
+            // Use 127.0.0.1 for services even though bound to 0.0.0.0
+            return Some((listener, loopback, port));
+        }
+    }
+    None
+}
+
+// Run the sync server and mDNS registration until the mDNS browser stops, which
+// happens when `stop` turns true
+pub async fn run(
+    host: SyncHost,
+    config: network::ServerConfig,
+    listen_ips: Vec<IpAddr>,
+    stop: watch::Receiver<bool>,
+) {
+    let Some((listener, bound_ip, bound_port)) = bind(&config, &listen_ips).await else {
+        println!("Failed to bind to any port");
+        return;
+    };
+    let tls_config = match host.cert.server_config() {
+        Ok(config) => axum_server::tls_rustls::RustlsConfig::from_config(config),
+        Err(e) => {
+            println!("Failed to set up TLS: {}", e);
+            return;
+        }
+    };
+    let listener = match listener.into_std() {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Failed to hand the listener to the server: {}", e);
+            return;
+        }
+    };
+    println!("HTTPS server listening on {}:{}", bound_ip, bound_port);
+
+    // Clone the device ID and name for mDNS
+    let (device_id, device_name) = {
+        let mut guard = match host.state.lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                println!("Failed to lock app state");
+                return;
+            }
+        };
+        // The name may have been changed in settings since the last start
+        guard.device_name = config.device_name.clone();
+        (guard.device_id.clone(), guard.device_name.clone())
+    };
+
+    let info = peers::PeerInfo {
+        id: device_id.clone(),
+        name: device_name.clone(),
+        protocol: protocol::PROTOCOL_VERSION,
+    };
+    let request_host = host.clone();
+    let response_host = host.clone();
+    let routes = host.store.routes();
+    let server_stop = stop.clone();
+
+    tokio::spawn(async move {
+        // Set up the HTTP server using axum with increased limits
+        let router = axum::Router::new()
+            // Lets static peers check that we're up and learn who we are
+            .route(
+                "/sync/info",
+                axum::routing::get(move || async move { axum::Json(info) }),
+            )
+            .route(
+                "/sync/request",
+                axum::routing::post(move |headers: HeaderMap, body: RawBody| {
+                    let host = request_host.clone();
+                    async move {
+                        let token = pairing::bearer_token(&headers);
+                        let (status, body) = match protocol::read_body(body) {
+                            Ok(body) => handle_request(&host, token, body).await,
+                            Err((status, error)) => failure(status, error),
+                        };
+                        (status, axum::Json(body))
+                    }
+                }),
+            )
+            .route(
+                "/sync/response",
+                axum::routing::post(move |headers: HeaderMap, body: RawBody| {
+                    let host = response_host.clone();
+                    async move {
+                        let token = pairing::bearer_token(&headers);
+                        let (status, body) = match protocol::read_body(body) {
+                            Ok(body) => handle_response(&host, token, body),
+                            Err((status, error)) => failure(status, error),
+                        };
+                        (status, axum::Json(body))
+                    }
+                }),
+            )
+            .merge(routes);
+
+        // Configure the router with proper limits for large attachments
+        let app = router.layer(
+            tower::ServiceBuilder::new()
+                .layer(axum::extract::DefaultBodyLimit::max(50 * 1024 * 1024)), // 50 MB limit
+        );
+
+        let handle = axum_server::Handle::new();
+        let shutdown = handle.clone();
+        tokio::spawn(async move {
+            stopped(server_stop).await;
+            shutdown.graceful_shutdown(None);
+        });
+
+        if let Err(e) = axum_server::from_tcp_rustls(listener, tls_config)
+            .handle(handle)
+            .serve(app.into_make_service())
+            .await
+        {
+            println!("HTTP server error: {}", e);
+        }
+    });
+
+    // Try to set up mDNS service with the bound port
+    let mdns = match ServiceDaemon::new() {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            println!("Failed to create mDNS daemon: {}", e);
+            return;
+        }
+    };
+
+    // Shutting the daemon down ends the browse loop below
+    {
+        let daemon = mdns.clone();
+        tokio::spawn(async move {
+            stopped(stop).await;
+            let _ = daemon.shutdown();
+        });
+    }
+
+    // Advertise every address we listen on, or else the one we bound to
+    let advertised_ips = if listen_ips.is_empty() {
+        vec![bound_ip]
+    } else {
+        listen_ips
+    };
+
+    // Convert IPs to IPv4 for mDNS
+    let ipv4_addrs: Vec<String> = advertised_ips
+        .iter()
+        .filter(|ip| ip.is_ipv4())
+        .map(|ip| ip.to_string())
+        .collect();
+    if ipv4_addrs.is_empty() {
+        println!("IPv6 not supported for mDNS");
+        return;
+    }
+
+    // Create service info
+    let service_type = "_notes-sync._tcp.local.";
+    let instance_name = format!("{}_{}", device_name, device_id);
+
+    let properties = HashMap::from([
+        ("id".into(), device_id.clone().into()),
+        ("name".into(), device_name.clone().into()),
+        ("protocol".into(), protocol::PROTOCOL_VERSION.to_string()),
+        ("fp".into(), host.cert.fingerprint.clone()),
+    ]);
+
+    let service_info = match ServiceInfo::new(
+        service_type,
+        &instance_name,
+        "local.", // Use a fixed domain name instead of hostname-based one
+        ipv4_addrs.join(",").as_str(),
+        bound_port,
+        Some(properties),
+    ) {
+        Ok(info) => info,
+        Err(e) => {
+            println!("Failed to create mDNS service info: {}", e);
+            return;
+        }
+    };
+
+    // Register service
+    if let Err(e) = mdns.register(service_info) {
+        println!("Failed to register mDNS service: {}", e);
+        return;
+    }
+
+    println!("mDNS service registered successfully");
+
+    // Browse for other services
+    let browser = match mdns.browse(service_type) {
+        Ok(browser) => browser,
+        Err(e) => {
+            println!("Failed to browse mDNS: {}", e);
+            return;
+        }
+    };
+
+    // Handle mDNS events
+    loop {
+        match browser.recv() {
+            Ok(ServiceEvent::ServiceResolved(info)) => {
+                // Skip our own service
+                if let Some(peer_id) = info.get_property("id").and_then(|id| id.to_string().into())
+                {
+                    if peer_id == device_id {
+                        continue;
+                    }
+
+                    let peer_name = info
+                        .get_property("name")
+                        .and_then(|name| name.to_string().into())
+                        .unwrap_or_else(|| "Unknown".to_string());
+
+                    // Get IP addresses, most likely reachable first
+                    let addresses = network::order_peer_addresses(
+                        info.get_addresses()
+                            .iter()
+                            .map(|addr| IpAddr::V4(*addr))
+                            .collect(),
+                    );
+                    if let Some(addr) = addresses.first() {
+                        let peer = PeerDevice {
+                            id: peer_id.clone(),
+                            name: peer_name,
+                            ip: *addr,
+                            port: info.get_port(),
+                            addresses: addresses.clone(),
+                            protocol: protocol::parse_version(
+                                info.get_property_val_str("protocol"),
+                            ),
+                            fingerprint: info.get_property_val_str("fp").map(str::to_string),
+                        };
+
+                        // Add the peer
+                        if let Ok(mut state) = host.state.lock() {
+                            state.peers.insert(peer_id, peer.clone());
+                        }
+
+                        // Notify frontend - outside of lock scope
+                        let update = PeersUpdated {
+                            updated: vec![peer],
+                            removed: Vec::new(),
+                        };
+                        notify(&host, events::PEERS_UPDATED, &update);
+                    }
+                }
+            }
+            Ok(ServiceEvent::ServiceRemoved(_service_type, instance_name)) => {
+                // Extract the ID from the instance name
+                if let Some(peer_id) = instance_name.split('_').last() {
+                    // Remove the peer
+                    let removed = match host.state.lock() {
+                        Ok(mut state) => state.peers.remove(peer_id).is_some(),
+                        Err(_) => false,
+                    };
+
+                    // Notify frontend if needed - outside of lock scope
+                    if removed {
+                        let update = PeersUpdated {
+                            updated: Vec::new(),
+                            removed: vec![peer_id.to_string()],
+                        };
+                        notify(&host, events::PEERS_UPDATED, &update);
+                    }
+                }
+            }
+            Ok(_) => { /* Ignore other events */ }
+            Err(e) => {
+                println!("Error receiving mDNS event: {:?}", e);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crate::load_notes;
+use crate::overrides;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use tauri::{AppHandle, Wry};
+
+// What the app looked like when it was last closed, restored on the next launch
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct WindowLayout {
+    pub label: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+    // Notes open in this window, in tab order
+    pub open_notes: Vec<String>,
+    pub active_note: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Session {
+    pub windows: Vec<WindowLayout>,
+    // Editor scroll offset by note id
+    pub scroll_positions: HashMap<String, f64>,
+    pub selected_folder: Option<String>,
+}
+
+fn get_session_path(app_handle: &AppHandle<Wry>) -> PathBuf {
+    let mut path = overrides::app_data_dir(app_handle).expect("Failed to get app data directory");
+    fs::create_dir_all(&path).expect("Failed to create app data directory");
+    path.push("session.json");
+    path
+}
+
+#[tauri::command]
+pub async fn get_session(app_handle: AppHandle<Wry>) -> Result<Session, String> {
+    let mut session: Session = match fs::read_to_string(get_session_path(&app_handle)) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            println!("Failed to parse session, starting fresh: {}", e);
+            Session::default()
+        }),
+        Err(_) => return Ok(Session::default()),
+    };
+
+    // Forget notes that were deleted since the session was saved
+    let ids: Vec<String> = load_notes(&app_handle)?.into_iter().map(|n| n.id).collect();
+    for window in &mut session.windows {
+        window.open_notes.retain(|id| ids.contains(id));
+        if window
+            .active_note
+            .as_ref()
+            .is_some_and(|id| !ids.contains(id))
+        {
+            window.active_note = window.open_notes.first().cloned();
+        }
+    }
+    session.scroll_positions.retain(|id, _| ids.contains(id));
+
+    Ok(session)
+}
+
+#[tauri::command]
+pub async fn save_session(app_handle: AppHandle<Wry>, session: Session) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(&session).map_err(|e| e.to_string())?;
+    fs::write(get_session_path(&app_handle), content).map_err(|e| e.to_string())
+}
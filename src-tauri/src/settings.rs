@@ -0,0 +1,245 @@
+use crate::conditions::SyncConditions;
+use crate::embeddings::EmbeddingConfig;
+use crate::events;
+use crate::fields::FieldDefinition;
+use crate::get_notes_dir;
+use crate::lock;
+use crate::ordering::SortMode;
+use crate::overrides;
+use crate::peers::StaticPeer;
+use crate::pins::PinSection;
+use crate::proxy::{self, ProxyConfig};
+use crate::storage::StoragePolicy;
+use crate::summarize::SummaryConfig;
+use crate::tags::TagMeta;
+use crate::translate::TranslationConfig;
+use crate::webhooks::WebhookConfig;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager, Wry};
+use tokio::sync::watch;
+
+// User-configurable settings come in two parts. Vault settings describe the notes
+// and live in `notes/.settings.json`, so they move along with the vault. Device
+// settings describe this machine's network, services and plugins and stay in the
+// app data directory.
+//
+// Saved settings take effect right away: a `settings-changed` event goes to the
+// frontend, and background tasks (networking, static peers) subscribe to a channel
+// that wakes them to pick up the new values.
+
+const VAULT_SETTINGS_FILE: &str = ".settings.json";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct VaultSettings {
+    // Typed metadata fields that notes may carry in their frontmatter
+    pub field_definitions: Vec<FieldDefinition>,
+    // Re-align every markdown table in a note when it is saved
+    pub format_tables_on_save: bool,
+    // Tidy markdown formatting when a note is saved, unless the note sets `normalize: false`
+    pub normalize_markdown_on_save: bool,
+    // When to move untouched notes into compressed cold storage
+    pub storage_policy: StoragePolicy,
+    // How `get_notes` orders notes; `manual` follows the order set with `set_note_order`
+    pub sort_mode: SortMode,
+    // Pinned notes, grouped into named sections in the order shown in the sidebar
+    pub pinned: Vec<PinSection>,
+    // Color, description and icon of tags, by full tag path
+    pub tag_meta: HashMap<String, TagMeta>,
+    // Store new attachments of compressible types zstd-compressed
+    pub compress_attachments: bool,
+    // Days a deleted note's tombstone is kept and synced to peers; 90 when unset
+    pub tombstone_retention_days: Option<u32>,
+    // Notebooks created in the app, kept even while no note is filed in them
+    pub notebooks: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct DeviceSettings {
+    pub webhooks: Vec<WebhookConfig>,
+    // Names of plugins from the plugins directory that are allowed to run
+    pub enabled_plugins: Vec<String>,
+    // Fetch remote images referenced by pasted HTML instead of leaving web links
+    pub paste_download_remote_images: bool,
+    // Whether bulk sync and background work pause on metered networks and low battery
+    pub sync_conditions: SyncConditions,
+    // Proxy for outbound HTTP requests, optionally overridden per feature
+    // (`sync`, `webhooks`, `paste`, `link_check`, `embeddings`, `summarize`,
+    // `translate`)
+    pub proxy: ProxyConfig,
+    pub proxy_overrides: HashMap<String, ProxyConfig>,
+    // Network interfaces (by name) to listen on and advertise to peers; empty picks automatically
+    pub network_interfaces: Vec<String>,
+    // Peers reached by hostname instead of mDNS, e.g. over Tailscale
+    pub static_peers: Vec<StaticPeer>,
+    // Local embedding server used by semantic search; off unless enabled
+    pub embeddings: EmbeddingConfig,
+    // Language model that writes note summaries; nothing is sent unless enabled
+    pub summarizer: SummaryConfig,
+    // Service used by `translate_note`; off unless enabled
+    pub translator: TranslationConfig,
+    // Name shown to peers; the hostname when unset
+    pub device_name: Option<String>,
+    // First port the sync server tries, moving up while ports are taken; 8000 when unset
+    pub sync_port: Option<u16>,
+    // Where notes are kept; `notes` in the app data directory when unset
+    pub notes_dir: Option<PathBuf>,
+    // Seconds between health checks of static peers; 60 when unset
+    pub static_peer_interval: Option<u64>,
+    // Take notes from devices that haven't paired with this one
+    pub allow_unpaired_sync: bool,
+}
+
+// Device settings in effect, kept in memory since path helpers read them constantly
+pub struct LiveSettings {
+    device: Mutex<Option<DeviceSettings>>,
+    changed: watch::Sender<()>,
+}
+
+impl Default for LiveSettings {
+    fn default() -> Self {
+        LiveSettings {
+            device: Mutex::new(None),
+            changed: watch::channel(()).0,
+        }
+    }
+}
+
+fn get_device_settings_path(app_handle: &AppHandle<Wry>) -> PathBuf {
+    let mut path = overrides::app_data_dir(app_handle).expect("Failed to get app data directory");
+    fs::create_dir_all(&path).expect("Failed to create app data directory");
+    path.push("settings.json");
+    path
+}
+
+// Device settings kept in `data_dir`, for running without the app
+pub fn device_settings_in(data_dir: &Path) -> DeviceSettings {
+    read_settings(&data_dir.join("settings.json"))
+}
+
+fn get_vault_settings_path(app_handle: &AppHandle<Wry>) -> PathBuf {
+    get_notes_dir(app_handle).join(VAULT_SETTINGS_FILE)
+}
+
+fn read_settings<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            println!("Failed to parse {:?}, using defaults: {}", path, e);
+            T::default()
+        }),
+        // A missing file just means nothing has been configured yet
+        Err(_) => T::default(),
+    }
+}
+
+fn write_settings(path: &Path, settings: &impl Serialize) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+    fs::write(path, content).map_err(|e| e.to_string())
+}
+
+// Vault settings used to be kept in the device's settings file. Copy them out
+// before the device file is rewritten without them.
+fn split_legacy_settings(app_handle: &AppHandle<Wry>) {
+    let vault_path = get_vault_settings_path(app_handle);
+    let device_path = get_device_settings_path(app_handle);
+    if vault_path.exists() || !device_path.exists() {
+        return;
+    }
+    let vault: VaultSettings = read_settings(&device_path);
+    if let Err(e) = write_settings(&vault_path, &vault) {
+        println!("Failed to move vault settings into the vault: {}", e);
+    }
+}
+
+pub fn load_vault_settings(app_handle: &AppHandle<Wry>) -> VaultSettings {
+    split_legacy_settings(app_handle);
+    read_settings(&get_vault_settings_path(app_handle))
+}
+
+pub fn save_vault_settings(
+    app_handle: &AppHandle<Wry>,
+    settings: &VaultSettings,
+) -> Result<(), String> {
+    write_settings(&get_vault_settings_path(app_handle), settings)?;
+    notify_changed(app_handle);
+    Ok(())
+}
+
+pub fn load_device_settings(app_handle: &AppHandle<Wry>) -> DeviceSettings {
+    let live = app_handle.state::<LiveSettings>();
+    let Ok(mut device) = live.device.lock() else {
+        return read_settings(&get_device_settings_path(app_handle));
+    };
+    device
+        .get_or_insert_with(|| read_settings(&get_device_settings_path(app_handle)))
+        .clone()
+}
+
+pub fn save_device_settings(
+    app_handle: &AppHandle<Wry>,
+    settings: &DeviceSettings,
+) -> Result<(), String> {
+    split_legacy_settings(app_handle);
+    write_settings(&get_device_settings_path(app_handle), settings)?;
+    reload_device_settings(app_handle);
+    Ok(())
+}
+
+// Pick up a settings file that was replaced on disk
+pub fn reload_device_settings(app_handle: &AppHandle<Wry>) {
+    if let Ok(mut device) = app_handle.state::<LiveSettings>().device.lock() {
+        *device = None;
+    }
+    notify_changed(app_handle);
+}
+
+// Wakes when any settings are saved
+pub fn subscribe(app_handle: &AppHandle<Wry>) -> watch::Receiver<()> {
+    app_handle.state::<LiveSettings>().changed.subscribe()
+}
+
+fn notify_changed(app_handle: &AppHandle<Wry>) {
+    app_handle.state::<LiveSettings>().changed.send_replace(());
+    let _ = app_handle.emit("settings-changed", ());
+}
+
+#[tauri::command]
+pub async fn get_vault_settings(app_handle: AppHandle<Wry>) -> Result<VaultSettings, String> {
+    Ok(load_vault_settings(&app_handle))
+}
+
+#[tauri::command]
+pub async fn update_vault_settings(
+    app_handle: AppHandle<Wry>,
+    settings: VaultSettings,
+) -> Result<(), String> {
+    save_vault_settings(&app_handle, &settings)
+}
+
+#[tauri::command]
+pub async fn get_device_settings(app_handle: AppHandle<Wry>) -> Result<DeviceSettings, String> {
+    Ok(load_device_settings(&app_handle))
+}
+
+#[tauri::command]
+pub async fn update_device_settings(
+    app_handle: AppHandle<Wry>,
+    settings: DeviceSettings,
+) -> Result<(), String> {
+    proxy::validate(&settings)?;
+    let previous = load_device_settings(&app_handle);
+    save_device_settings(&app_handle, &settings)?;
+
+    // Another notes directory means another set of notes
+    if previous.notes_dir != settings.notes_dir {
+        lock::lock_notes_dir(&app_handle);
+        events::notes_reloaded(&app_handle)?;
+    }
+    Ok(())
+}
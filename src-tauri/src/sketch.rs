@@ -0,0 +1,283 @@
+use crate::{compression, writable_attachments_dir};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::path::Path;
+use tauri::{AppHandle, Wry};
+
+// Hand-drawn sketches are stored as SVG attachments that any viewer can show, with
+// a PNG preview next to them for places that only handle raster images. The stroke
+// data is embedded in the SVG so the sketch can be opened again for editing.
+
+const SKETCH_SUFFIX: &str = "_sketch.svg";
+const STROKES_START: &str = "<metadata id=\"strokes\"><![CDATA[";
+const STROKES_END: &str = "]]></metadata>";
+// Longest side of the PNG preview
+const PREVIEW_SIZE: f32 = 800.0;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+    // Stylus pressure from 0 to 1, when the device reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Stroke {
+    // `#rrggbb`
+    pub color: String,
+    pub width: f32,
+    pub points: Vec<Point>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Sketch {
+    pub width: f32,
+    pub height: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    pub strokes: Vec<Stroke>,
+}
+
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn validate(sketch: &Sketch) -> Result<(), String> {
+    let valid_size = |v: f32| v.is_finite() && v > 0.0 && v <= 10000.0;
+    if !valid_size(sketch.width) || !valid_size(sketch.height) {
+        return Err("Sketch size is out of range".to_string());
+    }
+    let colors = sketch
+        .strokes
+        .iter()
+        .map(|s| s.color.as_str())
+        .chain(sketch.background.as_deref());
+    for color in colors {
+        if parse_color(color).is_none() {
+            return Err(format!("Not a color: {:?}", color));
+        }
+    }
+    for stroke in &sketch.strokes {
+        if !stroke.width.is_finite() || stroke.width <= 0.0 || stroke.width > 500.0 {
+            return Err("Stroke width is out of range".to_string());
+        }
+        if stroke
+            .points
+            .iter()
+            .any(|p| !p.x.is_finite() || !p.y.is_finite())
+        {
+            return Err("Stroke has an invalid point".to_string());
+        }
+    }
+    Ok(())
+}
+
+// SVG paths have a single width, so pressure scales each stroke by its average
+fn stroke_width(stroke: &Stroke) -> f32 {
+    let pressures: Vec<f32> = stroke
+        .points
+        .iter()
+        .filter_map(|p| p.pressure)
+        .map(|p| p.clamp(0.1, 1.0))
+        .collect();
+    if pressures.is_empty() {
+        stroke.width
+    } else {
+        stroke.width * pressures.iter().sum::<f32>() / pressures.len() as f32
+    }
+}
+
+fn render_svg(sketch: &Sketch) -> Result<String, String> {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+        w = sketch.width,
+        h = sketch.height
+    );
+    if let Some(background) = &sketch.background {
+        let _ = writeln!(
+            svg,
+            "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>",
+            background
+        );
+    }
+    for stroke in &sketch.strokes {
+        let Some((first, rest)) = stroke.points.split_first() else {
+            continue;
+        };
+        let mut path = format!("M{} {}", first.x, first.y);
+        // A single tap still leaves a dot
+        if rest.is_empty() {
+            path.push_str(" l0 0");
+        }
+        for point in rest {
+            let _ = write!(path, " L{} {}", point.x, point.y);
+        }
+        let _ = writeln!(
+            svg,
+            "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" stroke-linecap=\"round\" stroke-linejoin=\"round\"/>",
+            path,
+            stroke.color,
+            stroke_width(stroke)
+        );
+    }
+
+    // Colors are validated and the rest are numbers, so the JSON can't close the CDATA
+    let strokes = serde_json::to_string(sketch).map_err(|e| e.to_string())?;
+    let _ = writeln!(svg, "{}{}{}", STROKES_START, strokes, STROKES_END);
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+// Stamp filled circles along a segment
+fn draw_segment(
+    image: &mut RgbaImage,
+    from: (f32, f32),
+    to: (f32, f32),
+    radius: f32,
+    color: Rgba<u8>,
+) {
+    let length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt();
+    let steps = (length / (radius / 2.0).max(0.5)).ceil().max(1.0) as usize;
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        let (cx, cy) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+        let (x0, x1) = (
+            (cx - radius).floor().max(0.0) as u32,
+            (cx + radius).ceil() as u32,
+        );
+        let (y0, y1) = (
+            (cy - radius).floor().max(0.0) as u32,
+            (cy + radius).ceil() as u32,
+        );
+        for y in y0..=y1.min(image.height().saturating_sub(1)) {
+            for x in x0..=x1.min(image.width().saturating_sub(1)) {
+                if (x as f32 - cx).powi(2) + (y as f32 - cy).powi(2) <= radius * radius {
+                    image.put_pixel(x, y, color);
+                }
+            }
+        }
+    }
+}
+
+fn render_preview(sketch: &Sketch, dest: &Path) -> Result<(), String> {
+    let scale = (PREVIEW_SIZE / sketch.width.max(sketch.height)).min(1.0);
+    let width = ((sketch.width * scale).round() as u32).max(1);
+    let height = ((sketch.height * scale).round() as u32).max(1);
+
+    let background = match sketch.background.as_deref().and_then(parse_color) {
+        Some([r, g, b]) => Rgba([r, g, b, 255]),
+        None => Rgba([0, 0, 0, 0]),
+    };
+    let mut image = RgbaImage::from_pixel(width, height, background);
+
+    for stroke in &sketch.strokes {
+        let [r, g, b] = parse_color(&stroke.color).unwrap_or([0, 0, 0]);
+        let color = Rgba([r, g, b, 255]);
+        let points: Vec<(f32, f32, f32)> = stroke
+            .points
+            .iter()
+            .map(|p| {
+                let width = stroke.width * p.pressure.map_or(1.0, |p| p.clamp(0.1, 1.0));
+                (p.x * scale, p.y * scale, (width * scale / 2.0).max(0.5))
+            })
+            .collect();
+        if let [only] = points.as_slice() {
+            draw_segment(
+                &mut image,
+                (only.0, only.1),
+                (only.0, only.1),
+                only.2,
+                color,
+            );
+        }
+        for pair in points.windows(2) {
+            let radius = (pair[0].2 + pair[1].2) / 2.0;
+            draw_segment(
+                &mut image,
+                (pair[0].0, pair[0].1),
+                (pair[1].0, pair[1].1),
+                radius,
+                color,
+            );
+        }
+    }
+
+    image
+        .save_with_format(dest, image::ImageFormat::Png)
+        .map_err(|e| e.to_string())
+}
+
+// File name of the PNG preview that goes with a sketch
+fn preview_name(sketch_name: &str) -> String {
+    format!("{}.png", sketch_name.trim_end_matches(".svg"))
+}
+
+// Only names `save_sketch` hands out can be reopened or overwritten
+fn check_sketch_name(name: &str) -> Result<(), String> {
+    let valid = name.strip_suffix(SKETCH_SUFFIX).is_some_and(|stem| {
+        !stem.is_empty() && stem.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Not a sketch attachment: {}", name))
+    }
+}
+
+// Save a sketch as an attachment of the note. Passing the name of an existing sketch
+// replaces it; otherwise a new attachment is created. Returns the SVG's file name.
+#[tauri::command]
+pub async fn save_sketch(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    sketch: Sketch,
+    name: Option<String>,
+) -> Result<String, String> {
+    validate(&sketch)?;
+    let name = match name {
+        Some(name) => {
+            check_sketch_name(&name)?;
+            name
+        }
+        None => format!("{}{}", chrono::Utc::now().timestamp_millis(), SKETCH_SUFFIX),
+    };
+
+    let attachments_dir = writable_attachments_dir(&app_handle, &note_id)?;
+    let svg = render_svg(&sketch)?;
+    let path = attachments_dir.join(&name);
+    compression::write_attachment(&app_handle, &path, svg.as_bytes())?;
+
+    let preview = attachments_dir.join(preview_name(&name));
+    tokio::task::spawn_blocking(move || render_preview(&sketch, &preview))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    Ok(name)
+}
+
+// The strokes of a saved sketch, for editing it further
+#[tauri::command]
+pub async fn get_sketch(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    name: String,
+) -> Result<Sketch, String> {
+    check_sketch_name(&name)?;
+    let data = compression::read_note_attachment(&app_handle, &note_id, &name)
+        .map_err(|e| e.to_string())?;
+    let svg = String::from_utf8_lossy(&data);
+    let start = svg.find(STROKES_START).ok_or("Sketch has no stroke data")? + STROKES_START.len();
+    let end = svg[start..]
+        .find(STROKES_END)
+        .ok_or("Sketch has no stroke data")?
+        + start;
+    serde_json::from_str(&svg[start..end]).map_err(|e| e.to_string())
+}
@@ -0,0 +1,167 @@
+use crate::{new_note_id, overrides, persist_note, AppState, Note};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, Wry};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+
+// Snippets may reference other snippets; this stops runaway recursion
+const MAX_EXPANSION_DEPTH: usize = 5;
+
+fn get_app_data_file(app_handle: &AppHandle<Wry>, name: &str) -> PathBuf {
+    let mut path = overrides::app_data_dir(app_handle).expect("Failed to get app data directory");
+    fs::create_dir_all(&path).expect("Failed to create app data directory");
+    path.push(name);
+    path
+}
+
+fn get_templates_dir(app_handle: &AppHandle<Wry>) -> PathBuf {
+    let path = get_app_data_file(app_handle, "templates");
+    fs::create_dir_all(&path).expect("Failed to create templates directory");
+    path
+}
+
+fn load_snippets(app_handle: &AppHandle<Wry>) -> HashMap<String, String> {
+    fs::read_to_string(get_app_data_file(app_handle, "snippets.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+// Values for the built-in placeholders, resolved once per expansion
+fn builtin_values(app_handle: &AppHandle<Wry>) -> HashMap<&'static str, String> {
+    let now = chrono::Local::now();
+    let device_name = {
+        let state = app_handle.state::<Arc<Mutex<AppState>>>();
+        let app_state = state.lock();
+        match app_state {
+            Ok(app_state) => app_state.device_name.clone(),
+            Err(_) => String::new(),
+        }
+    };
+
+    HashMap::from([
+        ("date", now.format("%Y-%m-%d").to_string()),
+        ("time", now.format("%H:%M").to_string()),
+        ("datetime", now.format("%Y-%m-%d %H:%M").to_string()),
+        (
+            "clipboard",
+            app_handle.clipboard().read_text().unwrap_or_default(),
+        ),
+        ("device", device_name),
+    ])
+}
+
+fn expand(
+    text: &str,
+    builtins: &HashMap<&'static str, String>,
+    snippets: &HashMap<String, String>,
+    depth: usize,
+) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let key = rest[start + 2..start + 2 + len].trim();
+        let placeholder = &rest[start..start + len + 4];
+
+        result.push_str(&rest[..start]);
+        if let Some(value) = builtins.get(key) {
+            result.push_str(value);
+        } else if let Some(snippet) = snippets.get(key).filter(|_| depth < MAX_EXPANSION_DEPTH) {
+            result.push_str(&expand(snippet, builtins, snippets, depth + 1));
+        } else {
+            // Leave unknown placeholders untouched so nothing is silently lost
+            result.push_str(placeholder);
+        }
+        rest = &rest[start + len + 4..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+fn expand_text(app_handle: &AppHandle<Wry>, text: &str) -> String {
+    expand(
+        text,
+        &builtin_values(app_handle),
+        &load_snippets(app_handle),
+        0,
+    )
+}
+
+#[tauri::command]
+pub async fn expand_snippet(app_handle: AppHandle<Wry>, text: String) -> Result<String, String> {
+    Ok(expand_text(&app_handle, &text))
+}
+
+#[tauri::command]
+pub async fn get_snippets(app_handle: AppHandle<Wry>) -> Result<HashMap<String, String>, String> {
+    Ok(load_snippets(&app_handle))
+}
+
+#[tauri::command]
+pub async fn save_snippets(
+    app_handle: AppHandle<Wry>,
+    snippets: HashMap<String, String>,
+) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(&snippets).map_err(|e| e.to_string())?;
+    fs::write(get_app_data_file(&app_handle, "snippets.json"), content).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn list_templates(app_handle: AppHandle<Wry>) -> Result<Vec<String>, String> {
+    let mut templates = Vec::new();
+
+    for entry in fs::read_dir(get_templates_dir(&app_handle)).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|s| s.to_str()) == Some("md") {
+            if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                templates.push(name.to_string());
+            }
+        }
+    }
+
+    templates.sort();
+    Ok(templates)
+}
+
+#[tauri::command]
+pub async fn create_note_from_template(
+    app_handle: AppHandle<Wry>,
+    template: String,
+) -> Result<Note, String> {
+    let path = get_templates_dir(&app_handle).join(format!("{}.md", template));
+    if path.parent() != Some(get_templates_dir(&app_handle).as_path()) || !path.exists() {
+        return Err("Template not found".to_string());
+    }
+
+    let expanded = expand_text(
+        &app_handle,
+        &fs::read_to_string(&path).map_err(|e| e.to_string())?,
+    );
+
+    // A leading "# Heading" in the template becomes the note title
+    let (title, content) = match expanded.strip_prefix("# ") {
+        Some(rest) => {
+            let (title, body) = rest.split_once('\n').unwrap_or((rest, ""));
+            (title.trim().to_string(), body.trim_start().to_string())
+        }
+        None => (template.clone(), expanded),
+    };
+
+    let note = Note {
+        id: new_note_id(),
+        title,
+        content,
+        datetime: chrono::Utc::now().timestamp().to_string(),
+        ..Default::default()
+    };
+
+    persist_note(&app_handle, note.clone()).await?;
+    Ok(note)
+}
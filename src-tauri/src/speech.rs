@@ -0,0 +1,206 @@
+use crate::{load_notes, markdown};
+use serde::Serialize;
+use std::process::Stdio;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager, Wry};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::watch;
+
+// Reading notes aloud with the platform's speech synthesizer. The note is spoken one
+// sentence at a time, and a `speech-position` event goes out before each sentence so
+// the editor can highlight it. Only one note is spoken at a time.
+
+#[derive(Default)]
+pub struct Speech {
+    // Signals the running playback to stop
+    stop: Option<watch::Sender<bool>>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SpeechPosition {
+    note_id: String,
+    // Index of the sentence within the note
+    index: usize,
+    // 0-based line of the note's content the sentence is on
+    line: usize,
+    text: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SpeechFinished {
+    note_id: String,
+    // False when playback was stopped or the synthesizer failed
+    completed: bool,
+}
+
+#[derive(Debug)]
+struct Sentence {
+    line: usize,
+    text: String,
+}
+
+// Sentences of the note's readable text, skipping code blocks
+fn sentences(content: &str) -> Vec<Sentence> {
+    let mut sentences = Vec::new();
+    let mut in_fence = false;
+
+    for (line, raw) in content.lines().enumerate() {
+        let trimmed = raw.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+
+        let text = markdown::strip_inline(raw);
+        let text = text.trim_start_matches(['#', '>', '-', '+', '|', ' ']);
+        let mut current = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            current.push(c);
+            let at_end = matches!(c, '.' | '!' | '?')
+                && chars.peek().map_or(true, |next| next.is_whitespace());
+            if at_end {
+                push_sentence(&mut sentences, line, &current);
+                current.clear();
+            }
+        }
+        push_sentence(&mut sentences, line, &current);
+    }
+
+    sentences
+}
+
+fn push_sentence(sentences: &mut Vec<Sentence>, line: usize, text: &str) {
+    let text = text.trim();
+    if text.chars().any(char::is_alphanumeric) {
+        sentences.push(Sentence {
+            line,
+            text: text.to_string(),
+        });
+    }
+}
+
+// The synthesizer reads the text from stdin and exits when done speaking
+#[cfg(target_os = "macos")]
+fn synthesizer() -> Command {
+    Command::new("say")
+}
+
+#[cfg(target_os = "linux")]
+fn synthesizer() -> Command {
+    Command::new("espeak-ng")
+}
+
+#[cfg(target_os = "windows")]
+fn synthesizer() -> Command {
+    let mut command = Command::new("powershell");
+    command.args([
+        "-NoProfile",
+        "-Command",
+        "Add-Type -AssemblyName System.Speech; \
+         (New-Object System.Speech.Synthesis.SpeechSynthesizer).Speak([Console]::In.ReadToEnd())",
+    ]);
+    command
+}
+
+// Speak one sentence. Returns false if playback was stopped meanwhile.
+async fn speak(text: &str, stop: &mut watch::Receiver<bool>) -> Result<bool, String> {
+    let mut child = synthesizer()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Can't start speech synthesizer: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(text.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    tokio::select! {
+        status = child.wait() => {
+            let status = status.map_err(|e| e.to_string())?;
+            if !status.success() {
+                return Err(format!("Speech synthesizer exited with {}", status));
+            }
+            Ok(!*stop.borrow())
+        }
+        _ = stop.changed() => {
+            let _ = child.kill().await;
+            Ok(false)
+        }
+    }
+}
+
+async fn play(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    sentences: Vec<Sentence>,
+    mut stop: watch::Receiver<bool>,
+) {
+    let mut completed = true;
+    for (index, sentence) in sentences.into_iter().enumerate() {
+        let position = SpeechPosition {
+            note_id: note_id.clone(),
+            index,
+            line: sentence.line,
+            text: sentence.text,
+        };
+        let _ = app_handle.emit("speech-position", &position);
+
+        match speak(&position.text, &mut stop).await {
+            Ok(true) => {}
+            Ok(false) => {
+                completed = false;
+                break;
+            }
+            Err(e) => {
+                println!("Speech failed: {}", e);
+                completed = false;
+                break;
+            }
+        }
+    }
+
+    let _ = app_handle.emit("speech-finished", SpeechFinished { note_id, completed });
+}
+
+// Start reading a note aloud, stopping whatever is being read
+#[tauri::command]
+pub async fn speak_note(app_handle: AppHandle<Wry>, note_id: String) -> Result<(), String> {
+    let note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+    let sentences = sentences(&note.content);
+    if sentences.is_empty() {
+        return Err("Note has no text to read".to_string());
+    }
+
+    let (sender, receiver) = watch::channel(false);
+    {
+        let state = app_handle.state::<Mutex<Speech>>();
+        let mut speech = state.lock().map_err(|e| e.to_string())?;
+        if let Some(previous) = speech.stop.replace(sender) {
+            let _ = previous.send(true);
+        }
+    }
+
+    tauri::async_runtime::spawn(play(app_handle.clone(), note.id, sentences, receiver));
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn stop_speaking(app_handle: AppHandle<Wry>) -> Result<(), String> {
+    let state = app_handle.state::<Mutex<Speech>>();
+    let mut speech = state.lock().map_err(|e| e.to_string())?;
+    if let Some(stop) = speech.stop.take() {
+        let _ = stop.send(true);
+    }
+    Ok(())
+}
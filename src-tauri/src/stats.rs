@@ -0,0 +1,63 @@
+use crate::{get_attachments_dir, get_notes_by_id, index, secret, terms, timestamps};
+use serde::Serialize;
+use std::fs;
+use tauri::{AppHandle, Wry};
+
+// Counts about a single note for the editor's stats footer. They are worked out from
+// the note as stored rather than taken from the index, which may lag behind a save.
+
+#[derive(Debug, Serialize)]
+pub struct NoteStats {
+    note_id: String,
+    // Of the plain text, without markdown syntax
+    words: usize,
+    characters: usize,
+    characters_without_spaces: usize,
+    reading_minutes: usize,
+    attachments: usize,
+    // Space the attachments take on disk, compressed or not
+    attachment_bytes: u64,
+    // Seconds since the epoch, like `Note::datetime`
+    created: f64,
+    updated: f64,
+}
+
+#[tauri::command]
+pub async fn get_note_stats(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+) -> Result<NoteStats, String> {
+    let note = get_notes_by_id(app_handle.clone(), vec![note_id.clone()])
+        .await?
+        .pop()
+        .ok_or("Note not found")?;
+    // Only the title of a locked note is read, which would be all there is to count
+    if secret::is_locked(&note) {
+        return Err("The note is locked".to_string());
+    }
+
+    let text = terms::note_text(&note.content);
+    let words = text.split_whitespace().count();
+    let attachment_bytes = fs::read_dir(get_attachments_dir(&app_handle, &note_id))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|e| e.metadata().ok())
+                .filter(|m| m.is_file())
+                .map(|m| m.len())
+                .sum()
+        })
+        .unwrap_or(0);
+
+    Ok(NoteStats {
+        words,
+        characters: text.chars().count(),
+        characters_without_spaces: text.chars().filter(|c| !c.is_whitespace()).count(),
+        reading_minutes: index::reading_minutes(words),
+        attachments: note.attachments.len(),
+        attachment_bytes,
+        created: timestamps::created_of(&note.fields, &note.datetime),
+        updated: note.datetime.parse().unwrap_or_default(),
+        note_id,
+    })
+}
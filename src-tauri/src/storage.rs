@@ -0,0 +1,506 @@
+use crate::conditions;
+use crate::ordering::folder_of;
+use crate::settings::{load_vault_settings, save_vault_settings};
+use crate::{
+    atomic, blobs, compression, encryption, frontmatter, get_note_path, get_notes_dir, load_notes,
+    note_from_file, notebooks, overrides, Note,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tauri::{AppHandle, Wry};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+// Notes that haven't been touched for a while are moved, together with their
+// attachments, into `notes/.cold/<id>.zip`. They still show up in `get_notes`, their
+// attachments are read straight from the archive, and they are unpacked again as
+// soon as anything writes to them. In an encrypted vault the archive as a whole is
+// encrypted too, names included.
+
+const ARCHIVE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const MONTH: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StoragePolicy {
+    pub enabled: bool,
+    // Notes whose file and attachments are older than this are archived
+    pub archive_after_months: u32,
+}
+
+impl Default for StoragePolicy {
+    fn default() -> Self {
+        StoragePolicy {
+            enabled: false,
+            archive_after_months: 12,
+        }
+    }
+}
+
+fn get_cold_dir(app_handle: &AppHandle<Wry>) -> PathBuf {
+    let mut path = get_notes_dir(app_handle);
+    path.push(".cold");
+    fs::create_dir_all(&path).expect("Failed to create cold storage directory");
+    path
+}
+
+fn get_cold_path(app_handle: &AppHandle<Wry>, note_id: &str) -> PathBuf {
+    get_cold_dir(app_handle).join(format!("{}.zip", note_id))
+}
+
+// The attachments directory without creating it (or restoring anything into it)
+fn attachments_path(app_handle: &AppHandle<Wry>, note_id: &str) -> PathBuf {
+    get_notes_dir(app_handle).join("attachments").join(note_id)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub fn set_modified(path: &Path, time: SystemTime) {
+    if let Err(e) = File::options()
+        .write(true)
+        .open(path)
+        .and_then(|f| f.set_modified(time))
+    {
+        println!("Failed to keep modification time of {:?}: {}", path, e);
+    }
+}
+
+// The most recent modification of a note file or any of its attachments
+fn last_touched(note_path: &Path, attachments_dir: &Path) -> Option<SystemTime> {
+    let mut latest = modified(note_path)?;
+    if let Ok(entries) = fs::read_dir(attachments_dir) {
+        for entry in entries.flatten() {
+            if let Some(time) = modified(&entry.path()) {
+                latest = latest.max(time);
+            }
+        }
+    }
+    Some(latest)
+}
+
+fn archive_note(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<(), String> {
+    let note_path = get_note_path(app_handle, note_id);
+    let attachments_dir = attachments_path(app_handle, note_id);
+    let touched = last_touched(&note_path, &attachments_dir).ok_or("Note file is missing")?;
+
+    let cold_path = get_cold_path(app_handle, note_id);
+    let archive = {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        let mut files = vec![(format!("{}.md", note_id), note_path.clone())];
+        if let Ok(entries) = fs::read_dir(&attachments_dir) {
+            for entry in entries.flatten() {
+                if let Some(name) = entry.file_name().to_str() {
+                    files.push((format!("attachments/{}", name), entry.path()));
+                }
+            }
+        }
+
+        for (name, path) in &files {
+            let data = fs::read(path).map_err(|e| e.to_string())?;
+            zip.start_file(name.as_str(), options)
+                .map_err(|e| e.to_string())?;
+            zip.write_all(&data).map_err(|e| e.to_string())?;
+        }
+        zip.finish().map_err(|e| e.to_string())?.into_inner()
+    };
+
+    // Only remove the originals once the archive is complete
+    encryption::write(app_handle, &cold_path, archive)?;
+    set_modified(&cold_path, touched);
+    fs::remove_file(&note_path).map_err(|e| e.to_string())?;
+    if attachments_dir.exists() {
+        // The archive has its own copies now
+        blobs::release(app_handle, note_id);
+        fs::remove_dir_all(&attachments_dir).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+// A cold storage archive, decrypted when the vault is encrypted
+fn open_archive(path: &Path) -> Result<ZipArchive<Cursor<Vec<u8>>>, String> {
+    let data = encryption::read(path).map_err(|e| e.to_string())?;
+    ZipArchive::new(Cursor::new(data)).map_err(|e| e.to_string())
+}
+
+// Unpack a note from cold storage, if it is there. A newer live copy of the note
+// file (e.g. one written by sync) is kept.
+pub fn restore_note(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<(), String> {
+    let cold_path = get_cold_path(app_handle, note_id);
+    if !cold_path.exists() {
+        return Ok(());
+    }
+
+    let archived_at = modified(&cold_path);
+    let note_path = get_note_path(app_handle, note_id);
+    let attachments_dir = attachments_path(app_handle, note_id);
+    let mut archive = open_archive(&cold_path)?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| e.to_string())?;
+        let name = entry.name().to_string();
+
+        let dest = if name == format!("{}.md", note_id) {
+            note_path.clone()
+        } else {
+            // Only plain file names are ever written under attachments/
+            match name.strip_prefix("attachments/") {
+                Some(file) if !file.is_empty() && !file.contains(['/', '\\']) && file != ".." => {
+                    fs::create_dir_all(&attachments_dir).map_err(|e| e.to_string())?;
+                    attachments_dir.join(file)
+                }
+                _ => continue,
+            }
+        };
+        if dest.exists() {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).map_err(|e| e.to_string())?;
+        atomic::write(&dest, data).map_err(|e| e.to_string())?;
+        if let Some(time) = archived_at {
+            set_modified(&dest, time);
+        }
+    }
+
+    fs::remove_file(&cold_path).map_err(|e| e.to_string())?;
+
+    // The archive doesn't keep which notebook directory the note was in
+    let notebook = encryption::read_to_string(&note_path)
+        .ok()
+        .and_then(|raw| folder_of(&frontmatter::split(&raw).0).map(str::to_string));
+    notebooks::file_note(app_handle, note_id, notebook.as_deref())
+}
+
+// An attachment of a note in cold storage, read without unpacking the note
+pub fn read_archived_attachment(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    name: &str,
+) -> std::io::Result<Vec<u8>> {
+    let cold_path = get_cold_path(app_handle, note_id);
+    if !cold_path.exists() {
+        return Err(std::io::ErrorKind::NotFound.into());
+    }
+    let mut archive = open_archive(&cold_path).map_err(std::io::Error::other)?;
+
+    // Attachments were archived as they were stored, compressed or not
+    let entry = format!("attachments/{}", name);
+    let compressed = format!("{}{}", entry, compression::COMPRESSED_SUFFIX);
+    let mut read = |entry: &str| -> std::io::Result<Option<Vec<u8>>> {
+        let Ok(mut file) = archive.by_name(entry) else {
+            return Ok(None);
+        };
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        encryption::open(data).map(Some)
+    };
+    if let Some(data) = read(&entry)? {
+        return Ok(data);
+    }
+    match read(&compressed)? {
+        Some(data) => zstd::decode_all(data.as_slice()),
+        None => Err(std::io::ErrorKind::NotFound.into()),
+    }
+}
+
+pub fn delete_archived(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<(), String> {
+    let cold_path = get_cold_path(app_handle, note_id);
+    if cold_path.exists() {
+        fs::remove_file(cold_path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Notes in cold storage, read without unpacking them. Notes that also have a live
+// file are skipped since that copy wins.
+pub fn load_archived_notes(app_handle: &AppHandle<Wry>) -> Result<Vec<Note>, String> {
+    let mut notes = Vec::new();
+
+    for entry in fs::read_dir(get_cold_dir(app_handle)).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("zip") {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if get_note_path(app_handle, id).exists() {
+            continue;
+        }
+
+        let mut archive = match open_archive(&path) {
+            Ok(archive) => archive,
+            Err(e) => {
+                println!("Skipping unreadable archive {:?}: {}", path, e);
+                continue;
+            }
+        };
+
+        let attachments: Vec<String> = archive
+            .file_names()
+            .filter_map(|name| name.strip_prefix("attachments/"))
+            .map(str::to_string)
+            .collect();
+
+        let mut raw = Vec::new();
+        let Ok(mut file) = archive.by_name(&format!("{}.md", id)) else {
+            continue;
+        };
+        file.read_to_end(&mut raw).map_err(|e| e.to_string())?;
+        // The note file was archived as it was stored
+        let raw = encryption::open(raw)
+            .and_then(|raw| {
+                String::from_utf8(raw)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+            })
+            .map_err(|e| e.to_string())?;
+
+        let datetime = modified(&path)
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs_f64().to_string())
+            .unwrap_or_default();
+        notes.push(note_from_file(id, &raw, datetime, attachments));
+    }
+
+    Ok(notes)
+}
+
+// Archive every live note that has gone untouched for longer than the policy allows
+pub fn archive_cold_notes(app_handle: &AppHandle<Wry>) -> Result<usize, String> {
+    let policy = load_vault_settings(app_handle).storage_policy;
+    if !policy.enabled {
+        return Ok(0);
+    }
+
+    let cutoff = SystemTime::now() - MONTH * policy.archive_after_months;
+    let mut archived = 0;
+
+    for path in notebooks::note_files(&get_notes_dir(app_handle)) {
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+
+        let is_cold = last_touched(&path, &attachments_path(app_handle, id))
+            .is_some_and(|touched| touched < cutoff);
+        if !is_cold {
+            continue;
+        }
+
+        match archive_note(app_handle, id) {
+            Ok(()) => archived += 1,
+            Err(e) => println!("Failed to archive note {}: {}", id, e),
+        }
+    }
+
+    Ok(archived)
+}
+
+// Check for notes to archive at startup and then once a day
+pub fn spawn_archiver(app_handle: AppHandle<Wry>) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            // Try again on the next round when on a metered connection or low battery
+            if conditions::check_bulk_work(&app_handle).await.is_err() {
+                tokio::time::sleep(ARCHIVE_INTERVAL).await;
+                continue;
+            }
+
+            let handle = app_handle.clone();
+            match tokio::task::spawn_blocking(move || archive_cold_notes(&handle)).await {
+                Ok(Ok(count)) if count > 0 => println!("Moved {} notes to cold storage", count),
+                Ok(Err(e)) => println!("Cold storage sweep failed: {}", e),
+                _ => {}
+            }
+            tokio::time::sleep(ARCHIVE_INTERVAL).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_storage_policy(app_handle: AppHandle<Wry>) -> Result<StoragePolicy, String> {
+    Ok(load_vault_settings(&app_handle).storage_policy)
+}
+
+#[tauri::command]
+pub async fn set_storage_policy(
+    app_handle: AppHandle<Wry>,
+    policy: StoragePolicy,
+) -> Result<(), String> {
+    if policy.archive_after_months == 0 {
+        return Err("Notes must go untouched for at least one month".to_string());
+    }
+
+    let mut settings = load_vault_settings(&app_handle);
+    settings.storage_policy = policy;
+    save_vault_settings(&app_handle, &settings)?;
+
+    // Apply a newly enabled or shortened policy right away
+    let handle = app_handle.clone();
+    tokio::task::spawn_blocking(move || archive_cold_notes(&handle))
+        .await
+        .map_err(|e| e.to_string())??;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct NoteUsage {
+    note_id: String,
+    title: String,
+    bytes: u64,
+    archived: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttachmentUsage {
+    note_id: String,
+    name: String,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StorageReport {
+    total_bytes: u64,
+    // Largest first
+    notes: Vec<NoteUsage>,
+    // Attachment bytes per lowercase file extension
+    attachment_types: HashMap<String, u64>,
+    // Size of each folder in the app data directory, by relative path
+    folders: HashMap<String, u64>,
+    largest_attachments: Vec<AttachmentUsage>,
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+// Sizes of every folder up to two levels below `root`
+fn folder_sizes(root: &Path, folders: &mut HashMap<String, u64>) {
+    let subdirs = |path: &Path| -> Vec<PathBuf> {
+        fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|e| e.path())
+                    .filter(|p| p.is_dir())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    for dir in subdirs(root) {
+        for path in std::iter::once(dir.clone()).chain(subdirs(&dir)) {
+            if let Ok(relative) = path.strip_prefix(root) {
+                folders.insert(
+                    relative.to_string_lossy().replace('\\', "/"),
+                    dir_size(&path),
+                );
+            }
+        }
+    }
+}
+
+fn extension_of(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_else(|| "(none)".to_string())
+}
+
+// Break down disk usage by note, attachment type and folder. Archived attachments
+// are counted at their compressed size.
+#[tauri::command]
+pub async fn get_storage_report(
+    app_handle: AppHandle<Wry>,
+    largest: Option<usize>,
+) -> Result<StorageReport, String> {
+    let app_dir = overrides::app_data_dir(app_handle).map_err(|e| e.to_string())?;
+    let mut notes = Vec::new();
+    let mut attachments = Vec::new();
+
+    for note in load_notes(&app_handle)? {
+        let cold_path = get_cold_path(&app_handle, &note.id);
+        let archived = cold_path.exists();
+        let mut bytes = 0;
+
+        if archived {
+            bytes = fs::metadata(&cold_path).map(|m| m.len()).unwrap_or(0);
+            let mut archive = open_archive(&cold_path)?;
+            for i in 0..archive.len() {
+                let entry = archive.by_index(i).map_err(|e| e.to_string())?;
+                if let Some(name) = entry.name().strip_prefix("attachments/") {
+                    attachments.push(AttachmentUsage {
+                        note_id: note.id.clone(),
+                        name: name.to_string(),
+                        bytes: entry.compressed_size(),
+                    });
+                }
+            }
+        } else {
+            bytes += fs::metadata(get_note_path(&app_handle, &note.id))
+                .map(|m| m.len())
+                .unwrap_or(0);
+            let attachments_dir = attachments_path(&app_handle, &note.id);
+            for name in &note.attachments {
+                let size = fs::metadata(attachments_dir.join(name))
+                    .map(|m| m.len())
+                    .unwrap_or(0);
+                bytes += size;
+                attachments.push(AttachmentUsage {
+                    note_id: note.id.clone(),
+                    name: name.clone(),
+                    bytes: size,
+                });
+            }
+        }
+
+        notes.push(NoteUsage {
+            note_id: note.id,
+            title: note.title,
+            bytes,
+            archived,
+        });
+    }
+
+    let mut attachment_types: HashMap<String, u64> = HashMap::new();
+    for attachment in &attachments {
+        *attachment_types
+            .entry(extension_of(&attachment.name))
+            .or_default() += attachment.bytes;
+    }
+
+    let mut folders = HashMap::new();
+    folder_sizes(&app_dir, &mut folders);
+
+    notes.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    attachments.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    attachments.truncate(largest.unwrap_or(20));
+
+    Ok(StorageReport {
+        total_bytes: dir_size(&app_dir),
+        notes,
+        attachment_types,
+        folders,
+        largest_attachments: attachments,
+    })
+}
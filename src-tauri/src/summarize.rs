@@ -0,0 +1,132 @@
+use crate::proxy::{self, HttpFeature};
+use crate::settings::load_device_settings;
+use crate::{keychain, load_notes, persist_note, terms, Note};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tauri::{AppHandle, Wry};
+
+// Summaries come from a language model the user points us at: a local Ollama or any
+// server speaking the OpenAI chat completions API. Nothing leaves the machine until
+// summarization is enabled in settings. The summary is kept in the note's
+// frontmatter so it syncs with the note.
+
+const SUMMARY_FIELD: &str = "summary";
+const KEY_ACCOUNT: &str = "summarizer";
+// Keeps long notes within the context window of small local models
+const MAX_INPUT_CHARS: usize = 12000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryApi {
+    // POST {model, prompt, stream} -> {response}
+    #[default]
+    Ollama,
+    // POST {model, messages} -> {choices: [{message: {content}}]}, with the key as a
+    // bearer token
+    Openai,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SummaryConfig {
+    pub enabled: bool,
+    pub api: SummaryApi,
+    pub url: String,
+    pub model: String,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        SummaryConfig {
+            enabled: false,
+            api: SummaryApi::Ollama,
+            url: "http://localhost:11434/api/generate".to_string(),
+            model: "llama3.2".to_string(),
+        }
+    }
+}
+
+fn prompt(note: &Note) -> String {
+    let text: String = terms::note_text(&note.content)
+        .chars()
+        .take(MAX_INPUT_CHARS)
+        .collect();
+    format!(
+        "Summarize the following note in two or three sentences. \
+         Reply with the summary only.\n\n{}",
+        text
+    )
+}
+
+async fn request_summary(
+    client: &reqwest::Client,
+    config: &SummaryConfig,
+    key: Option<&str>,
+    prompt: &str,
+) -> Result<String, String> {
+    let body = match config.api {
+        SummaryApi::Ollama => json!({ "model": config.model, "prompt": prompt, "stream": false }),
+        SummaryApi::Openai => json!({
+            "model": config.model,
+            "messages": [{ "role": "user", "content": prompt }],
+        }),
+    };
+    let mut request = client.post(&config.url).json(&body);
+    if let Some(key) = key {
+        request = request.bearer_auth(key);
+    }
+    let response: Value = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Summarization failed: {}", e))?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let summary = match config.api {
+        SummaryApi::Ollama => response.get("response"),
+        SummaryApi::Openai => response.pointer("/choices/0/message/content"),
+    };
+    summary
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| "Summarization returned no text".to_string())
+}
+
+// Summarize a note with the configured model and store the summary in its metadata
+#[tauri::command]
+pub async fn summarize_note(app_handle: AppHandle<Wry>, note_id: String) -> Result<Note, String> {
+    let config = load_device_settings(&app_handle).summarizer;
+    if !config.enabled {
+        return Err("Summarization is turned off".to_string());
+    }
+
+    let mut note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    let key = keychain::get_key(KEY_ACCOUNT)?;
+    let client = proxy::config_for(&app_handle, HttpFeature::Summarize)
+        .apply(reqwest::Client::builder())?
+        .timeout(Duration::from_secs(120))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let summary = request_summary(&client, &config, key.as_deref(), &prompt(&note)).await?;
+
+    note.fields
+        .insert(SUMMARY_FIELD.to_string(), Value::String(summary));
+    persist_note(&app_handle, note.clone()).await?;
+
+    Ok(note)
+}
+
+// Store the API key for the summarization endpoint in the keychain, or remove it
+#[tauri::command]
+pub async fn set_summarizer_key(key: Option<String>) -> Result<(), String> {
+    keychain::set_key(KEY_ACCOUNT, key.as_deref())
+}
@@ -0,0 +1,86 @@
+use crate::{emoji, load_notes, Note};
+use serde::Serialize;
+use tauri::{AppHandle, Wry};
+
+// Ranking for the quick switcher: a note matches on its title or on its emoji
+
+#[derive(Debug, Serialize)]
+pub struct SwitcherMatch {
+    id: String,
+    title: String,
+    emoji: Option<String>,
+    score: u32,
+}
+
+// Whether every character of `query` appears in `text` in order
+fn is_subsequence(query: &str, text: &str) -> bool {
+    let mut chars = text.chars();
+    query.chars().all(|q| chars.any(|c| c == q))
+}
+
+fn title_score(title: &str, query: &str) -> Option<u32> {
+    let title = title.to_lowercase();
+    let query = query.to_lowercase();
+    if title == query {
+        Some(90)
+    } else if title.starts_with(&query) {
+        Some(80)
+    } else if title
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(&query))
+    {
+        Some(60)
+    } else if title.contains(&query) {
+        Some(40)
+    } else if is_subsequence(&query, &title) {
+        Some(20)
+    } else {
+        None
+    }
+}
+
+// Typing a note's emoji, alone or in front of part of its title, ranks it first
+fn score(note: &Note, query: &str) -> Option<u32> {
+    if let Some(rest) = emoji::emoji_of(note).and_then(|emoji| query.strip_prefix(emoji)) {
+        let rest = rest.trim();
+        if rest.is_empty() {
+            return Some(100);
+        }
+        if let Some(score) = title_score(&note.title, rest) {
+            return Some(score + 10);
+        }
+    }
+    title_score(&note.title, query)
+}
+
+#[tauri::command]
+pub async fn quick_switch(
+    app_handle: AppHandle<Wry>,
+    query: String,
+    limit: Option<usize>,
+) -> Result<Vec<SwitcherMatch>, String> {
+    let query = query.trim();
+    let notes = load_notes(&app_handle)?;
+
+    // Notes come newest first, and the sort is stable, so ties go to recent notes
+    let mut matches: Vec<SwitcherMatch> = notes
+        .iter()
+        .filter_map(|note| {
+            let score = if query.is_empty() {
+                0
+            } else {
+                score(note, query)?
+            };
+            Some(SwitcherMatch {
+                id: note.id.clone(),
+                title: note.title.clone(),
+                emoji: emoji::emoji_of(note).map(str::to_string),
+                score,
+            })
+        })
+        .collect();
+    matches.sort_by(|a, b| b.score.cmp(&a.score));
+    matches.truncate(limit.unwrap_or(20));
+
+    Ok(matches)
+}
@@ -0,0 +1,220 @@
+// Markdown pipe tables:
+//
+// | Name  | Count |
+// | ----- | ----: |
+// | apple |     3 |
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alignment {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+pub fn escape_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+        .replace("\r\n", " ")
+        .replace(['\n', '\r'], " ")
+        .trim()
+        .to_string()
+}
+
+fn separator(alignment: Alignment, width: usize) -> String {
+    match alignment {
+        Alignment::None => "-".repeat(width),
+        Alignment::Left => format!(":{}", "-".repeat(width - 1)),
+        Alignment::Right => format!("{}:", "-".repeat(width - 1)),
+        Alignment::Center => format!(":{}:", "-".repeat(width - 2)),
+    }
+}
+
+fn pad(cell: &str, alignment: Alignment, width: usize) -> String {
+    let padding = width.saturating_sub(cell.chars().count());
+    match alignment {
+        Alignment::Right => format!("{}{}", " ".repeat(padding), cell),
+        Alignment::Center => format!(
+            "{}{}{}",
+            " ".repeat(padding / 2),
+            cell,
+            " ".repeat(padding - padding / 2)
+        ),
+        _ => format!("{}{}", cell, " ".repeat(padding)),
+    }
+}
+
+// Render rows as a table with every column padded to the same width. The first
+// row is the header; cells must already be escaped. Missing cells are left empty.
+pub fn render_table(rows: &[Vec<String>], alignments: &[Alignment]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    if columns == 0 {
+        return String::new();
+    }
+
+    let alignment = |column: usize| alignments.get(column).copied().unwrap_or(Alignment::None);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+                .max(3)
+        })
+        .collect();
+
+    let render_row = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+    let row_cells = |row: &Vec<String>| {
+        (0..columns)
+            .map(|column| {
+                let cell = row.get(column).map(String::as_str).unwrap_or("");
+                pad(cell, alignment(column), widths[column])
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut table = render_row(row_cells(&rows[0]));
+    table.push_str(&render_row(
+        (0..columns)
+            .map(|column| separator(alignment(column), widths[column]))
+            .collect(),
+    ));
+    for row in &rows[1..] {
+        table.push_str(&render_row(row_cells(row)));
+    }
+    table
+}
+
+// Split a table row into its cells, honouring `\|` escapes
+pub fn split_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = match line.strip_suffix('|') {
+        Some(rest) if !rest.ends_with('\\') => rest,
+        _ => line,
+    };
+
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut escaped = false;
+    for c in line.chars() {
+        if c == '|' && !escaped {
+            cells.push(cell.trim().to_string());
+            cell.clear();
+        } else {
+            cell.push(c);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    cells.push(cell.trim().to_string());
+    cells
+}
+
+fn parse_alignment(cell: &str) -> Option<Alignment> {
+    let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+    if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+        return None;
+    }
+    Some(match (cell.starts_with(':'), cell.ends_with(':')) {
+        (true, true) => Alignment::Center,
+        (true, false) => Alignment::Left,
+        (false, true) => Alignment::Right,
+        (false, false) => Alignment::None,
+    })
+}
+
+fn separator_alignments(line: &str) -> Option<Vec<Alignment>> {
+    if !line.contains('-') {
+        return None;
+    }
+    split_row(line)
+        .iter()
+        .map(|cell| parse_alignment(cell))
+        .collect()
+}
+
+// Line ranges (end exclusive) of the tables in `lines`, skipping fenced code blocks
+pub fn find_tables(lines: &[&str]) -> Vec<(usize, usize)> {
+    let mut tables = Vec::new();
+    let mut in_fence = false;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i].trim();
+        if line.starts_with("```") || line.starts_with("~~~") {
+            in_fence = !in_fence;
+            i += 1;
+            continue;
+        }
+
+        let is_table = !in_fence
+            && line.contains('|')
+            && lines
+                .get(i + 1)
+                .and_then(|next| separator_alignments(next))
+                .is_some();
+        if !is_table {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        i += 2;
+        while i < lines.len() && lines[i].contains('|') && !lines[i].trim().is_empty() {
+            i += 1;
+        }
+        tables.push((start, i));
+    }
+
+    tables
+}
+
+fn format_lines(lines: &[&str]) -> Vec<String> {
+    let indent: String = lines[0].chars().take_while(|c| c.is_whitespace()).collect();
+    let alignments = separator_alignments(lines[1]).unwrap_or_default();
+
+    let mut rows = vec![split_row(lines[0])];
+    rows.extend(lines[2..].iter().map(|line| split_row(line)));
+
+    render_table(&rows, &alignments)
+        .lines()
+        .map(|line| format!("{}{}", indent, line))
+        .collect()
+}
+
+fn format_ranges(content: &str, ranges: &[(usize, usize)]) -> String {
+    let lines: Vec<&str> = content.split('\n').collect();
+    let mut output: Vec<String> = Vec::with_capacity(lines.len());
+    let mut i = 0;
+
+    for &(start, end) in ranges {
+        output.extend(lines[i..start].iter().map(|line| line.to_string()));
+        output.extend(format_lines(&lines[start..end]));
+        i = end;
+    }
+    output.extend(lines[i..].iter().map(|line| line.to_string()));
+
+    output.join("\n")
+}
+
+// Re-align the table containing the given character offset; other content is untouched
+pub fn format_table_at(content: &str, cursor: usize) -> String {
+    let line = content.chars().take(cursor).filter(|&c| c == '\n').count();
+    let lines: Vec<&str> = content.split('\n').collect();
+
+    let ranges: Vec<_> = find_tables(&lines)
+        .into_iter()
+        .filter(|&(start, end)| (start..end).contains(&line))
+        .collect();
+    format_ranges(content, &ranges)
+}
+
+pub fn format_all_tables(content: &str) -> String {
+    let lines: Vec<&str> = content.split('\n').collect();
+    format_ranges(content, &find_tables(&lines))
+}
+
+#[tauri::command]
+pub async fn format_table(content: String, cursor: usize) -> Result<String, String> {
+    Ok(format_table_at(&content, cursor))
+}
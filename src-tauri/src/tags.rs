@@ -0,0 +1,581 @@
+use crate::changes;
+use crate::events;
+use crate::settings::{load_vault_settings, save_vault_settings};
+use crate::{
+    annotate_metadata, encryption, get_note_path, load_notes, persist_note, storage, terms, Note,
+    NoteMetadata,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use tauri::{AppHandle, Wry};
+
+// Tags live in the `tags` frontmatter list, or are written in the text as `#tag`. A
+// tag can be nested with slashes, e.g. `project/alpha/backend`, which also places
+// the note under `project` and `project/alpha` when browsing the tree. Tagging notes
+// from the app edits the frontmatter list; renaming a tag rewrites both.
+
+const TAGS_FIELD: &str = "tags";
+
+// How a tag is shown, kept in settings by full tag path
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct TagMeta {
+    // CSS hex color like `#3b82f6`
+    pub color: Option<String>,
+    pub description: Option<String>,
+    pub icon: Option<String>,
+}
+
+// A note's tag with its display metadata, for rendering chips
+#[derive(Debug, Serialize, Clone)]
+pub struct TagChip {
+    name: String,
+    #[serde(flatten)]
+    meta: TagMeta,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagCount {
+    tag: String,
+    // Notes carrying the tag itself, not counting tags nested below it
+    count: usize,
+    #[serde(flatten)]
+    meta: TagMeta,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagNode {
+    // Last segment of the tag, e.g. `backend`
+    name: String,
+    // Full tag, e.g. `project/alpha/backend`
+    path: String,
+    // Notes tagged with exactly this tag
+    count: usize,
+    // Notes tagged with this tag or any tag below it
+    total: usize,
+    meta: Option<TagMeta>,
+    children: Vec<TagNode>,
+}
+
+// Tidy a tag as typed: no `#`, surrounding whitespace or empty segments
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim()
+        .trim_start_matches('#')
+        .split('/')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Tags from the frontmatter list and the text, each once
+pub fn tags_of(note: &Note) -> Vec<String> {
+    let mut tags = frontmatter_tags(note);
+    for tag in inline_tags(&note.content) {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+fn frontmatter_tags(note: &Note) -> Vec<String> {
+    let tags = match note.fields.get(TAGS_FIELD) {
+        Some(Value::Array(items)) => items.iter().filter_map(Value::as_str).collect(),
+        Some(Value::String(tag)) => vec![tag.as_str()],
+        _ => Vec::new(),
+    };
+    let mut unique: Vec<String> = Vec::new();
+    for tag in tags.into_iter().map(normalize_tag) {
+        if !tag.is_empty() && !unique.contains(&tag) {
+            unique.push(tag);
+        }
+    }
+    unique
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '-' | '_' | '/')
+}
+
+// Call `rewrite` with every `#tag` written in the text (without the `#`) and put
+// what it returns in place of the tag. A tag starts a word with `#` and a letter,
+// outside code blocks and inline code, so headings and `#123` aren't tags.
+fn rewrite_inline_tags(content: &str, mut rewrite: impl FnMut(&str) -> Option<String>) -> String {
+    let mut rewritten = String::with_capacity(content.len());
+    let mut in_fence = false;
+
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let fence = trimmed.starts_with("```") || trimmed.starts_with("~~~");
+        if fence {
+            in_fence = !in_fence;
+        }
+        if fence || in_fence {
+            rewritten.push_str(line);
+            continue;
+        }
+
+        let mut in_code = false;
+        let mut prev: Option<char> = None;
+        let mut copied = 0;
+        for (i, c) in line.char_indices() {
+            if c == '`' {
+                in_code = !in_code;
+            } else if c == '#' && !in_code && prev.is_none_or(char::is_whitespace) {
+                let rest = &line[i + 1..];
+                if rest.starts_with(char::is_alphabetic) {
+                    let len = rest.find(|c: char| !is_tag_char(c)).unwrap_or(rest.len());
+                    let tag = rest[..len].trim_end_matches('/');
+                    if let Some(replacement) = rewrite(tag) {
+                        rewritten.push_str(&line[copied..i + 1]);
+                        rewritten.push_str(&replacement);
+                        copied = i + 1 + tag.len();
+                    }
+                }
+            }
+            prev = Some(c);
+        }
+        rewritten.push_str(&line[copied..]);
+    }
+
+    rewritten
+}
+
+pub fn inline_tags(content: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    rewrite_inline_tags(content, |tag| {
+        let tag = normalize_tag(tag);
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+        None
+    });
+    tags
+}
+
+pub fn set_tags(note: &mut Note, tags: Vec<String>) {
+    if tags.is_empty() {
+        note.fields.remove(TAGS_FIELD);
+    } else {
+        note.fields.insert(
+            TAGS_FIELD.to_string(),
+            Value::Array(tags.into_iter().map(Value::String).collect()),
+        );
+    }
+}
+
+// Whether `tag` is `ancestor` itself or nested somewhere below it
+pub fn is_within(tag: &str, ancestor: &str) -> bool {
+    tag == ancestor
+        || tag
+            .strip_prefix(ancestor)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+// Fill in the tag chips of every note in a metadata listing
+pub fn annotate(app_handle: &AppHandle<Wry>, notes: &mut [NoteMetadata]) {
+    let registry = load_vault_settings(app_handle).tag_meta;
+    for note in notes {
+        note.tags = note
+            .tag_names
+            .iter()
+            .map(|name| TagChip {
+                name: name.clone(),
+                meta: registry.get(name).cloned().unwrap_or_default(),
+            })
+            .collect();
+    }
+}
+
+#[derive(Default)]
+struct Branch {
+    count: usize,
+    // Ids of notes at or below this tag, so a note with two tags in the same
+    // subtree is only counted once
+    notes: Vec<String>,
+    children: BTreeMap<String, Branch>,
+}
+
+fn into_nodes(
+    children: BTreeMap<String, Branch>,
+    parent: &str,
+    registry: &HashMap<String, TagMeta>,
+) -> Vec<TagNode> {
+    children
+        .into_iter()
+        .map(|(name, branch)| {
+            let path = if parent.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", parent, name)
+            };
+            TagNode {
+                count: branch.count,
+                total: branch.notes.len(),
+                meta: registry.get(&path).cloned(),
+                children: into_nodes(branch.children, &path, registry),
+                name,
+                path,
+            }
+        })
+        .collect()
+}
+
+fn tag_tree(notes: &[Note], registry: &HashMap<String, TagMeta>) -> Vec<TagNode> {
+    let mut root: BTreeMap<String, Branch> = BTreeMap::new();
+
+    for note in notes {
+        for tag in tags_of(note) {
+            let mut level = &mut root;
+            let segments: Vec<&str> = tag.split('/').collect();
+            for (i, segment) in segments.iter().enumerate() {
+                let branch = level.entry(segment.to_string()).or_default();
+                if !branch.notes.contains(&note.id) {
+                    branch.notes.push(note.id.clone());
+                }
+                if i + 1 == segments.len() {
+                    branch.count += 1;
+                }
+                level = &mut branch.children;
+            }
+        }
+    }
+
+    into_nodes(root, "", registry)
+}
+
+#[tauri::command]
+pub async fn get_tag_tree(app_handle: AppHandle<Wry>) -> Result<Vec<TagNode>, String> {
+    let registry = load_vault_settings(&app_handle).tag_meta;
+    Ok(tag_tree(&load_notes(&app_handle)?, &registry))
+}
+
+// Every tag in use, from frontmatter lists and the text alike, sorted by name
+#[tauri::command]
+pub async fn get_tags(app_handle: AppHandle<Wry>) -> Result<Vec<TagCount>, String> {
+    let registry = load_vault_settings(&app_handle).tag_meta;
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for note in load_notes(&app_handle)? {
+        for tag in note.tags {
+            *counts.entry(tag).or_default() += 1;
+        }
+    }
+    Ok(counts
+        .into_iter()
+        .map(|(tag, count)| TagCount {
+            meta: registry.get(&tag).cloned().unwrap_or_default(),
+            tag,
+            count,
+        })
+        .collect())
+}
+
+// Notes tagged with `tag`, and with `include_descendants` also those tagged with
+// anything nested below it
+#[tauri::command]
+pub async fn get_notes_by_tag(
+    app_handle: AppHandle<Wry>,
+    tag: String,
+    include_descendants: Option<bool>,
+) -> Result<Vec<NoteMetadata>, String> {
+    let tag = normalize_tag(&tag);
+    let include_descendants = include_descendants.unwrap_or(false);
+
+    let mut notes: Vec<NoteMetadata> = load_notes(&app_handle)?
+        .iter()
+        .filter(|note| {
+            tags_of(note).iter().any(|t| {
+                if include_descendants {
+                    is_within(t, &tag)
+                } else {
+                    *t == tag
+                }
+            })
+        })
+        .map(NoteMetadata::from)
+        .collect();
+    annotate_metadata(&app_handle, &mut notes);
+    Ok(notes)
+}
+
+// Rename a tag at any level of the tree. Everything nested below it moves along,
+// so renaming `project/alpha` to `archive/alpha` turns `project/alpha/backend` into
+// `archive/alpha/backend`. Returns the number of notes changed.
+#[tauri::command]
+pub async fn rename_tag(
+    app_handle: AppHandle<Wry>,
+    from: String,
+    to: String,
+) -> Result<usize, String> {
+    let from = normalize_tag(&from);
+    let to = normalize_tag(&to);
+    if from.is_empty() || to.is_empty() {
+        return Err("Tag can't be empty".to_string());
+    }
+    if from == to {
+        return Ok(0);
+    }
+
+    let rename = |tag: &str| format!("{}{}", to, &tag[from.len()..]);
+    let mut changed = 0;
+    for mut note in load_notes(&app_handle)? {
+        let mut renamed_inline = false;
+        let content = rewrite_inline_tags(&note.content, |tag| {
+            let tag = normalize_tag(tag);
+            renamed_inline |= is_within(&tag, &from);
+            is_within(&tag, &from).then(|| rename(&tag))
+        });
+        let tags = frontmatter_tags(&note);
+        let in_frontmatter = tags.iter().any(|t| is_within(t, &from));
+        if !in_frontmatter && !renamed_inline {
+            continue;
+        }
+
+        if in_frontmatter {
+            let mut renamed: Vec<String> = Vec::new();
+            for tag in tags {
+                let tag = if is_within(&tag, &from) {
+                    rename(&tag)
+                } else {
+                    tag
+                };
+                // Renaming onto an existing tag merges the two
+                if !renamed.contains(&tag) {
+                    renamed.push(tag);
+                }
+            }
+            set_tags(&mut note, renamed);
+        }
+        note.content = content;
+
+        persist_note(&app_handle, note).await?;
+        changed += 1;
+    }
+
+    // Colors and descriptions follow the tags they belong to
+    let mut settings = load_vault_settings(&app_handle);
+    let moved: Vec<String> = settings
+        .tag_meta
+        .keys()
+        .filter(|tag| is_within(tag, &from))
+        .cloned()
+        .collect();
+    if !moved.is_empty() {
+        for tag in moved {
+            if let Some(meta) = settings.tag_meta.remove(&tag) {
+                let renamed = format!("{}{}", to, &tag[from.len()..]);
+                settings.tag_meta.entry(renamed).or_insert(meta);
+            }
+        }
+        save_vault_settings(&app_handle, &settings)?;
+        // Every note showing the moved tags gets their colors anew
+        events::notes_reloaded(&app_handle)?;
+    }
+
+    Ok(changed)
+}
+
+fn is_hex_color(color: &str) -> bool {
+    color
+        .strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+// Set the color, description and icon of a tag. Clearing all three removes the tag
+// from the registry.
+#[tauri::command]
+pub async fn update_tag_meta(
+    app_handle: AppHandle<Wry>,
+    tag: String,
+    meta: TagMeta,
+) -> Result<(), String> {
+    let tag = normalize_tag(&tag);
+    if tag.is_empty() {
+        return Err("Tag can't be empty".to_string());
+    }
+
+    let clean = |value: Option<String>| {
+        value
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let meta = TagMeta {
+        color: clean(meta.color),
+        description: clean(meta.description),
+        icon: clean(meta.icon),
+    };
+    if let Some(color) = &meta.color {
+        if !is_hex_color(color) {
+            return Err(format!("Invalid color: {}", color));
+        }
+    }
+
+    let mut settings = load_vault_settings(&app_handle);
+    if meta == TagMeta::default() {
+        settings.tag_meta.remove(&tag);
+    } else {
+        settings.tag_meta.insert(tag, meta);
+    }
+    save_vault_settings(&app_handle, &settings)?;
+
+    events::notes_reloaded(&app_handle)
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkTagResult {
+    // Ids of notes whose tags changed
+    updated: Vec<String>,
+    // Notes that already had every tag added and none of those removed
+    unchanged: usize,
+}
+
+// Add and remove tags on many notes at once. Either every note is updated or, when
+// a write fails, the notes written so far are put back the way they were.
+#[tauri::command]
+pub async fn tag_notes(
+    app_handle: AppHandle<Wry>,
+    note_ids: Vec<String>,
+    add_tags: Vec<String>,
+    remove_tags: Vec<String>,
+) -> Result<BulkTagResult, String> {
+    let add: Vec<String> = add_tags
+        .iter()
+        .map(|t| normalize_tag(t))
+        .filter(|t| !t.is_empty())
+        .collect();
+    let remove: Vec<String> = remove_tags.iter().map(|t| normalize_tag(t)).collect();
+
+    let notes = load_notes(&app_handle)?;
+    let mut targets = Vec::new();
+    for id in &note_ids {
+        let note = notes
+            .iter()
+            .find(|n| n.id == *id)
+            .ok_or_else(|| format!("Note not found: {}", id))?;
+        targets.push(note.clone());
+    }
+
+    let mut result = BulkTagResult {
+        updated: Vec::new(),
+        unchanged: 0,
+    };
+    // Note files as they were before this call, for rolling back
+    let mut originals: Vec<(String, String)> = Vec::new();
+
+    // Only the frontmatter list is edited; `#tags` in the text stay as written
+    for mut note in targets {
+        let before = frontmatter_tags(&note);
+        let mut tags: Vec<String> = before
+            .iter()
+            .filter(|t| !remove.contains(t))
+            .cloned()
+            .collect();
+        for tag in &add {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        if tags == before || result.updated.contains(&note.id) {
+            result.unchanged += 1;
+            continue;
+        }
+
+        let write = async {
+            storage::restore_note(&app_handle, &note.id)?;
+            let raw = encryption::read_to_string(get_note_path(&app_handle, &note.id))
+                .map_err(|e| e.to_string())?;
+            originals.push((note.id.clone(), raw));
+            set_tags(&mut note, tags);
+            persist_note(&app_handle, note.clone()).await
+        };
+        if let Err(e) = write.await {
+            for (id, raw) in &originals {
+                if let Err(e) = encryption::write(&app_handle, get_note_path(&app_handle, id), raw)
+                {
+                    println!("Failed to roll back tags of note {}: {}", id, e);
+                }
+                let _ = changes::publish_saved(&app_handle, id, false);
+            }
+            return Err(format!("Failed to tag note {}: {}", note.id, e));
+        }
+        result.updated.push(note.id);
+    }
+
+    Ok(result)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagSuggestion {
+    tag: String,
+    score: f64,
+}
+
+const MAX_SUGGESTIONS: usize = 8;
+// Bonus when a segment of the tag appears in the note as a word
+const KEYWORD_BONUS: f64 = 0.3;
+const MIN_SCORE: f64 = 0.05;
+
+// Rank existing tags for a note by how similar its text is to the notes already
+// carrying each tag (TF-IDF), plus a bonus when the tag's name appears in the note.
+// Everything is computed locally.
+#[tauri::command]
+pub async fn suggest_tags_for_note(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+) -> Result<Vec<TagSuggestion>, String> {
+    let notes = load_notes(&app_handle)?;
+    let index = notes
+        .iter()
+        .position(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    let documents: Vec<HashMap<String, usize>> = notes
+        .iter()
+        .map(|n| terms::term_counts(&terms::note_text(&n.content)))
+        .collect();
+    let idf = terms::idf(&documents);
+    let target = terms::weights(&documents[index], &idf);
+    let own_tags = tags_of(&notes[index]);
+
+    // Each tag's profile is the sum of the weights of the notes tagged with it
+    let mut profiles: HashMap<String, HashMap<String, f64>> = HashMap::new();
+    for (i, note) in notes.iter().enumerate() {
+        if i == index {
+            continue;
+        }
+        let tags = tags_of(note);
+        if tags.is_empty() {
+            continue;
+        }
+        let weights = terms::weights(&documents[i], &idf);
+        for tag in tags {
+            let profile = profiles.entry(tag).or_default();
+            for (term, weight) in &weights {
+                *profile.entry(term.clone()).or_insert(0.0) += weight;
+            }
+        }
+    }
+
+    let mut suggestions: Vec<TagSuggestion> = profiles
+        .into_iter()
+        .filter(|(tag, _)| !own_tags.contains(tag))
+        .map(|(tag, profile)| {
+            let mentioned = tag
+                .split('/')
+                .flat_map(terms::terms)
+                .any(|word| target.contains_key(&word));
+            let bonus = if mentioned { KEYWORD_BONUS } else { 0.0 };
+            TagSuggestion {
+                score: terms::cosine(&target, &profile) + bonus,
+                tag,
+            }
+        })
+        .filter(|s| s.score >= MIN_SCORE)
+        .collect();
+
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    suggestions.truncate(MAX_SUGGESTIONS);
+    Ok(suggestions)
+}
@@ -0,0 +1,97 @@
+use crate::markdown;
+use std::collections::HashMap;
+
+// Word statistics over note text, for tag suggestions and related notes
+
+// Common English words that say nothing about what a note is about
+const STOPWORDS: &[&str] = &[
+    "about", "after", "again", "also", "and", "any", "are", "because", "been", "before", "being",
+    "but", "can", "could", "did", "does", "doing", "done", "each", "for", "from", "had", "has",
+    "have", "her", "here", "him", "his", "how", "into", "its", "just", "like", "more", "most",
+    "much", "need", "not", "now", "off", "once", "one", "only", "other", "our", "out", "over",
+    "own", "same", "she", "should", "some", "such", "than", "that", "the", "their", "them", "then",
+    "there", "these", "they", "this", "those", "through", "too", "under", "until", "use", "very",
+    "was", "way", "were", "what", "when", "where", "which", "while", "who", "why", "will", "with",
+    "would", "you", "your",
+];
+
+// Lowercased words of at least three characters, without stopwords or plain numbers
+pub fn terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3 && !word.chars().all(|c| c.is_ascii_digit()))
+        .map(str::to_lowercase)
+        .filter(|word| !STOPWORDS.contains(&word.as_str()))
+        .collect()
+}
+
+// How often each term occurs
+pub fn term_counts(text: &str) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for term in terms(text) {
+        *counts.entry(term).or_insert(0) += 1;
+    }
+    counts
+}
+
+// Inverse document frequency of every term across `documents`, each given as its
+// term counts. Rare terms weigh more than ones that appear everywhere.
+pub fn idf(documents: &[HashMap<String, usize>]) -> HashMap<String, f64> {
+    let mut frequency: HashMap<&str, usize> = HashMap::new();
+    for document in documents {
+        for term in document.keys() {
+            *frequency.entry(term).or_insert(0) += 1;
+        }
+    }
+
+    let total = documents.len() as f64;
+    frequency
+        .into_iter()
+        .map(|(term, count)| (term.to_string(), (1.0 + total / count as f64).ln()))
+        .collect()
+}
+
+// TF-IDF weights of one document's terms, normalized to unit length
+pub fn weights(
+    counts: &HashMap<String, usize>,
+    idf: &HashMap<String, f64>,
+) -> HashMap<String, f64> {
+    let mut weights: HashMap<String, f64> = counts
+        .iter()
+        .map(|(term, &count)| {
+            let weight = (1.0 + count as f64).ln() * idf.get(term).copied().unwrap_or(1.0);
+            (term.clone(), weight)
+        })
+        .collect();
+
+    let norm = weights.values().map(|w| w * w).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        for weight in weights.values_mut() {
+            *weight /= norm;
+        }
+    }
+    weights
+}
+
+// Cosine similarity of two weight vectors
+pub fn cosine(a: &HashMap<String, f64>, b: &HashMap<String, f64>) -> f64 {
+    let dot: f64 = a
+        .iter()
+        .filter_map(|(term, w)| b.get(term).map(|v| w * v))
+        .sum();
+    let norm = |v: &HashMap<String, f64>| v.values().map(|w| w * w).sum::<f64>().sqrt();
+    let denominator = norm(a) * norm(b);
+    if denominator > 0.0 {
+        dot / denominator
+    } else {
+        0.0
+    }
+}
+
+// The note's text as a reader sees it, for term statistics
+pub fn note_text(content: &str) -> String {
+    content
+        .lines()
+        .map(markdown::strip_inline)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
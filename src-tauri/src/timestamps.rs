@@ -0,0 +1,87 @@
+use crate::frontmatter::{self, Frontmatter};
+use crate::{encryption, Note};
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+// When a note was created and last updated, kept in its frontmatter as RFC 3339
+// times. File times don't survive backups, copies or sync (a received note would
+// date from when it arrived), so a note's date comes from `updated` and only falls
+// back to the file's modification time for notes saved before these fields existed.
+
+pub const CREATED_FIELD: &str = "created";
+pub const UPDATED_FIELD: &str = "updated";
+
+fn format(time: DateTime<Utc>) -> Value {
+    Value::String(time.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+fn parse(fields: &Frontmatter, key: &str) -> Option<DateTime<Utc>> {
+    let value = fields.get(key)?.as_str()?;
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// A note's date as listings sort it: seconds since the epoch
+fn to_datetime(time: DateTime<Utc>) -> String {
+    (time.timestamp_millis() as f64 / 1000.0).to_string()
+}
+
+fn from_datetime(datetime: &str) -> Option<DateTime<Utc>> {
+    let seconds = datetime.parse::<f64>().ok()?;
+    Utc.timestamp_millis_opt((seconds * 1000.0) as i64).single()
+}
+
+// The note's date from its `updated` field, if it has one
+pub fn datetime_of(fields: &Frontmatter) -> Option<String> {
+    parse(fields, UPDATED_FIELD).map(to_datetime)
+}
+
+// When a note was created, in seconds since the epoch. Notes saved before creation
+// times were kept go by their date.
+pub fn created_of(fields: &Frontmatter, datetime: &str) -> f64 {
+    parse(fields, CREATED_FIELD)
+        .map(|time| time.timestamp_millis() as f64 / 1000.0)
+        .or_else(|| datetime.parse().ok())
+        .unwrap_or_default()
+}
+
+// Stamp a note that is about to be written to `path` as updated now. Its creation
+// time is kept from the note, or the file it replaces, or failing both is now.
+pub fn stamp(note: &mut Note, path: &Path) {
+    let now = Utc::now();
+    if parse(&note.fields, CREATED_FIELD).is_none() {
+        let created = encryption::read_to_string(path)
+            .ok()
+            .and_then(|raw| parse(&frontmatter::split(&raw).0, CREATED_FIELD))
+            .or_else(|| {
+                let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+                let millis = modified.duration_since(UNIX_EPOCH).ok()?.as_millis();
+                Utc.timestamp_millis_opt(millis as i64).single()
+            })
+            .unwrap_or(now);
+        note.fields
+            .insert(CREATED_FIELD.to_string(), format(created));
+    }
+    note.fields.insert(UPDATED_FIELD.to_string(), format(now));
+}
+
+// Give a note received from a peer that doesn't write these fields the dates the
+// peer listed it with, so it isn't dated from when it arrived
+pub fn adopt(note: &mut Note) {
+    if parse(&note.fields, UPDATED_FIELD).is_some() {
+        return;
+    }
+    let Some(updated) = from_datetime(&note.datetime) else {
+        return;
+    };
+    if parse(&note.fields, CREATED_FIELD).is_none() {
+        note.fields
+            .insert(CREATED_FIELD.to_string(), format(updated));
+    }
+    note.fields
+        .insert(UPDATED_FIELD.to_string(), format(updated));
+}
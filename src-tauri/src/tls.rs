@@ -0,0 +1,176 @@
+use crate::proxy::{self, HttpFeature};
+use crate::{atomic, overrides, pairing, PeerDevice};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, PrivateKey, ServerName};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tauri::{AppHandle, Wry};
+
+// Sync between devices runs over TLS. Each device makes itself a self-signed
+// certificate on first launch, kept next to its identity in the app data directory,
+// and advertises the certificate's SHA-256 fingerprint over mDNS. Nobody signs these
+// certificates, so a peer is trusted by its fingerprint alone: a connection is only
+// made when the certificate the peer presents is the one it advertised. Static peers
+// aren't found over mDNS; their fingerprint is taken from the health check.
+
+const CERT_FILE: &str = "sync-cert.der";
+const KEY_FILE: &str = "sync-key.der";
+// Every certificate has the same name; it is never checked
+const CERT_NAME: &str = "himoji-notes.local";
+
+#[derive(Clone)]
+pub struct DeviceCert {
+    cert: Certificate,
+    key: PrivateKey,
+    // Lowercase hex SHA-256 of the certificate, as advertised to peers
+    pub fingerprint: String,
+}
+
+pub fn fingerprint(der: &[u8]) -> String {
+    hex::encode(Sha256::digest(der))
+}
+
+impl DeviceCert {
+    fn new(cert: Vec<u8>, key: Vec<u8>) -> DeviceCert {
+        DeviceCert {
+            fingerprint: fingerprint(&cert),
+            cert: Certificate(cert),
+            key: PrivateKey(key),
+        }
+    }
+
+    // A new certificate that lasts as long as it is kept
+    pub fn generate() -> Result<DeviceCert, String> {
+        let cert = rcgen::generate_simple_self_signed(vec![CERT_NAME.to_string()])
+            .map_err(|e| e.to_string())?;
+        Ok(DeviceCert::new(
+            cert.serialize_der().map_err(|e| e.to_string())?,
+            cert.serialize_private_key_der(),
+        ))
+    }
+
+    pub fn server_config(&self) -> Result<Arc<rustls::ServerConfig>, String> {
+        rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![self.cert.clone()], self.key.clone())
+            .map(Arc::new)
+            .map_err(|e| e.to_string())
+    }
+}
+
+// The certificate kept in `data_dir`, created and stored on first use. A
+// certificate that can't be stored still works for this run.
+pub fn load_cert(data_dir: &Path) -> Result<DeviceCert, String> {
+    let cert_path = data_dir.join(CERT_FILE);
+    let key_path = data_dir.join(KEY_FILE);
+    // Missing files just mean this is the first launch
+    if let (Ok(cert), Ok(key)) = (fs::read(&cert_path), fs::read(&key_path)) {
+        let stored = DeviceCert::new(cert, key);
+        match stored.server_config() {
+            Ok(_) => return Ok(stored),
+            Err(e) => println!(
+                "Stored sync certificate is unusable, creating a new one: {}",
+                e
+            ),
+        }
+    }
+
+    let created = DeviceCert::generate()?;
+    let written = fs::create_dir_all(data_dir)
+        .and_then(|()| atomic::write_private(&key_path, &created.key.0))
+        .and_then(|()| fs::write(&cert_path, &created.cert.0));
+    if let Err(e) = written {
+        println!(
+            "Failed to store the sync certificate in {:?}: {}",
+            data_dir, e
+        );
+    }
+    Ok(created)
+}
+
+pub fn device_cert(app_handle: &AppHandle<Wry>) -> Result<DeviceCert, String> {
+    load_cert(&overrides::app_data_dir(app_handle).map_err(|e| e.to_string())?)
+}
+
+// Accepts a server's certificate when its fingerprint is the expected one, or any
+// certificate when nothing is expected. The handshake still proves the server
+// holds the certificate's key.
+struct Fingerprint(Option<String>);
+
+impl ServerCertVerifier for Fingerprint {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match &self.0 {
+            Some(expected) if *expected != fingerprint(&end_entity.0) => Err(
+                rustls::Error::General("Peer certificate doesn't match its fingerprint".into()),
+            ),
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+}
+
+fn client_config(expected: Option<String>) -> rustls::ClientConfig {
+    rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(Fingerprint(expected)))
+        .with_no_client_auth()
+}
+
+// Connect only to the device `peer` advertised. Peers that advertise no
+// certificate run a version that doesn't sync over TLS.
+pub fn for_peer(
+    builder: reqwest::ClientBuilder,
+    peer: &PeerDevice,
+) -> Result<reqwest::ClientBuilder, String> {
+    let expected = peer.fingerprint.clone().ok_or_else(|| {
+        format!(
+            "{} doesn't support encrypted sync; update it to sync with it",
+            peer.name
+        )
+    })?;
+    Ok(builder.use_preconfigured_tls(client_config(Some(expected))))
+}
+
+// Connect only to the device with the certificate `fingerprint`, whatever the peer
+// advertises now
+pub fn pinned(builder: reqwest::ClientBuilder, fingerprint: String) -> reqwest::ClientBuilder {
+    builder.use_preconfigured_tls(client_config(Some(fingerprint)))
+}
+
+// Connect to whatever device answers, keeping its certificate on the response
+// (`reqwest::tls::TlsInfo`) to learn its fingerprint
+pub fn unpinned(builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+    builder
+        .use_preconfigured_tls(client_config(None))
+        .tls_info(true)
+}
+
+// The fingerprint of the certificate a response came with
+pub fn peer_fingerprint(response: &reqwest::Response) -> Option<String> {
+    response
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .map(fingerprint)
+}
+
+// A client for sync requests to `peer`, through the proxy chosen for sync and
+// with our pairing token, pinned to the peer's certificate
+pub fn client(app_handle: &AppHandle<Wry>, peer: &PeerDevice) -> Result<reqwest::Client, String> {
+    let builder =
+        proxy::config_for(app_handle, HttpFeature::Sync).apply(reqwest::Client::builder())?;
+    pairing::authorize(app_handle, &peer.id, for_peer(builder, peer)?)
+        .build()
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,300 @@
+use crate::clock::Timestamp;
+use crate::settings::load_vault_settings;
+use crate::{
+    atomic, changes, clock, get_note_path, get_notes_dir, protocol, read_note, storage, trash, Note,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use tauri::{AppHandle, Wry};
+
+// Deleted notes leave a tombstone in `notes/.tombstones.json` recording when they
+// were deleted. Tombstones travel with every sync request, so a peer that still has
+// the note deletes it too, and a deleted note that a peer sends back is dropped
+// instead of reappearing. They are forgotten after the retention window.
+//
+// A peer's tombstone only deletes a note that wasn't edited here after the deletion;
+// an edit made since wins, as does restoring the note from the trash. Either leaves
+// a newer record marked `restored` in place of the tombstone, which travels the same
+// way and undoes the deletion on peers that took it.
+//
+// Deletions and restores are stamped with the hybrid logical clock, like saved notes,
+// and ordered against edits by it. The Unix time is kept alongside for the retention
+// window and for peers and records that predate the clock.
+
+const TOMBSTONES_FILE: &str = ".tombstones.json";
+const DEFAULT_RETENTION_DAYS: u32 = 90;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tombstone {
+    pub note_id: String,
+    // Unix timestamp of the deletion, or of the restore
+    pub deleted_at: i64,
+    // The note was brought back after being deleted
+    #[serde(default)]
+    pub restored: bool,
+    // Clock timestamp of the deletion or restore, `<millis>:<counter>:<device>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hlc: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Record {
+    at: i64,
+    restored: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hlc: Option<String>,
+}
+
+// Files written before restores were recorded hold bare deletion times
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Deleted(i64),
+    Record(Record),
+}
+
+// Latest deletion or restore by note id
+type Tombstones = HashMap<String, Record>;
+
+fn get_tombstones_path(app_handle: &AppHandle<Wry>) -> PathBuf {
+    get_notes_dir(app_handle).join(TOMBSTONES_FILE)
+}
+
+// Tombstones still within the retention window
+fn load_tombstones(app_handle: &AppHandle<Wry>) -> Tombstones {
+    let retention_days = load_vault_settings(app_handle)
+        .tombstone_retention_days
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    let cutoff = chrono::Utc::now().timestamp() - i64::from(retention_days) * 24 * 60 * 60;
+
+    let stored: HashMap<String, Stored> = fs::read_to_string(get_tombstones_path(app_handle))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    stored
+        .into_iter()
+        .map(|(note_id, stored)| {
+            let record = match stored {
+                Stored::Deleted(at) => Record {
+                    at,
+                    restored: false,
+                    hlc: None,
+                },
+                Stored::Record(record) => record,
+            };
+            (note_id, record)
+        })
+        .filter(|(_, record)| record.at >= cutoff)
+        .collect()
+}
+
+fn save_tombstones(app_handle: &AppHandle<Wry>, tombstones: &Tombstones) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(tombstones).map_err(|e| e.to_string())?;
+    atomic::write(get_tombstones_path(app_handle), content).map_err(|e| e.to_string())
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn tick(app_handle: &AppHandle<Wry>) -> Option<String> {
+    clock::tick(app_handle).map(|timestamp| timestamp.to_string())
+}
+
+// Whether a change at Unix time `at` with clock `hlc` came after one at `other_at`
+// with `other_hlc`: by the full clock when both have one, else by the second
+fn is_after(
+    (at, hlc): (i64, Option<&Timestamp>),
+    (other_at, other_hlc): (i64, Option<&Timestamp>),
+) -> bool {
+    match (hlc, other_hlc) {
+        (Some(hlc), Some(other_hlc)) => hlc > other_hlc,
+        _ => at > other_at,
+    }
+}
+
+// Record that a note was deleted on this device
+pub fn record(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<(), String> {
+    let mut tombstones = load_tombstones(app_handle);
+    let deleted = Record {
+        at: now(),
+        restored: false,
+        hlc: tick(app_handle),
+    };
+    tombstones.insert(note_id.to_string(), deleted);
+    save_tombstones(app_handle, &tombstones)
+}
+
+// A note restored from the trash is no longer deleted, here or on peers that deleted
+// it along with us. Nothing is recorded for a note that was never deleted.
+pub fn resurrect(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<(), String> {
+    let mut tombstones = load_tombstones(app_handle);
+    let Some(record) = tombstones.get_mut(note_id) else {
+        return Ok(());
+    };
+    // Newer than the tombstone even if the wall clock went back since
+    *record = Record {
+        at: now().max(record.at + 1),
+        restored: true,
+        hlc: tick(app_handle),
+    };
+    save_tombstones(app_handle, &tombstones)
+}
+
+pub fn is_deleted(app_handle: &AppHandle<Wry>, note_id: &str) -> bool {
+    load_tombstones(app_handle)
+        .get(note_id)
+        .is_some_and(|record| !record.restored)
+}
+
+// Tombstones to send along with a sync request to a peer speaking `version`. Peers
+// that predate restores would take a restore for a deletion, so they don't get them.
+pub fn current(app_handle: &AppHandle<Wry>, version: u32) -> Vec<Tombstone> {
+    load_tombstones(app_handle)
+        .into_iter()
+        .filter(|(_, record)| !record.restored || version >= protocol::RESTORES_VERSION)
+        .map(|(note_id, record)| Tombstone {
+            note_id,
+            deleted_at: record.at,
+            restored: record.restored,
+            hlc: record.hlc,
+        })
+        .collect()
+}
+
+// When a note was last edited: its clock, and the time in Unix seconds by the clock
+// or, for notes saved without one, by its date
+fn edited_at(note: &Note) -> Option<(i64, Option<Timestamp>)> {
+    let timestamp = clock::timestamp_of(note);
+    let at = timestamp
+        .as_ref()
+        .map(|seen| seen.millis / 1000)
+        .or_else(|| {
+            note.datetime
+                .parse::<f64>()
+                .ok()
+                .map(|seconds| seconds as i64)
+        })?;
+    Some((at, timestamp))
+}
+
+// Take in a peer's tombstones. Notes it deleted go to the trash here as well, unless
+// they were edited here after the deletion, and notes it restored come back.
+pub fn apply(app_handle: &AppHandle<Wry>, incoming: &[Tombstone]) -> Result<(), String> {
+    let mut tombstones = load_tombstones(app_handle);
+    for tombstone in incoming {
+        let hlc = tombstone.hlc.as_deref().and_then(Timestamp::parse);
+        if let Some(seen) = &hlc {
+            clock::observe_timestamp(app_handle, seen);
+        }
+        let at = (tombstone.deleted_at, hlc.as_ref());
+        let known = tombstones.get(&tombstone.note_id).is_some_and(|record| {
+            let recorded = record.hlc.as_deref().and_then(Timestamp::parse);
+            !is_after(at, (record.at, recorded.as_ref()))
+        });
+        if known {
+            continue;
+        }
+        let note_id = &tombstone.note_id;
+
+        if tombstone.restored {
+            tombstones.insert(
+                note_id.clone(),
+                Record {
+                    at: tombstone.deleted_at,
+                    restored: true,
+                    hlc: tombstone.hlc.clone(),
+                },
+            );
+            // The peer has it again; a note purged here comes back with its next sync
+            let trashed = trash::get_trash_dir(app_handle).join(format!("{}.md", note_id));
+            if trashed.exists() {
+                match trash::restore(app_handle, note_id) {
+                    Ok(()) => changes::publish_saved(app_handle, note_id, true)?,
+                    Err(e) => println!("Failed to restore note {}: {}", note_id, e),
+                }
+            }
+            continue;
+        }
+
+        storage::restore_note(app_handle, note_id)?;
+        let path = get_note_path(app_handle, note_id);
+        let edited = read_note(app_handle, note_id, &path)
+            .ok()
+            .and_then(|note| edited_at(&note));
+        if edited.is_some_and(|(edited, edited_hlc)| is_after((edited, edited_hlc.as_ref()), at)) {
+            // Our edit is newer than the deletion, so the note stays, on the peer too
+            println!("Keeping note {} edited since a peer deleted it", note_id);
+            let restored = Record {
+                at: now().max(tombstone.deleted_at + 1),
+                restored: true,
+                hlc: tick(app_handle),
+            };
+            tombstones.insert(note_id.clone(), restored);
+            continue;
+        }
+
+        tombstones.insert(
+            note_id.clone(),
+            Record {
+                at: tombstone.deleted_at,
+                restored: false,
+                hlc: tombstone.hlc.clone(),
+            },
+        );
+        if path.exists() {
+            trash::move_to_trash(app_handle, note_id)?;
+        }
+    }
+    save_tombstones(app_handle, &tombstones)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> Timestamp {
+        Timestamp::parse(value).unwrap()
+    }
+
+    #[test]
+    fn clocks_order_changes_within_a_second() {
+        let deleted = at("1700000000100:0:a");
+        let edited = at("1700000000900:0:b");
+        assert!(is_after(
+            (1_700_000_000, Some(&edited)),
+            (1_700_000_000, Some(&deleted))
+        ));
+        assert!(!is_after(
+            (1_700_000_000, Some(&deleted)),
+            (1_700_000_000, Some(&edited))
+        ));
+    }
+
+    #[test]
+    fn clocks_win_over_wall_time() {
+        // The deleting device's wall clock ran ahead, but it saw the edit first
+        let edited = at("1700000000000:0:b");
+        let deleted = at("1700000000000:1:a");
+        assert!(!is_after(
+            (1_700_000_000, Some(&edited)),
+            (1_700_000_100, Some(&deleted))
+        ));
+        assert!(is_after(
+            (1_700_000_100, Some(&deleted)),
+            (1_700_000_000, Some(&edited))
+        ));
+    }
+
+    #[test]
+    fn seconds_decide_without_both_clocks() {
+        let deleted = at("1700000000000:0:a");
+        assert!(is_after(
+            (1_700_000_001, None),
+            (1_700_000_000, Some(&deleted))
+        ));
+        assert!(!is_after((1_700_000_000, None), (1_700_000_000, None)));
+    }
+}
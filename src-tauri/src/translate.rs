@@ -0,0 +1,263 @@
+use crate::proxy::{self, HttpFeature};
+use crate::settings::load_device_settings;
+use crate::{atomic, encryption, keychain, load_notes, new_note_id, overrides, persist_note, Note};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::{AppHandle, Wry};
+
+// Translation goes through a service the user configures: a LibreTranslate server
+// (self-hosted or public) or DeepL. Results are cached per note and language, keyed
+// by a hash of the note's content, so showing a translation again doesn't call the
+// service until the note changes. An encrypted vault's translations aren't cached,
+// as the cache would hold the notes' text unencrypted.
+
+const KEY_ACCOUNT: &str = "translator";
+// Frontmatter of a note created from a translation
+const TRANSLATION_OF_FIELD: &str = "translation_of";
+const LANGUAGE_FIELD: &str = "language";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranslationBackend {
+    #[default]
+    LibreTranslate,
+    Deepl,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TranslationConfig {
+    pub enabled: bool,
+    pub backend: TranslationBackend,
+    // Base URL of the service, e.g. `http://localhost:5000` or
+    // `https://api-free.deepl.com`
+    pub url: String,
+}
+
+impl Default for TranslationConfig {
+    fn default() -> Self {
+        TranslationConfig {
+            enabled: false,
+            backend: TranslationBackend::LibreTranslate,
+            url: "http://localhost:5000".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranslationMode {
+    // Return the translated text for the frontend to show in place of the note
+    #[default]
+    View,
+    // Save the translation as a new note that points back at the original
+    Note,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Translation {
+    language: String,
+    content: String,
+    // Whether the text came from the cache rather than the service
+    cached: bool,
+    // Set when the translation was saved as a new note
+    note: Option<Note>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedTranslation {
+    source_hash: String,
+    content: String,
+}
+
+fn get_cache_dir(app_handle: &AppHandle<Wry>) -> Result<PathBuf, String> {
+    Ok(overrides::app_cache_dir(app_handle)
+        .map_err(|e| e.to_string())?
+        .join("translations"))
+}
+
+fn get_cache_path(
+    app_handle: &AppHandle<Wry>,
+    note_id: &str,
+    language: &str,
+) -> Result<PathBuf, String> {
+    let dir = get_cache_dir(app_handle)?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.join(format!("{}.{}.json", note_id, language)))
+}
+
+// Delete every cached translation
+pub fn clear_cache(app_handle: &AppHandle<Wry>) {
+    let Ok(dir) = get_cache_dir(app_handle) else {
+        return;
+    };
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            println!("Failed to clear the translation cache: {}", e);
+        }
+    }
+}
+
+// Language codes go into file names, so only letters and dashes are accepted
+fn normalize_language(language: &str) -> Result<String, String> {
+    let language = language.trim().to_lowercase();
+    if language.is_empty()
+        || language.len() > 8
+        || !language
+            .chars()
+            .all(|c| c.is_ascii_alphabetic() || c == '-')
+    {
+        return Err(format!("Not a language code: {:?}", language));
+    }
+    Ok(language)
+}
+
+async fn request_translation(
+    client: &reqwest::Client,
+    config: &TranslationConfig,
+    key: Option<&str>,
+    text: &str,
+    language: &str,
+) -> Result<String, String> {
+    let base = config.url.trim_end_matches('/');
+    let request = match config.backend {
+        TranslationBackend::LibreTranslate => {
+            client.post(format!("{}/translate", base)).json(&json!({
+                "q": text,
+                "source": "auto",
+                "target": language,
+                "format": "text",
+                "api_key": key.unwrap_or_default(),
+            }))
+        }
+        TranslationBackend::Deepl => client
+            .post(format!("{}/v2/translate", base))
+            .header(
+                "Authorization",
+                format!("DeepL-Auth-Key {}", key.ok_or("DeepL needs an API key")?),
+            )
+            .json(&json!({ "text": [text], "target_lang": language.to_uppercase() })),
+    };
+
+    let response: Value = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Translation failed: {}", e))?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+    let translated = match config.backend {
+        TranslationBackend::LibreTranslate => response.get("translatedText"),
+        TranslationBackend::Deepl => response.pointer("/translations/0/text"),
+    };
+    translated
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| "Translation service returned no text".to_string())
+}
+
+// Translate a note into `target_lang`, either for viewing or as a new note
+#[tauri::command]
+pub async fn translate_note(
+    app_handle: AppHandle<Wry>,
+    note_id: String,
+    target_lang: String,
+    mode: Option<TranslationMode>,
+) -> Result<Translation, String> {
+    let config = load_device_settings(&app_handle).translator;
+    if !config.enabled {
+        return Err("Translation is turned off".to_string());
+    }
+    let language = normalize_language(&target_lang)?;
+
+    let note = load_notes(&app_handle)?
+        .into_iter()
+        .find(|n| n.id == note_id)
+        .ok_or("Note not found")?;
+
+    let source_hash = hex::encode(Sha256::digest(note.content.as_bytes()));
+    let cache_path = get_cache_path(&app_handle, &note.id, &language)?;
+    let use_cache = !encryption::is_enabled(&app_handle);
+    let cached = fs::read_to_string(&cache_path)
+        .ok()
+        .filter(|_| use_cache)
+        .and_then(|raw| serde_json::from_str::<CachedTranslation>(&raw).ok())
+        .filter(|c| c.source_hash == source_hash);
+
+    let (content, cached) = match cached {
+        Some(cached) => (cached.content, true),
+        None => {
+            let key = keychain::get_key(KEY_ACCOUNT)?;
+            let client = proxy::config_for(&app_handle, HttpFeature::Translate)
+                .apply(reqwest::Client::builder())?
+                .timeout(Duration::from_secs(60))
+                .build()
+                .map_err(|e| e.to_string())?;
+            let content =
+                request_translation(&client, &config, key.as_deref(), &note.content, &language)
+                    .await?;
+
+            if use_cache {
+                let entry = CachedTranslation {
+                    source_hash,
+                    content: content.clone(),
+                };
+                let raw = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+                if let Err(e) = atomic::write(&cache_path, raw) {
+                    println!("Failed to cache translation of {}: {}", note.id, e);
+                }
+            }
+            (content, false)
+        }
+    };
+
+    let note = match mode.unwrap_or_default() {
+        TranslationMode::View => None,
+        TranslationMode::Note => {
+            // The translated first heading becomes the title
+            let title = content
+                .lines()
+                .next()
+                .and_then(|line| line.trim().strip_prefix("# "))
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .unwrap_or_else(|| format!("{} ({})", note.title, language));
+
+            let mut translated = Note {
+                id: new_note_id(),
+                title,
+                content: content.clone(),
+                datetime: chrono::Utc::now().timestamp().to_string(),
+                ..Default::default()
+            };
+            translated.fields.insert(
+                TRANSLATION_OF_FIELD.to_string(),
+                Value::String(note.id.clone()),
+            );
+            translated
+                .fields
+                .insert(LANGUAGE_FIELD.to_string(), Value::String(language.clone()));
+
+            persist_note(&app_handle, translated.clone()).await?;
+            Some(translated)
+        }
+    };
+
+    Ok(Translation {
+        language,
+        content,
+        cached,
+        note,
+    })
+}
+
+// Store the API key for the translation service in the keychain, or remove it
+#[tauri::command]
+pub async fn set_translator_key(key: Option<String>) -> Result<(), String> {
+    keychain::set_key(KEY_ACCOUNT, key.as_deref())
+}
@@ -0,0 +1,206 @@
+use crate::changes::{self, NoteChange};
+use crate::{
+    blobs, encryption, frontmatter, get_note_path, get_notes_dir, lock, note_from_file, ordering,
+    storage, tombstones, NoteMetadata,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use tauri::{AppHandle, Wry};
+
+// Trashed notes keep their file and attachments under `notes/.trash`, next to a
+// small record of when they were deleted:
+//
+// .trash/<id>.md
+// .trash/<id>.json
+// .trash/attachments/<id>/...
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrashRecord {
+    // Unix timestamp of the deletion
+    pub deleted_at: i64,
+    // The note's `folder` field at the time, so the list can show where it came from
+    #[serde(default)]
+    pub folder: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletedNote {
+    #[serde(flatten)]
+    note: NoteMetadata,
+    deleted_at: i64,
+    folder: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreReport {
+    restored: Vec<String>,
+    // Reason by note id for notes that stayed in the trash
+    failed: HashMap<String, String>,
+}
+
+pub fn get_trash_dir(app_handle: &AppHandle<Wry>) -> PathBuf {
+    get_notes_dir(app_handle).join(".trash")
+}
+
+pub fn move_to_trash(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<(), String> {
+    lock::ensure_writable(app_handle)?;
+    // Archived notes are unpacked first so the trash holds plain files
+    storage::restore_note(app_handle, note_id)?;
+
+    let note_path = get_note_path(app_handle, note_id);
+    if !note_path.exists() {
+        return Err("Note not found".to_string());
+    }
+
+    let trash_dir = get_trash_dir(app_handle);
+    fs::create_dir_all(trash_dir.join("attachments")).map_err(|e| e.to_string())?;
+
+    let attachments_dir = get_notes_dir(app_handle).join("attachments").join(note_id);
+    if attachments_dir.exists() {
+        // The trashed files no longer count towards the shared blobs; a restore shares
+        // them again
+        blobs::release(app_handle, note_id);
+        let dest = trash_dir.join("attachments").join(note_id);
+        if dest.exists() {
+            fs::remove_dir_all(&dest).map_err(|e| e.to_string())?;
+        }
+        fs::rename(&attachments_dir, dest).map_err(|e| e.to_string())?;
+    }
+
+    let raw = encryption::read_to_string(&note_path).map_err(|e| e.to_string())?;
+    let record = TrashRecord {
+        deleted_at: chrono::Utc::now().timestamp(),
+        folder: ordering::folder_of(&frontmatter::split(&raw).0).map(str::to_string),
+    };
+    fs::write(
+        trash_dir.join(format!("{}.json", note_id)),
+        serde_json::to_string(&record).map_err(|e| e.to_string())?,
+    )
+    .map_err(|e| e.to_string())?;
+    fs::rename(note_path, trash_dir.join(format!("{}.md", note_id))).map_err(|e| e.to_string())?;
+
+    storage::delete_archived(app_handle, note_id)?;
+    changes::publish(app_handle, NoteChange::Deleted(note_id.to_string()));
+    Ok(())
+}
+
+fn read_record(app_handle: &AppHandle<Wry>, note_id: &str) -> Option<TrashRecord> {
+    let path = get_trash_dir(app_handle).join(format!("{}.json", note_id));
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+pub fn restore(app_handle: &AppHandle<Wry>, note_id: &str) -> Result<(), String> {
+    let trash_dir = get_trash_dir(app_handle);
+    let trashed_path = trash_dir.join(format!("{}.md", note_id));
+    if !trashed_path.exists() {
+        return Err("Note is not in the trash".to_string());
+    }
+    let note_path = get_note_path(app_handle, note_id);
+    if note_path.exists() {
+        return Err("A note with this id already exists".to_string());
+    }
+
+    let trashed_attachments = trash_dir.join("attachments").join(note_id);
+    if trashed_attachments.exists() {
+        let dest = get_notes_dir(app_handle).join("attachments").join(note_id);
+        // An empty directory may have been created for the id in the meantime
+        if dest.exists() {
+            fs::remove_dir(&dest).map_err(|e| e.to_string())?;
+        }
+        fs::rename(&trashed_attachments, &dest).map_err(|e| e.to_string())?;
+        for entry in fs::read_dir(&dest).map_err(|e| e.to_string())?.flatten() {
+            if let Err(e) = blobs::share(app_handle, &entry.path()) {
+                println!("Failed to deduplicate attachment {:?}: {}", entry.path(), e);
+            }
+        }
+    }
+
+    fs::rename(&trashed_path, note_path).map_err(|e| e.to_string())?;
+    let _ = fs::remove_file(trash_dir.join(format!("{}.json", note_id)));
+    tombstones::resurrect(app_handle, note_id)
+}
+
+// Trashed notes, most recently deleted first
+#[tauri::command]
+pub async fn get_recently_deleted(app_handle: AppHandle<Wry>) -> Result<Vec<DeletedNote>, String> {
+    let trash_dir = get_trash_dir(&app_handle);
+    if !trash_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut deleted = Vec::new();
+    for entry in fs::read_dir(&trash_dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("md") {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let raw = match encryption::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(e) => {
+                println!("Failed to read trashed note {}: {}", id, e);
+                continue;
+            }
+        };
+
+        let attachments = fs::read_dir(trash_dir.join("attachments").join(id))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|e| e.file_name().to_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // Without a record, the file's mtime is the best guess at the deletion time
+        let record = read_record(&app_handle, id).unwrap_or_else(|| TrashRecord {
+            deleted_at: fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0),
+            folder: None,
+        });
+
+        let note = note_from_file(id, &raw, record.deleted_at.to_string(), attachments);
+        deleted.push(DeletedNote {
+            note: NoteMetadata::from(&note),
+            deleted_at: record.deleted_at,
+            folder: record.folder,
+        });
+    }
+
+    deleted.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    Ok(deleted)
+}
+
+// Move notes back out of the trash. Notes that can't be restored are reported
+// rather than failing the whole batch.
+#[tauri::command]
+pub async fn restore_notes(
+    app_handle: AppHandle<Wry>,
+    ids: Vec<String>,
+) -> Result<RestoreReport, String> {
+    let mut report = RestoreReport {
+        restored: Vec::new(),
+        failed: HashMap::new(),
+    };
+
+    for id in ids {
+        match restore(&app_handle, &id) {
+            Ok(()) => {
+                changes::publish_saved(&app_handle, &id, true)?;
+                report.restored.push(id);
+            }
+            Err(e) => {
+                report.failed.insert(id, e);
+            }
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,231 @@
+use crate::launch::{self, LaunchTarget};
+use crate::{conditions, events, index, new_note_id, persist_note, AppState, Note};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tauri::tray::TrayIconBuilder;
+use tauri::{AppHandle, Listener, Manager, Wry};
+
+// The system tray icon. Its menu lists the most recently modified notes, how many
+// peers are around and whether sync is paused, with actions that don't need the
+// main window to be open first. The menu is rebuilt after the events that tell the
+// frontend about note, peer and sync pause changes go out, once a burst of them
+// (a sync, an import) has settled. Recent notes come from the index as the app
+// keeps it; the notes directory is only scanned again after `notes-updated`.
+
+const TRAY_ID: &str = "main";
+const RECENT_NOTES: usize = 5;
+// Menu item ids; recent notes are `note:<id>`
+const NOTE_ITEM_PREFIX: &str = "note:";
+const NEW_NOTE: &str = "new-note";
+const PAUSE_SYNC: &str = "pause-sync";
+const QUIT: &str = "quit";
+// How long to wait for further events before rebuilding
+const REBUILD_DELAY: Duration = Duration::from_millis(500);
+
+// Events after which the menu is out of date
+const REBUILD_ON: [&str; 6] = [
+    events::NOTE_CREATED,
+    events::NOTE_UPDATED,
+    events::NOTE_DELETED,
+    events::NOTES_UPDATED,
+    events::PEERS_UPDATED,
+    events::SYNC_PAUSE_CHANGED,
+];
+
+// A rebuild is waiting for `REBUILD_DELAY`, and whether it has to rescan the notes
+#[derive(Default)]
+struct PendingRebuild {
+    scheduled: AtomicBool,
+    rescan: AtomicBool,
+}
+
+// Ids and titles of the most recently modified notes, newest first
+fn recent_notes(app_handle: &AppHandle<Wry>, rescan: bool) -> Vec<(String, String)> {
+    let list = |index: &mut index::NoteIndex| {
+        let mut notes: Vec<_> = index.entries.iter().collect();
+        notes.sort_by(|a, b| b.1.modified.cmp(&a.1.modified));
+        notes
+            .into_iter()
+            .take(RECENT_NOTES)
+            .map(|(id, entry)| (id.clone(), entry.title.clone()))
+            .collect()
+    };
+    let recent = if rescan {
+        index::with_index(app_handle, list)
+    } else {
+        index::with_cached_index(app_handle, list)
+    };
+    recent.unwrap_or_else(|e| {
+        println!("Failed to list recent notes for the tray: {}", e);
+        Vec::new()
+    })
+}
+
+fn peer_count(app_handle: &AppHandle<Wry>) -> usize {
+    let state = app_handle.state::<Arc<Mutex<AppState>>>();
+    let count = state.lock().map(|state| state.peers.len()).unwrap_or(0);
+    count
+}
+
+fn build_menu(app_handle: &AppHandle<Wry>, rescan: bool) -> tauri::Result<Menu<Wry>> {
+    let menu = Menu::new(app_handle)?;
+
+    let recent = recent_notes(app_handle, rescan);
+    if recent.is_empty() {
+        menu.append(&MenuItem::new(
+            app_handle,
+            "No notes yet",
+            false,
+            None::<&str>,
+        )?)?;
+    }
+    for (id, title) in recent {
+        let title = if title.trim().is_empty() {
+            "Untitled".to_string()
+        } else {
+            title
+        };
+        let item_id = format!("{}{}", NOTE_ITEM_PREFIX, id);
+        menu.append(&MenuItem::with_id(
+            app_handle,
+            item_id,
+            title,
+            true,
+            None::<&str>,
+        )?)?;
+    }
+    menu.append(&PredefinedMenuItem::separator(app_handle)?)?;
+
+    let peers = match peer_count(app_handle) {
+        1 => "1 peer nearby".to_string(),
+        count => format!("{} peers nearby", count),
+    };
+    menu.append(&MenuItem::new(app_handle, peers, false, None::<&str>)?)?;
+    menu.append(&CheckMenuItem::with_id(
+        app_handle,
+        PAUSE_SYNC,
+        "Pause sync",
+        true,
+        conditions::is_sync_paused(app_handle),
+        None::<&str>,
+    )?)?;
+    menu.append(&PredefinedMenuItem::separator(app_handle)?)?;
+
+    menu.append(&MenuItem::with_id(
+        app_handle,
+        NEW_NOTE,
+        "New note",
+        true,
+        None::<&str>,
+    )?)?;
+    menu.append(&MenuItem::with_id(
+        app_handle,
+        QUIT,
+        "Quit",
+        true,
+        None::<&str>,
+    )?)?;
+    Ok(menu)
+}
+
+// Replace the menu once events stop coming for `REBUILD_DELAY`, off the thread that
+// sent the event that called for it
+fn rebuild(app_handle: &AppHandle<Wry>, rescan: bool) {
+    let pending = app_handle.state::<PendingRebuild>();
+    if rescan {
+        pending.rescan.store(true, Ordering::SeqCst);
+    }
+    if pending.scheduled.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(REBUILD_DELAY).await;
+        let pending = app_handle.state::<PendingRebuild>();
+        pending.scheduled.store(false, Ordering::SeqCst);
+        let rescan = pending.rescan.swap(false, Ordering::SeqCst);
+
+        let built = tauri::async_runtime::spawn_blocking(move || {
+            let Some(tray) = app_handle.tray_by_id(TRAY_ID) else {
+                return Ok(());
+            };
+            build_menu(&app_handle, rescan).and_then(|menu| tray.set_menu(Some(menu)))
+        });
+        match built.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => println!("Failed to rebuild the tray menu: {}", e),
+            Err(e) => println!("Failed to rebuild the tray menu: {}", e),
+        }
+    });
+}
+
+// Create an empty note and open it in the main window
+async fn new_note(app_handle: &AppHandle<Wry>) -> Result<(), String> {
+    let note = Note {
+        id: new_note_id(),
+        title: "Untitled".to_string(),
+        datetime: chrono::Utc::now().timestamp().to_string(),
+        ..Default::default()
+    };
+    let id = note.id.clone();
+    persist_note(app_handle, note).await?;
+    launch::open(app_handle, vec![LaunchTarget::Note { id }]);
+    Ok(())
+}
+
+fn on_menu_event(app_handle: &AppHandle<Wry>, event: MenuEvent) {
+    match event.id().as_ref() {
+        NEW_NOTE => {
+            let app_handle = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = new_note(&app_handle).await {
+                    println!("Failed to create a note from the tray: {}", e);
+                }
+            });
+        }
+        PAUSE_SYNC => {
+            conditions::set_paused(app_handle, !conditions::is_sync_paused(app_handle));
+        }
+        QUIT => app_handle.exit(0),
+        id => {
+            if let Some(note_id) = id.strip_prefix(NOTE_ITEM_PREFIX) {
+                let target = LaunchTarget::Note {
+                    id: note_id.to_string(),
+                };
+                launch::open(app_handle, vec![target]);
+            }
+        }
+    }
+}
+
+// Add the tray icon. Desktops without a tray only lose the icon.
+pub fn init(app_handle: &AppHandle<Wry>) {
+    app_handle.manage(PendingRebuild::default());
+    let menu = match build_menu(app_handle, true) {
+        Ok(menu) => menu,
+        Err(e) => {
+            println!("Failed to build the tray menu: {}", e);
+            return;
+        }
+    };
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip("Notes")
+        .menu(&menu)
+        .on_menu_event(on_menu_event);
+    if let Some(icon) = app_handle.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    if let Err(e) = builder.build(app_handle) {
+        println!("Failed to create the tray icon: {}", e);
+        return;
+    }
+
+    for event in REBUILD_ON {
+        let handle = app_handle.clone();
+        let rescan = event == events::NOTES_UPDATED;
+        app_handle.listen_any(event, move |_| rebuild(&handle, rescan));
+    }
+}
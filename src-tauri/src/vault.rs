@@ -0,0 +1,404 @@
+use crate::blobs;
+use crate::changes;
+use crate::compression;
+use crate::events;
+use crate::markdown::{self, ATTACHMENT_SCHEME};
+use crate::{
+    encryption, frontmatter, get_notes_dir, notebooks, secret, storage, AppState, SyncStatus,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, Wry};
+
+#[derive(Debug, Serialize)]
+pub struct OrphanedAttachment {
+    note_id: String,
+    name: String,
+    bytes: u64,
+    // False when the whole note is gone rather than just the reference to the file
+    note_exists: bool,
+}
+
+// Note file contents by id, including notes staged by a pending sync
+fn note_sources(notes_dir: &Path) -> Result<Vec<(String, String)>, String> {
+    let mut sources = Vec::new();
+
+    for path in notebooks::note_dir_files(notes_dir) {
+        if !matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("md") | Some("sync")
+        ) {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        // Staged syncs are written as `<id>.md.sync`
+        let id = id.strip_suffix(".md").unwrap_or(id);
+        match encryption::read_to_string(&path) {
+            Ok(raw) => sources.push((id.to_string(), raw)),
+            Err(e) => println!("Failed to read {:?}: {}", path, e),
+        }
+    }
+
+    Ok(sources)
+}
+
+// Any mention of the file name counts as a reference, so links written by hand or
+// in raw HTML keep their files too
+fn is_referenced(name: &str, sources: &[&str]) -> bool {
+    let encoded = name.replace(' ', "%20");
+    sources
+        .iter()
+        .any(|source| source.contains(name) || source.contains(&encoded))
+}
+
+pub fn find_orphans(app_handle: &AppHandle<Wry>) -> Result<Vec<OrphanedAttachment>, String> {
+    let notes_dir = get_notes_dir(app_handle);
+    let attachments_root = notes_dir.join("attachments");
+    if !attachments_root.exists() {
+        return Ok(Vec::new());
+    }
+
+    let sources = note_sources(&notes_dir)?;
+    // Notes still waiting for the user to accept or reject them
+    let pending: Vec<String> = {
+        let state = app_handle.state::<Arc<Mutex<AppState>>>();
+        let state = state.lock().map_err(|e| e.to_string())?;
+        state
+            .sync_notifications
+            .iter()
+            .filter(|n| matches!(n.status, SyncStatus::Pending))
+            .map(|n| n.note_id.clone())
+            .collect()
+    };
+    let mut orphans = Vec::new();
+
+    for dir in fs::read_dir(&attachments_root).map_err(|e| e.to_string())? {
+        let dir = dir.map_err(|e| e.to_string())?.path();
+        let Some(note_id) = dir.file_name().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if !dir.is_dir() {
+            continue;
+        }
+
+        // Archived notes keep their attachments inside the archive
+        let archived = notes_dir
+            .join(".cold")
+            .join(format!("{}.zip", note_id))
+            .exists();
+        let note_sources: Vec<&str> = sources
+            .iter()
+            .filter(|(id, _)| id == note_id)
+            .map(|(_, raw)| raw.as_str())
+            .collect();
+        // A pending sync keeps every file that came with it, linked or not, until
+        // the note is accepted or rejected
+        let pending = pending.iter().any(|id| id == note_id);
+        let note_exists = archived || pending || !note_sources.is_empty();
+        // A locked note's body is encrypted, so its files can't be found in it
+        let locked = note_sources
+            .iter()
+            .any(|raw| frontmatter::split(raw).0.contains_key(secret::LOCKED_FIELD));
+
+        for file in fs::read_dir(&dir).map_err(|e| e.to_string())?.flatten() {
+            let Some(name) = file.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let referenced = is_referenced(compression::attachment_name(&name), &note_sources);
+            if note_exists && (archived || pending || locked || referenced) {
+                continue;
+            }
+
+            orphans.push(OrphanedAttachment {
+                note_id: note_id.to_string(),
+                name,
+                bytes: file.metadata().map(|m| m.len()).unwrap_or(0),
+                note_exists,
+            });
+        }
+    }
+
+    Ok(orphans)
+}
+
+#[tauri::command]
+pub async fn find_orphaned_attachments(
+    app_handle: AppHandle<Wry>,
+) -> Result<Vec<OrphanedAttachment>, String> {
+    find_orphans(&app_handle)
+}
+
+// Delete orphaned attachments. Without `confirm` nothing is removed and the files
+// that would be deleted are returned instead.
+#[tauri::command]
+pub async fn cleanup_orphans(
+    app_handle: AppHandle<Wry>,
+    confirm: bool,
+) -> Result<Vec<OrphanedAttachment>, String> {
+    let orphans = find_orphans(&app_handle)?;
+    if !confirm {
+        return Ok(orphans);
+    }
+
+    let attachments_root = get_notes_dir(&app_handle).join("attachments");
+    let mut removed = Vec::new();
+    for orphan in orphans {
+        let dir = attachments_root.join(&orphan.note_id);
+        let path = dir.join(&orphan.name);
+        blobs::unshare_file(&app_handle, &path);
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                // Drop the directory of a deleted note once it is empty
+                if !orphan.note_exists {
+                    let _ = fs::remove_dir(&dir);
+                }
+                removed.push(orphan);
+            }
+            Err(e) => println!("Failed to remove {}/{}: {}", orphan.note_id, orphan.name, e),
+        }
+    }
+
+    // Notes that still exist list their attachments anew
+    let mut changed: Vec<&str> = Vec::new();
+    for orphan in removed.iter().filter(|o| o.note_exists) {
+        if !changed.contains(&orphan.note_id.as_str()) {
+            changed.push(&orphan.note_id);
+            changes::publish_saved(&app_handle, &orphan.note_id, false)?;
+        }
+    }
+
+    Ok(removed)
+}
+
+#[derive(Debug, Serialize)]
+pub struct VaultIssue {
+    kind: &'static str,
+    // File or directory, relative to the notes directory
+    path: String,
+    message: String,
+    // Whether `repair_vault` can fix this without losing anything
+    repairable: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepairReport {
+    repaired: Vec<VaultIssue>,
+    remaining: Vec<VaultIssue>,
+}
+
+fn issue(kind: &'static str, path: String, message: String, repairable: bool) -> VaultIssue {
+    VaultIssue {
+        kind,
+        path,
+        message,
+        repairable,
+    }
+}
+
+fn title_of(raw: &str) -> Option<String> {
+    let (_, body) = frontmatter::split(raw);
+    body.lines()
+        .next()
+        .and_then(|line| line.strip_prefix("# "))
+        .map(|title| title.trim().to_string())
+}
+
+pub fn verify(app_handle: &AppHandle<Wry>) -> Result<Vec<VaultIssue>, String> {
+    let notes_dir = get_notes_dir(app_handle);
+    let mut issues = Vec::new();
+
+    // Titles of notes still waiting for the user to accept or reject them
+    let pending_titles: Vec<String> = {
+        let state = app_handle.state::<Arc<Mutex<AppState>>>();
+        let state = state.lock().map_err(|e| e.to_string())?;
+        state
+            .sync_notifications
+            .iter()
+            .filter(|n| matches!(n.status, SyncStatus::Pending))
+            .map(|n| n.note_title.clone())
+            .collect()
+    };
+
+    let mut ids_by_lowercase: HashMap<String, Vec<String>> = HashMap::new();
+
+    for path in notebooks::note_dir_files(&notes_dir) {
+        // Relative to the notes directory, as repairs take it
+        let Some(file_name) = path
+            .strip_prefix(&notes_dir)
+            .ok()
+            .and_then(|p| p.to_str())
+            .map(|p| p.replace('\\', "/"))
+        else {
+            continue;
+        };
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("md") => {
+                ids_by_lowercase
+                    .entry(id.to_lowercase())
+                    .or_default()
+                    .push(file_name.clone());
+
+                let raw = match encryption::read_to_string(&path) {
+                    Ok(raw) => raw,
+                    Err(e) => {
+                        issues.push(issue(
+                            "unreadable_file",
+                            file_name,
+                            format!("Note file can't be read: {}", e),
+                            false,
+                        ));
+                        continue;
+                    }
+                };
+
+                // Links to attachments of a note whose attachment directory is gone
+                let mut linked = Vec::new();
+                markdown::rewrite_link_targets(&raw, |target| {
+                    if let Some(name) = target.strip_prefix(ATTACHMENT_SCHEME) {
+                        linked.push(name.replace("%20", " "));
+                    }
+                    None
+                });
+                let attachments_dir = notes_dir.join("attachments").join(id);
+                let archived = notes_dir.join(".cold").join(format!("{}.zip", id)).exists();
+                if !linked.is_empty() && !attachments_dir.exists() && !archived {
+                    issues.push(issue(
+                        "missing_attachment_dir",
+                        format!("attachments/{}", id),
+                        format!(
+                            "Note links to {} attachment(s) but has no attachment directory",
+                            linked.len()
+                        ),
+                        false,
+                    ));
+                }
+            }
+            Some("sync") => {
+                let title = encryption::read_to_string(&path)
+                    .ok()
+                    .and_then(|raw| title_of(&raw));
+                let pending = title.is_some_and(|title| pending_titles.contains(&title));
+                if !pending {
+                    issues.push(issue(
+                        "sync_leftover",
+                        file_name,
+                        "Staged sync file with no pending sync request".to_string(),
+                        true,
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Ids that only differ in case collide on case-insensitive file systems
+    for files in ids_by_lowercase.values().filter(|files| files.len() > 1) {
+        issues.push(issue(
+            "duplicate_id",
+            files.join(", "),
+            "Note ids differ only in letter case".to_string(),
+            false,
+        ));
+    }
+
+    // Archives that were interrupted, or whose note also has a live copy
+    let cold_dir = notes_dir.join(".cold");
+    if let Ok(entries) = fs::read_dir(&cold_dir) {
+        for path in entries.flatten().map(|e| e.path()) {
+            let Some(file_name) = path.file_name().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let relative = format!(".cold/{}", file_name);
+
+            if file_name.ends_with(".zip.tmp") {
+                issues.push(issue(
+                    "archive_leftover",
+                    relative,
+                    "Incomplete cold storage archive".to_string(),
+                    true,
+                ));
+            } else if let Some(id) = file_name.strip_suffix(".zip") {
+                if notebooks::find_note_file(&notes_dir, id).is_some() {
+                    issues.push(issue(
+                        "archive_drift",
+                        relative,
+                        "Note is both archived and live".to_string(),
+                        true,
+                    ));
+                }
+            }
+        }
+    }
+
+    for orphan in find_orphans(app_handle)?.iter().filter(|o| !o.note_exists) {
+        issues.push(issue(
+            "orphaned_attachment",
+            format!("attachments/{}/{}", orphan.note_id, orphan.name),
+            "Attachment belongs to a note that no longer exists".to_string(),
+            false,
+        ));
+    }
+
+    Ok(issues)
+}
+
+fn repair(app_handle: &AppHandle<Wry>, issue: &VaultIssue) -> Result<(), String> {
+    let notes_dir = get_notes_dir(app_handle);
+    match issue.kind {
+        "sync_leftover" | "archive_leftover" => {
+            fs::remove_file(notes_dir.join(&issue.path)).map_err(|e| e.to_string())
+        }
+        // Unpacking keeps the live note file and brings back any attachments
+        "archive_drift" => {
+            let id = issue
+                .path
+                .trim_start_matches(".cold/")
+                .trim_end_matches(".zip");
+            storage::restore_note(app_handle, id)
+        }
+        _ => Err("Not repairable".to_string()),
+    }
+}
+
+#[tauri::command]
+pub async fn verify_vault(app_handle: AppHandle<Wry>) -> Result<Vec<VaultIssue>, String> {
+    verify(&app_handle)
+}
+
+// Fix the issues that can be fixed without losing data and report the rest
+#[tauri::command]
+pub async fn repair_vault(app_handle: AppHandle<Wry>) -> Result<RepairReport, String> {
+    let mut report = RepairReport {
+        repaired: Vec::new(),
+        remaining: Vec::new(),
+    };
+
+    for issue in verify(&app_handle)? {
+        if !issue.repairable {
+            report.remaining.push(issue);
+            continue;
+        }
+        match repair(&app_handle, &issue) {
+            Ok(()) => report.repaired.push(issue),
+            Err(e) => {
+                println!("Failed to repair {}: {}", issue.path, e);
+                report.remaining.push(issue);
+            }
+        }
+    }
+
+    if !report.repaired.is_empty() {
+        events::notes_reloaded(&app_handle)?;
+    }
+
+    Ok(report)
+}
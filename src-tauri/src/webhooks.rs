@@ -0,0 +1,192 @@
+use crate::changes::{self, NoteChange};
+use crate::proxy::{self, HttpFeature};
+use crate::settings::load_device_settings;
+use crate::{AppState, Note};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, Wry};
+
+const MAX_ATTEMPTS: u32 = 4;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    NoteCreated,
+    NoteUpdated,
+    NoteShared,
+}
+
+impl WebhookEvent {
+    fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::NoteCreated => "note_created",
+            WebhookEvent::NoteUpdated => "note_updated",
+            WebhookEvent::NoteShared => "note_shared",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    // Used to sign payloads so the receiver can verify they came from us
+    #[serde(default)]
+    pub secret: Option<String>,
+    // An empty list subscribes to every event
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    event: WebhookEvent,
+    timestamp: String,
+    device_id: String,
+    note: &'a Note,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer_id: Option<&'a str>,
+}
+
+// HMAC-SHA256 of the request body, hex encoded
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut key = if secret.len() > BLOCK_SIZE {
+        Sha256::digest(secret).to_vec()
+    } else {
+        secret.to_vec()
+    };
+    key.resize(BLOCK_SIZE, 0);
+
+    let inner_pad: Vec<u8> = key.iter().map(|b| b ^ 0x36).collect();
+    let outer_pad: Vec<u8> = key.iter().map(|b| b ^ 0x5c).collect();
+
+    let inner = Sha256::new()
+        .chain_update(&inner_pad)
+        .chain_update(body)
+        .finalize();
+    let outer = Sha256::new()
+        .chain_update(&outer_pad)
+        .chain_update(inner)
+        .finalize();
+
+    hex::encode(outer)
+}
+
+// Queue delivery of `event` to every matching webhook. Delivery happens in the
+// background so callers never wait on (or fail because of) a slow endpoint.
+fn dispatch(app_handle: &AppHandle<Wry>, event: WebhookEvent, note: &Note, peer_id: Option<&str>) {
+    let hooks: Vec<WebhookConfig> = load_device_settings(app_handle)
+        .webhooks
+        .into_iter()
+        .filter(|hook| hook.enabled && (hook.events.is_empty() || hook.events.contains(&event)))
+        .collect();
+
+    if hooks.is_empty() {
+        return;
+    }
+
+    let device_id = {
+        let state = app_handle.state::<Arc<Mutex<AppState>>>();
+        let app_state = state.lock();
+        match app_state {
+            Ok(app_state) => app_state.device_id.clone(),
+            Err(_) => String::new(),
+        }
+    };
+
+    let payload = WebhookPayload {
+        event,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        device_id,
+        note,
+        peer_id,
+    };
+
+    let body = match serde_json::to_vec(&payload) {
+        Ok(body) => body,
+        Err(e) => {
+            println!("Failed to serialize webhook payload: {}", e);
+            return;
+        }
+    };
+
+    let client = match proxy::client(app_handle, HttpFeature::Webhooks) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("Failed to create webhook client: {}", e);
+            return;
+        }
+    };
+
+    for hook in hooks {
+        let body = body.clone();
+        let client = client.clone();
+        tauri::async_runtime::spawn(async move {
+            deliver(client, hook, event, body).await;
+        });
+    }
+}
+
+// Send created, updated and shared notes to the configured webhooks
+pub fn spawn_dispatcher(app_handle: &AppHandle<Wry>) {
+    changes::subscribe(app_handle, "Webhooks", |app_handle, change| match change {
+        NoteChange::Created(note) => dispatch(app_handle, WebhookEvent::NoteCreated, &note, None),
+        NoteChange::Updated(note) => dispatch(app_handle, WebhookEvent::NoteUpdated, &note, None),
+        NoteChange::Shared { note, peer_id } => {
+            dispatch(app_handle, WebhookEvent::NoteShared, &note, Some(&peer_id))
+        }
+        NoteChange::Deleted(_) => {}
+    });
+}
+
+async fn deliver(client: reqwest::Client, hook: WebhookConfig, event: WebhookEvent, body: Vec<u8>) {
+    let signature = hook
+        .secret
+        .as_ref()
+        .map(|secret| format!("sha256={}", sign(secret.as_bytes(), &body)));
+
+    for attempt in 0..MAX_ATTEMPTS {
+        let mut request = client
+            .post(&hook.url)
+            .header("Content-Type", "application/json")
+            .header("X-Notes-Event", event.as_str())
+            .body(body.clone())
+            .timeout(Duration::from_secs(10));
+
+        if let Some(signature) = &signature {
+            request = request.header("X-Notes-Signature", signature.as_str());
+        }
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => println!(
+                "Webhook {} returned status {} (attempt {})",
+                hook.url,
+                response.status(),
+                attempt + 1
+            ),
+            Err(e) => println!(
+                "Webhook {} failed: {} (attempt {})",
+                hook.url,
+                e,
+                attempt + 1
+            ),
+        }
+
+        // Back off 1s, 2s, 4s between attempts
+        if attempt + 1 < MAX_ATTEMPTS {
+            tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+        }
+    }
+
+    println!("Giving up on webhook {} for {}", hook.url, event.as_str());
+}
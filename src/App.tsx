@@ -4,11 +4,13 @@ import { Toaster } from "@/components/ui/toaster";
 import { open } from "@tauri-apps/plugin-dialog";
 import { invoke } from "@tauri-apps/api/core";
 import { toast } from "@/hooks/use-toast";
-import { ViewMode } from "./types";
+import { AttachmentInfo, VaultAccess, ViewMode } from "./types";
 import { NoteList } from "./components/NoteList";
 import { NoteEditor } from "./components/NoteEditor";
 import { LoadingSpinner } from "./components/LoadingSpinner";
 import { useNotes } from "./hooks/useNotes";
+import { useAutosave } from "./hooks/useAutosave";
+import { useLaunchTargets } from "./hooks/useLaunchTargets";
 import { usePeers } from "./hooks/usePeers";
 import { useSyncNotifications } from "./hooks/useSyncNotifications";
 import { ShareDialog } from "./components/ShareDialog";
@@ -22,17 +24,53 @@ const App: React.FC = () => {
   const [shareDialogOpen, setShareDialogOpen] = useState(false);
   const [isSharing, setIsSharing] = useState(false);
   const [notesToShare, setNotesToShare] = useState<any[]>([]);
+  const [vaultAccess, setVaultAccess] = useState<VaultAccess | null>(null);
+
+  useEffect(() => {
+    invoke<VaultAccess>("get_vault_access")
+      .then(setVaultAccess)
+      .catch((error) => console.error("Failed to get vault access:", error));
+  }, []);
 
   const {
     notes,
     selectedNote,
     isLoading: notesLoading,
+    progress: notesProgress,
     setSelectedNote,
+    withRevision,
     createNewNote,
     updateNote,
     deleteNote,
   } = useNotes();
 
+  const { status: saveStatus, autosave, flush } = useAutosave();
+
+  // Notes and files opened with the app, or with a later launch of it
+  useLaunchTargets(setSelectedNote);
+
+  // Later saves of the open note start from what autosave wrote
+  const autosavedRevision = selectedNote && saveStatus[selectedNote.id]?.note_revision;
+  useEffect(() => {
+    if (autosavedRevision) {
+      setSelectedNote((note) => note && withRevision(note, autosavedRevision));
+    }
+  }, [autosavedRevision]);
+
+  // The open note changed elsewhere, so autosave didn't write over it
+  const autosaveError = selectedNote && saveStatus[selectedNote.id]?.error;
+  useEffect(() => {
+    if (autosaveError?.kind === "conflict") {
+      toast({
+        title: "Note changed elsewhere",
+        description:
+          "This note was changed since you opened it. Showing the latest version.",
+        variant: "destructive",
+      });
+      setSelectedNote(autosaveError.current);
+    }
+  }, [autosaveError]);
+
   const { peers, isLoading: peersLoading, shareNotes } = usePeers();
 
   const {
@@ -59,8 +97,21 @@ const App: React.FC = () => {
       });
     });
 
+    // Shares and answers that didn't get through to the peer
+    const unlistenFailed = listen("sync-failed", (event: any) => {
+      const { error } = event.payload;
+      toast({
+        title: "Sync Failed",
+        description: error.retryable
+          ? `${error.message}. Try again later.`
+          : error.message,
+        variant: "destructive",
+      });
+    });
+
     return () => {
       unlisten.then((fn) => fn());
+      unlistenFailed.then((fn) => fn());
     };
   }, []);
 
@@ -73,15 +124,7 @@ const App: React.FC = () => {
     if (!selectedNote) return;
 
     try {
-      const selected = await open({
-        multiple: false,
-        filters: [
-          {
-            name: "Image",
-            extensions: ["png", "jpg", "jpeg", "gif", "webp"],
-          },
-        ],
-      });
+      const selected = await open({ multiple: false });
 
       if (selected) {
         const filePath = selected as string;
@@ -89,11 +132,19 @@ const App: React.FC = () => {
           noteId: selectedNote.id,
           sourcePath: filePath,
         });
+        const info = await invoke<AttachmentInfo>("get_attachment_info", {
+          noteId: selectedNote.id,
+          fileName,
+        });
 
-        const imageMarkdown = `![${fileName}](attachment://${fileName})`;
+        // Images show inline; anything else becomes a link that opens it
+        const target = `attachment://${fileName.replace(/ /g, "%20")}`;
+        const attachmentMarkdown = info.mime.startsWith("image/")
+          ? `![${fileName}](${target})`
+          : `[${fileName}](${target})`;
         const updatedNote = {
           ...selectedNote,
-          content: selectedNote.content + "\n" + imageMarkdown,
+          content: selectedNote.content + "\n" + attachmentMarkdown,
           attachments: [...selectedNote.attachments, fileName],
           datetime: new Date().toISOString(),
         };
@@ -103,7 +154,7 @@ const App: React.FC = () => {
     } catch (error) {
       toast({
         title: "Error",
-        description: "Failed to upload image",
+        description: "Failed to add attachment",
         variant: "destructive",
       });
     }
@@ -180,17 +231,50 @@ const App: React.FC = () => {
     }
   };
 
-  if (notesLoading) {
-    return <LoadingSpinner />;
+  const handleKeepBothSync = async (notificationId: string) => {
+    const kept = await respondToSync(notificationId, false, "keep_both");
+    toast(
+      kept
+        ? {
+            title: "Kept both versions",
+            description: "Their version was saved as a new note",
+          }
+        : {
+            title: "Error",
+            description: "Failed to keep both versions",
+            variant: "destructive",
+          }
+    );
+  };
+
+  const progressLabel =
+    notesProgress &&
+    `${notesProgress.phase === "index" ? "Indexing" : "Loading"} notes… ${
+      notesProgress.scanned
+    } of ${notesProgress.total}`;
+
+  // Notes read so far are shown while a large vault is still loading
+  if (notesLoading && notes.length === 0) {
+    return <LoadingSpinner label={progressLabel || undefined} />;
   }
 
   return (
     <div className="h-screen flex flex-col p-4 dark:bg-gray-900">
       <div className="flex-1 flex flex-col gap-4 min-h-0 overflow-hidden">
+        {vaultAccess?.read_only && (
+          <p className="text-sm text-amber-600 dark:text-amber-400">
+            These notes are open in {vaultAccess.holder}, so this window is
+            read-only. Close that one and restart the app to edit them here.
+          </p>
+        )}
+        {notesLoading && progressLabel && (
+          <p className="text-sm text-muted-foreground">{progressLabel}</p>
+        )}
         <SyncNotificationList
           notifications={notifications}
           onAccept={handleAcceptSync}
           onReject={handleRejectSync}
+          onKeepBoth={handleKeepBothSync}
           isLoading={notificationsLoading}
         />
 
@@ -216,6 +300,17 @@ const App: React.FC = () => {
                 note={selectedNote}
                 viewMode={viewMode}
                 onUpdateNote={updateNote}
+                onAutosave={(content) => {
+                  autosave(
+                    selectedNote.id,
+                    content,
+                    (selectedNote.fields?.hlc as string | undefined) ?? null,
+                  );
+                  // Keep the selection current for edits made outside the editor
+                  setSelectedNote({ ...selectedNote, content });
+                }}
+                onFlush={() => flush(selectedNote.id)}
+                saveStatus={saveStatus[selectedNote.id]}
                 onViewModeChange={setViewMode}
                 onImageUpload={handleImageUpload}
               />
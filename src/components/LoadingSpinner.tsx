@@ -1,7 +1,8 @@
 import React from 'react';
 
-export const LoadingSpinner: React.FC = () => (
-    <div className="h-screen flex items-center justify-center">
+export const LoadingSpinner: React.FC<{ label?: string }> = ({ label }) => (
+    <div className="h-screen flex flex-col items-center justify-center gap-4">
         <div className="animate-spin rounded-full h-32 w-32 border-b-2 border-gray-900"></div>
+        {label && <p className="text-sm text-muted-foreground">{label}</p>}
     </div>
-);
\ No newline at end of file
+);
@@ -1,18 +1,21 @@
 import React, { useRef, useEffect } from 'react';
 import { Button } from "@/components/ui/button";
-import { Eye, Code } from 'lucide-react';
+import { Eye, Code, FileArchive, FileText, Paperclip } from 'lucide-react';
 import { Separator } from '@/components/ui/separator';
 import ReactMarkdown from 'react-markdown';
 import remarkGfm from 'remark-gfm';
-import { Note, ViewMode } from '@/types';
+import { AttachmentInfo, AutosaveStatus, Note, ViewMode } from '@/types';
 import { useUndoRedo } from '@/hooks/useUndoRedo';
-import { invoke } from "@tauri-apps/api/core";
+import { convertFileSrc, invoke } from "@tauri-apps/api/core";
 import { toast } from "@/hooks/use-toast.ts";
 
 interface NoteEditorProps {
     note: Note;
     viewMode: ViewMode;
     onUpdateNote: (note: Note) => void;
+    onAutosave: (content: string) => void;
+    onFlush: () => void;
+    saveStatus?: AutosaveStatus;
     onViewModeChange: (mode: ViewMode) => void;
     onImageUpload: () => void;
 }
@@ -21,6 +24,9 @@ export const NoteEditor: React.FC<NoteEditorProps> = ({
                                                           note,
                                                           viewMode,
                                                           onUpdateNote,
+                                                          onAutosave,
+                                                          onFlush,
+                                                          saveStatus,
                                                           onViewModeChange,
                                                       }) => {
     const textAreaRef = useRef<HTMLTextAreaElement>(null);
@@ -48,16 +54,15 @@ export const NoteEditor: React.FC<NoteEditorProps> = ({
         return () => document.removeEventListener('keydown', handleKeyDown);
     }, [canUndo, canRedo, undo, redo]);
 
-    // Handle content updates
+    // Every edit goes to autosave, which decides when to write
     useEffect(() => {
-        const timer = setTimeout(() => {
-            if (content !== note.content) {
-                onUpdateNote({ ...note, content });
-            }
-        }, 300);
+        if (content !== note.content) {
+            onAutosave(content);
+        }
+    }, [content]);
 
-        return () => clearTimeout(timer);
-    }, [content, note, onUpdateNote]);
+    // Write what's pending when switching to another note
+    useEffect(() => () => onFlush(), []);
 
     // Fixed paste handler
     const handlePaste = async (e: React.ClipboardEvent<HTMLTextAreaElement>) => {
@@ -139,33 +144,8 @@ export const NoteEditor: React.FC<NoteEditorProps> = ({
     const customMarkdownComponents = {
         img: ({ src, alt, ...props }: any) => {
             const fileName = alt;
-            const [imageSrc, setImageSrc] = React.useState<string>('');
-
-            React.useEffect(() => {
-                const loadImage = async () => {
-                    try {
-                        const imageData = await invoke<number[]>('serve_attachment', {
-                            noteId: note.id,
-                            fileName
-                        });
-
-                        const blob = new Blob([new Uint8Array(imageData)], { type: 'image/png' });
-                        const url = URL.createObjectURL(blob);
-                        setImageSrc(url);
-
-                        return () => URL.revokeObjectURL(url);
-                    } catch (error) {
-                        console.error('Failed to load image:', error);
-                        toast({
-                            title: "Error",
-                            description: `Failed to load image: ${fileName}`,
-                            variant: "destructive",
-                        });
-                    }
-                };
-
-                loadImage();
-            }, [fileName]);
+            // Served straight from disk by the notes-asset protocol
+            const imageSrc = convertFileSrc(`${note.id}/${fileName}`, 'notes-asset');
 
             return (
                 <img
@@ -175,6 +155,60 @@ export const NoteEditor: React.FC<NoteEditorProps> = ({
                     {...props}
                 />
             );
+        },
+        a: ({ href, children, ...props }: any) => {
+            // Other attachments open in the app the system picks for them
+            const fileName = href?.startsWith('attachment://')
+                ? decodeURIComponent(href.slice('attachment://'.length))
+                : null;
+            const [info, setInfo] = React.useState<AttachmentInfo | null>(null);
+
+            React.useEffect(() => {
+                if (!fileName) return;
+                invoke<AttachmentInfo>('get_attachment_info', { noteId: note.id, fileName })
+                    .then(setInfo)
+                    .catch((error) => console.error('Failed to read attachment info:', error));
+            }, [fileName]);
+
+            if (!fileName) {
+                return <a href={href} {...props}>{children}</a>;
+            }
+
+            const Icon = !info
+                ? Paperclip
+                : info.mime === 'application/pdf' || info.mime.startsWith('text/')
+                  ? FileText
+                  : /zip|tar|gzip|compressed/.test(info.mime)
+                    ? FileArchive
+                    : Paperclip;
+            const openAttachment = async () => {
+                try {
+                    await invoke('open_attachment', { noteId: note.id, fileName });
+                } catch (error) {
+                    console.error('Failed to open attachment:', error);
+                    toast({
+                        title: "Error",
+                        description: `Failed to open ${fileName}`,
+                        variant: "destructive",
+                    });
+                }
+            };
+
+            return (
+                <button
+                    type="button"
+                    onClick={openAttachment}
+                    className="inline-flex items-center gap-1 rounded border px-2 py-0.5 text-sm hover:bg-gray-100 dark:hover:bg-gray-800"
+                >
+                    <Icon className="h-4 w-4" />
+                    {children}
+                    {info && (
+                        <span className="text-xs text-gray-500">
+                            {(info.size / 1024).toFixed(0)} KB
+                        </span>
+                    )}
+                </button>
+            );
         }
     };
 
@@ -200,6 +234,15 @@ export const NoteEditor: React.FC<NoteEditorProps> = ({
                             <><Code className="h-4 w-4 mr-2" /> Edit</>
                         )}
                     </Button>
+                    {saveStatus && (
+                        <div className="text-sm text-gray-400 dark:text-gray-500">
+                            {saveStatus.error
+                                ? "Not saved"
+                                : saveStatus.dirty
+                                    ? "Unsaved changes"
+                                    : "Saved"}
+                        </div>
+                    )}
                     <div className="text-sm text-gray-400 dark:text-gray-500">
                         {new Date(Number(note.datetime) * 1000).toLocaleString("de-DE")}
                     </div>
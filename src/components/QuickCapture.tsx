@@ -0,0 +1,63 @@
+import React, { useEffect, useRef, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { getCurrentWindow } from "@tauri-apps/api/window";
+import { Button } from "@/components/ui/button";
+
+// The quick capture window: type, then Ctrl/Cmd+Enter saves a new inbox note and
+// Escape puts the window away
+export const QuickCapture: React.FC = () => {
+  const [content, setContent] = useState("");
+  const [error, setError] = useState<string | null>(null);
+  const [isSaving, setIsSaving] = useState(false);
+  const textareaRef = useRef<HTMLTextAreaElement>(null);
+
+  useEffect(() => {
+    // The window is hidden rather than closed, so focus again each time it shows
+    const focus = () => textareaRef.current?.focus();
+    focus();
+    window.addEventListener("focus", focus);
+    return () => window.removeEventListener("focus", focus);
+  }, []);
+
+  const save = async () => {
+    if (!content.trim() || isSaving) return;
+    setIsSaving(true);
+    setError(null);
+    try {
+      await invoke("quick_capture", { content });
+      setContent("");
+    } catch (e) {
+      setError(String(e));
+    } finally {
+      setIsSaving(false);
+    }
+  };
+
+  const handleKeyDown = (e: React.KeyboardEvent) => {
+    if (e.key === "Enter" && (e.ctrlKey || e.metaKey)) {
+      e.preventDefault();
+      save();
+    } else if (e.key === "Escape") {
+      getCurrentWindow().hide();
+    }
+  };
+
+  return (
+    <div className="h-screen flex flex-col p-3 gap-2 bg-white dark:bg-gray-800">
+      <textarea
+        ref={textareaRef}
+        value={content}
+        onChange={(e) => setContent(e.target.value)}
+        onKeyDown={handleKeyDown}
+        placeholder="Capture a thought…"
+        className="flex-1 resize-none p-2 rounded border border-gray-200 dark:border-gray-700 bg-transparent dark:text-white focus:outline-none"
+      />
+      <div className="flex items-center justify-between">
+        <span className="text-xs text-red-500">{error}</span>
+        <Button size="sm" onClick={save} disabled={!content.trim() || isSaving}>
+          Save to inbox
+        </Button>
+      </div>
+    </div>
+  );
+};
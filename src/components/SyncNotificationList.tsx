@@ -1,6 +1,6 @@
 import React, { useEffect } from "react";
 import { Button } from "@/components/ui/button";
-import { Check, X } from "lucide-react";
+import { Check, Copy, X } from "lucide-react";
 import { SyncNotification, SyncStatus } from "@/types";
 import { LoadingSpinner } from "./LoadingSpinner";
 import { Card } from "@/components/ui/card";
@@ -10,6 +10,8 @@ interface SyncNotificationListProps {
   notifications: SyncNotification[];
   onAccept: (id: string) => void;
   onReject: (id: string) => void;
+  // Keep a conflicting note as a copy next to ours
+  onKeepBoth: (id: string) => void;
   isLoading: boolean;
 }
 
@@ -17,6 +19,7 @@ export const SyncNotificationList: React.FC<SyncNotificationListProps> = ({
   notifications,
   onAccept,
   onReject,
+  onKeepBoth,
   isLoading,
 }) => {
   // Only show pending notifications
@@ -58,6 +61,11 @@ export const SyncNotificationList: React.FC<SyncNotificationListProps> = ({
                   <div className="text-xs text-gray-500">
                     From: {notification.from_peer.name}
                   </div>
+                  {notification.conflict && (
+                    <div className="text-xs text-amber-600 dark:text-amber-400">
+                      Also edited on this device
+                    </div>
+                  )}
                 </div>
                 <div className="flex gap-2">
                   <Button
@@ -71,8 +79,18 @@ export const SyncNotificationList: React.FC<SyncNotificationListProps> = ({
                       onAccept(notification.id);
                     }}
                   >
-                    <Check className="h-4 w-4 mr-1" /> Accept
+                    <Check className="h-4 w-4 mr-1" />{" "}
+                    {notification.conflict ? "Take theirs" : "Accept"}
                   </Button>
+                  {notification.conflict && (
+                    <Button
+                      size="sm"
+                      variant="outline"
+                      onClick={() => onKeepBoth(notification.id)}
+                    >
+                      <Copy className="h-4 w-4 mr-1" /> Keep both
+                    </Button>
+                  )}
                   <Button
                     size="sm"
                     variant="outline"
@@ -84,7 +102,8 @@ export const SyncNotificationList: React.FC<SyncNotificationListProps> = ({
                       onReject(notification.id);
                     }}
                   >
-                    <X className="h-4 w-4 mr-1" /> Reject
+                    <X className="h-4 w-4 mr-1" />{" "}
+                    {notification.conflict ? "Keep mine" : "Reject"}
                   </Button>
                 </div>
               </div>
@@ -0,0 +1,76 @@
+import { useState, useEffect, useRef } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { AutosaveStatus } from "@/types";
+
+// Saves editor content through `autosave_note`, which debounces the writes, and
+// follows `note-saved` to know which revision of each note is on disk. The note's
+// stored revision the edit started from goes along, so a note changed elsewhere is
+// reported as a conflict instead of being overwritten.
+export function useAutosave() {
+  const revisions = useRef<Record<string, number>>({});
+  const [status, setStatus] = useState<Record<string, AutosaveStatus>>({});
+
+  const track = (update: AutosaveStatus) =>
+    setStatus((current) => {
+      const known = current[update.note_id];
+      // A write that finished late doesn't undo a newer edit's dirty state
+      if (known && known.revision > update.revision) {
+        return {
+          ...current,
+          [update.note_id]: {
+            ...known,
+            saved_revision: update.saved_revision,
+            error: update.error,
+          },
+        };
+      }
+      return { ...current, [update.note_id]: update };
+    });
+
+  useEffect(() => {
+    const unlisten = listen<AutosaveStatus>("note-saved", (event) => {
+      track(event.payload);
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  const autosave = async (
+    noteId: string,
+    content: string,
+    expectedRevision: string | null,
+  ) => {
+    const revision = (revisions.current[noteId] ?? 0) + 1;
+    revisions.current[noteId] = revision;
+    try {
+      const update = await invoke<AutosaveStatus>("autosave_note", {
+        noteId,
+        content,
+        revision,
+        expectedRevision,
+      });
+      // The backend is ahead, e.g. after a reload of the window: continue from
+      // its revision so later edits aren't taken as out of order
+      if (update.revision > revision) {
+        revisions.current[noteId] = update.revision;
+        return autosave(noteId, content, expectedRevision);
+      }
+      track(update);
+    } catch (error) {
+      console.error("Failed to autosave note:", error);
+    }
+  };
+
+  const flush = async (noteId: string) => {
+    try {
+      track(await invoke<AutosaveStatus>("flush_autosave", { noteId }));
+    } catch (error) {
+      console.error("Failed to save note:", error);
+    }
+  };
+
+  return { status, autosave, flush };
+}
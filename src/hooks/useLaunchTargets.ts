@@ -0,0 +1,61 @@
+import { useEffect } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { LaunchTarget, Note } from "@/types";
+import { toast } from "@/hooks/use-toast";
+
+// Imports by file extension, for files opened with the app
+const IMPORTERS: Record<string, string> = {
+  md: "import_markdown",
+  markdown: "import_markdown",
+  docx: "import_document",
+  odt: "import_document",
+};
+
+async function resolve(target: LaunchTarget): Promise<Note | undefined> {
+  if (target.kind === "note") {
+    const [note] = await invoke<Note[]>("get_notes_by_id", {
+      noteIds: [target.id],
+    });
+    if (!note) throw new Error(`No note with id ${target.id}`);
+    return note;
+  }
+
+  const extension = target.path.split(".").pop()?.toLowerCase() ?? "";
+  const command = IMPORTERS[extension];
+  if (!command) throw new Error(`Can't open ${target.path}`);
+  return invoke<Note>(command, { path: target.path });
+}
+
+// Open what the app was launched with, and what later launches forward to it
+export function useLaunchTargets(onOpen: (note: Note) => void) {
+  useEffect(() => {
+    const open = async (targets: LaunchTarget[]) => {
+      for (const target of targets) {
+        try {
+          const note = await resolve(target);
+          if (note) onOpen(note);
+        } catch (error) {
+          console.error("Failed to open launch target:", error);
+          toast({
+            title: "Couldn't open",
+            description: String(error),
+            variant: "destructive",
+          });
+        }
+      }
+    };
+
+    invoke<LaunchTarget[]>("take_launch_targets")
+      .then(open)
+      .catch((error) => console.error("Failed to get launch targets:", error));
+
+    const unlisten = listen<LaunchTarget[]>("open-targets", (event) => {
+      open(event.payload);
+    });
+
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+}
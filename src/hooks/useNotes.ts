@@ -1,13 +1,21 @@
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
-import { Note } from "@/types";
-import { v4 as uuidv4 } from "uuid";
+import {
+  IndexProgress,
+  Note,
+  NoteDeleted,
+  NoteMetadata,
+  SaveError,
+} from "@/types";
+import { toast } from "@/hooks/use-toast";
+import { v7 as uuidv7 } from "uuid";
 import { listen } from "@tauri-apps/api/event";
 
 export function useNotes() {
   const [notes, setNotes] = useState<Note[]>([]);
   const [selectedNote, setSelectedNote] = useState<Note | null>(null);
   const [isLoading, setIsLoading] = useState(true);
+  const [progress, setProgress] = useState<IndexProgress | null>(null);
 
   const loadNotes = async () => {
     try {
@@ -20,27 +28,97 @@ export function useNotes() {
     }
   };
 
+  // Put a created or updated note in place, newest first. Metadata carries no
+  // content, so the note itself is fetched to fill it in.
+  const applyChange = async (metadata: NoteMetadata) => {
+    const merge = (note?: Note): Note => ({
+      content: "",
+      ...note,
+      id: metadata.id,
+      title: metadata.title,
+      datetime: metadata.datetime,
+      attachments: metadata.attachments,
+      fields: metadata.fields,
+    });
+    const place = (update: (note?: Note) => Note) =>
+      setNotes((current) => [
+        update(current.find((note) => note.id === metadata.id)),
+        ...current.filter((note) => note.id !== metadata.id),
+      ]);
+
+    place(merge);
+    try {
+      const [note] = await invoke<Note[]>("get_notes_by_id", {
+        noteIds: [metadata.id],
+      });
+      if (note) place(() => note);
+    } catch (error) {
+      console.error("Failed to load note:", error);
+    }
+  };
+
+  const applyDelete = ({ id }: NoteDeleted) => {
+    setNotes((current) => current.filter((note) => note.id !== id));
+    setSelectedNote((selected) => (selected?.id === id ? null : selected));
+  };
+
   useEffect(() => {
+    // Large vaults arrive in batches while the first load runs
+    const unlistenProgress = listen<IndexProgress>(
+      "index-progress",
+      (event) => {
+        const { notes: batch, ...rest } = event.payload;
+        setProgress({ ...rest });
+        if (batch && batch.length > 0) {
+          // A later full load reports again, so replace what's already listed
+          const ids = new Set(batch.map((note) => note.id));
+          setNotes((current) =>
+            [...current.filter((note) => !ids.has(note.id)), ...batch].sort(
+              (a, b) => parseFloat(b.datetime) - parseFloat(a.datetime)
+            )
+          );
+        }
+      }
+    );
+
     const init = async () => {
       setIsLoading(true);
       await loadNotes();
       setIsLoading(false);
+      setProgress(null);
     };
     init();
 
-    // Listen for notes update events (when sync occurs)
-    const unlisten = listen("notes-updated", async () => {
-      await loadNotes();
-    });
+    // Saves, deletions and sync announce each note they touch
+    const unlisteners = [
+      listen<NoteMetadata>("note-created", (event) =>
+        applyChange(event.payload)
+      ),
+      listen<NoteMetadata>("note-updated", (event) =>
+        applyChange(event.payload)
+      ),
+      listen<NoteDeleted>("note-deleted", (event) =>
+        applyDelete(event.payload)
+      ),
+      // Changes that aren't about particular notes, e.g. ordering or pins
+      listen("notes-updated", async () => {
+        await loadNotes();
+      }),
+      unlistenProgress,
+    ];
 
     return () => {
-      unlisten.then((fn) => fn());
+      unlisteners.forEach((unlisten) => unlisten.then((fn) => fn()));
     };
   }, []);
 
+  // The revision a note was loaded or last saved at, kept in its `hlc` field
+  const withRevision = (note: Note, revision: string | null): Note =>
+    revision ? { ...note, fields: { ...note.fields, hlc: revision } } : note;
+
   const createNewNote = async () => {
     const newNote: Note = {
-      id: uuidv4(),
+      id: uuidv7(),
       title: "Untitled",
       content: "",
       datetime: (Date.now() / 1000).toString(),
@@ -48,10 +126,11 @@ export function useNotes() {
     };
 
     try {
-      await invoke("save_note", { note: newNote });
-      const updatedNotes = await loadNotes();
-      setNotes(updatedNotes);
-      setSelectedNote(newNote); // Immediately select the new note
+      // The list picks the note up from `note-created`
+      const revision = await invoke<string | null>("save_note", {
+        note: newNote,
+      });
+      setSelectedNote(withRevision(newNote, revision)); // Immediately select the new note
     } catch (error) {
       console.error("Failed to create note:", error);
     }
@@ -59,11 +138,32 @@ export function useNotes() {
 
   const updateNote = async (note: Note) => {
     try {
-      await invoke("save_note", { note });
-      const updatedNotes = await loadNotes();
-      setNotes(updatedNotes);
-      setSelectedNote(note); // Keep the current note selected
+      // Saving over a version this edit didn't start from is refused
+      const revision = await invoke<string | null>("save_note", {
+        note,
+        expectedRevision: note.fields?.hlc ?? null,
+      });
+      setSelectedNote(withRevision(note, revision)); // Keep the current note selected
     } catch (error) {
+      const saveError = error as SaveError;
+      if (saveError?.kind === "conflict") {
+        toast({
+          title: "Note changed elsewhere",
+          description:
+            "This note was changed since you opened it. Showing the latest version.",
+          variant: "destructive",
+        });
+        setSelectedNote(saveError.current);
+        return;
+      }
+      if (saveError?.kind === "locked") {
+        toast({
+          title: "Notes are read-only",
+          description: `The notes are open in ${saveError.holder}.`,
+          variant: "destructive",
+        });
+        return;
+      }
       console.error("Failed to update note:", error);
     }
   };
@@ -71,10 +171,8 @@ export function useNotes() {
   const deleteNote = async (id: string) => {
     try {
       await invoke("delete_note", { noteId: id });
-      const updatedNotes = await loadNotes();
-      setNotes(updatedNotes);
       if (selectedNote?.id === id) {
-        setSelectedNote(updatedNotes[0] || null);
+        setSelectedNote(notes.find((note) => note.id !== id) || null);
       }
     } catch (error) {
       console.error("Failed to delete note:", error);
@@ -85,7 +183,9 @@ export function useNotes() {
     notes,
     selectedNote,
     isLoading,
+    progress,
     setSelectedNote,
+    withRevision,
     createNewNote,
     updateNote,
     deleteNote,
@@ -1,6 +1,6 @@
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
-import { PeerDevice } from "@/types";
+import { PeerDevice, PeersUpdated } from "@/types";
 import { listen } from "@tauri-apps/api/event";
 
 export function usePeers() {
@@ -27,8 +27,13 @@ export function usePeers() {
     init();
 
     // Listen for peer update events
-    const unlisten = listen("peers-updated", async () => {
-      await loadPeers();
+    const unlisten = listen<PeersUpdated>("peers-updated", (event) => {
+      const { updated, removed } = event.payload;
+      const changed = new Set([...removed, ...updated.map((peer) => peer.id)]);
+      setPeers((current) => [
+        ...current.filter((peer) => !changed.has(peer.id)),
+        ...updated,
+      ]);
     });
 
     return () => {
@@ -1,6 +1,6 @@
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
-import { SyncNotification } from "@/types";
+import { SyncNotification, SyncResolution } from "@/types";
 import { listen } from "@tauri-apps/api/event";
 
 export function useSyncNotifications() {
@@ -32,9 +32,14 @@ export function useSyncNotifications() {
 
     // Listen for sync notification events
     console.log("Setting up sync-notification event listener");
-    const unlisten = listen("sync-notification", async (event) => {
+    const unlisten = listen<SyncNotification>("sync-notification", (event) => {
       console.log("Received sync-notification event:", event);
-      await loadNotifications();
+      const notification = event.payload;
+      setNotifications((current) =>
+        current.some((n) => n.id === notification.id)
+          ? current
+          : [...current, notification]
+      );
     });
 
     return () => {
@@ -43,14 +48,22 @@ export function useSyncNotifications() {
     };
   }, []);
 
-  const respondToSync = async (notificationId: string, accept: boolean) => {
+  const respondToSync = async (
+    notificationId: string,
+    accept: boolean,
+    resolution?: SyncResolution
+  ) => {
     try {
       console.log(
         `Responding to sync notification ${notificationId}: ${
-          accept ? "accept" : "reject"
+          resolution ?? (accept ? "accept" : "reject")
         }`
       );
-      await invoke("respond_to_sync", { notificationId, accept });
+      await invoke("respond_to_sync", {
+        notificationId,
+        accept,
+        resolution: resolution ?? null,
+      });
       console.log("respond_to_sync invoke completed successfully");
       await loadNotifications();
       return true;
@@ -1,9 +1,13 @@
 import React from "react";
 import ReactDOM from "react-dom/client";
 import App from "./App";
+import { QuickCapture } from "./components/QuickCapture";
+
+// The quick capture window loads the same page with `?capture`
+const isCapture = new URLSearchParams(window.location.search).has("capture");
 
 ReactDOM.createRoot(document.getElementById("root") as HTMLElement).render(
   <React.StrictMode>
-    <App />
+    {isCapture ? <QuickCapture /> : <App />}
   </React.StrictMode>,
 );
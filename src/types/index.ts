@@ -4,6 +4,11 @@ export interface Note {
   content: string;
   datetime: string;
   attachments: string[];
+  fields?: Record<string, unknown>;
+  // Frontmatter tags and `#tags` written in the content
+  tags?: string[];
+  // Notebook the note is filed in, e.g. `work/clients`
+  notebook?: string | null;
 }
 
 export type ViewMode = "write" | "preview";
@@ -13,12 +18,17 @@ export interface PeerDevice {
   name: string;
   ip: string;
   port: number;
+  addresses?: string[];
+  protocol?: number;
+  // SHA-256 of the certificate the peer syncs over; missing for older versions
+  fingerprint?: string | null;
 }
 
 export enum SyncStatus {
   Pending = "Pending",
   Accepted = "Accepted",
   Rejected = "Rejected",
+  Copied = "Copied",
 }
 
 export interface SyncNotification {
@@ -26,4 +36,143 @@ export interface SyncNotification {
   from_peer: PeerDevice;
   note_title: string;
   status: SyncStatus;
+  note_id: string;
+  // Our copy was edited too, so taking theirs would lose those edits
+  conflict: boolean;
+}
+
+// How to settle a note from a peer in `respond_to_sync`
+export type SyncResolution = "keep_mine" | "take_theirs" | "keep_both";
+
+// Payload of the `note-created` and `note-updated` events
+export interface NoteMetadata {
+  id: string;
+  title: string;
+  datetime: string;
+  attachments: string[];
+  fields: Record<string, unknown>;
+  excerpt: string;
+  cover?: string | null;
+  // Estimated minutes to read; missing for archived notes
+  reading_minutes?: number | null;
+  // Whole days since the note was last edited
+  days_since_edit?: number | null;
+}
+
+// Payload of the `note-deleted` event
+export interface NoteDeleted {
+  id: string;
+}
+
+// Payload of the `peers-updated` event
+export interface PeersUpdated {
+  updated: PeerDevice[];
+  removed: string[];
+}
+
+// Payload of the `index-progress` event
+export interface IndexProgress {
+  phase: "notes" | "index";
+  scanned: number;
+  total: number;
+  // Notes read since the last event, while `phase` is "notes"
+  notes?: Note[];
+}
+
+// Result of `autosave_note` and payload of the `note-saved` event
+export interface AutosaveStatus {
+  note_id: string;
+  revision: number;
+  saved_revision: number;
+  // The latest revision isn't on disk yet
+  dirty: boolean;
+  // A conflict means the note changed elsewhere and the content wasn't written
+  error?: SaveError;
+  // The note's stored revision after the write
+  note_revision?: string;
+}
+
+// Why `save_note` refused a note
+export type SaveError =
+  | { kind: "read_only"; note_id: string }
+  | { kind: "conflict"; note_id: string; current: Note }
+  | { kind: "locked"; holder: string }
+  | { kind: "password_locked"; note_id: string }
+  | { kind: "failed"; message: string };
+
+// Whether this instance may write the notes, or another one holds them
+export interface VaultAccess {
+  read_only: boolean;
+  notes_dir: string;
+  holder: string | null;
+}
+
+// A note or file the app was launched to open
+export type LaunchTarget =
+  | { kind: "note"; id: string }
+  | { kind: "file"; path: string };
+
+// This device as its peers see it
+export interface DeviceInfo {
+  device_id: string;
+  device_name: string;
+  hostname: string;
+  custom_name: boolean;
+  fingerprint: string;
+}
+
+// A device this one is paired with, from `list_paired_peers`
+export interface PairedPeer {
+  id: string;
+  name: string;
+  paired_at: string;
+}
+
+// Payload of `pair-request`: a device waiting for the PIN it shows
+export interface PairRequest {
+  peer_id: string;
+  peer_name: string;
+}
+
+// A tag in use, as listed by `get_tags`
+export interface TagCount {
+  tag: string;
+  count: number;
+  color?: string | null;
+  description?: string | null;
+  icon?: string | null;
+}
+
+// A notebook as listed by `get_notebooks`
+export interface Notebook {
+  name: string;
+  count: number;
+}
+
+// A note changed on both devices, left alone by `sync_with_peer`
+export interface LibraryConflict {
+  note_id: string;
+  title: string;
+  local_modified: number | null;
+  peer_modified: number | null;
+}
+
+// What `sync_with_peer` did, from this device's side
+export interface LibrarySummary {
+  peer_name: string;
+  created: number;
+  updated: number;
+  sent_created: number;
+  sent_updated: number;
+  conflicts: LibraryConflict[];
+  failed: number;
+}
+
+// What `get_attachment_info` knows about an attachment
+export interface AttachmentInfo {
+  name: string;
+  original_name: string;
+  mime: string;
+  size: number;
+  added_at: string | null;
 }